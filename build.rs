@@ -1,5 +1,20 @@
+use std::{env, fs, path::PathBuf};
+
 fn main() {
-    let arch = std::env::var("CARGO_CFG_TARGET_ARCH").unwrap();
+    let arch = env::var("CARGO_CFG_TARGET_ARCH").unwrap();
     println!("cargo:rustc-link-arg=-Tresources/linker-{arch}.lds");
     println!("cargo:rerun-if-changed=resources/linker-{arch}.lds");
+
+    // the key signed modules are checked against, see src/modules/signature.rs; without one, the
+    // kernel gets an empty key and won't load anything that has to be signed
+    let key = PathBuf::from(env::var("OUT_DIR").unwrap()).join("module_signing_key");
+    println!("cargo:rerun-if-env-changed=MODULE_SIGNING_KEY");
+
+    match env::var("MODULE_SIGNING_KEY") {
+        Ok(path) => {
+            println!("cargo:rerun-if-changed={path}");
+            fs::copy(&path, &key).expect("failed to read MODULE_SIGNING_KEY");
+        }
+        Err(_) => fs::write(&key, []).unwrap(),
+    }
 }
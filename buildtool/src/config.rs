@@ -0,0 +1,161 @@
+// buildtool.toml, next to the workspace's Cargo.toml; every key is optional:
+//
+//   [qemu]
+//   memory = 4 # GiB, unless -m is given
+//   cores = 1 # unless -j is given
+//   # passed to qemu after the machine, firmware, disk and serial ports
+//   args = ["-d", "int,cpu_reset", "-D", "qemu.log", "-no-reboot", "-no-shutdown", "-s", "-S"]
+//
+//   [build]
+//   # added to the RUSTFLAGS the kernel is built with
+//   rustflags = ["-C", "opt-level=1"]
+//   # keeps rbp as a frame pointer; without it, the kernel can only unwind through the CFI in the
+//   # debug module, so backtraces from before it is loaded come out short or wrong
+//   frame_pointers = true
+//
+//   [downloads]
+//   # in place of the limine release and OVMF nightly in main.rs
+//   limine_url = "https://github.com/limine-bootloader/limine/raw/refs/tags/v10.3.0-binary"
+//   ovmf_url = "https://..."
+//   # files to use as they are instead of downloading them, by their name in buildtool-cache
+//   paths = { "ovmf-x86_64.fd" = "/usr/share/OVMF/OVMF_CODE.fd" }
+//
+//   # kernel command lines, each a boot entry in limine.conf, picked with --profile <name>; these
+//   # are added to the built-in ones in PROFILES, or take their place
+//   [cmdline]
+//   trace = "logging: { serial: { enable: true } }, crashdump: { enable: true }"
+
+use anyhow::{Error, Result};
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::env::current_dir;
+use std::fs;
+use std::path::PathBuf;
+use std::sync::OnceLock;
+
+const CONFIG_FILE: &str = "buildtool.toml";
+
+pub const DEFAULT_PROFILE: &str = "default";
+// what `buildtool profile` boots, unless it is given another one
+pub const SAMPLING_PROFILE: &str = "sampling";
+
+const PROFILES: [(&str, &str); 5] = [
+    (DEFAULT_PROFILE, "logging: { serial: { enable: true } }"),
+    (
+        "serial-debug",
+        "logging: { serial: { enable: true }, options: { src: true }, \
+         targets: { wasm_kernel: debug } }, crashdump: { enable: true }",
+    ),
+    ("quiet", "logging: { targets: { wasm_kernel: warn } }"),
+    // so that a boot on many cores isn't mostly the bringup of each, and whatever breaks is dumped
+    (
+        "smp-stress",
+        "logging: { serial: { enable: true }, targets: { init_smp: warn } }, \
+         crashdump: { enable: true }",
+    ),
+    (
+        SAMPLING_PROFILE,
+        "logging: { serial: { enable: true } }, profile: { enable: true }",
+    ),
+];
+
+#[derive(Deserialize, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub qemu: QemuConfig,
+    pub build: BuildConfig,
+    pub downloads: DownloadConfig,
+    pub cmdline: BTreeMap<String, String>,
+}
+
+#[derive(Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct QemuConfig {
+    pub memory: u8,
+    pub cores: u8,
+    pub args: Vec<String>,
+}
+
+impl Default for QemuConfig {
+    fn default() -> QemuConfig {
+        QemuConfig {
+            memory: 4,
+            cores: 1,
+            args: [
+                "-d",
+                "int,cpu_reset",
+                "-D",
+                "qemu.log",
+                "-no-reboot",
+                "-no-shutdown",
+                "-s",
+                "-S",
+            ]
+            .map(String::from)
+            .into(),
+        }
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct BuildConfig {
+    pub rustflags: Vec<String>,
+    pub frame_pointers: bool,
+}
+
+impl Default for BuildConfig {
+    fn default() -> BuildConfig {
+        BuildConfig {
+            rustflags: Vec::new(),
+            frame_pointers: true,
+        }
+    }
+}
+
+#[derive(Deserialize, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct DownloadConfig {
+    pub limine_url: Option<String>,
+    pub ovmf_url: Option<String>,
+    pub paths: BTreeMap<String, PathBuf>,
+}
+
+static CONFIG: OnceLock<Config> = OnceLock::new();
+
+pub fn path() -> Result<PathBuf> {
+    Ok(current_dir()?.join(CONFIG_FILE))
+}
+
+// reads the config, or takes the defaults if there is none; must run before get()
+pub fn load() -> Result<()> {
+    let path = path()?;
+
+    let config = if path.exists() {
+        toml::from_str(&fs::read_to_string(&path)?)
+            .map_err(|err| Error::msg(format!("{}: {}", CONFIG_FILE, err)))?
+    } else {
+        Config::default()
+    };
+
+    let _ = CONFIG.set(config);
+    Ok(())
+}
+
+pub fn get() -> &'static Config {
+    CONFIG.get().expect("config::load() wasn't called")
+}
+
+impl Config {
+    // the built-in profiles, and those in buildtool.toml
+    pub fn profiles(&self) -> BTreeMap<&str, &str> {
+        PROFILES
+            .into_iter()
+            .chain(
+                self.cmdline
+                    .iter()
+                    .map(|(name, cmdline)| (name.as_str(), cmdline.as_str())),
+            )
+            .collect()
+    }
+}
@@ -0,0 +1,278 @@
+// decodes the crash dump the kernel writes over serial on panic, see src/crashdump/mod.rs
+//
+// the frames, past the kind, carry little endian:
+// - begin: nothing; a dump starts here, and anything from an earlier one is dropped
+// - panic: a piece of the panic report
+// - cpu: the core id as a u32, then its registers as in gdb's amd64 `g` packet
+// - stack: the core id as a u32, the address of the page part as a u64, the offset into it as a
+//   u32, then the bytes
+// - memmap: the base and length of an entry as u64s, then its type as a u8
+// - dmesg: the offset into the log as a u32, then the text
+// - end: the number of frames before it, as a u32
+
+use anyhow::{Error, Result};
+use std::collections::BTreeMap;
+use std::fmt::Write;
+
+const MAGIC: &str = "KDUMP1";
+
+// see debug::Trap::read_registers in the kernel
+const WIDE_REGISTERS: [&str; 17] = [
+    "rax", "rbx", "rcx", "rdx", "rsi", "rdi", "rbp", "rsp", "r8", "r9", "r10", "r11", "r12", "r13",
+    "r14", "r15", "rip",
+];
+const NARROW_REGISTERS: [&str; 3] = ["eflags", "cs", "ss"];
+
+// in the order of MemoryMapType
+const MEMORY_MAP_TYPES: [&str; 9] = [
+    "usable",
+    "reserved",
+    "acpi reclaimable",
+    "acpi nvs",
+    "bad memory",
+    "bootloader reclaimable",
+    "kernel and modules",
+    "framebuffer",
+    "unknown",
+];
+
+#[derive(Default)]
+struct Dump {
+    frames: u32,
+    corrupt: u32,
+    end: Option<u32>,
+    panic: Vec<u8>,
+    cpus: BTreeMap<u32, Vec<u8>>,
+    stacks: BTreeMap<u32, BTreeMap<u64, u8>>,
+    memmap: Vec<(u64, u64, u8)>,
+    dmesg: BTreeMap<usize, u8>,
+}
+
+fn checksum(kind: &str, data: &[u8]) -> u32 {
+    // fnv-1a
+    kind.bytes()
+        .chain(data.iter().copied())
+        .fold(0x811c9dc5, |hash, byte| {
+            (hash ^ byte as u32).wrapping_mul(0x01000193)
+        })
+}
+
+fn decode_hex(text: &str) -> Option<Vec<u8>> {
+    if !text.len().is_multiple_of(2) {
+        return None;
+    }
+
+    (0..text.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(text.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+fn u32_at(data: &[u8], offset: usize) -> Option<u32> {
+    Some(u32::from_le_bytes(
+        data.get(offset..offset + 4)?.try_into().ok()?,
+    ))
+}
+
+fn u64_at(data: &[u8], offset: usize) -> Option<u64> {
+    Some(u64::from_le_bytes(
+        data.get(offset..offset + 8)?.try_into().ok()?,
+    ))
+}
+
+// `kind data checksum`, if the checksum matches
+fn parse_fields(text: &str) -> Option<(&str, Vec<u8>)> {
+    let mut fields = text.split_whitespace();
+    let kind = fields.next()?;
+
+    // a frame without data has nothing between the kind and the checksum
+    let (data, sum) = match (fields.next()?, fields.next()) {
+        (sum, None) => ("", sum),
+        (data, Some(sum)) => (data, sum),
+    };
+
+    let data = decode_hex(data)?;
+    (u32::from_str_radix(sum, 16).ok()? == checksum(kind, &data)).then_some((kind, data))
+}
+
+// the kind and data of the frame on `line`, if there is one; Err for a frame that is there but
+// broken. the profiler's samples come in the same frames
+pub fn parse_frame(line: &str) -> Option<Result<(&str, Vec<u8>), ()>> {
+    let start = line.find(MAGIC)?;
+    Some(parse_fields(&line[start + MAGIC.len()..]).ok_or(()))
+}
+
+impl Dump {
+    // None for a frame that checked out, but doesn't hold what its kind says it should
+    fn add(&mut self, kind: &str, data: &[u8]) -> Option<()> {
+        match kind {
+            "panic" => self.panic.extend_from_slice(data),
+            "cpu" => {
+                self.cpus.insert(u32_at(data, 0)?, data[4..].to_vec());
+            }
+            "stack" => {
+                let start = u64_at(data, 4)? + u32_at(data, 12)? as u64;
+                let stack = self.stacks.entry(u32_at(data, 0)?).or_default();
+
+                for (addr, &byte) in (start..).zip(&data[16..]) {
+                    stack.insert(addr, byte);
+                }
+            }
+            "memmap" => self
+                .memmap
+                .push((u64_at(data, 0)?, u64_at(data, 8)?, *data.get(16)?)),
+            "dmesg" => {
+                let start = u32_at(data, 0)? as usize;
+
+                for (offset, &byte) in (start..).zip(&data[4..]) {
+                    self.dmesg.insert(offset, byte);
+                }
+            }
+            "end" => self.end = Some(u32_at(data, 0)?),
+            _ => return None,
+        }
+
+        Some(())
+    }
+}
+
+fn write_registers(out: &mut String, regs: &[u8]) -> Result<()> {
+    for (i, name) in WIDE_REGISTERS.iter().enumerate() {
+        let value = u64_at(regs, i * 8).ok_or(Error::msg("truncated registers"))?;
+        write!(out, "  {:>6} {:#018x}", name, value)?;
+
+        if i % 3 == 2 || i == WIDE_REGISTERS.len() - 1 {
+            writeln!(out)?;
+        }
+    }
+
+    for (i, name) in NARROW_REGISTERS.iter().enumerate() {
+        let value = u32_at(regs, WIDE_REGISTERS.len() * 8 + i * 4)
+            .ok_or(Error::msg("truncated registers"))?;
+        write!(out, "  {:>6} {:#010x}", name, value)?;
+    }
+
+    writeln!(out)?;
+    Ok(())
+}
+
+// 16 bytes to a line, with a gap wherever a stretch is missing
+fn write_stack(out: &mut String, stack: &BTreeMap<u64, u8>) -> Result<()> {
+    let mut lines: Vec<(u64, Vec<u8>)> = Vec::new();
+
+    for (&addr, &byte) in stack {
+        match lines.last_mut() {
+            Some((start, bytes)) if *start + bytes.len() as u64 == addr && bytes.len() < 16 => {
+                bytes.push(byte)
+            }
+            _ => lines.push((addr, vec![byte])),
+        }
+    }
+
+    let mut next = None;
+
+    for (start, bytes) in lines {
+        if next.is_some_and(|next| next != start) {
+            writeln!(out, "  ...")?;
+        }
+
+        write_stack_line(out, start, &bytes)?;
+        next = Some(start + bytes.len() as u64);
+    }
+
+    Ok(())
+}
+
+fn write_stack_line(out: &mut String, start: u64, bytes: &[u8]) -> Result<()> {
+    write!(out, "  {:#018x}:", start)?;
+
+    for word in bytes.chunks(8) {
+        let mut padded = [0; 8];
+        padded[..word.len()].copy_from_slice(word);
+        write!(out, " {:016x}", u64::from_le_bytes(padded))?;
+    }
+
+    writeln!(out)?;
+    Ok(())
+}
+
+pub fn decode(capture: &[u8]) -> Result<String> {
+    let capture = String::from_utf8_lossy(capture);
+    let mut dump = None;
+
+    for line in capture.lines() {
+        let Some(frame) = parse_frame(line) else {
+            continue;
+        };
+
+        if let Ok(("begin", _)) = frame {
+            dump = Some(Dump::default());
+            continue;
+        }
+
+        // frames from before the first begin have nothing to go with
+        let Some(dump) = &mut dump else {
+            continue;
+        };
+
+        dump.frames += 1;
+
+        if frame
+            .ok()
+            .and_then(|(kind, data)| dump.add(kind, &data))
+            .is_none()
+        {
+            dump.corrupt += 1;
+        }
+    }
+
+    let dump = dump.ok_or(Error::msg("no crash dump found"))?;
+    let mut out = String::new();
+
+    match dump.end {
+        // `end` counts the begin frame but not itself, and `frames` the other way around
+        Some(end) if end == dump.frames => {}
+        Some(end) => writeln!(
+            out,
+            "warning: {} of {} frames are missing",
+            end.saturating_sub(dump.frames),
+            end
+        )?,
+        None => writeln!(out, "warning: the dump is cut short")?,
+    }
+
+    if dump.corrupt != 0 {
+        writeln!(out, "warning: {} frames are corrupt", dump.corrupt)?;
+    }
+
+    writeln!(out, "== panic ==")?;
+    writeln!(out, "{}", String::from_utf8_lossy(&dump.panic).trim_end())?;
+
+    for (core, regs) in &dump.cpus {
+        writeln!(out, "\n== core {} ==", core)?;
+        write_registers(&mut out, regs)?;
+
+        if let Some(stack) = dump.stacks.get(core) {
+            writeln!(out, "stack:")?;
+            write_stack(&mut out, stack)?;
+        }
+    }
+
+    writeln!(out, "\n== memory map ==")?;
+
+    for (base, len, ty) in &dump.memmap {
+        writeln!(
+            out,
+            "  {:#018x}-{:#018x} {}",
+            base,
+            base + len,
+            MEMORY_MAP_TYPES.get(*ty as usize).unwrap_or(&"?")
+        )?;
+    }
+
+    writeln!(out, "\n== dmesg ==")?;
+    let dmesg: Vec<_> = dump.dmesg.values().copied().collect();
+    write!(out, "{}", String::from_utf8_lossy(&dmesg))?;
+
+    Ok(out)
+}
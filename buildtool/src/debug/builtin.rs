@@ -0,0 +1,63 @@
+// the function name table that goes into the kernel's .builtin_symbols section, for when the debug
+// module can't be loaded; see src/modules/builtin_symbols.rs for the format
+
+use anyhow::{Error, Result};
+use object::{Object, ObjectSection, ObjectSymbol, SymbolKind};
+use rustc_demangle::demangle;
+
+const SECTION: &str = ".builtin_symbols";
+const TABLE_MAGIC: &[u8; 4] = b"KSYM";
+
+pub fn gen_builtin_symbols(elf_contents: &[u8]) -> Result<Vec<u8>> {
+    let object = object::File::parse(elf_contents)?;
+
+    let size = object
+        .section_by_name(SECTION)
+        .ok_or(Error::msg(
+            "the kernel has no .builtin_symbols section, build it with `builtin-symbols`",
+        ))?
+        .size() as usize;
+
+    let mut functions: Vec<_> = object
+        .symbols()
+        .filter(|sym| sym.kind() == SymbolKind::Text && sym.size() != 0)
+        .filter(|sym| sym.address() >= 0xffffffff80000000)
+        .filter_map(|sym| Some((sym.address(), sym.size(), sym.name().ok()?)))
+        .collect();
+
+    functions.sort_by_key(|&(addr, _, _)| addr);
+    functions.dedup_by_key(|&mut (addr, _, _)| addr);
+
+    let mut entries = Vec::new();
+    let mut names = Vec::new();
+    let names_start = 8 + functions.len() * 12;
+
+    for (addr, size, name) in functions {
+        entries.extend_from_slice(&((addr - 0xffffffff80000000) as u32).to_le_bytes());
+        entries.extend_from_slice(&(size as u32).to_le_bytes());
+        entries.extend_from_slice(&((names_start + names.len()) as u32).to_le_bytes());
+
+        // without the hash, which is most of a mangled name
+        names.extend_from_slice(format!("{:#}", demangle(name)).as_bytes());
+        names.push(0);
+    }
+
+    let mut table = Vec::with_capacity(size);
+    table.extend_from_slice(TABLE_MAGIC);
+    table.extend_from_slice(&((entries.len() / 12) as u32).to_le_bytes());
+    table.extend_from_slice(&entries);
+    table.extend_from_slice(&names);
+
+    if table.len() > size {
+        return Err(Error::msg(format!(
+            "the function name table is {} bytes, but the kernel only has room for {}",
+            table.len(),
+            size
+        )));
+    }
+
+    // objcopy would otherwise resize the section, and move everything after it
+    table.resize(size, 0);
+
+    Ok(table)
+}
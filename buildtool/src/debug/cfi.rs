@@ -0,0 +1,93 @@
+use anyhow::Result;
+use gimli::{
+    BaseAddresses, CfaRule, CieOrFde, EhFrame, Reader, ReaderOffset, RegisterRule, UnwindContext,
+    UnwindSection, UnwindTableRow, X86_64,
+};
+
+type Range = std::ops::Range<u64>;
+
+// how to recover the caller's frame at a given address; the return address always sits right
+// below the CFA on x86_64, so only the CFA and rbp need describing
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CfaRegister {
+    Rsp,
+    Rbp,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct UnwindRule {
+    pub cfa_register: CfaRegister,
+    pub cfa_offset: i32,
+    // where rbp was saved relative to the CFA, or None if it still holds the caller's value
+    pub rbp_offset: Option<i32>,
+}
+
+pub struct UnwindRow {
+    pub range: Range,
+    pub rule: Option<UnwindRule>,
+}
+
+fn translate_rule<T: ReaderOffset>(row: &UnwindTableRow<T>) -> Option<UnwindRule> {
+    let (cfa_register, cfa_offset) = match row.cfa() {
+        CfaRule::RegisterAndOffset { register, offset } if *register == X86_64::RSP => {
+            (CfaRegister::Rsp, *offset)
+        }
+        CfaRule::RegisterAndOffset { register, offset } if *register == X86_64::RBP => {
+            (CfaRegister::Rbp, *offset)
+        }
+        _ => return None,
+    };
+
+    if !matches!(row.register(X86_64::RA), RegisterRule::Offset(-8)) {
+        return None;
+    }
+
+    let rbp_offset = match row.register(X86_64::RBP) {
+        RegisterRule::Undefined | RegisterRule::SameValue => None,
+        RegisterRule::Offset(offset) => Some(offset.try_into().ok()?),
+        _ => return None,
+    };
+
+    Some(UnwindRule {
+        cfa_register,
+        cfa_offset: cfa_offset.try_into().ok()?,
+        rbp_offset,
+    })
+}
+
+// evaluates every FDE in .eh_frame into a flat list of address ranges and their rules; rows that
+// can't be expressed as an UnwindRule are kept with no rule, so the kernel falls back to frame
+// pointers there
+pub fn parse_eh_frame<R: Reader>(
+    eh_frame: &EhFrame<R>,
+    section_address: u64,
+) -> Result<Vec<UnwindRow>> {
+    let bases = BaseAddresses::default().set_eh_frame(section_address);
+    let mut ctx = UnwindContext::new();
+    let mut rows = Vec::new();
+
+    let mut entries = eh_frame.entries(&bases);
+
+    while let Some(entry) = entries.next()? {
+        let CieOrFde::Fde(partial) = entry else {
+            continue;
+        };
+
+        let fde = partial.parse(EhFrame::cie_from_offset)?;
+
+        if fde.initial_address() < 0xffffffff80000000 {
+            continue;
+        }
+
+        let mut table = fde.rows(eh_frame, &bases, &mut ctx)?;
+
+        while let Some(row) = table.next_row()? {
+            rows.push(UnwindRow {
+                range: row.start_address()..row.end_address(),
+                rule: translate_rule(row),
+            });
+        }
+    }
+
+    Ok(rows)
+}
@@ -1,8 +1,73 @@
 use super::{
+    cfi::{CfaRegister, UnwindRow},
     dwarf::{FunctionInfo, InlinedFunctionInfo, LineInfo, SourceLocation},
     util::IntervalMap,
 };
 use crate::debug::util::InternStringTable;
+use anyhow::{Error, Result};
+
+// see src/modules/symbols.rs for the layout; buildtool symbolize reads modules with these too
+pub const MODULE_MAGIC: &[u8; 8] = b"KSYMBOLS";
+pub const VERSION_MAJOR: u16 = 1;
+pub const VERSION_MINOR: u16 = 1;
+pub const HEADER_SIZE: usize = 8 + 2 + 2 + 4;
+pub const SECTION_ENTRY_SIZE: usize = 4 + 4 + 8 + 8;
+
+pub const SECTION_STRINGS: u32 = 1;
+pub const SECTION_FUNCTIONS: u32 = 2;
+pub const SECTION_LOCATION_SEARCH: u32 = 3;
+pub const SECTION_FUNCTION_SEARCH: u32 = 4;
+pub const SECTION_UNWIND_SEARCH: u32 = 5;
+pub const SECTION_DATA_SYMBOLS: u32 = 6;
+
+// entry sizes; search table entries are these after a u32 offset
+pub const LOCATION_SIZE: usize = 8 + 4 + 4;
+pub const FUNCTION_SIZE: usize = 8 + 8 + LOCATION_SIZE;
+pub const DATA_SYMBOL_SIZE: usize = 4 + 4 + 8;
+
+fn bytes<const N: usize>(buf: &[u8], offset: usize) -> Result<[u8; N]> {
+    Ok(buf
+        .get(offset..offset + N)
+        .ok_or(Error::msg("malformed symbol module"))?
+        .try_into()?)
+}
+
+// the id and contents of each section of a module, after checking its header
+pub fn read_sections(src: &[u8]) -> Result<Vec<(u32, &[u8])>> {
+    let u16_at = |offset| bytes(src, offset).map(u16::from_le_bytes);
+    let u32_at = |offset| bytes(src, offset).map(u32::from_le_bytes);
+    let u64_at = |offset| bytes(src, offset).map(u64::from_le_bytes);
+
+    if !src.starts_with(MODULE_MAGIC) {
+        return Err(Error::msg("not a symbol module"));
+    }
+
+    let major = u16_at(8)?;
+
+    if major != VERSION_MAJOR {
+        return Err(Error::msg(format!(
+            "the symbol module is version {}.{}, but only {}.x can be read",
+            major,
+            u16_at(10)?,
+            VERSION_MAJOR
+        )));
+    }
+
+    (0..u32_at(12)? as usize)
+        .map(|i| {
+            let entry = HEADER_SIZE + i * SECTION_ENTRY_SIZE;
+            let offset = u64_at(entry + 8)? as usize;
+            let size = u64_at(entry + 16)? as usize;
+
+            let data = offset
+                .checked_add(size)
+                .and_then(|end| src.get(offset..end))
+                .ok_or(Error::msg("malformed symbol module"))?;
+
+            Ok((u32_at(entry)?, data))
+        })
+        .collect()
+}
 
 #[derive(Debug, Clone, PartialEq, Eq, Copy)]
 struct LocationEntry {
@@ -19,6 +84,21 @@ impl LocationEntry {
     };
 }
 
+#[derive(Debug, Clone, PartialEq, Eq, Copy)]
+struct UnwindEntry {
+    cfa_register: u32, // 0 if there is no rule, 1 for rsp, 2 for rbp
+    cfa_offset: i32,
+    rbp_offset: i32, // 0 if rbp wasn't saved
+}
+
+impl UnwindEntry {
+    const NULL: UnwindEntry = UnwindEntry {
+        cfa_register: 0,
+        cfa_offset: 0,
+        rbp_offset: 0,
+    };
+}
+
 struct FunctionEntry {
     inline_parent: usize, // possibly -1
     name: usize,          // index into string table, or -1
@@ -30,19 +110,18 @@ pub struct DebugModuleFileWriter {
     functions: Vec<FunctionEntry>,
     location_search: IntervalMap<u64, Vec<(u64, LocationEntry)>>,
     function_search: IntervalMap<u64, usize>,
+    unwind_search: IntervalMap<u64, UnwindEntry>,
+    // address, size and name of each global variable
+    data_symbols: Vec<(u64, u64, usize)>,
 }
 
 trait WritableEntry: Sized {
     fn write<T: Fn(usize) -> usize>(&self, str_resolve: &T, out: &mut Vec<u8>);
 
     fn write_all<T: Fn(usize) -> usize>(vec: &Vec<Self>, str_resolve: &T, out: &mut Vec<u8>) {
-        let mut buf = Vec::new();
         for func in vec {
-            func.write(str_resolve, &mut buf);
+            func.write(str_resolve, out);
         }
-
-        out.extend_from_slice(&buf.len().to_le_bytes());
-        out.extend_from_slice(&buf);
     }
 }
 
@@ -64,6 +143,14 @@ impl WritableEntry for usize {
     }
 }
 
+impl WritableEntry for UnwindEntry {
+    fn write<T: Fn(usize) -> usize>(&self, _str_resolve: &T, out: &mut Vec<u8>) {
+        out.extend_from_slice(&self.cfa_register.to_le_bytes());
+        out.extend_from_slice(&self.cfa_offset.to_le_bytes());
+        out.extend_from_slice(&self.rbp_offset.to_le_bytes());
+    }
+}
+
 impl WritableEntry for FunctionEntry {
     fn write<T: Fn(usize) -> usize>(&self, str_resolve: &T, out: &mut Vec<u8>) {
         out.extend_from_slice(&self.inline_parent.to_le_bytes());
@@ -80,6 +167,10 @@ impl SearchTableWritable for usize {
     const NULL: usize = usize::MAX;
 }
 
+impl SearchTableWritable for UnwindEntry {
+    const NULL: UnwindEntry = UnwindEntry::NULL;
+}
+
 impl DebugModuleFileWriter {
     pub fn new() -> DebugModuleFileWriter {
         DebugModuleFileWriter {
@@ -87,6 +178,8 @@ impl DebugModuleFileWriter {
             functions: Vec::new(),
             location_search: IntervalMap::new(),
             function_search: IntervalMap::new(),
+            unwind_search: IntervalMap::new(),
+            data_symbols: Vec::new(),
         }
     }
 
@@ -139,37 +232,31 @@ impl DebugModuleFileWriter {
         str_resolve: &V,
         iter: U,
     ) {
-        let mut buf = Vec::new();
-
         let mut prev_addr = 0;
 
         for (addr, inst) in Self::filter_ranges(iter) {
             assert!(prev_addr < addr, "{:#x}, {:#x}", prev_addr, addr);
             prev_addr = addr;
-            buf.extend_from_slice(
+            out.extend_from_slice(
                 &TryInto::<u32>::try_into(addr - 0xffffffff80000000)
                     .unwrap()
                     .to_le_bytes(),
             );
 
-            inst.write(str_resolve, &mut buf);
+            inst.write(str_resolve, out);
         }
-
-        out.extend_from_slice(&buf.len().to_le_bytes());
-        out.extend_from_slice(&buf);
     }
 
     pub fn write(&self) -> Vec<u8> {
-        let mut res = Vec::new();
+        let mut strings = Vec::new();
+        let str_resolve = self.strings.write(&mut strings);
 
-        res.extend_from_slice(&0u64.to_le_bytes());
-
-        let str_resolve = self.strings.write(&mut res);
-
-        WritableEntry::write_all(&self.functions, &str_resolve, &mut res);
+        let mut functions = Vec::new();
+        WritableEntry::write_all(&self.functions, &str_resolve, &mut functions);
 
+        let mut location_search = Vec::new();
         Self::write_ranges(
-            &mut res,
+            &mut location_search,
             &str_resolve,
             gen {
                 for (range, value) in self.location_search.iter() {
@@ -183,8 +270,9 @@ impl DebugModuleFileWriter {
             .into_iter(),
         );
 
+        let mut function_search = Vec::new();
         Self::write_ranges(
-            &mut res,
+            &mut function_search,
             &str_resolve,
             gen {
                 for (range, value) in self.function_search.iter() {
@@ -195,6 +283,76 @@ impl DebugModuleFileWriter {
             .into_iter(),
         );
 
+        let mut unwind_search = Vec::new();
+        Self::write_ranges(
+            &mut unwind_search,
+            &str_resolve,
+            gen {
+                for (range, value) in self.unwind_search.iter() {
+                    yield (*range.start, *value);
+                    yield (*range.end, UnwindEntry::NULL);
+                }
+            }
+            .into_iter(),
+        );
+
+        let mut data_symbols = Vec::new();
+        Self::write_data_symbols(&mut data_symbols, &str_resolve, &self.data_symbols);
+
+        Self::write_sections(&[
+            (SECTION_STRINGS, strings),
+            (SECTION_FUNCTIONS, functions),
+            (SECTION_LOCATION_SEARCH, location_search),
+            (SECTION_FUNCTION_SEARCH, function_search),
+            (SECTION_UNWIND_SEARCH, unwind_search),
+            (SECTION_DATA_SYMBOLS, data_symbols),
+        ])
+    }
+
+    // sorted by address, each as its offset and size as u32s, then its name
+    fn write_data_symbols<V: Fn(usize) -> usize>(
+        out: &mut Vec<u8>,
+        str_resolve: &V,
+        symbols: &Vec<(u64, u64, usize)>,
+    ) {
+        let mut symbols = symbols.clone();
+        symbols.sort_by_key(|&(addr, _, _)| addr);
+        symbols.dedup_by_key(|&mut (addr, _, _)| addr);
+
+        for (addr, size, name) in symbols {
+            out.extend_from_slice(
+                &TryInto::<u32>::try_into(addr - 0xffffffff80000000)
+                    .unwrap()
+                    .to_le_bytes(),
+            );
+            out.extend_from_slice(&TryInto::<u32>::try_into(size).unwrap().to_le_bytes());
+            out.extend_from_slice(&str_resolve(name).to_le_bytes());
+        }
+    }
+
+    // the header and section table, then the sections in the same order
+    fn write_sections(sections: &[(u32, Vec<u8>)]) -> Vec<u8> {
+        let mut res = Vec::new();
+
+        res.extend_from_slice(MODULE_MAGIC);
+        res.extend_from_slice(&VERSION_MAJOR.to_le_bytes());
+        res.extend_from_slice(&VERSION_MINOR.to_le_bytes());
+        res.extend_from_slice(&(sections.len() as u32).to_le_bytes());
+
+        let mut offset = HEADER_SIZE + sections.len() * SECTION_ENTRY_SIZE;
+
+        for (id, data) in sections {
+            res.extend_from_slice(&id.to_le_bytes());
+            res.extend_from_slice(&0u32.to_le_bytes());
+            res.extend_from_slice(&(offset as u64).to_le_bytes());
+            res.extend_from_slice(&(data.len() as u64).to_le_bytes());
+            offset += data.len();
+        }
+
+        for (_, data) in sections {
+            res.extend_from_slice(data);
+        }
+
         res
     }
 
@@ -363,4 +521,34 @@ impl DebugModuleFileWriter {
             }
         }
     }
+
+    pub fn write_unwind_row(&mut self, row: &UnwindRow) {
+        if row.range.is_empty() {
+            return;
+        }
+
+        let entry = match row.rule {
+            Some(rule) => UnwindEntry {
+                cfa_register: match rule.cfa_register {
+                    CfaRegister::Rsp => 1,
+                    CfaRegister::Rbp => 2,
+                },
+                cfa_offset: rule.cfa_offset,
+                rbp_offset: rule.rbp_offset.unwrap_or(0),
+            },
+            None => UnwindEntry::NULL,
+        };
+
+        if !self.unwind_search.insert(&row.range, entry) {
+            eprintln!(
+                "warning: unwind row {:#x}-{:#x} overlaps another FDE",
+                row.range.start, row.range.end
+            );
+        }
+    }
+
+    pub fn write_data_symbol(&mut self, name: &String, addr: u64, size: u64) {
+        let name = self.strings.intern(name);
+        self.data_symbols.push((addr, size, name));
+    }
 }
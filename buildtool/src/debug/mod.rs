@@ -2,15 +2,23 @@
 
 use anyhow::Result;
 use dwarf::{Context, FunctionInfo, LineInfo};
-use gimli::{DwarfSections, EndianSlice, RunTimeEndian, SectionId};
+use gimli::{DwarfSections, EhFrame, EndianSlice, RunTimeEndian, SectionId};
 use io::DebugModuleFileWriter;
-use object::{Object, ObjectSection};
+use object::{Architecture, Object, ObjectSection, ObjectSymbol, SymbolKind};
 use std::{borrow::Cow, path::PathBuf};
 
+pub use builtin::gen_builtin_symbols;
+
+mod builtin;
+mod cfi;
 mod dwarf;
-mod io;
+pub mod io;
 mod util;
 
+// the module is only rebuilt along with the kernel, so this can afford to be slow-ish; see
+// src/modules/symbols.rs for the other end
+const COMPRESSION_LEVEL: i32 = 15;
+
 pub fn gen_debug_module(
     elf_contents: Vec<u8>,
     crate_paths: &Vec<(String, PathBuf)>,
@@ -52,5 +60,43 @@ pub fn gen_debug_module(
         }
     }
 
-    Ok(writer.write())
+    // the linker script keeps .eh_frame around just so it can be turned into the unwind table,
+    // which only has x86_64's registers so far
+    if object.architecture() != Architecture::X86_64 {
+        eprintln!(
+            "warning: no unwind table for this architecture, unwinding will rely on frame pointers"
+        );
+    } else if let Some(section) = object.section_by_name(".eh_frame") {
+        let data = section.uncompressed_data()?;
+        let eh_frame = EhFrame::new(&data, endian);
+
+        for row in cfi::parse_eh_frame(&eh_frame, section.address())? {
+            writer.write_unwind_row(&row);
+        }
+    } else {
+        eprintln!("warning: no .eh_frame section, unwinding will rely on frame pointers");
+    }
+
+    // statics, so that fault addresses in them can be put to a name; kept mangled, like the
+    // function names
+    for sym in object.symbols() {
+        if sym.kind() == SymbolKind::Data
+            && sym.size() != 0
+            && sym.address() >= 0xffffffff80000000
+            && let Ok(name) = sym.name()
+        {
+            writer.write_data_symbol(&name.to_string(), sym.address(), sym.size());
+        }
+    }
+
+    let data = writer.write();
+    let compressed = zstd::bulk::compress(&data, COMPRESSION_LEVEL)?;
+
+    eprintln!(
+        "kernel_symbols.mod is {} bytes, {} before compression",
+        compressed.len(),
+        data.len()
+    );
+
+    Ok(compressed)
 }
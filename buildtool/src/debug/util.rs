@@ -105,7 +105,6 @@ impl InternStringTable {
             buf.push(0);
         }
 
-        out.extend_from_slice(&buf.len().to_le_bytes());
         out.extend_from_slice(&buf);
 
         move |index| {
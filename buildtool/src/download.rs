@@ -0,0 +1,149 @@
+// fetches limine and OVMF into the cache
+//
+// every download is pinned by its SHA-256 in buildtool.sha256, next to the workspace's Cargo.toml,
+// in the format sha256sum uses. a file without a pin is an error, cached or not, unless
+// --update-pins is given, which pins it as it is; a file that doesn't match its pin is always an
+// error, so that an upstream nightly moving on doesn't change what gets built without anyone
+// noticing. to take a new version on purpose, drop its line and run with --update-pins.
+//
+// a file with a path in [downloads.paths] in buildtool.toml is taken from there as is, and
+// --offline makes anything that isn't cached an error instead of a download.
+
+use crate::{cache_dir, config};
+use anyhow::{Error, Result};
+use reqwest::blocking;
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
+use std::env::current_dir;
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+const SUMS_FILE: &str = "buildtool.sha256";
+
+static OFFLINE: AtomicBool = AtomicBool::new(false);
+static UPDATE_PINS: AtomicBool = AtomicBool::new(false);
+
+pub fn set_offline(offline: bool) {
+    OFFLINE.store(offline, Ordering::Relaxed);
+}
+
+pub fn offline() -> bool {
+    OFFLINE.load(Ordering::Relaxed)
+}
+
+pub fn set_update_pins(update_pins: bool) {
+    UPDATE_PINS.store(update_pins, Ordering::Relaxed);
+}
+
+fn missing_pin(name: &str) -> Error {
+    Error::msg(format!(
+        "{} has no pin in {}; check where it comes from, then run with --update-pins to pin it",
+        name, SUMS_FILE
+    ))
+}
+
+fn sha256(data: &[u8]) -> String {
+    Sha256::digest(data)
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
+}
+
+// the pins, by file name
+fn read_sums() -> Result<BTreeMap<String, String>> {
+    let path = current_dir()?.join(SUMS_FILE);
+
+    if !path.exists() {
+        return Ok(BTreeMap::new());
+    }
+
+    let mut sums = BTreeMap::new();
+
+    for line in fs::read_to_string(path)?.lines() {
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        let (sum, name) = line
+            .split_once(char::is_whitespace)
+            .ok_or(Error::msg(format!("{}: bad line {:?}", SUMS_FILE, line)))?;
+
+        sums.insert(name.trim_start().into(), sum.into());
+    }
+
+    Ok(sums)
+}
+
+fn pin(name: &str, sum: &str) -> Result<()> {
+    let mut file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(current_dir()?.join(SUMS_FILE))?;
+
+    writeln!(file, "{}  {}", sum, name)?;
+    eprintln!("pinned {} to {} in {}", name, sum, SUMS_FILE);
+
+    Ok(())
+}
+
+// `url`, cached as `name` and checked against its pin
+pub fn fetch(url: &str, name: &str) -> Result<PathBuf> {
+    if let Some(path) = config::get().downloads.paths.get(name) {
+        return Ok(current_dir()?.join(path));
+    }
+
+    let path = cache_dir()?.join(name);
+    let sums = read_sums()?;
+    let pinned = sums.get(name);
+
+    if pinned.is_none() && !UPDATE_PINS.load(Ordering::Relaxed) {
+        return Err(missing_pin(name));
+    }
+
+    if path.exists() {
+        let sum = sha256(&fs::read(&path)?);
+
+        match pinned {
+            // cached before it was pinned, and --update-pins was given
+            None => {
+                pin(name, &sum)?;
+                return Ok(path);
+            }
+            Some(pinned) if *pinned == sum => return Ok(path),
+            // most likely cut short, so try again
+            Some(_) => eprintln!(
+                "the cached {} doesn't match its pin, fetching it again",
+                name
+            ),
+        }
+    }
+
+    if offline() {
+        return Err(Error::msg(format!(
+            "no usable {} in the cache, and --offline was given",
+            name
+        )));
+    }
+
+    eprintln!("downloading: {}", url);
+    let content = blocking::get(url)?.error_for_status()?.bytes()?;
+    let sum = sha256(&content);
+
+    match pinned {
+        None => pin(name, &sum)?,
+        Some(pinned) if *pinned == sum => {}
+        Some(pinned) => {
+            return Err(Error::msg(format!(
+                "{} from {} has SHA-256 {}, but is pinned to {} in {}; if it was meant to change, \
+                 drop its line",
+                name, url, sum, pinned, SUMS_FILE
+            )));
+        }
+    }
+
+    fs::write(&path, &content)?;
+
+    Ok(path)
+}
@@ -0,0 +1,141 @@
+// packs a directory into the USTAR archive the kernel reads as its initrd, see
+// src/modules/initrd.rs
+//
+// entries are sorted and their times zeroed, so that the same directory always makes the same
+// archive; symlinks to files are followed, while symlinks to directories (which could loop) and
+// anything that isn't a file or a directory are left out with a warning.
+
+use anyhow::{Error, Result};
+use std::fs;
+use std::path::Path;
+
+const BLOCK_SIZE: usize = 512;
+
+const NAME: (usize, usize) = (0, 100);
+const MODE: (usize, usize) = (100, 8);
+const UID: (usize, usize) = (108, 8);
+const GID: (usize, usize) = (116, 8);
+const SIZE: (usize, usize) = (124, 12);
+const MTIME: (usize, usize) = (136, 12);
+const CHECKSUM: (usize, usize) = (148, 8);
+const TYPE: usize = 156;
+const MAGIC: (usize, usize) = (257, 6);
+const VERSION: (usize, usize) = (263, 2);
+const PREFIX: (usize, usize) = (345, 155);
+
+const TYPE_FILE: u8 = b'0';
+const TYPE_DIR: u8 = b'5';
+
+fn set(header: &mut [u8], (start, len): (usize, usize), value: &[u8]) {
+    header[start..start + value.len().min(len)].copy_from_slice(&value[..value.len().min(len)]);
+}
+
+// zero padded, and NUL terminated
+fn set_octal(header: &mut [u8], field: (usize, usize), value: u64) -> Result<()> {
+    let digits = format!("{:0width$o}", value, width = field.1 - 1);
+
+    if digits.len() > field.1 - 1 {
+        return Err(Error::msg(format!(
+            "{} doesn't fit in a USTAR header",
+            value
+        )));
+    }
+
+    set(header, field, digits.as_bytes());
+    Ok(())
+}
+
+// a path that is too long for the name field is split at a slash, into the prefix field
+fn set_path(header: &mut [u8], path: &str) -> Result<()> {
+    if path.len() <= NAME.1 {
+        set(header, NAME, path.as_bytes());
+        return Ok(());
+    }
+
+    let (prefix, name) = path
+        .match_indices('/')
+        .map(|(i, _)| (&path[..i], &path[i + 1..]))
+        .find(|(prefix, name)| prefix.len() <= PREFIX.1 && name.len() <= NAME.1)
+        .ok_or(Error::msg(format!(
+            "{} is too long for a USTAR archive",
+            path
+        )))?;
+
+    set(header, PREFIX, prefix.as_bytes());
+    set(header, NAME, name.as_bytes());
+    Ok(())
+}
+
+fn header(path: &str, kind: u8, mode: u64, size: u64) -> Result<[u8; BLOCK_SIZE]> {
+    let mut header = [0; BLOCK_SIZE];
+
+    set_path(&mut header, path)?;
+    set_octal(&mut header, MODE, mode)?;
+    set_octal(&mut header, UID, 0)?;
+    set_octal(&mut header, GID, 0)?;
+    set_octal(&mut header, SIZE, size)?;
+    set_octal(&mut header, MTIME, 0)?;
+    header[TYPE] = kind;
+    set(&mut header, MAGIC, b"ustar\0");
+    set(&mut header, VERSION, b"00");
+
+    // the sum of the header bytes, with the checksum field taken as spaces
+    set(&mut header, CHECKSUM, b"        ");
+    let sum: u64 = header.iter().map(|&ch| ch as u64).sum();
+    set(&mut header, CHECKSUM, format!("{:06o}\0 ", sum).as_bytes());
+
+    Ok(header)
+}
+
+fn pack_dir(archive: &mut Vec<u8>, root: &Path, dir: &Path) -> Result<()> {
+    let mut entries = fs::read_dir(dir)?
+        .map(|entry| Ok(entry?.path()))
+        .collect::<Result<Vec<_>>>()?;
+    entries.sort();
+
+    for entry in entries {
+        let path = entry
+            .strip_prefix(root)?
+            .to_str()
+            .ok_or(Error::msg("bad path"))?;
+        let metadata = fs::metadata(&entry)?;
+
+        // these could lead back up the tree, and round forever
+        if metadata.is_dir() && fs::symlink_metadata(&entry)?.is_symlink() {
+            eprintln!(
+                "warning: {} is a symlink to a directory, leaving it out",
+                path
+            );
+        } else if metadata.is_dir() {
+            archive.extend_from_slice(&header(&format!("{}/", path), TYPE_DIR, 0o755, 0)?);
+            pack_dir(archive, root, &entry)?;
+        } else if metadata.is_file() {
+            let data = fs::read(&entry)?;
+
+            archive.extend_from_slice(&header(path, TYPE_FILE, 0o644, data.len() as u64)?);
+            archive.extend_from_slice(&data);
+            archive.resize(archive.len().next_multiple_of(BLOCK_SIZE), 0);
+        } else {
+            eprintln!(
+                "warning: {} isn't a file or a directory, leaving it out",
+                path
+            );
+        }
+    }
+
+    Ok(())
+}
+
+pub fn pack(dir: &Path) -> Result<Vec<u8>> {
+    if !dir.is_dir() {
+        return Err(Error::msg(format!("{} isn't a directory", dir.display())));
+    }
+
+    let mut archive = Vec::new();
+    pack_dir(&mut archive, dir, dir)?;
+
+    // the end of the archive
+    archive.resize(archive.len() + 2 * BLOCK_SIZE, 0);
+
+    Ok(archive)
+}
@@ -0,0 +1,108 @@
+// reads the results of a `ktest` kernel out of its serial log, see src/ktest/mod.rs
+//
+// the kernel writes `KTEST start <name>` and then `KTEST pass <name>` or `KTEST fail <name>` for
+// each test, `KTEST panic` for a panic outside of any, and `KTEST done <count>` once all have
+// passed; it then exits qemu with EXIT_SUCCESS, or anything else on failure.
+
+use std::fmt::{self, Display, Formatter};
+
+const MAGIC: &str = "KTEST";
+
+// what qemu exits with when the kernel writes 0x10 to isa-debug-exit
+pub const EXIT_SUCCESS: i32 = (0x10 << 1) | 1;
+
+#[derive(PartialEq, Eq)]
+enum Status {
+    // started, but there was no word of how it went
+    Running,
+    Passed,
+    Failed,
+}
+
+#[derive(Default)]
+pub struct Results {
+    tests: Vec<(String, Status)>,
+    done: Option<usize>,
+    panicked: bool,
+}
+
+impl Results {
+    fn finish(&mut self, name: &str, status: Status) {
+        if let Some(test) = self.tests.iter_mut().rev().find(|(test, _)| test == name) {
+            test.1 = status;
+        }
+    }
+
+    // whether every test ran, and passed
+    pub fn passed(&self) -> bool {
+        !self.panicked
+            && self.done == Some(self.tests.len())
+            && self
+                .tests
+                .iter()
+                .all(|(_, status)| *status == Status::Passed)
+    }
+}
+
+pub fn parse(serial: &[u8]) -> Results {
+    let mut results = Results::default();
+
+    for line in String::from_utf8_lossy(serial).lines() {
+        let Some(start) = line.find(MAGIC) else {
+            continue;
+        };
+
+        let mut fields = line[start + MAGIC.len()..].split_whitespace();
+
+        match (fields.next(), fields.next()) {
+            (Some("start"), Some(name)) => results.tests.push((name.into(), Status::Running)),
+            (Some("pass"), Some(name)) => results.finish(name, Status::Passed),
+            (Some("fail"), Some(name)) => results.finish(name, Status::Failed),
+            (Some("panic"), None) => results.panicked = true,
+            (Some("done"), Some(count)) => results.done = count.parse().ok(),
+            _ => {}
+        }
+    }
+
+    results
+}
+
+impl Display for Results {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        for (name, status) in &self.tests {
+            match status {
+                Status::Running => writeln!(f, "????? {} (never finished)", name)?,
+                Status::Passed => writeln!(f, "ok    {}", name)?,
+                Status::Failed => writeln!(f, "FAIL  {}", name)?,
+            }
+        }
+
+        let passed = self
+            .tests
+            .iter()
+            .filter(|(_, status)| *status == Status::Passed)
+            .count();
+
+        writeln!(
+            f,
+            "\n{} passed, {} failed",
+            passed,
+            self.tests.len() - passed
+        )?;
+
+        if self.panicked {
+            writeln!(f, "the kernel panicked outside of a test")?;
+        }
+
+        match self.done {
+            Some(count) if count != self.tests.len() => writeln!(
+                f,
+                "the kernel ran {} tests, but only {} were reported",
+                count,
+                self.tests.len()
+            ),
+            Some(_) => Ok(()),
+            None => writeln!(f, "the run ended before every test had run"),
+        }
+    }
+}
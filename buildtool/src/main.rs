@@ -4,56 +4,351 @@
 
 use anyhow::{Error, Result};
 use cargo_metadata::{Message, MetadataCommand};
-use clap::{Parser, Subcommand};
-use debug::gen_debug_module;
+use clap::{Args, Parser, Subcommand, ValueEnum};
+use debug::{gen_builtin_symbols, gen_debug_module};
+use ed25519_compact::{KeyPair, Seed};
 use fatfs::{FatType, FileSystem, FormatVolumeOptions, FsOptions, format_volume};
 use fscommon::StreamSlice;
 use gptman::{GPT, GPTPartitionEntry};
-use reqwest::blocking;
 use std::env::{current_dir, current_exe};
-use std::fs::{self, File};
+use std::fs::{self, File, OpenOptions};
 use std::io::{self, BufReader, Write};
+use std::net::TcpStream;
 use std::os::unix::process::CommandExt;
 use std::path::PathBuf;
-use std::process::{Command, Stdio};
+use std::process::{self, Child, Command, Stdio};
+use std::str::FromStr;
+use std::thread;
+use std::time::{Duration, Instant, SystemTime};
 use tempfile::NamedTempFile;
 use uuid::Uuid;
 
+mod config;
+mod crashdump;
 mod debug;
-
-const LIMINE_URL: &str =
-    "https://github.com/limine-bootloader/limine/raw/refs/heads/v10.x-binary/BOOTX64.EFI";
-const OVMF_URL: &str = "https://github.com/osdev0/edk2-ovmf-nightly/releases/download/nightly-20251126T024608Z/ovmf-code-x86_64.fd";
+mod download;
+mod initrd;
+mod ktest;
+mod profile;
+mod size;
+mod symbolize;
+
+// a tag rather than the v10.x-binary branch, which moves with every release
+const LIMINE_URL: &str = "https://github.com/limine-bootloader/limine/raw/refs/tags/v10.3.0-binary";
+const OVMF_URL: &str =
+    "https://github.com/osdev0/edk2-ovmf-nightly/releases/download/nightly-20251126T024608Z";
 const LIMINE_CONF: &str = "limine.conf";
+const LIMINE_TEMPLATE: &str = "limine.conf.template";
+// where the initrd goes on the boot volume
+const INITRD_FILE: &str = "initrd.tar";
+// what `watch` looks at, from the workspace root; buildtool.toml isn't among them, since it is only
+// read at startup
+const WATCHED: [&str; 7] = [
+    "src",
+    "resources",
+    "proc-macros",
+    "flanterm",
+    "build.rs",
+    "Cargo.toml",
+    "rust-toolchain.toml",
+];
+const WATCH_INTERVAL: Duration = Duration::from_millis(500);
+const WATCH_SETTLE: Duration = Duration::from_millis(300);
+// what a --disk that doesn't exist yet is created with
+const BLANK_DISK_SIZE: u64 = 64 * 1024 * 1024;
+// where qemu's gdb stub listens, with -s
+const GDB_PORT: u16 = 1234;
+const GDB_STUB_TIMEOUT: Duration = Duration::from_secs(30);
+const GDB_STUB_POLL: Duration = Duration::from_millis(100);
+// see src/modules/signature.rs
+const SIGNATURE_MAGIC: &[u8] = b"~module signature~\n";
 
 #[derive(Parser)]
 #[command(version, about, long_about = None)]
 struct Cli {
     #[command(subcommand)]
     command: Commands,
+    // only use what is already in the cache, see download.rs
+    #[arg(long, global = true)]
+    offline: bool,
+    // pin downloads that have no pin yet instead of failing, see download.rs
+    #[arg(long, global = true)]
+    update_pins: bool,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum Arch {
+    #[value(name = "x86_64")]
+    X86_64,
+    Aarch64,
+}
+
+impl Arch {
+    fn name(self) -> &'static str {
+        match self {
+            Arch::X86_64 => "x86_64",
+            Arch::Aarch64 => "aarch64",
+        }
+    }
+
+    fn target(self) -> String {
+        format!("{}-unknown-none", self.name())
+    }
+
+    // the name gdb's `set architecture` takes, so that it doesn't assume the host's
+    fn gdb_name(self) -> &'static str {
+        match self {
+            Arch::X86_64 => "i386:x86-64",
+            Arch::Aarch64 => "aarch64",
+        }
+    }
+
+    // limine's UEFI executable, and where the firmware looks for it on the boot volume
+    fn limine_efi(self) -> (&'static str, &'static str) {
+        match self {
+            Arch::X86_64 => ("BOOTX64.EFI", "efi/boot/bootx64.efi"),
+            Arch::Aarch64 => ("BOOTAA64.EFI", "efi/boot/bootaa64.efi"),
+        }
+    }
+}
+
+// --disk path[:format], raw unless given
+#[derive(Clone)]
+struct Disk {
+    path: PathBuf,
+    format: String,
+}
+
+impl FromStr for Disk {
+    type Err = String;
+
+    fn from_str(arg: &str) -> Result<Disk, String> {
+        let (path, format) = match arg.rsplit_once(':') {
+            Some((path, format)) if !path.is_empty() && !format.contains('/') => (path, format),
+            _ => (arg, "raw"),
+        };
+
+        if format.is_empty() {
+            return Err("empty disk format".into());
+        }
+
+        Ok(Disk {
+            path: path.into(),
+            format: format.into(),
+        })
+    }
+}
+
+// what the --disk drives are plugged into
+#[derive(Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum DiskBus {
+    Virtio,
+    Nvme,
+    // nothing, for a `-device ...,drive=diskN` in the args in buildtool.toml
+    None,
+}
+
+// --forward [tcp:|udp:]host[:guest], a host port forwarded to the guest, to the same port unless
+// given
+#[derive(Clone)]
+struct Forward {
+    protocol: &'static str,
+    host: u16,
+    guest: u16,
+}
+
+impl FromStr for Forward {
+    type Err = String;
+
+    fn from_str(arg: &str) -> Result<Forward, String> {
+        let (protocol, ports) = match arg.split_once(':') {
+            Some(("tcp", ports)) => ("tcp", ports),
+            Some(("udp", ports)) => ("udp", ports),
+            _ => ("tcp", arg),
+        };
+
+        let port = |port: &str| {
+            port.parse::<u16>()
+                .map_err(|_| format!("bad port {} in {}", port, arg))
+        };
+
+        let (host, guest) = match ports.split_once(':') {
+            Some((host, guest)) => (port(host)?, port(guest)?),
+            None => (port(ports)?, port(ports)?),
+        };
+
+        Ok(Forward {
+            protocol,
+            host,
+            guest,
+        })
+    }
+}
+
+// --add-symbols path[:offset], an ELF for the debugger to take symbols from, moved by the hex
+// offset if it isn't loaded where it was linked
+#[derive(Clone)]
+struct SymbolFile {
+    path: PathBuf,
+    offset: Option<u64>,
+}
+
+impl FromStr for SymbolFile {
+    type Err = String;
+
+    fn from_str(arg: &str) -> Result<SymbolFile, String> {
+        let Some((path, offset)) = arg.rsplit_once(':').filter(|(path, _)| !path.is_empty()) else {
+            return Ok(SymbolFile {
+                path: arg.into(),
+                offset: None,
+            });
+        };
+
+        let hex = offset.strip_prefix("0x").unwrap_or(offset);
+
+        Ok(SymbolFile {
+            path: path.into(),
+            offset: Some(
+                u64::from_str_radix(hex, 16)
+                    .map_err(|_| format!("bad offset {} in {}", offset, arg))?,
+            ),
+        })
+    }
+}
+
+#[derive(Args)]
+struct QemuArgs {
+    #[arg(long, value_enum, default_value_t = Arch::X86_64)]
+    arch: Arch,
+    #[arg(long, default_value = config::DEFAULT_PROFILE)]
+    profile: String,
+    // a directory, packed into the initrd module
+    #[arg(long)]
+    initrd: Option<PathBuf>,
+    #[arg(long)]
+    kvm: bool,
+    // these default to the ones in buildtool.toml
+    #[arg(short = 'j', long)]
+    cores: Option<u8>,
+    #[arg(short, long)]
+    mem: Option<u8>,
+    #[arg(long)]
+    release: bool,
+    #[arg(long)]
+    builtin_symbols: bool,
+    // extra disks, as drives disk0, disk1, ...; those that don't exist are created blank
+    #[arg(long = "disk")]
+    disks: Vec<Disk>,
+    #[arg(long, value_enum, default_value_t = DiskBus::Virtio)]
+    disk_bus: DiskBus,
+    // a virtio-net NIC on qemu's user mode network, where the host is 10.0.2.2; implied by
+    // --forward
+    #[arg(long)]
+    net: bool,
+    #[arg(long = "forward")]
+    forwards: Vec<Forward>,
 }
 
 #[derive(Subcommand)]
 enum Commands {
     Image {
+        #[arg(long, value_enum, default_value_t = Arch::X86_64)]
+        arch: Arch,
+        // the cmdline profile to boot, see config.rs
+        #[arg(long, default_value = config::DEFAULT_PROFILE)]
+        profile: String,
+        // a directory, packed into the initrd module
+        #[arg(long)]
+        initrd: Option<PathBuf>,
+        #[arg(long)]
+        release: bool,
+        // embed a table of function names in the kernel, for backtraces without the debug module
+        #[arg(long)]
+        builtin_symbols: bool,
+    },
+    Qemu(QemuArgs),
+    // boots like qemu, and then rebuilds and reboots whenever the kernel's sources change; -S is
+    // left out of the qemu args
+    Watch(QemuArgs),
+    // boots a kernel built with the `ktest` feature headless, and exits with 0 only if every
+    // test passed; x86_64 only, since it relies on isa-debug-exit
+    Test {
+        #[arg(short = 'j', long)]
+        cores: Option<u8>,
+        #[arg(short, long)]
+        mem: Option<u8>,
         #[arg(long)]
         release: bool,
+        // in seconds, after which the run counts as failed
+        #[arg(long, default_value_t = 120)]
+        timeout: u64,
     },
-    Qemu {
+    // a hybrid BIOS/UEFI ISO, for machines and VMs that would rather boot from a CD
+    Iso {
+        #[arg(long, value_enum, default_value_t = Arch::X86_64)]
+        arch: Arch,
+        #[arg(long, default_value = config::DEFAULT_PROFILE)]
+        profile: String,
         #[arg(long)]
-        kvm: bool,
-        #[arg(short = 'j', long, default_value_t = 1)]
-        cores: u8,
-        #[arg(short, long, default_value_t = 4)]
-        mem: u8,
+        initrd: Option<PathBuf>,
         #[arg(long)]
         release: bool,
+        #[arg(long)]
+        builtin_symbols: bool,
     },
+    // attaches a debugger to qemu's gdb stub, stopping at kmain; with --launch, qemu is started in
+    // the background for it and stopped along with the debugger, and otherwise it is waited for
     Gdb {
+        #[command(flatten)]
+        qemu: QemuArgs,
         #[arg(long)]
-        kvm: bool,
+        launch: bool,
+        // rust-lldb instead of rust-gdb
+        #[arg(long)]
+        lldb: bool,
+        // symbols for code loaded at runtime, which the kernel ELF doesn't cover
+        #[arg(long = "add-symbols")]
+        symbols: Vec<SymbolFile>,
+    },
+    // boots with the kernel's sampling profiler on for --duration seconds, then writes what it
+    // sampled to run/, as collapsed stacks (profile.folded) and a flamegraph (profile.svg); the
+    // cmdline profile is `sampling`, unless another one with `profile:{enable}` is picked
+    Profile {
+        #[command(flatten)]
+        qemu: QemuArgs,
+        #[arg(long, default_value_t = 10)]
+        duration: u64,
+    },
+    // the kernel's size by section and by crate; with --diff, next to the last report's
+    Size {
+        #[arg(long, value_enum, default_value_t = Arch::X86_64)]
+        arch: Arch,
+        #[arg(long)]
+        release: bool,
+        #[arg(long)]
+        diff: bool,
+    },
+    // appends a signature to a module; `key` is a 32 byte ed25519 seed, and the public key to
+    // build the kernel with (MODULE_SIGNING_KEY) is written next to it, as <key>.pub
+    Sign {
+        #[arg(long)]
+        key: PathBuf,
+        module: PathBuf,
+    },
+    // decodes a crash dump (`crashdump:{enable}`) out of a serial capture, by default the one from
+    // the last `qemu` run
+    Decode {
+        capture: Option<PathBuf>,
+    },
+    // resolves the kernel addresses in text on stdin, such as a panic log from a serial capture,
+    // with the debug module the last build left in the cache, or the module or kernel ELF given
+    Symbolize {
+        #[arg(long, value_enum, default_value_t = Arch::X86_64)]
+        arch: Arch,
         #[arg(long)]
         release: bool,
+        #[arg(long)]
+        symbols: Option<PathBuf>,
     },
     Clean,
 }
@@ -75,40 +370,76 @@ fn run_dir() -> Result<PathBuf> {
     Ok(root)
 }
 
-fn download_limine() -> Result<PathBuf> {
-    let root = cache_dir()?;
-    let limine_path = root.join("limine.efi");
+// a file from limine's binary release, cached as `name`
+fn download_limine_file(file: &str, name: &str) -> Result<PathBuf> {
+    let base = config::get().downloads.limine_url.as_deref();
+    download::fetch(&format!("{}/{}", base.unwrap_or(LIMINE_URL), file), name)
+}
 
-    if !limine_path.exists() {
-        let response = blocking::get(LIMINE_URL)?;
-        let mut dest = File::create(&limine_path)?;
-        let content = response.bytes()?;
-        io::copy(&mut content.as_ref(), &mut dest)?;
+fn download_limine(arch: Arch) -> Result<PathBuf> {
+    let (file, _) = arch.limine_efi();
+    download_limine_file(file, &format!("limine-{}.efi", arch.name()))
+}
+
+// the host tool, which is shipped as source; only needed to make ISOs bootable from BIOS
+fn build_limine_tool() -> Result<PathBuf> {
+    let source = download_limine_file("limine.c", "limine.c")?;
+    let header = download_limine_file("limine-bios-hdd.h", "limine-bios-hdd.h")?;
+    let header_dir = header.parent().ok_or(Error::msg("bad path"))?.to_path_buf();
+
+    let tool = cache_dir()?.join("limine");
+
+    if is_stale(&tool, &[&source, &header])? {
+        run(
+            "cc",
+            vec![
+                "-O2".into(),
+                "-std=c99".into(),
+                format!("-I{}", path_to_string(&header_dir)?),
+                path_to_string(&source)?,
+                "-o".into(),
+                "limine".into(),
+            ],
+            &cache_dir()?,
+        )?;
     }
 
-    Ok(limine_path)
+    Ok(tool)
 }
 
-fn download_ovmf() -> Result<PathBuf> {
-    let root = cache_dir()?;
-    let ovmf_path = root.join("ovmf.fd");
+// OVMF, or its aarch64 counterpart AAVMF
+fn download_ovmf(arch: Arch) -> Result<PathBuf> {
+    let base = config::get().downloads.ovmf_url.as_deref();
+    download::fetch(
+        &format!("{}/ovmf-code-{}.fd", base.unwrap_or(OVMF_URL), arch.name()),
+        &format!("ovmf-{}.fd", arch.name()),
+    )
+}
 
-    if !ovmf_path.exists() {
-        let response = blocking::get(OVMF_URL)?;
-        let mut dest = File::create(&ovmf_path)?;
-        let content = response.bytes()?;
-        io::copy(&mut content.as_ref(), &mut dest)?;
+fn build_kernel(
+    arch: Arch,
+    release: bool,
+    builtin_symbols: bool,
+    ktest: bool,
+) -> Result<(PathBuf, Vec<(String, PathBuf)>)> {
+    // build.rs links with resources/linker-<arch>.lds; without one there is no port of the kernel,
+    // and the link would fail with an error that doesn't say so
+    if !resources_dir()?
+        .join(format!("linker-{}.lds", arch.name()))
+        .exists()
+    {
+        return Err(Error::msg(format!(
+            "{} kernel port not present",
+            arch.name()
+        )));
     }
 
-    Ok(ovmf_path)
-}
-
-fn build_kernel(release: bool) -> Result<(PathBuf, Vec<(String, PathBuf)>)> {
+    let target = arch.target();
     let mut args = vec![
         "build",
         "--message-format=json-render-diagnostics",
         "--target",
-        "x86_64-unknown-none",
+        &target,
         "-Zbuild-std=core,alloc",
     ];
 
@@ -116,7 +447,23 @@ fn build_kernel(release: bool) -> Result<(PathBuf, Vec<(String, PathBuf)>)> {
         args.push("--release");
     }
 
-    let mut crate_paths: Vec<(String, PathBuf)> = MetadataCommand::new()
+    if builtin_symbols {
+        args.push("--features=builtin-symbols");
+    }
+
+    if ktest {
+        args.push("--features=ktest");
+    }
+
+    let mut metadata = MetadataCommand::new();
+
+    // so that cargo doesn't go to the network either
+    if download::offline() {
+        args.push("--offline");
+        metadata.other_options(vec!["--offline".into()]);
+    }
+
+    let mut crate_paths: Vec<(String, PathBuf)> = metadata
         .exec()?
         .packages
         .iter()
@@ -153,12 +500,18 @@ fn build_kernel(release: bool) -> Result<(PathBuf, Vec<(String, PathBuf)>)> {
         sys_root.join("lib/rustlib/src/rust/library/compiler-builtins/compiler-builtins"),
     ));
 
+    let mut rustflags = vec!["-C relocation-model=static", "-C force-unwind-tables=yes"];
+
+    if config::get().build.frame_pointers {
+        rustflags.push("-C force-frame-pointers=yes");
+    }
+
+    rustflags.extend(config::get().build.rustflags.iter().map(String::as_str));
+    let rustflags = rustflags.join(" ");
+
     let mut cmd = Command::new("cargo")
         .args(args)
-        .env(
-            "RUSTFLAGS",
-            "-C relocation-model=static -C force-frame-pointers=yes",
-        )
+        .env("RUSTFLAGS", rustflags)
         .stdout(Stdio::piped())
         .spawn()?;
 
@@ -182,10 +535,39 @@ fn build_kernel(release: bool) -> Result<(PathBuf, Vec<(String, PathBuf)>)> {
     cmd.wait()?;
 
     let executable = res.ok_or(Error::msg("failed to locate executable"))?;
+
+    if builtin_symbols {
+        embed_builtin_symbols(&executable)?;
+    }
+
     eprintln!("kernel binary path: {}", path_to_string(&executable)?);
     Ok((executable, crate_paths))
 }
 
+// fills in the table the kernel set aside room for; the section keeps its size, so nothing else in
+// the binary moves
+fn embed_builtin_symbols(elf: &PathBuf) -> Result<()> {
+    let table = NamedTempFile::new_in(cache_dir()?)?;
+    fs::write(table.path(), gen_builtin_symbols(&fs::read(elf)?)?)?;
+
+    let status = Command::new("objcopy")
+        .args([
+            "--update-section".into(),
+            format!(
+                ".builtin_symbols={}",
+                path_to_string(&table.path().to_path_buf())?
+            ),
+            path_to_string(elf)?,
+        ])
+        .status()?;
+
+    if !status.success() {
+        return Err(Error::msg("failed to embed the function name table"));
+    }
+
+    Ok(())
+}
+
 fn path_to_string(path: &PathBuf) -> Result<String> {
     Ok(path
         .canonicalize()?
@@ -210,27 +592,171 @@ fn split_debug_info(elf: &PathBuf) -> Result<Vec<u8>> {
     Ok(fs::read(tmp_stripped)?)
 }
 
-fn build_image(build_res: &(PathBuf, Vec<(String, PathBuf)>), release: bool) -> Result<PathBuf> {
+// whether `output` is missing or older than any of `inputs` (or anything in them, for
+// directories), the buildtool itself or its config
+fn is_stale(output: &PathBuf, inputs: &[&PathBuf]) -> Result<bool> {
+    if !fs::exists(output)? {
+        return Ok(true);
+    }
+
+    let built = fs::metadata(output)?.modified()?;
+    let config = config::path()?;
+    let config = config.exists().then_some(&config);
+
+    for input in inputs
+        .iter()
+        .copied()
+        .chain([&current_exe()?])
+        .chain(config)
+    {
+        if newest_change(input)?.is_some_and(|changed| changed > built) {
+            return Ok(true);
+        }
+    }
+
+    Ok(false)
+}
+
+// limine.conf from the template, with an entry for each cmdline profile, `profile` first
+fn gen_limine_conf(arch: Arch, profile: &str, initrd: bool) -> Result<String> {
+    let template = fs::read_to_string(resources_dir()?.join(LIMINE_TEMPLATE))?;
+    let mut profiles = config::get().profiles();
+
+    let first = profiles.remove(profile).ok_or_else(|| {
+        Error::msg(format!(
+            "no cmdline profile named {}, there are: {}",
+            profile,
+            profiles.keys().copied().collect::<Vec<_>>().join(", ")
+        ))
+    })?;
+
+    // the first line that starts an entry
+    let start = if template.starts_with('/') {
+        0
+    } else {
+        template
+            .find("\n/")
+            .ok_or(Error::msg("limine.conf.template has no entry"))?
+            + 1
+    };
+
+    let (header, entry) = template.split_at(start);
+    let mut out = header.to_string();
+
+    for (name, cmdline) in [(profile, first)].into_iter().chain(profiles) {
+        out += entry
+            .replace("${arch}", arch.name())
+            .replace("${profile}", name)
+            .replace("${cmdline}", cmdline)
+            .trim_end();
+
+        if initrd {
+            out += &format!(
+                "\n\n    module_path: boot():/{}\n    module_cmdline: initrd",
+                INITRD_FILE
+            );
+        }
+
+        out += "\n\n";
+    }
+
+    Ok(out)
+}
+
+// where an image of the kernel goes in the cache, e.g. kernel-x86_64-debug-trace.img
+fn output_path(
+    arch: Arch,
+    release: bool,
+    profile: &str,
+    initrd: bool,
+    extension: &str,
+) -> Result<PathBuf> {
+    Ok(cache_dir()?.join(format!(
+        "kernel-{}-{}{}{}.{}",
+        arch.name(),
+        if release { "release" } else { "debug" },
+        if profile == config::DEFAULT_PROFILE {
+            String::new()
+        } else {
+            format!("-{}", profile)
+        },
+        if initrd { "-initrd" } else { "" },
+        extension
+    )))
+}
+
+// where boot_files keeps the debug module of the last build
+fn debug_module_path(arch: Arch, release: bool) -> Result<PathBuf> {
+    Ok(cache_dir()?.join(format!(
+        "kernel-debug_info-{}-{}.mod",
+        arch.name(),
+        if release { "release" } else { "debug" }
+    )))
+}
+
+// the files limine boots from, by path on the boot volume, save for limine itself; the debug
+// module is also kept in the cache, for the tools that symbolize on the host
+fn boot_files(
+    arch: Arch,
+    build_res: &(PathBuf, Vec<(String, PathBuf)>),
+    release: bool,
+    profile: &str,
+    initrd: Option<&PathBuf>,
+) -> Result<Vec<(&'static str, Vec<u8>)>> {
     let (kernel_elf, package_data) = build_res;
 
+    let debug_mod = debug_module_path(arch, release)?;
+
+    let elf_data = split_debug_info(kernel_elf)?;
+    let debug_data = gen_debug_module(fs::read(kernel_elf)?, package_data)?;
+
+    fs::write(debug_mod, &debug_data)?;
+
+    eprintln!("kernel.elf is {} bytes", elf_data.len());
+
+    let mut files = vec![
+        (
+            LIMINE_CONF,
+            gen_limine_conf(arch, profile, initrd.is_some())?.into_bytes(),
+        ),
+        ("kernel_symbols.mod", debug_data),
+        ("kernel.elf", elf_data),
+    ];
+
+    if let Some(dir) = initrd {
+        files.push((INITRD_FILE, initrd::pack(dir)?));
+    }
+
+    Ok(files)
+}
+
+// enough for `contents` bytes of files on a FAT32 ESP, with room for the FAT, the GPT and cluster
+// slack; never below the 64 MiB it always was, which leaves FAT32 enough clusters
+fn image_size(contents: u64) -> u64 {
+    const MIB: u64 = 1024 * 1024;
+    (contents + contents / 4 + 16 * MIB)
+        .next_multiple_of(MIB)
+        .max(64 * MIB)
+}
+
+fn build_image(
+    arch: Arch,
+    build_res: &(PathBuf, Vec<(String, PathBuf)>),
+    release: bool,
+    profile: &str,
+    initrd: Option<&PathBuf>,
+) -> Result<PathBuf> {
+    let (kernel_elf, _) = build_res;
+
     let cache_dir = cache_dir()?;
-    let limine_efi = download_limine()?;
-    let limine_cfg = resources_dir()?.join(LIMINE_CONF);
-    let output_img = cache_dir.join(format!(
-        "kernel-{}.img",
-        if release { "release" } else { "debug" }
-    ));
-    let debug_mod = cache_dir.join(format!(
-        "kernel-debug_info-{}.mod",
-        if release { "release" } else { "debug" }
-    ));
+    let limine_efi = download_limine(arch)?;
+    let limine_cfg = resources_dir()?.join(LIMINE_TEMPLATE);
+    let output_img = output_path(arch, release, profile, initrd.is_some(), "img")?;
 
-    if !fs::exists(&output_img)?
-        || fs::metadata(&kernel_elf)?.modified()? > fs::metadata(&output_img)?.modified()?
-        || fs::metadata(&limine_efi)?.modified()? > fs::metadata(&output_img)?.modified()?
-        || fs::metadata(&limine_cfg)?.modified()? > fs::metadata(&output_img)?.modified()?
-        || fs::metadata(&current_exe()?)?.modified()? > fs::metadata(&output_img)?.modified()?
-    {
+    let mut inputs = vec![kernel_elf, &limine_efi, &limine_cfg];
+    inputs.extend(initrd);
+
+    if is_stale(&output_img, &inputs)? {
         eprintln!(
             "rebuilding image: {}",
             output_img
@@ -238,10 +764,14 @@ fn build_image(build_res: &(PathBuf, Vec<(String, PathBuf)>), release: bool) ->
                 .ok_or(Error::msg("could not convert image file"))?
         );
 
+        let files = boot_files(arch, build_res, release, profile, initrd)?;
+        let contents = fs::metadata(&limine_efi)?.len()
+            + files.iter().map(|(_, data)| data.len() as u64).sum::<u64>();
+
         let temp_img_out = NamedTempFile::new_in(cache_dir)?;
         let mut output_file = temp_img_out.as_file();
 
-        output_file.set_len(64 * 1024 * 1024)?;
+        output_file.set_len(image_size(contents))?;
 
         let disk_guid = *Uuid::new_v4().as_bytes();
         let sector_size = 512;
@@ -280,27 +810,12 @@ fn build_image(build_res: &(PathBuf, Vec<(String, PathBuf)>), release: bool) ->
 
         io::copy(
             &mut File::open(limine_efi)?,
-            &mut fs.root_dir().create_file("efi/boot/bootx64.efi")?,
-        )?;
-        io::copy(
-            &mut File::open(limine_cfg)?,
-            &mut fs.root_dir().create_file(LIMINE_CONF)?,
+            &mut fs.root_dir().create_file(arch.limine_efi().1)?,
         )?;
 
-        let elf_data = split_debug_info(kernel_elf)?;
-        let debug_data = gen_debug_module(fs::read(kernel_elf)?, package_data)?;
-
-        fs.root_dir()
-            .create_file("kernel_symbols.mod")?
-            .write_all(&debug_data)?;
-
-        fs::write(debug_mod, &debug_data)?;
-
-        eprintln!("kernel.elf is {} bytes", elf_data.len());
-
-        fs.root_dir()
-            .create_file("kernel.elf")?
-            .write_all(&elf_data)?;
+        for (path, data) in files {
+            fs.root_dir().create_file(path)?.write_all(&data)?;
+        }
 
         fs.unmount()?;
 
@@ -312,6 +827,127 @@ fn build_image(build_res: &(PathBuf, Vec<(String, PathBuf)>), release: bool) ->
     Ok(output_img)
 }
 
+// a hybrid ISO: El Torito entries for both BIOS and UEFI, plus an MBR so that it also boots when
+// written to a USB stick. needs xorriso, and on x86_64 a C compiler for limine's host tool; there
+// is no BIOS on aarch64, so those ISOs only boot from UEFI
+fn build_iso(
+    arch: Arch,
+    build_res: &(PathBuf, Vec<(String, PathBuf)>),
+    release: bool,
+    profile: &str,
+    initrd: Option<&PathBuf>,
+) -> Result<PathBuf> {
+    let (kernel_elf, _) = build_res;
+    let bios = arch == Arch::X86_64;
+
+    let cache_dir = cache_dir()?;
+    let limine_cfg = resources_dir()?.join(LIMINE_TEMPLATE);
+    let mut limine_files = vec![
+        (arch.limine_efi().1, download_limine(arch)?),
+        (
+            "limine-uefi-cd.bin",
+            download_limine_file("limine-uefi-cd.bin", "limine-uefi-cd.bin")?,
+        ),
+    ];
+
+    if bios {
+        for file in ["limine-bios.sys", "limine-bios-cd.bin"] {
+            limine_files.push((file, download_limine_file(file, file)?));
+        }
+    }
+
+    let output_iso = output_path(arch, release, profile, initrd.is_some(), "iso")?;
+
+    let mut inputs = vec![kernel_elf, &limine_cfg];
+    inputs.extend(limine_files.iter().map(|(_, path)| path));
+    inputs.extend(initrd);
+
+    if is_stale(&output_iso, &inputs)? {
+        eprintln!(
+            "rebuilding iso: {}",
+            output_iso
+                .to_str()
+                .ok_or(Error::msg("could not convert iso file"))?
+        );
+
+        let root = tempfile::tempdir_in(&cache_dir)?;
+        fs::create_dir_all(root.path().join("efi/boot"))?;
+
+        for (path, source) in &limine_files {
+            fs::copy(source, root.path().join(path))?;
+        }
+
+        for (path, data) in boot_files(arch, build_res, release, profile, initrd)? {
+            fs::write(root.path().join(path), data)?;
+        }
+
+        let temp_iso_out = NamedTempFile::new_in(&cache_dir)?;
+        let temp_iso_path = path_to_string(&temp_iso_out.path().to_path_buf())?;
+
+        let mut args = vec!["-as", "mkisofs", "-R", "-r", "-J"];
+
+        if bios {
+            args.extend([
+                "-b",
+                "limine-bios-cd.bin",
+                "-no-emul-boot",
+                "-boot-load-size",
+                "4",
+                "-boot-info-table",
+            ]);
+        }
+
+        args.extend([
+            "-hfsplus",
+            "-apm-block-size",
+            "2048",
+            "--efi-boot",
+            "limine-uefi-cd.bin",
+            "-efi-boot-part",
+            "--efi-boot-image",
+            "--protective-msdos-label",
+            ".",
+            "-o",
+            temp_iso_path.as_str(),
+        ]);
+
+        run("xorriso", args, &root.path().to_path_buf())?;
+
+        // puts limine's BIOS stage 1 in the MBR
+        if bios {
+            run(
+                &path_to_string(&build_limine_tool()?)?,
+                vec!["bios-install", temp_iso_path.as_str()],
+                &cache_dir,
+            )?;
+        }
+
+        fs::rename(temp_iso_out.path(), &output_iso)?;
+    }
+
+    Ok(output_iso)
+}
+
+// runs `command` to completion in `dir`, failing if it does
+fn run<T: std::fmt::Debug + AsRef<std::ffi::OsStr>>(
+    command: &str,
+    args: Vec<T>,
+    dir: &PathBuf,
+) -> Result<()> {
+    eprintln!("running: {} {:?}", command, args);
+    let status = Command::new(command)
+        .args(args)
+        .current_dir(dir)
+        .stdout(Stdio::null())
+        .status()?;
+
+    if !status.success() {
+        return Err(Error::msg(format!("{} failed: {}", command, status)));
+    }
+
+    Ok(())
+}
+
 fn exec<T: std::fmt::Debug + AsRef<std::ffi::OsStr>>(command: &str, args: Vec<T>) -> Result<()> {
     eprintln!("running: {} {:?}", command, args);
     let err = Command::new(command)
@@ -321,80 +957,695 @@ fn exec<T: std::fmt::Debug + AsRef<std::ffi::OsStr>>(command: &str, args: Vec<T>
     Err(err.into())
 }
 
-fn qemu(kvm: bool, cores: u8, mem_g: u8, release: bool) -> Result<()> {
-    let path = build_image(&build_kernel(release)?, release)?;
+// builds the image, and returns the qemu command line to boot it with
+fn qemu_args(opts: &QemuArgs) -> Result<Vec<String>> {
+    let config = &config::get().qemu;
+    let arch = opts.arch;
+    let path = build_image(
+        arch,
+        &build_kernel(arch, opts.release, opts.builtin_symbols, false)?,
+        opts.release,
+        &opts.profile,
+        opts.initrd.as_ref(),
+    )?;
+    let firmware = path_to_string(&download_ovmf(arch)?)?;
+    let image = path_to_string(&path)?;
+
+    // the machine, firmware, boot disk and display
+    let mut args: Vec<String> = match arch {
+        Arch::X86_64 => vec![
+            "-bios".into(),
+            firmware,
+            "-hda".into(),
+            image,
+            "-M".into(),
+            "smm=off".into(),
+            "-vga".into(),
+            "std".into(),
+        ],
+        // virt has no legacy devices to speak of: the disk goes on virtio, and the display and
+        // keyboard are ramfb and usb
+        Arch::Aarch64 => vec![
+            "-M".into(),
+            "virt".into(),
+            "-cpu".into(),
+            if opts.kvm { "host" } else { "max" }.into(),
+            "-drive".into(),
+            format!("if=pflash,format=raw,readonly=on,file={}", firmware),
+            "-drive".into(),
+            format!("if=none,id=boot,format=raw,file={}", image),
+            "-device".into(),
+            "virtio-blk-pci,drive=boot".into(),
+            "-device".into(),
+            "ramfb".into(),
+            "-device".into(),
+            "qemu-xhci".into(),
+            "-device".into(),
+            "usb-kbd".into(),
+        ],
+    };
+
+    args.extend([
+        "-monitor".into(),
+        "stdio".into(),
+        "-m".into(),
+        format!("{}G", opts.mem.unwrap_or(config.memory)),
+        "-smp".into(),
+        format!("{}", opts.cores.unwrap_or(config.cores)),
+        "-serial".into(),
+        format!("file:{}/serial.txt", path_to_string(&run_dir()?)?),
+    ]);
 
-    let mut args = vec![
+    // for the kernel's gdb stub (`gdb:{enable}`), as `target remote :1235`; virt only has the one
+    // uart
+    if arch == Arch::X86_64 {
+        args.push("-serial".into());
+        args.push("tcp::1235,server,nowait".into());
+    }
+
+    for (i, disk) in opts.disks.iter().enumerate() {
+        args.push("-drive".into());
+        args.push(format!(
+            "if=none,id=disk{},format={},file={}",
+            i,
+            disk.format,
+            path_to_string(&create_disk(disk)?)?
+        ));
+
+        match opts.disk_bus {
+            DiskBus::Virtio => {
+                args.push("-device".into());
+                args.push(format!("virtio-blk-pci,drive=disk{}", i));
+            }
+            DiskBus::Nvme => {
+                args.push("-device".into());
+                args.push(format!("nvme,drive=disk{0},serial=disk{0}", i));
+            }
+            DiskBus::None => {}
+        }
+    }
+
+    if opts.net || !opts.forwards.is_empty() {
+        let mut netdev = String::from("user,id=net0");
+
+        for forward in &opts.forwards {
+            netdev += &format!(
+                ",hostfwd={}::{}-:{}",
+                forward.protocol, forward.host, forward.guest
+            );
+        }
+
+        args.push("-netdev".into());
+        args.push(netdev);
+        args.push("-device".into());
+        args.push("virtio-net-pci,netdev=net0".into());
+    }
+
+    if opts.kvm {
+        args.push("-enable-kvm".into());
+
+        if arch == Arch::X86_64 {
+            args.push("-cpu".into());
+            args.push("host".into());
+        }
+    }
+
+    // the logging and debugging flags, and whatever else buildtool.toml has
+    args.extend(config.args.iter().cloned());
+
+    Ok(args)
+}
+
+fn qemu(opts: QemuArgs) -> Result<()> {
+    exec(
+        &format!("qemu-system-{}", opts.arch.name()),
+        qemu_args(&opts)?,
+    )
+}
+
+// the newest modification time under `path`, leaving out build output
+fn newest_change(path: &PathBuf) -> Result<Option<SystemTime>> {
+    if !path.exists() {
+        return Ok(None);
+    }
+
+    let mut newest = fs::metadata(path)?.modified()?;
+
+    if path.is_dir() {
+        for entry in fs::read_dir(path)? {
+            let entry = entry?.path();
+
+            if entry.file_name().is_some_and(|name| name == "target") {
+                continue;
+            }
+
+            newest = newest.max(newest_change(&entry)?.unwrap_or(newest));
+        }
+    }
+
+    Ok(Some(newest))
+}
+
+// SIGTERM rather than the SIGKILL of Child::kill, so that qemu gets to put the terminal back the
+// way it was
+fn stop(qemu: &mut Child) -> Result<()> {
+    let killed = run(
+        "kill",
+        vec!["-TERM".into(), qemu.id().to_string()],
+        &run_dir()?,
+    );
+
+    // qemu may have exited by itself since it was last checked, and then there's nothing to kill
+    if killed.is_err() && qemu.try_wait()?.is_none() {
+        return killed;
+    }
+
+    qemu.wait()?;
+    Ok(())
+}
+
+fn watch(opts: QemuArgs) -> Result<()> {
+    let root = current_dir()?;
+    let mut watched = WATCHED.map(|path| root.join(path)).to_vec();
+    watched.extend(opts.initrd.clone());
+    let command = format!("qemu-system-{}", opts.arch.name());
+
+    let newest = || -> Result<Option<SystemTime>> {
+        let mut newest = None;
+
+        for path in &watched {
+            newest = newest.max(newest_change(path)?);
+        }
+
+        Ok(newest)
+    };
+
+    let mut seen = newest()?;
+    let mut qemu = None;
+    let mut changed = true;
+
+    loop {
+        if changed {
+            changed = false;
+
+            match qemu_args(&opts) {
+                Ok(mut args) => {
+                    // nothing would be there to let it go on
+                    args.retain(|arg| arg != "-S");
+
+                    if let Some(mut old) = qemu.take() {
+                        stop(&mut old)?;
+                    }
+
+                    eprintln!("running: {} {:?}", command, args);
+
+                    qemu = Some(
+                        Command::new(&command)
+                            .args(args)
+                            .current_dir(run_dir()?)
+                            .spawn()?,
+                    );
+                }
+                // the last good build keeps running
+                Err(err) => eprintln!("watch: the build failed: {}", err),
+            }
+
+            eprintln!("watch: waiting for changes");
+        }
+
+        thread::sleep(WATCH_INTERVAL);
+
+        if let Some(child) = &mut qemu
+            && let Some(status) = child.try_wait()?
+        {
+            eprintln!("watch: qemu exited ({}), waiting for changes", status);
+            qemu = None;
+        }
+
+        if newest()? != seen {
+            // an editor saving several files, or a checkout, shouldn't be built halfway through
+            thread::sleep(WATCH_SETTLE);
+            seen = newest()?;
+            changed = true;
+        }
+    }
+}
+
+fn profile(mut opts: QemuArgs, duration: u64) -> Result<()> {
+    // the samples are timed by the LAPIC
+    if opts.arch != Arch::X86_64 {
+        return Err(Error::msg("the profiler is only there on x86_64"));
+    }
+
+    if opts.profile == config::DEFAULT_PROFILE {
+        opts.profile = config::SAMPLING_PROFILE.into();
+    }
+
+    let capture = run_dir()?.join("profile.txt");
+    let _ = fs::remove_file(&capture);
+
+    let mut args = Vec::new();
+    let mut given = qemu_args(&opts)?.into_iter();
+
+    while let Some(arg) = given.next() {
+        match arg.as_str() {
+            // nothing would be there to let it go on
+            "-S" => {}
+            // logging every interrupt would be most of what gets sampled
+            "-d" => {
+                given.next();
+            }
+            _ => args.push(arg),
+        }
+    }
+
+    // COM3, after the log and the gdb stub; see src/profile/options.rs
+    args.push("-serial".into());
+    args.push(format!("file:{}", path_to_string(&capture)?));
+
+    let command = format!("qemu-system-{}", opts.arch.name());
+    eprintln!("running: {} {:?}", command, args);
+
+    let mut qemu = Command::new(&command)
+        .args(args)
+        .current_dir(run_dir()?)
+        .spawn()?;
+
+    thread::sleep(Duration::from_secs(duration));
+
+    match qemu.try_wait()? {
+        Some(status) => eprintln!("profile: qemu exited early ({})", status),
+        None => stop(&mut qemu)?,
+    }
+
+    let symbols =
+        symbolize::Symbols::parse(&fs::read(debug_module_path(opts.arch, opts.release)?)?)?;
+    let profile = profile::Profile::new(&fs::read(&capture)?, &symbols);
+
+    if profile.samples == 0 {
+        return Err(Error::msg(format!(
+            "no samples in {}; does the cmdline profile have profile:{{enable}}?",
+            path_to_string(&capture)?
+        )));
+    }
+
+    eprintln!(
+        "profile: {} samples, {} corrupt",
+        profile.samples, profile.corrupt
+    );
+
+    fs::write(run_dir()?.join("profile.folded"), profile.collapsed())?;
+
+    let svg = run_dir()?.join("profile.svg");
+    fs::write(&svg, profile.flamegraph()?)?;
+    println!("{}", path_to_string(&svg)?);
+
+    Ok(())
+}
+
+// the disk's path, creating it blank if it doesn't exist
+fn create_disk(disk: &Disk) -> Result<PathBuf> {
+    let path = current_dir()?.join(&disk.path);
+
+    if path.exists() {
+        return Ok(path);
+    }
+
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+
+    if disk.format == "raw" {
+        File::create(&path)?.set_len(BLANK_DISK_SIZE)?;
+    } else {
+        run(
+            "qemu-img",
+            vec![
+                "create".into(),
+                "-f".into(),
+                disk.format.clone(),
+                path.to_str().ok_or(Error::msg("bad path"))?.into(),
+                BLANK_DISK_SIZE.to_string(),
+            ],
+            &run_dir()?,
+        )?;
+    }
+
+    Ok(path)
+}
+
+fn test(cores: Option<u8>, mem_g: Option<u8>, release: bool, timeout: u64) -> Result<()> {
+    let arch = Arch::X86_64;
+    let config = &config::get().qemu;
+    let path = build_image(
+        arch,
+        &build_kernel(arch, release, false, true)?,
+        release,
+        config::DEFAULT_PROFILE,
+        None,
+    )?;
+    let serial = run_dir()?.join("test-serial.txt");
+
+    // so that a qemu that fails to start isn't judged by the last run's log
+    if serial.exists() {
+        fs::remove_file(&serial)?;
+    }
+
+    let args = vec![
         "-bios".into(),
-        path_to_string(&download_ovmf()?)?,
+        path_to_string(&download_ovmf(arch)?)?,
         "-hda".into(),
         path_to_string(&path)?,
-        "-no-reboot".into(),
-        "-monitor".into(),
-        "stdio".into(),
-        "-d".into(),
-        "int,cpu_reset".into(),
-        "-D".into(),
-        "qemu.log".into(),
-        "-no-shutdown".into(),
-        "-s".into(),
-        "-S".into(),
         "-M".into(),
         "smm=off".into(),
+        "-display".into(),
+        "none".into(),
+        "-monitor".into(),
+        "none".into(),
+        "-no-reboot".into(),
         "-m".into(),
-        format!("{}G", mem_g).into(),
+        format!("{}G", mem_g.unwrap_or(config.memory)),
         "-smp".into(),
-        format!("{}", cores),
-        "-vga".into(),
-        "std".into(),
+        format!("{}", cores.unwrap_or(config.cores)),
         "-serial".into(),
-        format!("file:{}/serial.txt", path_to_string(&run_dir()?)?),
+        format!("file:{}", serial.to_str().ok_or(Error::msg("bad path"))?),
+        // see qemu_exit in the kernel
+        "-device".into(),
+        "isa-debug-exit,iobase=0xf4,iosize=0x04".into(),
     ];
 
-    if kvm {
-        args.push("-enable-kvm".into());
-        args.push("-cpu".into());
-        args.push("host".into());
+    eprintln!("running: qemu-system-x86_64 {:?}", args);
+
+    let mut qemu = Command::new("qemu-system-x86_64")
+        .args(args)
+        .current_dir(run_dir()?)
+        .stdin(Stdio::null())
+        .spawn()?;
+
+    let deadline = Instant::now() + Duration::from_secs(timeout);
+
+    let status = loop {
+        if let Some(status) = qemu.try_wait()? {
+            break Some(status);
+        }
+
+        if Instant::now() > deadline {
+            qemu.kill()?;
+            qemu.wait()?;
+            break None;
+        }
+
+        thread::sleep(Duration::from_millis(100));
+    };
+
+    let results = ktest::parse(&fs::read(&serial)?);
+    print!("{}", results);
+
+    if status.is_none() {
+        println!("timed out after {}s", timeout);
     }
 
-    exec("qemu-system-x86_64", args)
+    if status.and_then(|status| status.code()) == Some(ktest::EXIT_SUCCESS) && results.passed() {
+        return Ok(());
+    }
+
+    println!("the serial log is in {}", path_to_string(&serial)?);
+    process::exit(1)
 }
 
-fn gdb(kvm: bool, release: bool) -> Result<()> {
-    let (kernel_elf, _) = build_kernel(release)?;
+fn size(arch: Arch, release: bool, diff: bool) -> Result<()> {
+    let (kernel_elf, crate_paths) = build_kernel(arch, release, false, false)?;
+    let report = size::Report::new(&fs::read(&kernel_elf)?, &crate_paths)?;
+
+    let saved = cache_dir()?.join(format!(
+        "size-{}-{}.txt",
+        arch.name(),
+        if release { "release" } else { "debug" }
+    ));
 
-    let gdb_args;
+    match size::Report::load(&saved)? {
+        Some(previous) if diff => print!("{}", report.diff(&previous)),
+        None if diff => {
+            println!("no earlier report to compare with\n");
+            print!("{}", report);
+        }
+        _ => print!("{}", report),
+    }
+
+    report.save(&saved)
+}
+
+// until something takes connections on qemu's gdb stub, or `qemu` exits
+fn wait_for_gdb_stub(mut qemu: Option<&mut Child>) -> Result<()> {
+    let start = Instant::now();
+    eprintln!("waiting for qemu's gdb stub on port {}", GDB_PORT);
+
+    while TcpStream::connect(("127.0.0.1", GDB_PORT)).is_err() {
+        if let Some(qemu) = &mut qemu
+            && let Some(status) = qemu.try_wait()?
+        {
+            return Err(Error::msg(format!("qemu exited ({})", status)));
+        }
 
-    if kvm {
-        gdb_args = vec!["target remote localhost:1234", "hbreak kmain", "c"]
+        if start.elapsed() > GDB_STUB_TIMEOUT {
+            return Err(Error::msg(format!(
+                "nothing on port {} after {:?}; is qemu running with -s?",
+                GDB_PORT, GDB_STUB_TIMEOUT
+            )));
+        }
+
+        thread::sleep(GDB_STUB_POLL);
+    }
+
+    Ok(())
+}
+
+// the commands to connect, load `symbols` and run to kmain, for gdb or lldb; a breakpoint set
+// before the kernel is loaded only holds as a hardware one under KVM
+fn debugger_commands(
+    arch: Arch,
+    lldb: bool,
+    kvm: bool,
+    symbols: &[SymbolFile],
+) -> Result<Vec<String>> {
+    let mut commands = Vec::new();
+
+    if lldb {
+        for symbols in symbols {
+            let path = path_to_string(&current_dir()?.join(&symbols.path))?;
+
+            commands.push(format!("target modules add {}", path));
+            commands.push(format!(
+                "target modules load --file {} --slide {:#x}",
+                path,
+                symbols.offset.unwrap_or(0)
+            ));
+        }
+
+        commands.push(format!("gdb-remote localhost:{}", GDB_PORT));
+        commands.push(
+            if kvm {
+                "breakpoint set -H -n kmain"
+            } else {
+                "breakpoint set -n kmain"
+            }
+            .into(),
+        );
+        commands.push("continue".into());
     } else {
-        gdb_args = vec!["target remote localhost:1234", "b kmain", "c"]
+        // add-symbol-file asks before it does anything
+        commands.push("set confirm off".into());
+
+        for symbols in symbols {
+            let path = path_to_string(&current_dir()?.join(&symbols.path))?;
+
+            commands.push(match symbols.offset {
+                Some(offset) => format!("add-symbol-file {} -o {:#x}", path, offset),
+                None => format!("add-symbol-file {}", path),
+            });
+        }
+
+        commands.push("set confirm on".into());
+        commands.push(format!("set architecture {}", arch.gdb_name()));
+        commands.push(format!("target remote localhost:{}", GDB_PORT));
+        commands.push(if kvm { "hbreak kmain" } else { "b kmain" }.into());
+        commands.push("c".into());
+    }
+
+    Ok(commands)
+}
+
+fn gdb(opts: QemuArgs, launch: bool, lldb: bool, symbols: &[SymbolFile]) -> Result<()> {
+    let mut qemu = None;
+
+    // the image is built first, so that the ELF the debugger reads is the one that boots
+    if launch {
+        let mut args = Vec::new();
+        let mut given = qemu_args(&opts)?.into_iter();
+
+        while let Some(arg) = given.next() {
+            match arg.as_str() {
+                // the debugger has the terminal; its `monitor` command reaches qemu's instead
+                "-monitor" => {
+                    given.next();
+                    args.extend(["-monitor".into(), "none".into()]);
+                }
+                "-s" | "-S" => {}
+                _ => args.push(arg),
+            }
+        }
+
+        args.extend(["-s".into(), "-S".into()]);
+
+        let command = format!("qemu-system-{}", opts.arch.name());
+        eprintln!("running: {} {:?}", command, args);
+
+        // in a process group of its own, so that ^C in the debugger doesn't reach it
+        qemu = Some(
+            Command::new(&command)
+                .args(args)
+                .current_dir(run_dir()?)
+                .stdin(Stdio::null())
+                .process_group(0)
+                .spawn()?,
+        );
     }
 
+    let (kernel_elf, _) = build_kernel(opts.arch, opts.release, opts.builtin_symbols, false)?;
+    let command = if lldb { "rust-lldb" } else { "rust-gdb" };
+    let flag = if lldb { "-o" } else { "-ex" };
+
     let mut args = vec![path_to_string(&kernel_elf)?];
 
-    for ent in gdb_args {
-        args.push("-ex".into());
-        args.push(ent.into());
+    for command in debugger_commands(opts.arch, lldb, opts.kvm, symbols)? {
+        args.push(flag.into());
+        args.push(command);
     }
 
-    exec("rust-gdb", args)
+    let Some(mut qemu) = qemu else {
+        wait_for_gdb_stub(None)?;
+        return exec(command, args);
+    };
+
+    let debugged = wait_for_gdb_stub(Some(&mut qemu)).and_then(|()| {
+        eprintln!("running: {} {:?}", command, args);
+        Command::new(command)
+            .args(&args)
+            .current_dir(run_dir()?)
+            .status()?;
+        Ok(())
+    });
+
+    if qemu.try_wait()?.is_none() {
+        stop(&mut qemu)?;
+    }
+
+    debugged
+}
+
+fn sign(key: &PathBuf, module: &PathBuf) -> Result<()> {
+    let seed: [u8; Seed::BYTES] = fs::read(key)?
+        .try_into()
+        .map_err(|_| Error::msg("the key should be a 32 byte seed"))?;
+    let pair = KeyPair::from_seed(Seed::new(seed));
+
+    fs::write(key.with_extension("pub"), &pair.pk[..])?;
+
+    let signature = pair.sk.sign(fs::read(module)?, None);
+    let mut file = OpenOptions::new().append(true).open(module)?;
+    file.write_all(&signature[..])?;
+    file.write_all(SIGNATURE_MAGIC)?;
+
+    Ok(())
 }
 
 fn main() -> Result<()> {
     let cli = Cli::parse();
+    config::load()?;
+    download::set_offline(cli.offline);
+    download::set_update_pins(cli.update_pins);
 
     match cli.command {
-        Commands::Image { release } => {
-            build_image(&build_kernel(release)?, release)?;
+        Commands::Image {
+            arch,
+            profile,
+            initrd,
+            release,
+            builtin_symbols,
+        } => {
+            build_image(
+                arch,
+                &build_kernel(arch, release, builtin_symbols, false)?,
+                release,
+                &profile,
+                initrd.as_ref(),
+            )?;
         }
-        Commands::Qemu {
-            kvm,
+        Commands::Qemu(opts) => qemu(opts)?,
+        Commands::Watch(opts) => watch(opts)?,
+        Commands::Test {
             cores,
             mem,
             release,
-        } => qemu(kvm, cores, mem, release)?,
-        Commands::Gdb { kvm, release } => gdb(kvm, release)?,
+            timeout,
+        } => test(cores, mem, release, timeout)?,
+        Commands::Iso {
+            arch,
+            profile,
+            initrd,
+            release,
+            builtin_symbols,
+        } => {
+            let iso = build_iso(
+                arch,
+                &build_kernel(arch, release, builtin_symbols, false)?,
+                release,
+                &profile,
+                initrd.as_ref(),
+            )?;
+            println!("{}", path_to_string(&iso)?);
+        }
+        Commands::Gdb {
+            qemu,
+            launch,
+            lldb,
+            symbols,
+        } => gdb(qemu, launch, lldb, &symbols)?,
+        Commands::Profile { qemu, duration } => profile(qemu, duration)?,
+        Commands::Size {
+            arch,
+            release,
+            diff,
+        } => size(arch, release, diff)?,
+        Commands::Sign { key, module } => sign(&key, &module)?,
+        Commands::Decode { capture } => {
+            let capture = match capture {
+                Some(capture) => capture,
+                None => run_dir()?.join("serial.txt"),
+            };
+
+            print!("{}", crashdump::decode(&fs::read(capture)?)?);
+        }
+        Commands::Symbolize {
+            arch,
+            release,
+            symbols,
+        } => {
+            let symbols = match symbols {
+                Some(symbols) => symbols,
+                None => debug_module_path(arch, release)?,
+            };
+
+            if !symbols.exists() {
+                return Err(Error::msg(format!(
+                    "no symbols at {}; build an image first, or pass --symbols",
+                    path_to_string(&symbols)?
+                )));
+            }
+
+            let symbols = symbolize::Symbols::parse(&fs::read(symbols)?)?;
+            symbolize::run(&symbols, io::stdin().lock())?;
+        }
         Commands::Clean => {
             fs::remove_dir_all(cache_dir()?)?;
             cache_dir()?;
@@ -0,0 +1,87 @@
+// `buildtool profile`: turns the samples the kernel's profiler streams over serial, see
+// src/profile/mod.rs, into collapsed stacks and a flamegraph of them
+//
+// each address in a sample is put to its function and everything inlined there with the debug
+// module, so inlined functions get frames of their own; samples from every core are merged.
+
+use crate::crashdump::parse_frame;
+use crate::symbolize::Symbols;
+use anyhow::Result;
+use inferno::flamegraph::{self, Options};
+use std::collections::BTreeMap;
+
+pub struct Profile {
+    // `outermost;...;innermost`, and the number of samples with that stack
+    stacks: BTreeMap<String, u64>,
+    pub samples: u64,
+    pub corrupt: u64,
+}
+
+// a frame of a stack; `;` splits frames in the collapsed format, and shows up in names such as
+// `<[u8; 4] as Debug>::fmt`
+fn frame_name(name: &str) -> String {
+    name.replace(';', ",")
+}
+
+impl Profile {
+    pub fn new(capture: &[u8], symbols: &Symbols) -> Profile {
+        let capture = String::from_utf8_lossy(capture);
+
+        let mut profile = Profile {
+            stacks: BTreeMap::new(),
+            samples: 0,
+            corrupt: 0,
+        };
+
+        for line in capture.lines() {
+            let data = match parse_frame(line) {
+                Some(Ok(("sample", data))) if data.len() >= 12 => data,
+                Some(Ok(_)) | None => continue,
+                Some(Err(())) => {
+                    profile.corrupt += 1;
+                    continue;
+                }
+            };
+
+            // past the core id, the interrupted pc and then return addresses; those point after
+            // the call, which may already be the next function or line
+            let mut frames = Vec::new();
+
+            for (i, addr) in data[4..].chunks_exact(8).enumerate() {
+                let addr = u64::from_le_bytes(addr.try_into().unwrap());
+                let addr = if i == 0 { addr } else { addr.wrapping_sub(1) };
+
+                match &symbols.names(addr)[..] {
+                    [] => frames.push(format!("{:#x}", addr)),
+                    names => frames.extend(names.iter().map(|name| frame_name(name))),
+                }
+            }
+
+            frames.reverse();
+            *profile.stacks.entry(frames.join(";")).or_default() += 1;
+            profile.samples += 1;
+        }
+
+        profile
+    }
+
+    // one `stack count` per line, as flamegraph.pl and inferno take them
+    pub fn collapsed(&self) -> String {
+        self.stacks
+            .iter()
+            .map(|(stack, count)| format!("{} {}\n", stack, count))
+            .collect()
+    }
+
+    pub fn flamegraph(&self) -> Result<Vec<u8>> {
+        let mut options = Options::default();
+        options.title = "kernel".into();
+        options.count_name = "samples".into();
+
+        let collapsed = self.collapsed();
+        let mut svg = Vec::new();
+        flamegraph::from_lines(&mut options, collapsed.lines(), &mut svg)?;
+
+        Ok(svg)
+    }
+}
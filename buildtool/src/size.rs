@@ -0,0 +1,210 @@
+// `buildtool size`: what the kernel image is made of, by section and by crate
+//
+// a symbol is put down to the crate at the start of its demangled path, which for a trait impl is
+// the crate of the type; anything that doesn't demangle to a known crate is counted as `other`.
+// every report is saved to the cache, and --diff compares against the one saved before it.
+
+use anyhow::Result;
+use object::{Object, ObjectSection, ObjectSymbol, SymbolKind};
+use rustc_demangle::demangle;
+use std::collections::BTreeMap;
+use std::fmt::{self, Display, Formatter};
+use std::fs;
+use std::path::PathBuf;
+
+const OTHER: &str = "other";
+
+#[derive(Default)]
+pub struct Report {
+    // in the order they are in the ELF
+    sections: Vec<(String, u64)>,
+    // biggest first
+    crates: Vec<(String, u64)>,
+}
+
+// the crate name as it shows up in symbols, for each of the crate_paths from build_kernel
+fn crate_names(crate_paths: &[(String, PathBuf)]) -> BTreeMap<String, String> {
+    crate_paths
+        .iter()
+        .map(|(label, _)| {
+            let name = label.split('@').next().unwrap_or(label);
+            let name = name.strip_prefix("builtin::").unwrap_or(name);
+            (name.replace('-', "_"), label.clone())
+        })
+        .collect()
+}
+
+fn crate_of<'a>(name: &str, crates: &'a BTreeMap<String, String>) -> &'a str {
+    let name = format!("{:#}", demangle(name));
+    let path = name.trim_start_matches('<');
+    let first = path.split("::").next().unwrap_or(path);
+
+    crates.get(first).map_or(OTHER, String::as_str)
+}
+
+impl Report {
+    pub fn new(elf_contents: &[u8], crate_paths: &[(String, PathBuf)]) -> Result<Report> {
+        let object = object::File::parse(elf_contents)?;
+
+        // only what is loaded, which leaves out the debug info and symbol tables
+        let sections = object
+            .sections()
+            .filter(|section| section.address() != 0 && section.size() != 0)
+            .map(|section| Ok((section.name()?.to_string(), section.size())))
+            .collect::<Result<_>>()?;
+
+        let names = crate_names(crate_paths);
+        let mut crates = BTreeMap::<&str, u64>::new();
+
+        for sym in object.symbols() {
+            if !matches!(sym.kind(), SymbolKind::Text | SymbolKind::Data) || sym.size() == 0 {
+                continue;
+            }
+
+            *crates.entry(crate_of(sym.name()?, &names)).or_default() += sym.size();
+        }
+
+        let mut crates: Vec<_> = crates
+            .into_iter()
+            .map(|(name, size)| (name.to_string(), size))
+            .collect();
+        crates.sort_by_key(|&(_, size)| u64::MAX - size);
+
+        Ok(Report { sections, crates })
+    }
+
+    // one `section|crate <name> <size>` per line
+    pub fn load(path: &PathBuf) -> Result<Option<Report>> {
+        if !path.exists() {
+            return Ok(None);
+        }
+
+        let mut report = Report::default();
+
+        for line in fs::read_to_string(path)?.lines() {
+            let mut fields = line.split_whitespace();
+
+            let (Some(kind), Some(name), Some(Ok(size))) =
+                (fields.next(), fields.next(), fields.next().map(str::parse))
+            else {
+                continue;
+            };
+
+            match kind {
+                "section" => report.sections.push((name.into(), size)),
+                "crate" => report.crates.push((name.into(), size)),
+                _ => {}
+            }
+        }
+
+        Ok(Some(report))
+    }
+
+    pub fn save(&self, path: &PathBuf) -> Result<()> {
+        let mut out = String::new();
+
+        for (name, size) in &self.sections {
+            out += &format!("section {} {}\n", name, size);
+        }
+
+        for (name, size) in &self.crates {
+            out += &format!("crate {} {}\n", name, size);
+        }
+
+        fs::write(path, out)?;
+        Ok(())
+    }
+
+    pub fn diff<'a>(&'a self, previous: &'a Report) -> Diff<'a> {
+        Diff {
+            report: self,
+            previous: Some(previous),
+        }
+    }
+}
+
+pub struct Diff<'a> {
+    report: &'a Report,
+    previous: Option<&'a Report>,
+}
+
+fn write_table(
+    f: &mut Formatter<'_>,
+    title: &str,
+    rows: &[(String, u64)],
+    previous: Option<&[(String, u64)]>,
+) -> fmt::Result {
+    let width = rows
+        .iter()
+        .chain(previous.unwrap_or_default())
+        .map(|(name, _)| name.len())
+        .max()
+        .unwrap_or(0)
+        .max(title.len());
+
+    writeln!(f, "{:width$} {:>10}", title, "bytes")?;
+
+    let old = |name: &str| {
+        previous?
+            .iter()
+            .find(|(old, _)| old == name)
+            .map(|&(_, size)| size)
+    };
+
+    for (name, size) in rows {
+        write!(f, "{:width$} {:>10}", name, size)?;
+
+        match (previous, old(name)) {
+            (None, _) => writeln!(f)?,
+            (Some(_), Some(old)) if old == *size => writeln!(f)?,
+            (Some(_), Some(old)) => writeln!(f, " {:+}", *size as i64 - old as i64)?,
+            (Some(_), None) => writeln!(f, " (new)")?,
+        }
+    }
+
+    // the ones that are gone
+    for (name, size) in previous.unwrap_or_default() {
+        if !rows.iter().any(|(row, _)| row == name) {
+            writeln!(f, "{:width$} {:>10} -{}", name, 0, size)?;
+        }
+    }
+
+    let total: u64 = rows.iter().map(|(_, size)| size).sum();
+    write!(f, "{:width$} {:>10}", "total", total)?;
+
+    match previous {
+        Some(previous) => {
+            let old: u64 = previous.iter().map(|(_, size)| size).sum();
+            writeln!(f, " {:+}", total as i64 - old as i64)
+        }
+        None => writeln!(f),
+    }
+}
+
+impl Display for Diff<'_> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write_table(
+            f,
+            "section",
+            &self.report.sections,
+            self.previous.map(|previous| &previous.sections[..]),
+        )?;
+        writeln!(f)?;
+        write_table(
+            f,
+            "crate",
+            &self.report.crates,
+            self.previous.map(|previous| &previous.crates[..]),
+        )
+    }
+}
+
+impl Display for Report {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        Diff {
+            report: self,
+            previous: None,
+        }
+        .fmt(f)
+    }
+}
@@ -0,0 +1,334 @@
+// `buildtool symbolize`: puts names and source lines to the kernel addresses in text from stdin,
+// such as a panic log cut out of a CI serial capture, without booting anything
+//
+// every line is passed through as is, and each kernel address in it (0x..., in the top 2 GiB) is
+// followed by what the kernel's log::Symbolized would print for it. the symbols come from a debug
+// module, see src/modules/symbols.rs for the format, or from the symbol table of a kernel ELF,
+// which only has names.
+
+use crate::debug::io::{
+    DATA_SYMBOL_SIZE, FUNCTION_SIZE, LOCATION_SIZE, MODULE_MAGIC, SECTION_DATA_SYMBOLS,
+    SECTION_FUNCTION_SEARCH, SECTION_FUNCTIONS, SECTION_LOCATION_SEARCH, SECTION_STRINGS,
+    read_sections,
+};
+use anyhow::{Error, Result};
+use object::{Object, ObjectSymbol, SymbolKind};
+use rustc_demangle::demangle;
+use std::ffi::CStr;
+use std::fmt::Write;
+use std::io::BufRead;
+use std::iter;
+
+const ZSTD_MAGIC: &[u8; 4] = &[0x28, 0xb5, 0x2f, 0xfd];
+const KERNEL_BASE: u64 = 0xffffffff80000000;
+
+struct Location {
+    file: Option<String>,
+    row: u32,
+    col: u32,
+}
+
+struct Function {
+    inline_parent: Option<usize>,
+    name: Option<String>,
+    // the call site in the next function out, for an inlined one
+    location: Location,
+}
+
+// everything by offset from KERNEL_BASE, and sorted
+#[derive(Default)]
+pub struct Symbols {
+    functions: Vec<Function>,
+    function_search: Vec<(u32, Option<usize>)>,
+    location_search: Vec<(u32, Location)>,
+    // offset, size and name of each static
+    data: Vec<(u32, u32, String)>,
+}
+
+fn bytes<const N: usize>(buf: &[u8], offset: usize) -> Result<[u8; N]> {
+    Ok(buf
+        .get(offset..offset + N)
+        .ok_or(Error::msg("malformed symbol module"))?
+        .try_into()?)
+}
+
+fn read_u32(buf: &[u8], offset: usize) -> Result<u32> {
+    Ok(u32::from_le_bytes(bytes(buf, offset)?))
+}
+
+// an index or string offset, where usize::MAX is none
+fn read_index(buf: &[u8], offset: usize) -> Result<Option<usize>> {
+    Ok(match u64::from_le_bytes(bytes(buf, offset)?) {
+        u64::MAX => None,
+        index => Some(index as usize),
+    })
+}
+
+fn read_string(buf: &[u8], offset: usize, strings: &[u8]) -> Result<Option<String>> {
+    let Some(start) = read_index(buf, offset)? else {
+        return Ok(None);
+    };
+
+    let str = strings
+        .get(start..)
+        .and_then(|rest| CStr::from_bytes_until_nul(rest).ok())
+        .ok_or(Error::msg("malformed symbol module"))?;
+
+    Ok(Some(str.to_string_lossy().into_owned()))
+}
+
+fn read_location(buf: &[u8], offset: usize, strings: &[u8]) -> Result<Location> {
+    Ok(Location {
+        file: read_string(buf, offset, strings)?,
+        row: read_u32(buf, offset + 8)?,
+        col: read_u32(buf, offset + 8 + 4)?,
+    })
+}
+
+// the address and value of each entry in a search table
+fn read_search<T>(
+    table: &[u8],
+    size: usize,
+    read: impl Fn(usize) -> Result<T>,
+) -> Result<Vec<(u32, T)>> {
+    (0..table.len() / (4 + size))
+        .map(|i| {
+            let entry = i * (4 + size);
+            Ok((read_u32(table, entry)?, read(entry + 4)?))
+        })
+        .collect()
+}
+
+// the value of the last entry at or before `offset`, as the kernel looks them up
+fn search<T>(table: &[(u32, T)], offset: u32) -> Option<&T> {
+    match table.partition_point(|(start, _)| *start <= offset) {
+        0 => None,
+        end => Some(&table[end - 1].1),
+    }
+}
+
+impl Symbols {
+    // a debug module, compressed or not, or a kernel ELF
+    pub fn parse(data: &[u8]) -> Result<Symbols> {
+        if data.starts_with(ZSTD_MAGIC) {
+            return Self::parse(&zstd::decode_all(data)?);
+        }
+
+        if data.starts_with(MODULE_MAGIC) {
+            return Self::parse_module(data);
+        }
+
+        Self::parse_elf(data)
+    }
+
+    fn parse_module(src: &[u8]) -> Result<Symbols> {
+        let mut strings: &[u8] = &[];
+        let mut functions: &[u8] = &[];
+        let mut location_search: &[u8] = &[];
+        let mut function_search: &[u8] = &[];
+        let mut data_symbols: &[u8] = &[];
+
+        for (id, data) in read_sections(src)? {
+            match id {
+                SECTION_STRINGS => strings = data,
+                SECTION_FUNCTIONS => functions = data,
+                SECTION_LOCATION_SEARCH => location_search = data,
+                SECTION_FUNCTION_SEARCH => function_search = data,
+                SECTION_DATA_SYMBOLS => data_symbols = data,
+                _ => {}
+            }
+        }
+
+        Ok(Symbols {
+            functions: (0..functions.len() / FUNCTION_SIZE)
+                .map(|i| {
+                    let entry = i * FUNCTION_SIZE;
+                    Ok(Function {
+                        inline_parent: read_index(functions, entry)?,
+                        name: read_string(functions, entry + 8, strings)?,
+                        location: read_location(functions, entry + 16, strings)?,
+                    })
+                })
+                .collect::<Result<_>>()?,
+            function_search: read_search(function_search, 8, |entry| {
+                read_index(function_search, entry)
+            })?,
+            location_search: read_search(location_search, LOCATION_SIZE, |entry| {
+                read_location(location_search, entry, strings)
+            })?,
+            data: (0..data_symbols.len() / DATA_SYMBOL_SIZE)
+                .map(|i| {
+                    let entry = i * DATA_SYMBOL_SIZE;
+                    Ok((
+                        read_u32(data_symbols, entry)?,
+                        read_u32(data_symbols, entry + 4)?,
+                        read_string(data_symbols, entry + 8, strings)?.unwrap_or_default(),
+                    ))
+                })
+                .collect::<Result<_>>()?,
+        })
+    }
+
+    // only names: each function symbol is made a function with no location, covering just its
+    // own bytes
+    fn parse_elf(data: &[u8]) -> Result<Symbols> {
+        let object = object::File::parse(data)
+            .map_err(|_| Error::msg("neither a symbol module nor an ELF"))?;
+
+        let mut symbols = Symbols::default();
+        let mut ranges = Vec::new();
+
+        for sym in object.symbols() {
+            let (addr, size) = (sym.address(), sym.size());
+
+            if addr < KERNEL_BASE || size == 0 {
+                continue;
+            }
+
+            let offset = (addr - KERNEL_BASE) as u32;
+
+            match sym.kind() {
+                SymbolKind::Text => {
+                    ranges.push((offset, offset + size as u32, symbols.functions.len()));
+                    symbols.functions.push(Function {
+                        inline_parent: None,
+                        name: Some(sym.name()?.into()),
+                        location: Location {
+                            file: None,
+                            row: 0,
+                            col: 0,
+                        },
+                    });
+                }
+                SymbolKind::Data => symbols.data.push((offset, size as u32, sym.name()?.into())),
+                _ => {}
+            }
+        }
+
+        ranges.sort();
+
+        for (start, end, index) in ranges {
+            symbols.function_search.push((start, Some(index)));
+            symbols.function_search.push((end, None));
+        }
+
+        // an end and the start right after it share an offset; the start has to be the one found
+        symbols
+            .function_search
+            .sort_by_key(|&(offset, index)| (offset, index.is_some()));
+        symbols.data.sort();
+
+        Ok(symbols)
+    }
+
+    // the function at `offset`, then each one it is inlined into
+    fn functions(&self, offset: u32) -> impl Iterator<Item = &Function> {
+        let first = search(&self.function_search, offset)
+            .copied()
+            .flatten()
+            .and_then(|index| self.functions.get(index));
+
+        iter::successors(first, |function| {
+            function
+                .inline_parent
+                .and_then(|index| self.functions.get(index))
+        })
+    }
+
+    // the demangled names of the functions at `addr`, innermost first, as for a backtrace
+    pub fn names(&self, addr: u64) -> Vec<String> {
+        if addr < KERNEL_BASE {
+            return Vec::new();
+        }
+
+        self.functions((addr - KERNEL_BASE) as u32)
+            .map(|function| format!("{:#}", demangle(function.name.as_deref().unwrap_or("unk"))))
+            .collect()
+    }
+
+    // what log::Symbolized prints for `addr`, or nothing if it isn't known
+    pub fn symbolize(&self, addr: u64) -> String {
+        let mut out = String::new();
+
+        if addr < KERNEL_BASE {
+            return out;
+        }
+
+        let offset = (addr - KERNEL_BASE) as u32;
+
+        // a null entry, for a gap between functions
+        if let Some(loc) = search(&self.location_search, offset).filter(|loc| loc.row != 0) {
+            writeln!(
+                out,
+                "  at {}:{}:{}",
+                loc.file.as_deref().unwrap_or("unk"),
+                loc.row,
+                loc.col
+            )
+            .unwrap();
+        }
+
+        let mut functions = self.functions(offset);
+
+        if let Some(mut inlined) = functions.next() {
+            let name = inlined.name.as_deref().unwrap_or("unk");
+            writeln!(out, "  in {:#}", demangle(name)).unwrap();
+
+            for parent in functions {
+                let loc = &inlined.location;
+                writeln!(
+                    out,
+                    "    inlined at {}:{}:{}",
+                    loc.file.as_deref().unwrap_or("unk"),
+                    loc.row,
+                    loc.col
+                )
+                .unwrap();
+                let name = parent.name.as_deref().unwrap_or("unk");
+                writeln!(out, "    into {:#}", demangle(name)).unwrap();
+
+                inlined = parent;
+            }
+        }
+
+        if out.is_empty()
+            && let Some((start, _, name)) = search_data(&self.data, offset)
+        {
+            writeln!(out, "  static {:#}+{:#x}", demangle(name), offset - start).unwrap();
+        }
+
+        out
+    }
+}
+
+// the static containing `offset`
+fn search_data(data: &[(u32, u32, String)], offset: u32) -> Option<&(u32, u32, String)> {
+    let end = data.partition_point(|(start, _, _)| *start <= offset);
+    let entry = data.get(end.checked_sub(1)?)?;
+    (offset - entry.0 < entry.1).then_some(entry)
+}
+
+// the kernel addresses in a line, as 0x followed by hex digits
+fn addresses(line: &str) -> impl Iterator<Item = u64> + '_ {
+    line.split(|ch: char| !ch.is_ascii_alphanumeric())
+        .filter_map(|word| u64::from_str_radix(word.strip_prefix("0x")?, 16).ok())
+        .filter(|addr| *addr >= KERNEL_BASE)
+}
+
+// copies `input` to stdout, with the symbols of each address after the line it is on; serial
+// captures can have binary in them, such as a crash dump, so lines are taken as lossy UTF-8
+pub fn run(symbols: &Symbols, input: impl BufRead) -> Result<()> {
+    for line in input.split(b'\n') {
+        let line = line?;
+        let line = String::from_utf8_lossy(&line);
+        let line = line.trim_end_matches('\r');
+
+        println!("{}", line);
+
+        for addr in addresses(line) {
+            print!("{}", symbols.symbolize(addr));
+        }
+    }
+
+    Ok(())
+}
@@ -11,32 +11,211 @@ use syn::Token;
 use syn::Variant;
 use syn::punctuated::Punctuated;
 use syn::spanned::Spanned;
+use syn::{Attribute, Expr, Lit, Meta, NestedMeta};
 use syn::{Data, DataStruct, DeriveInput, Fields, Type, parse_macro_input};
 
+// the keyword a field or enumerator is matched by, plus any aliases, from
+// `#[cmdline(rename = "...", alias = "...")]`
+struct Keyword {
+    name: String,
+    aliases: Vec<String>,
+}
+
+impl Keyword {
+    fn parse(attrs: &[Attribute], default: String) -> Keyword {
+        let mut keyword = Keyword {
+            name: default,
+            aliases: Vec::new(),
+        };
+
+        for attr in attrs.iter().filter(|attr| attr.path.is_ident("cmdline")) {
+            let Ok(Meta::List(list)) = attr.parse_meta() else {
+                panic!("expected #[cmdline(...)]");
+            };
+
+            for nested in list.nested {
+                let NestedMeta::Meta(Meta::NameValue(pair)) = nested else {
+                    panic!("expected `rename = \"...\"` or `alias = \"...\"`");
+                };
+
+                let Lit::Str(value) = pair.lit else {
+                    panic!("cmdline keywords must be string literals");
+                };
+
+                if pair.path.is_ident("rename") {
+                    keyword.name = value.value();
+                } else if pair.path.is_ident("alias") {
+                    keyword.aliases.push(value.value());
+                } else {
+                    panic!("unknown cmdline attribute; expected rename or alias");
+                }
+            }
+        }
+
+        keyword
+    }
+
+    // match pattern accepting the name and every alias
+    fn pattern(&self) -> TokenStream {
+        let name = &self.name;
+        let aliases = &self.aliases;
+        quote! { #name #(| #aliases)* }
+    }
+}
+
+// parse-time checks on a field's value, from `#[validate(min = .., max = .., with = "..")]`;
+// `with` names a `fn(&T) -> Result<(), &'static str>`
+fn validation(attrs: &[Attribute], field: &Ident) -> Option<TokenStream> {
+    let mut min = quote! { None };
+    let mut max = quote! { None };
+    let mut has_bounds = false;
+    let mut validators = Vec::new();
+
+    for attr in attrs.iter().filter(|attr| attr.path.is_ident("validate")) {
+        let Ok(Meta::List(list)) = attr.parse_meta() else {
+            panic!("expected #[validate(...)]");
+        };
+
+        for nested in list.nested {
+            let NestedMeta::Meta(Meta::NameValue(pair)) = nested else {
+                panic!("expected `min = ..`, `max = ..` or `with = \"...\"`");
+            };
+
+            match pair.lit {
+                Lit::Int(value) if pair.path.is_ident("min") || pair.path.is_ident("max") => {
+                    let value: i64 = value
+                        .base10_parse()
+                        .expect("validation bounds must fit in an i64");
+
+                    if pair.path.is_ident("min") {
+                        min = quote! { Some(#value) };
+                    } else {
+                        max = quote! { Some(#value) };
+                    }
+
+                    has_bounds = true;
+                }
+                Lit::Str(value) if pair.path.is_ident("with") => {
+                    validators.push(
+                        value
+                            .parse::<syn::Path>()
+                            .expect("expected a function path"),
+                    );
+                }
+                _ => panic!("unknown validate attribute; expected min, max or with"),
+            }
+        }
+    }
+
+    if !has_bounds && validators.is_empty() {
+        return None;
+    }
+
+    let bounds = has_bounds.then(|| {
+        quote! {
+            if !crate::cmdline::CmdlineBounded::in_bounds(&*#field, #min, #max) {
+                return Err(crate::cmdline::CmdlineParseError(
+                    crate::cmdline::CmdlineErrorCode::OutOfRange { min: #min, max: #max },
+                    lexer.span_from(value_start),
+                ));
+            }
+        }
+    });
+
+    Some(quote! {
+        #bounds
+
+        #(
+            if let Err(reason) = #validators(&*#field) {
+                return Err(crate::cmdline::CmdlineParseError(
+                    crate::cmdline::CmdlineErrorCode::Invalid(reason),
+                    lexer.span_from(value_start),
+                ));
+            }
+        )*
+    })
+}
+
+// the expression from `#[default_value(...)]`
+fn default_value(attrs: &[Attribute]) -> Option<TokenStream> {
+    attrs
+        .iter()
+        .find(|attr| attr.path.is_ident("default_value"))
+        .map(|attr| {
+            let expr: Expr = attr
+                .parse_args()
+                .expect("#[default_value(...)] takes an expression");
+            quote! { #expr }
+        })
+}
+
+// `const DEFAULT` for a struct; fields without a #[default_value] take their type's DEFAULT, so
+// they have to be derived structs themselves
+fn default_struct(ident: &Ident, fields: &Fields) -> TokenStream {
+    let default = |f: &Field| {
+        let ty = &f.ty;
+        default_value(&f.attrs).unwrap_or(quote! { <#ty>::DEFAULT })
+    };
+
+    let init = match fields {
+        Fields::Named(fields) => {
+            let entries = fields.named.iter().map(|f| {
+                let name = f.ident.as_ref().unwrap();
+                let value = default(f);
+                quote! { #name: #value }
+            });
+
+            quote! { #ident { #(#entries,)* } }
+        }
+        Fields::Unnamed(fields) => {
+            let entries = fields.unnamed.iter().map(default);
+            quote! { #ident(#(#entries,)*) }
+        }
+        Fields::Unit => quote! { #ident },
+    };
+
+    quote! {
+        impl #ident {
+            pub const DEFAULT: #ident = #init;
+        }
+    }
+}
+
 fn is_bool(ty: &Type) -> bool {
     matches!(ty, Type::Path(type_path) if type_path.clone().into_token_stream().to_string() == "bool")
 }
 
+fn is_option(ty: &Type) -> bool {
+    matches!(ty, Type::Path(type_path) if type_path.path.segments.last().is_some_and(|seg| seg.ident == "Option"))
+}
+
 fn handle_named_struct(fields_named: &FieldsNamed) -> TokenStream {
-    fn unwrap_entry(f: &Field) -> (Ident, String) {
+    fn unwrap_entry(f: &Field) -> (Ident, Keyword) {
         let name = f.ident.as_ref().unwrap().to_token_stream();
         let name_str = name.to_string();
         (
             Ident::new(&format!("_f_{}", name_str), name.span()),
-            name_str,
+            Keyword::parse(&f.attrs, name_str),
         )
     }
 
-    let bool_fields: Vec<_> = fields_named
+    // `!field` clears bools and optional fields
+    let negatable_fields: Vec<_> = fields_named
         .named
         .iter()
-        .filter(|f| is_bool(&f.ty))
+        .filter(|f| is_bool(&f.ty) || is_option(&f.ty))
         .collect();
 
-    let bool_handler = if !bool_fields.is_empty() {
-        let entries = bool_fields.iter().map(|f| {
-            let (name, name_str) = unwrap_entry(f);
-            (quote! { #name_str => *#name = false }, name_str)
+    let bool_handler = if !negatable_fields.is_empty() {
+        let entries = negatable_fields.iter().map(|f| {
+            let (name, keyword) = unwrap_entry(f);
+            let pattern = keyword.pattern();
+
+            if is_bool(&f.ty) {
+                (quote! { #pattern => *#name = false }, keyword.name)
+            } else {
+                (quote! { #pattern => *#name = None }, keyword.name)
+            }
         });
 
         let matches = entries.clone().map(|f| f.0);
@@ -47,11 +226,13 @@ fn handle_named_struct(fields_named: &FieldsNamed) -> TokenStream {
                 match lexer.next()?.unwrap_ident()? {
                     #(#matches,)*
                     _ => {
-                        return Err(field_tok.make_error(
+                        lexer.skip_unknown(field_tok.make_error(
                             crate::cmdline::CmdlineErrorCode::UnknownFlagField(&[#(#names,)*])
-                        ));
+                        ))?;
                     }
                 }
+
+                return Ok(());
             }
         }
     } else {
@@ -59,12 +240,18 @@ fn handle_named_struct(fields_named: &FieldsNamed) -> TokenStream {
     };
 
     let main_handler = fields_named.named.iter().map(|f| {
-        let (name, name_str) = unwrap_entry(f);
+        let (name, keyword) = unwrap_entry(f);
+        let pattern = keyword.pattern();
 
         (
             if is_bool(&f.ty) {
+                // a bare flag has no value to check, so the attribute would silently do nothing
+                if f.attrs.iter().any(|attr| attr.path.is_ident("validate")) {
+                    panic!("#[validate(...)] can't be used on bool fields");
+                }
+
                 quote! {
-                    #name_str => {
+                    #pattern => {
                         if lexer.peek().0 != crate::cmdline::CmdlineTokenData::Colon {
                             *#name = true;
                         } else {
@@ -73,15 +260,24 @@ fn handle_named_struct(fields_named: &FieldsNamed) -> TokenStream {
                         }
                     }
                 }
+            } else if let Some(checks) = validation(&f.attrs, &name) {
+                quote! {
+                    #pattern => {
+                        lexer.expect(crate::cmdline::CmdlineTokenData::Colon)?;
+                        let value_start = lexer.peek().1.start;
+                        #name.parse(lexer)?;
+                        #checks
+                    }
+                }
             } else {
                 quote! {
-                    #name_str => {
+                    #pattern => {
                         lexer.expect(crate::cmdline::CmdlineTokenData::Colon)?;
                         #name.parse(lexer)?;
                     }
                 }
             },
-            name_str,
+            keyword.name,
         )
     });
 
@@ -101,9 +297,9 @@ fn handle_named_struct(fields_named: &FieldsNamed) -> TokenStream {
                 match field_tok.unwrap_ident()? {
                     #(#matches,)*
                     _ => {
-                        return Err(field_tok.make_error(
+                        lexer.skip_unknown(field_tok.make_error(
                             crate::cmdline::CmdlineErrorCode::UnknownField(&[#(#fields,)*])
-                        ));
+                        ))?;
                     }
                 }
 
@@ -157,11 +353,9 @@ fn handle_fields(fields: &Fields, allow_unit: bool) -> TokenStream {
         Fields::Named(fields) => handle_named_struct(fields),
         Fields::Unnamed(fields) => handle_unnamed_struct(fields),
         Fields::Unit => {
-            if allow_unit {
-                quote! {}
-            } else {
-                todo!()
-            }
+            // unit structs are rejected in derive()
+            assert!(allow_unit);
+            quote! {}
         }
     }
 }
@@ -173,7 +367,11 @@ fn handle_enum(variants: &Punctuated<Variant, Token![,]>) -> TokenStream {
         .iter()
         .map(|f| {
             let enum_name_ident = &f.ident;
-            let match_name = f.ident.to_token_stream().to_string().to_lowercase();
+            let keyword = Keyword::parse(
+                &f.attrs,
+                f.ident.to_token_stream().to_string().to_lowercase(),
+            );
+            let pattern = keyword.pattern();
 
             let init = match &f.fields {
                 Fields::Named(fields_named) => {
@@ -182,13 +380,8 @@ fn handle_enum(variants: &Punctuated<Variant, Token![,]>) -> TokenStream {
                         let init_ident = Ident::new(&format!("_i_{}", name), name.span());
                         let mangled = Ident::new(&format!("_f_{}", name), name.span());
                         let ty = &f.ty;
-                        let init = f
-                            .attrs
-                            .iter()
-                            .filter(|f| f.path.to_token_stream().to_string() == "default_value")
-                            .next()
-                            .map(|f| f.tokens.clone())
-                            .unwrap_or(quote! { std::default::Default::default() });
+                        let init = default_value(&f.attrs)
+                            .unwrap_or(quote! { core::default::Default::default() });
 
                         quote! {
                             let mut #init_ident: #ty = #init;
@@ -198,29 +391,72 @@ fn handle_enum(variants: &Punctuated<Variant, Token![,]>) -> TokenStream {
 
                     quote! { #(#initializers;)* }
                 }
-                Fields::Unnamed(_fields_unnamed) => todo!(),
+                Fields::Unnamed(fields_unnamed) => {
+                    let initializers =
+                        fields_unnamed.unnamed.iter().enumerate().map(|(index, f)| {
+                            let init_ident =
+                                Ident::new(&format!("_i_{}", index), Span::mixed_site());
+                            let mangled = Ident::new(&format!("_f_{}", index), Span::mixed_site());
+                            let ty = &f.ty;
+                            let init = default_value(&f.attrs)
+                                .unwrap_or(quote! { core::default::Default::default() });
+
+                            quote! {
+                                let mut #init_ident: #ty = #init;
+                                let #mangled = &mut #init_ident;
+                            }
+                        });
+
+                    quote! { #(#initializers;)* }
+                }
                 Fields::Unit => quote! {},
             };
 
-            let parse_body = handle_fields(&f.fields, true);
+            // the struct parsers return from the enclosing function once they're done, so the
+            // payload is parsed in a closure of its own before the variant is built
+            let parse_body = match &f.fields {
+                Fields::Unit => quote! {},
+                // named fields are options, so the braces can be left out to keep the defaults
+                fields @ Fields::Named(_) => {
+                    let body = handle_fields(fields, true);
+
+                    quote! {
+                        if lexer.peek().0 == crate::cmdline::CmdlineTokenData::OpenBrace {
+                            (|| -> Result<(), crate::cmdline::CmdlineParseError<'a>> { #body })()?;
+                        }
+                    }
+                }
+                fields => {
+                    let body = handle_fields(fields, true);
+
+                    quote! {
+                        (|| -> Result<(), crate::cmdline::CmdlineParseError<'a>> { #body })()?;
+                    }
+                }
+            };
 
             let build = match &f.fields {
                 Fields::Named(fields_named) => {
                     let initializers = fields_named.named.iter().map(|f| {
                         let name = f.ident.as_ref().unwrap();
-                        let mangled = Ident::new(&format!("_f_{}", name), name.span());
-                        quote! { #name: #mangled }
+                        let init_ident = Ident::new(&format!("_i_{}", name), name.span());
+                        quote! { #name: #init_ident }
                     });
 
                     quote! { Self::#enum_name_ident { #(#initializers,)* } }
                 }
-                Fields::Unnamed(_fields_unnamed) => todo!(),
+                Fields::Unnamed(fields_unnamed) => {
+                    let initializers = (0..fields_unnamed.unnamed.len())
+                        .map(|index| Ident::new(&format!("_i_{}", index), Span::mixed_site()));
+
+                    quote! { Self::#enum_name_ident(#(#initializers,)*) }
+                }
                 Fields::Unit => quote! { Self::#enum_name_ident },
             };
 
             (
                 quote! {
-                    #match_name => {
+                    #pattern => {
                         #init
 
                         #parse_body
@@ -228,7 +464,7 @@ fn handle_enum(variants: &Punctuated<Variant, Token![,]>) -> TokenStream {
                         #build
                     }
                 },
-                match_name,
+                keyword.name,
             )
         })
         .collect();
@@ -240,9 +476,14 @@ fn handle_enum(variants: &Punctuated<Variant, Token![,]>) -> TokenStream {
         let id_tok = lexer.next()?;
         *self = match id_tok.unwrap_ident()? {
             #(#handlers)*
-            _ => return Err(id_tok.make_error(
-                crate::cmdline::CmdlineErrorCode::UnknownEnumerator(&[#(#names,)*])
-            ))
+            // an enumerator that isn't known keeps the previous value
+            _ => {
+                lexer.skip_unknown(id_tok.make_error(
+                    crate::cmdline::CmdlineErrorCode::UnknownEnumerator(&[#(#names,)*])
+                ))?;
+
+                return Ok(());
+            }
         };
 
         Ok(())
@@ -272,10 +513,10 @@ fn handle_struct(fields: &Fields) -> TokenStream {
 
             quote! { #(#entries;)* }
         }
-        Fields::Unit => todo!(),
+        Fields::Unit => unreachable!(),
     };
 
-    let inner = handle_fields(&fields, false);
+    let inner = handle_fields(fields, false);
 
     quote! {
         #unwrapper
@@ -283,23 +524,110 @@ fn handle_struct(fields: &Fields) -> TokenStream {
     }
 }
 
-#[proc_macro_derive(CmdlineParsable, attributes(default_value))]
+fn schema_struct(fields: &Fields) -> TokenStream {
+    match fields {
+        Fields::Named(fields) => {
+            let entries = fields.named.iter().map(|f| {
+                let name = f.ident.as_ref().unwrap();
+                let keyword = Keyword::parse(&f.attrs, name.to_string());
+                let (name_str, aliases) = (&keyword.name, &keyword.aliases);
+
+                quote! { writer.field(#name_str, &[#(#aliases,)*], &self.#name)?; }
+            });
+
+            quote! {
+                writer.group("{", "}", |writer| {
+                    #(#entries)*
+                    Ok(())
+                })
+            }
+        }
+        Fields::Unnamed(fields) => {
+            let entries = fields.unnamed.iter().enumerate().map(|(index, _)| {
+                let name_str = format!("#{}", index);
+                let index = Index::from(index);
+
+                quote! { writer.field(#name_str, &[], &self.#index)?; }
+            });
+
+            quote! {
+                writer.group("(", ")", |writer| {
+                    #(#entries)*
+                    Ok(())
+                })
+            }
+        }
+        Fields::Unit => unreachable!(),
+    }
+}
+
+// enumerators are listed by name only, along with the current one
+fn schema_enum(variants: &Punctuated<Variant, Token![,]>) -> TokenStream {
+    let keywords: Vec<_> = variants
+        .iter()
+        .map(|f| {
+            Keyword::parse(
+                &f.attrs,
+                f.ident.to_token_stream().to_string().to_lowercase(),
+            )
+        })
+        .collect();
+
+    let names = keywords.iter().map(|k| &k.name);
+    let current = variants.iter().zip(keywords.iter()).map(|(f, k)| {
+        let ident = &f.ident;
+        let name = &k.name;
+        quote! { Self::#ident { .. } => #name }
+    });
+
+    quote! {
+        writer.choice(&[#(#names,)*], match self {
+            #(#current,)*
+        })
+    }
+}
+
+#[proc_macro_derive(CmdlineParsable, attributes(default_value, cmdline, validate))]
 pub fn derive(input: proc_macro::TokenStream) -> proc_macro::TokenStream {
     let input = parse_macro_input!(input);
 
     let DeriveInput { ident, data, .. } = input;
 
-    let body = match data {
-        Data::Struct(DataStruct { fields, .. }) => handle_struct(&fields),
-        Data::Enum(DataEnum { variants, .. }) => handle_enum(&variants),
+    let (body, schema, default) = match data {
+        // there's nothing to give a value to, so something like `name=...` can't ever parse
+        Data::Struct(DataStruct {
+            fields: Fields::Unit,
+            ..
+        }) => {
+            return syn::Error::new_spanned(
+                &ident,
+                "unit structs can't be parsed from the cmdline",
+            )
+            .to_compile_error()
+            .into();
+        }
+        Data::Struct(DataStruct { fields, .. }) => (
+            handle_struct(&fields),
+            schema_struct(&fields),
+            default_struct(&ident, &fields),
+        ),
+        Data::Enum(DataEnum { variants, .. }) => {
+            (handle_enum(&variants), schema_enum(&variants), quote! {})
+        }
         _ => return quote! { compile_error!("unsupported data type") }.into(),
     };
 
     quote! {
+        #default
+
         impl CmdlineParsable for #ident {
             fn parse<'a>(&mut self, lexer: &mut crate::cmdline::CmdlineLexer<'a>) -> Result<(), crate::cmdline::CmdlineParseError<'a>> {
                 #body
             }
+
+            fn schema(&self, writer: &mut crate::cmdline::SchemaWriter) -> core::fmt::Result {
+                #schema
+            }
         }
     }.into()
 }
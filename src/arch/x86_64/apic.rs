@@ -0,0 +1,186 @@
+// local APIC
+//
+// x2APIC (MSR based) is used wherever the CPU has it, falling back to xAPIC (mmio) otherwise.
+// every core's xAPIC sits at the same physical address and only ever answers to the core
+// accessing it, so a single mapping is shared by all of them.
+
+use core::{hint, time::Duration};
+
+use log::info;
+use spin::Once;
+
+use super::{cpu, msr::Msr, tsc};
+use crate::mem::{ByteSize, PhysicalAddress, VolatileRegion, Wrapper, map_mmio};
+
+const REG_ID: usize = 0x20;
+const REG_TPR: usize = 0x80;
+const REG_EOI: usize = 0xb0;
+const REG_SVR: usize = 0xf0;
+const REG_ICR_LOW: usize = 0x300;
+const REG_ICR_HIGH: usize = 0x310;
+const REG_LVT_TIMER: usize = 0x320;
+const REG_TIMER_INITIAL: usize = 0x380;
+const REG_TIMER_CURRENT: usize = 0x390;
+const REG_TIMER_DIVIDE: usize = 0x3e0;
+
+const SVR_ENABLE: u32 = 1 << 8;
+
+const LVT_MASKED: u32 = 1 << 16;
+const LVT_TIMER_PERIODIC: u32 = 1 << 17;
+const TIMER_DIVIDE_16: u32 = 0b0011;
+const TIMER_CALIBRATION: Duration = Duration::from_millis(10);
+
+const ICR_DELIVERY_NMI: u32 = 0b100 << 8;
+const ICR_PENDING: u32 = 1 << 12;
+const ICR_ASSERT: u32 = 1 << 14;
+
+const APIC_BASE_MASK: u64 = 0x000f_ffff_ffff_f000;
+const APIC_BASE_ENABLE: u64 = 1 << 11;
+const APIC_BASE_X2APIC: u64 = 1 << 10;
+
+// the low nibble must be all ones on older parts
+pub const SPURIOUS_VECTOR: u8 = 0xff;
+// cross-core requests, see mp::handle_ipi
+pub const IPI_VECTOR: u8 = 0xf0;
+// serial receive, see SerialCharSink::enable_rx
+pub const SERIAL_VECTOR: u8 = 0x30;
+// the sampling profiler's tick, see profile
+pub const TIMER_VECTOR: u8 = 0x31;
+// PS/2 keyboard, see enable_keyboard
+pub const KEYBOARD_VECTOR: u8 = 0x32;
+
+enum Mode {
+    XApic(VolatileRegion),
+    X2Apic,
+}
+
+static MODE: Once<Mode> = Once::new();
+// timer ticks per second, at TIMER_DIVIDE_16; the same on every core
+static TIMER_HZ: Once<u64> = Once::new();
+
+#[derive(Clone, Copy)]
+pub enum IpiTarget {
+    Apic(u32),
+}
+
+fn mode() -> &'static Mode {
+    MODE.get().expect("x86::apic: LAPIC not initialized")
+}
+
+fn read(reg: usize) -> u32 {
+    match mode() {
+        Mode::XApic(lapic) => lapic.read::<u32>(reg),
+        Mode::X2Apic => Msr::x2apic(reg).read() as u32,
+    }
+}
+
+fn write(reg: usize, value: u32) {
+    match mode() {
+        Mode::XApic(lapic) => lapic.write::<u32>(reg, value),
+        Mode::X2Apic => unsafe { Msr::x2apic(reg).write(value as u64) },
+    }
+}
+
+// enables the LAPIC of the current core; needs the kernel page tables
+pub fn init() {
+    let mode = MODE.call_once(|| {
+        if cpu::features().x2apic {
+            info!("x86::apic::init(): using x2APIC");
+            return Mode::X2Apic;
+        }
+
+        let base = PhysicalAddress::new(Msr::APIC_BASE.read() & APIC_BASE_MASK);
+        info!("x86::apic::init(): using xAPIC at {}", base);
+
+        Mode::XApic(
+            map_mmio(base, ByteSize::new(0x1000)).expect("x86::apic::init(): failed to map LAPIC"),
+        )
+    });
+
+    // x2APIC mode has to be switched on separately on each core
+    if let Mode::X2Apic = mode {
+        unsafe {
+            Msr::APIC_BASE.write(Msr::APIC_BASE.read() | APIC_BASE_ENABLE | APIC_BASE_X2APIC)
+        };
+    }
+
+    write(REG_TPR, 0);
+    write(REG_SVR, SVR_ENABLE | SPURIOUS_VECTOR as u32);
+}
+
+pub fn is_initialized() -> bool {
+    MODE.is_completed()
+}
+
+pub fn id() -> u32 {
+    match mode() {
+        Mode::XApic(_) => read(REG_ID) >> 24,
+        Mode::X2Apic => read(REG_ID),
+    }
+}
+
+pub fn eoi() {
+    write(REG_EOI, 0);
+}
+
+fn send(target: IpiTarget, command: u32) {
+    let IpiTarget::Apic(id) = target;
+
+    match mode() {
+        Mode::XApic(lapic) => {
+            while lapic.read::<u32>(REG_ICR_LOW) & ICR_PENDING != 0 {
+                core::hint::spin_loop();
+            }
+
+            lapic.write::<u32>(REG_ICR_HIGH, id << 24);
+            lapic.write::<u32>(REG_ICR_LOW, command | ICR_ASSERT);
+        }
+        // the ICR is a single 64 bit register here, with the full 32 bit destination on top and
+        // no delivery status to wait on
+        Mode::X2Apic => unsafe {
+            Msr::x2apic(REG_ICR_LOW).write(((id as u64) << 32) | (command | ICR_ASSERT) as u64)
+        },
+    }
+}
+
+pub fn send_ipi(target: IpiTarget, vector: u8) {
+    send(target, vector as u32);
+}
+
+pub fn send_nmi(target: IpiTarget) {
+    send(target, ICR_DELIVERY_NMI);
+}
+
+// counts the timer down from the top for TIMER_CALIBRATION, as the TSC sees it
+fn timer_frequency() -> u64 {
+    *TIMER_HZ.call_once(|| {
+        write(REG_TIMER_DIVIDE, TIMER_DIVIDE_16);
+        write(REG_LVT_TIMER, LVT_MASKED);
+        write(REG_TIMER_INITIAL, u32::MAX);
+
+        let start = tsc::now();
+
+        while tsc::now() - start < TIMER_CALIBRATION {
+            hint::spin_loop();
+        }
+
+        let ticks = u32::MAX - read(REG_TIMER_CURRENT);
+        write(REG_TIMER_INITIAL, 0);
+
+        let hz = ticks as u64 * 1000 / TIMER_CALIBRATION.as_millis() as u64;
+        info!(
+            "x86::apic::timer_frequency(): LAPIC timer running at {} kHz",
+            hz / 1000
+        );
+        hz
+    })
+}
+
+// raises `vector` on the current core `hz` times a second; needs the TSC
+pub fn start_timer(vector: u8, hz: u64) {
+    let count = (timer_frequency() / hz.max(1)).clamp(1, u32::MAX as u64);
+
+    write(REG_TIMER_DIVIDE, TIMER_DIVIDE_16);
+    write(REG_LVT_TIMER, LVT_TIMER_PERIODIC | vector as u32);
+    write(REG_TIMER_INITIAL, count as u32);
+}
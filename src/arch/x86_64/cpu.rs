@@ -0,0 +1,101 @@
+// cpuid feature detection
+//
+// features are detected once, on the BSP; every core is assumed to support the same set.
+
+use log::info;
+use spin::Once;
+use x86::cpuid::CpuId;
+
+use super::msr::Msr;
+
+#[derive(Clone, Copy, Debug, Default)]
+pub struct CpuFeatures {
+    // execute disable bit in page table entries
+    pub nx: bool,
+    pub pcid: bool,
+    pub invpcid: bool,
+    // 5-level paging
+    pub la57: bool,
+    pub x2apic: bool,
+    pub pat: bool,
+    pub tsc_deadline: bool,
+    pub rdrand: bool,
+    pub smep: bool,
+    pub smap: bool,
+    // 1GiB pages
+    pub gib_pages: bool,
+}
+
+static FEATURES: Once<CpuFeatures> = Once::new();
+
+fn detect() -> CpuFeatures {
+    let cpuid = CpuId::new();
+    let mut features = CpuFeatures::default();
+
+    if let Some(info) = cpuid.get_feature_info() {
+        features.pcid = info.has_pcid();
+        features.x2apic = info.has_x2apic();
+        features.pat = info.has_pat();
+        features.tsc_deadline = info.has_tsc_deadline();
+        features.rdrand = info.has_rdrand();
+    }
+
+    if let Some(info) = cpuid.get_extended_feature_info() {
+        features.invpcid = info.has_invpcid();
+        features.la57 = info.has_la57();
+        features.smep = info.has_smep();
+        features.smap = info.has_smap();
+    }
+
+    if let Some(info) = cpuid.get_extended_processor_and_feature_identifiers() {
+        features.nx = info.has_execute_disable();
+        features.gib_pages = info.has_1gib_pages();
+    }
+
+    features
+}
+
+// the revision of the microcode the current core is running, if the vendor is known
+fn microcode_revision(vendor: &str) -> Option<u64> {
+    match vendor {
+        // the revision only gets latched into the MSR by cpuid
+        "GenuineIntel" => unsafe {
+            Msr::BIOS_SIGN_ID.write(0);
+            CpuId::new().get_feature_info();
+            Some(Msr::BIOS_SIGN_ID.read() >> 32)
+        },
+        "AuthenticAMD" => Some(Msr::BIOS_SIGN_ID.read() & 0xffff_ffff),
+        _ => None,
+    }
+}
+
+// logs the model and microcode revision of the current core
+pub fn dump_cpu_info() {
+    let cpuid = CpuId::new();
+    let vendor = cpuid.get_vendor_info();
+    let vendor = vendor.as_ref().map_or("unknown", |vendor| vendor.as_str());
+    let brand = cpuid.get_processor_brand_string();
+
+    info!(
+        "cpu: {} ({})",
+        brand
+            .as_ref()
+            .map_or("unknown", |brand| brand.as_str().trim()),
+        vendor
+    );
+
+    match microcode_revision(vendor) {
+        Some(revision) => info!("cpu: microcode revision {:#x}", revision),
+        None => info!("cpu: microcode revision unknown"),
+    }
+}
+
+// detects and logs the features of the current core
+pub fn init() {
+    info!("x86::cpu::init(): {:?}", features());
+}
+
+// also usable before init, since paging needs it early on
+pub fn features() -> &'static CpuFeatures {
+    FEATURES.call_once(detect)
+}
@@ -0,0 +1,192 @@
+// what the gdb stub needs from a core that trapped: its registers, in the layout of gdb's amd64
+// `g` packet, and single stepping through the trap flag
+
+use core::arch::asm;
+
+use super::interrupt::InterruptContext;
+
+const RFLAGS_TF: u64 = 1 << 8;
+const DR6_BS: u64 = 1 << 14;
+
+// int3
+pub const BREAKPOINT_INSN: u8 = 0xcc;
+
+// rax, rbx, rcx, rdx, rsi, rdi, rbp, rsp, r8-r15 and rip, then eflags, cs, ss, ds, es, fs and gs as
+// 32 bits; gdb takes the floating point and vector registers that follow as unavailable
+const WIDE_REGISTERS: usize = 17;
+const NARROW_REGISTERS: usize = 7;
+pub const REGISTERS_SIZE: usize = WIDE_REGISTERS * 8 + NARROW_REGISTERS * 4;
+
+// the flags gdb may change; the rest decide whether the kernel keeps running at all
+const ARITHMETIC_FLAGS: u64 = 0xcd5;
+
+// where gdb's general purpose registers are in InterruptContext::regs, which are saved from rax to
+// r15; rbp and rsp are kept elsewhere
+const SAVED_INDEX: [Option<usize>; 16] = [
+    Some(13),
+    Some(10),
+    Some(12),
+    Some(11),
+    Some(9),
+    Some(8),
+    None,
+    None,
+    Some(7),
+    Some(6),
+    Some(5),
+    Some(4),
+    Some(3),
+    Some(2),
+    Some(1),
+    Some(0),
+];
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum TrapKind {
+    Breakpoint,
+    Step,
+}
+
+pub struct Trap(*mut InterruptContext);
+
+impl Trap {
+    pub(super) unsafe fn new(context: *mut InterruptContext) -> Trap {
+        Trap(context)
+    }
+
+    fn context(&self) -> &InterruptContext {
+        unsafe { &*self.0 }
+    }
+
+    fn context_mut(&mut self) -> &mut InterruptContext {
+        unsafe { &mut *self.0 }
+    }
+
+    // by its index in the `g` packet; the interrupted code's rbp is saved by the entry stub right
+    // below the frame marker, see UnwindContext::interrupt_context
+    fn register(&self, index: usize) -> *mut u64 {
+        let context = self.0;
+
+        unsafe {
+            match index {
+                6 => (context as *mut u64).wrapping_sub(2),
+                7 => &raw mut (*context).rsp,
+                16 => &raw mut (*context).rip,
+                _ => &raw mut (*context).regs[SAVED_INDEX[index].unwrap()],
+            }
+        }
+    }
+
+    pub fn pc(&self) -> u64 {
+        self.context().rip
+    }
+
+    pub fn set_pc(&mut self, pc: u64) {
+        self.context_mut().rip = pc;
+    }
+
+    pub fn read_registers(&self, out: &mut [u8; REGISTERS_SIZE]) {
+        let context = self.context();
+
+        encode_registers(
+            &core::array::from_fn(|i| unsafe { self.register(i).read() }),
+            &[context.rflags, context.cs, context.ss],
+            out,
+        );
+    }
+
+    // the segments are left alone, and so are the flags other than ARITHMETIC_FLAGS
+    pub fn write_registers(&mut self, data: &[u8; REGISTERS_SIZE]) {
+        let (wide, narrow) = data.split_at(WIDE_REGISTERS * 8);
+
+        for (i, bytes) in wide.chunks_exact(8).enumerate() {
+            unsafe {
+                self.register(i)
+                    .write(u64::from_le_bytes(bytes.try_into().unwrap()))
+            };
+        }
+
+        let rflags = u32::from_le_bytes(narrow[..4].try_into().unwrap()) as u64;
+        let context = self.context_mut();
+        context.rflags = (context.rflags & !ARITHMETIC_FLAGS) | (rflags & ARITHMETIC_FLAGS);
+    }
+
+    // whether to trap again after one instruction, once the interrupted code resumes
+    pub fn set_single_step(&mut self, step: bool) {
+        let context = self.context_mut();
+
+        if step {
+            context.rflags |= RFLAGS_TF;
+        } else {
+            context.rflags &= !RFLAGS_TF;
+        }
+    }
+}
+
+// `narrow` starts at eflags, and the registers past it are left as zero
+fn encode_registers(wide: &[u64; WIDE_REGISTERS], narrow: &[u64], out: &mut [u8; REGISTERS_SIZE]) {
+    out.fill(0);
+
+    let (wide_out, narrow_out) = out.split_at_mut(WIDE_REGISTERS * 8);
+
+    for (value, bytes) in wide.iter().zip(wide_out.chunks_exact_mut(8)) {
+        bytes.copy_from_slice(&value.to_le_bytes());
+    }
+
+    for (value, bytes) in narrow.iter().zip(narrow_out.chunks_exact_mut(4)) {
+        bytes.copy_from_slice(&(*value as u32).to_le_bytes());
+    }
+}
+
+// the registers of the caller, in the layout of Trap::read_registers, for when there is no trap to
+// read them from; only rbp, rsp, rip and the flags, which are enough to find the stack, and the
+// rest are zero
+#[inline(always)]
+pub fn current_registers() -> [u8; REGISTERS_SIZE] {
+    let (rbp, rsp, rip, rflags, cs, ss): (u64, u64, u64, u64, u64, u64);
+
+    unsafe {
+        asm!(
+            "movq %rbp, {rbp}",
+            "movq %rsp, {rsp}",
+            "leaq 0(%rip), {rip}",
+            "pushfq",
+            "popq {rflags}",
+            "movq %cs, {cs}",
+            "movq %ss, {ss}",
+            rbp = out(reg) rbp,
+            rsp = out(reg) rsp,
+            rip = out(reg) rip,
+            rflags = out(reg) rflags,
+            cs = out(reg) cs,
+            ss = out(reg) ss,
+            options(att_syntax),
+        );
+    }
+
+    let mut wide = [0; WIDE_REGISTERS];
+    wide[6] = rbp;
+    wide[7] = rsp;
+    wide[16] = rip;
+
+    let mut out = [0; REGISTERS_SIZE];
+    encode_registers(&wide, &[rflags, cs, ss], &mut out);
+    out
+}
+
+pub fn breakpoint() {
+    unsafe { asm!("int3") };
+}
+
+// whether a debug exception came from single stepping; this clears the status, which the cpu
+// never does by itself
+pub(super) fn take_single_step() -> bool {
+    let dr6: u64;
+
+    unsafe {
+        asm!("movq %dr6, {}", out(reg) dr6, options(att_syntax));
+        asm!("movq {}, %dr6", in(reg) 0u64, options(att_syntax));
+    }
+
+    dr6 & DR6_BS != 0
+}
@@ -38,6 +38,8 @@ pub(super) struct GlobalDescriptorTable {
     cs: Descriptor,
     ds: Descriptor,
     tss: Descriptor64,
+    user_ds: Descriptor,
+    user_cs: Descriptor,
 }
 
 #[repr(C, packed)]
@@ -49,6 +51,9 @@ impl GlobalDescriptorTable {
     pub const CS: u16 = 1;
     pub const DS: u16 = 2;
     pub const TSS: u16 = 3;
+    // the TSS descriptor takes up two slots
+    pub const USER_DS: u16 = 5;
+    pub const USER_CS: u16 = 6;
 
     pub fn new(ist: &InterruptStackTable) -> GlobalDescriptorTable {
         let cs: Descriptor =
@@ -67,6 +72,22 @@ impl GlobalDescriptorTable {
                 .l()
                 .finish();
 
+        let user_cs: Descriptor =
+            DescriptorBuilder::code_descriptor(0, 0xfffff, CodeSegmentType::ExecuteRead)
+                .present()
+                .dpl(Ring::Ring3)
+                .limit_granularity_4kb()
+                .l()
+                .finish();
+
+        let user_ds: Descriptor =
+            DescriptorBuilder::data_descriptor(0, 0xfffff, DataSegmentType::ReadWrite)
+                .present()
+                .dpl(Ring::Ring3)
+                .limit_granularity_4kb()
+                .l()
+                .finish();
+
         let tss: Descriptor64 = <DescriptorBuilder as GateDescriptorBuilder<u64>>::tss_descriptor(
             &raw const *ist as u64,
             size_of::<InterruptStackTable>() as u64,
@@ -80,6 +101,8 @@ impl GlobalDescriptorTable {
             cs,
             ds,
             tss,
+            user_ds,
+            user_cs,
         }
     }
 
@@ -98,6 +121,19 @@ impl GlobalDescriptorTable {
 }
 
 impl InterruptDescriptorTable {
+    // everything else runs on ist1; these can come in while a handler is already running there, and
+    // would overwrite its frame if they reused that stack
+    const DEDICATED_STACKS: [(usize, u8); 4] = [
+        // nmi
+        (2, 2),
+        // double fault
+        (8, 3),
+        // page fault, which a handler can take on a lazily populated page
+        (14, 5),
+        // machine check
+        (18, 4),
+    ];
+
     fn pack_idt_entry(addr: u64, ist: u8, dpl: Ring) -> Descriptor64 {
         DescriptorBuilder::interrupt_descriptor(
             SegmentSelector::new(GlobalDescriptorTable::CS, Ring::Ring0),
@@ -116,7 +152,6 @@ impl InterruptDescriptorTable {
             let mut entries = [0; 256];
 
             seq!(N in 0..=255 {
-                // always switch to stack 1
                 entries[N] = irq_handler_entry::<N> as *const () as u64;
             });
 
@@ -131,6 +166,10 @@ impl InterruptDescriptorTable {
             entries[i] = Self::pack_idt_entry(jmp_targets[i], 1, Ring::Ring0);
         }
 
+        for (vector, ist) in Self::DEDICATED_STACKS {
+            entries[vector] = Self::pack_idt_entry(jmp_targets[vector], ist, Ring::Ring0);
+        }
+
         InterruptDescriptorTable { entries }
     }
 
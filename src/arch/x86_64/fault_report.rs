@@ -0,0 +1,254 @@
+// what there is to say about an exception the kernel can't handle, which is what it panics with:
+// the exception, the interrupted registers, for a page fault what the address is mapped to, and
+// the backtrace of the interrupted code rather than the handler's
+
+use core::fmt::{self, Display, Formatter};
+
+use x86::controlregs::{cr2, cr3};
+
+use super::{
+    PAGE_SMALL_SIZE,
+    interrupt::{InterruptContext, PAGE_FAULT_VECTOR},
+    paging::PageTableSet,
+    unwind::UnwindContext,
+};
+use crate::{
+    log::{DataSymbolized, StackTrace, Symbolized},
+    mem::{VirtualAddress, Wrapper},
+};
+
+const EXCEPTION_NAMES: [&str; 32] = [
+    "divide error",
+    "debug",
+    "non-maskable interrupt",
+    "breakpoint",
+    "overflow",
+    "bound range exceeded",
+    "invalid opcode",
+    "device not available",
+    "double fault",
+    "coprocessor segment overrun",
+    "invalid tss",
+    "segment not present",
+    "stack segment fault",
+    "general protection fault",
+    "page fault",
+    "reserved",
+    "x87 floating point error",
+    "alignment check",
+    "machine check",
+    "simd floating point error",
+    "virtualization exception",
+    "control protection exception",
+    "reserved",
+    "reserved",
+    "reserved",
+    "reserved",
+    "reserved",
+    "reserved",
+    "hypervisor injection exception",
+    "vmm communication exception",
+    "security exception",
+    "reserved",
+];
+
+// by name, with their index in InterruptContext::regs, which is in reverse of the push order
+const SAVED_REGISTERS: [(&str, usize); 14] = [
+    ("rax", 13),
+    ("rbx", 10),
+    ("rcx", 12),
+    ("rdx", 11),
+    ("rsi", 9),
+    ("rdi", 8),
+    ("r8", 7),
+    ("r9", 6),
+    ("r10", 5),
+    ("r11", 4),
+    ("r12", 3),
+    ("r13", 2),
+    ("r14", 1),
+    ("r15", 0),
+];
+
+// the single bit flags, by bit; IOPL is the only wider one
+const RFLAGS: [(u32, &str); 16] = [
+    (0, "CF"),
+    (2, "PF"),
+    (4, "AF"),
+    (6, "ZF"),
+    (7, "SF"),
+    (8, "TF"),
+    (9, "IF"),
+    (10, "DF"),
+    (11, "OF"),
+    (14, "NT"),
+    (16, "RF"),
+    (17, "VM"),
+    (18, "AC"),
+    (19, "VIF"),
+    (20, "VIP"),
+    (21, "ID"),
+];
+
+// the page fault error code bits, by bit, as set and as clear
+const PAGE_FAULT_ERRORS: [(u32, &str, &str); 6] = [
+    (0, "protection violation", "not present"),
+    (1, "write", "read"),
+    (2, "user", "kernel"),
+    (3, "reserved bit set", ""),
+    (4, "instruction fetch", ""),
+    (5, "protection key", ""),
+];
+
+pub(super) struct FaultReport<'a> {
+    context: &'a InterruptContext,
+    // read right away, since the panic handler may fault again before the report is written
+    cr2: u64,
+    cr3: u64,
+}
+
+impl<'a> FaultReport<'a> {
+    pub(super) fn new(context: &'a InterruptContext) -> FaultReport<'a> {
+        FaultReport {
+            context,
+            cr2: unsafe { cr2() } as u64,
+            cr3: unsafe { cr3() },
+        }
+    }
+
+    fn write_registers(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        let context = self.context;
+        let rbp = unsafe {
+            (context as *const InterruptContext as *const u64)
+                .wrapping_sub(2)
+                .read()
+        };
+
+        let registers = SAVED_REGISTERS
+            .iter()
+            .map(|&(name, index)| (name, context.regs[index]))
+            .chain([("rbp", rbp), ("rsp", context.rsp), ("rip", context.rip)]);
+
+        for (i, (name, value)) in registers.enumerate() {
+            write!(f, "  {:>6} {:#018x}", name, value)?;
+
+            if i % 3 == 2 || i == SAVED_REGISTERS.len() + 2 {
+                writeln!(f)?;
+            }
+        }
+
+        writeln!(
+            f,
+            "  {:>6} {:#06x}  {:>6} {:#06x}",
+            "cs", context.cs, "ss", context.ss
+        )?;
+
+        write!(f, "  {:>6} {:#010x} [", "rflags", context.rflags)?;
+
+        let set = RFLAGS
+            .iter()
+            .filter(|&&(bit, _)| context.rflags & (1 << bit) != 0);
+
+        for (i, &(_, name)) in set.enumerate() {
+            if i != 0 {
+                write!(f, " ")?;
+            }
+
+            write!(f, "{}", name)?;
+        }
+
+        let iopl = (context.rflags >> 12) & 3;
+
+        if iopl != 0 {
+            write!(f, " IOPL={}", iopl)?;
+        }
+
+        writeln!(f, "]")?;
+        writeln!(f, "  {:>6} {:#018x}", "cr3", self.cr3)
+    }
+
+    fn write_page_fault(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "  {:>6} {} [", "cr2", DataSymbolized(self.cr2))?;
+
+        let reasons = PAGE_FAULT_ERRORS.iter().filter_map(|&(bit, set, clear)| {
+            let reason = if self.context.err & (1 << bit) != 0 {
+                set
+            } else {
+                clear
+            };
+
+            (!reason.is_empty()).then_some(reason)
+        });
+
+        for (i, reason) in reasons.enumerate() {
+            if i != 0 {
+                write!(f, ", ")?;
+            }
+
+            write!(f, "{}", reason)?;
+        }
+
+        writeln!(f, "]")?;
+
+        let virt = VirtualAddress::new(self.cr2).frame_containing();
+        let tables = PageTableSet::current();
+
+        match tables.lookup_mapping(virt) {
+            Some((mapping, frame)) => writeln!(
+                f,
+                "  mapped to {} by a {} KiB page at {}, {}{}{}",
+                frame.address(),
+                mapping.size.value() * PAGE_SMALL_SIZE / 1024,
+                mapping.virt.address(),
+                if mapping.write {
+                    "writable"
+                } else {
+                    "read only"
+                },
+                if mapping.execute { ", executable" } else { "" },
+                if tables.cow_frame(virt).is_some() {
+                    ", copy on write"
+                } else {
+                    ""
+                },
+            ),
+            None => writeln!(f, "  not mapped"),
+        }
+    }
+}
+
+impl Display for FaultReport<'_> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        let context = self.context;
+
+        writeln!(
+            f,
+            "unhandled exception {:#x} ({}), err = {:#x}",
+            context.id,
+            EXCEPTION_NAMES
+                .get(context.id as usize)
+                .unwrap_or(&"interrupt"),
+            context.err
+        )?;
+        write!(f, "{}", Symbolized(context.rip))?;
+
+        writeln!(f, "registers:")?;
+        self.write_registers(f)?;
+
+        if context.id == PAGE_FAULT_VECTOR {
+            self.write_page_fault(f)?;
+        }
+
+        // the frames of user code can't be trusted
+        if context.cs & 0b11 == 0 {
+            writeln!(f, "interrupted code:")?;
+            write!(
+                f,
+                "{}",
+                StackTrace::new(unsafe { UnwindContext::interrupted(context) })
+            )?;
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,256 @@
+// FPU/SIMD state management
+//
+// the kernel itself is built soft-float, so extended state only ever belongs to code running on
+// top of it. loading is lazy: switch_to sets CR0.TS, and the first FPU/SIMD instruction after it
+// traps with #NM, which is when the new context's registers are loaded. saving is eager: switching
+// away from a context that has its registers loaded saves them right there, so a state never has
+// live registers on a core it isn't current on, and is free to move to another core or be freed.
+// contexts that never touch the FPU never pay for it.
+
+extern crate alloc;
+
+use alloc::alloc::{alloc_zeroed, dealloc, handle_alloc_error};
+use core::{alloc::Layout, arch::asm, cell::Cell, ptr};
+use log::info;
+use spin::Once;
+use x86::{
+    controlregs::{Cr0, Cr4, Xcr0, xcr0_write},
+    cpuid::CpuId,
+};
+
+use super::msr;
+use crate::mp::core_local;
+
+#[derive(Clone, Copy)]
+enum SaveMode {
+    Fxsave,
+    Xsave { mask: Xcr0 },
+}
+
+struct FpuConfig {
+    mode: SaveMode,
+    size: usize,
+}
+
+static CONFIG: Once<FpuConfig> = Once::new();
+
+core_local! {
+    // whose registers are currently loaded on this core
+    OWNER: Cell<*mut FpuState> = Cell::new(ptr::null_mut());
+    // whose registers should be, as of the last switch_to
+    CURRENT: Cell<*mut FpuState> = Cell::new(ptr::null_mut());
+}
+
+// the default control words, as set by fninit and the reset state of MXCSR
+const FCW_DEFAULT: u16 = 0x037f;
+const MXCSR_DEFAULT: u32 = 0x1f80;
+const MXCSR_OFFSET: usize = 24;
+
+fn config() -> &'static FpuConfig {
+    CONFIG.get().expect("x86::fpu: not initialized")
+}
+
+fn set_task_switched() {
+    unsafe { msr::update_cr0(|cr0| cr0 | Cr0::CR0_TASK_SWITCHED) };
+}
+
+fn clear_task_switched() {
+    unsafe { asm!("clts", options(nostack, preserves_flags)) };
+}
+
+// enables SSE (and AVX/XSAVE where supported) on the current core
+pub fn init() {
+    let cpuid = CpuId::new();
+    let features = cpuid
+        .get_feature_info()
+        .expect("x86::fpu::init(): cpuid leaf 1 missing");
+
+    assert!(
+        features.has_fxsave_fxstor(),
+        "x86::fpu::init(): FXSAVE not supported"
+    );
+
+    unsafe {
+        msr::update_cr0(|cr0| {
+            (cr0 - Cr0::CR0_EMULATE_COPROCESSOR)
+                | Cr0::CR0_MONITOR_COPROCESSOR
+                | Cr0::CR0_NUMERIC_ERROR
+        })
+    };
+
+    let mut cr4_flags = Cr4::CR4_ENABLE_SSE | Cr4::CR4_UNMASKED_SSE;
+
+    if features.has_xsave() {
+        cr4_flags |= Cr4::CR4_ENABLE_OS_XSAVE;
+    }
+
+    unsafe { msr::update_cr4(|cr4| cr4 | cr4_flags) };
+
+    let mode = if features.has_xsave() {
+        let mut mask = Xcr0::XCR0_FPU_MMX_STATE | Xcr0::XCR0_SSE_STATE;
+
+        if features.has_avx() {
+            mask |= Xcr0::XCR0_AVX_STATE;
+        }
+
+        unsafe { xcr0_write(mask) };
+        SaveMode::Xsave { mask }
+    } else {
+        SaveMode::Fxsave
+    };
+
+    CONFIG.call_once(|| {
+        let size = match mode {
+            // only valid now that XCR0 is set up
+            SaveMode::Xsave { .. } => cpuid.get_extended_state_info().map_or(4096, |info| {
+                info.xsave_area_size_enabled_features() as usize
+            }),
+            SaveMode::Fxsave => 512,
+        };
+
+        info!(
+            "x86::fpu::init(): using {}, {} byte state areas",
+            match mode {
+                SaveMode::Xsave { .. } => "xsave",
+                SaveMode::Fxsave => "fxsave",
+            },
+            size
+        );
+
+        FpuConfig { mode, size }
+    });
+
+    // nothing owns the FPU yet, so the first use has to go through the trap
+    set_task_switched();
+}
+
+/// saved FPU/SIMD registers of one execution context
+/// a state must be switched away from on one core before it can be switched to on another, or
+/// dropped anywhere but the core it is current on.
+pub struct FpuState {
+    area: *mut u8,
+}
+
+impl FpuState {
+    fn layout() -> Layout {
+        Layout::from_size_align(config().size, 64).unwrap()
+    }
+
+    // a state that loads as the default register contents
+    pub fn new() -> FpuState {
+        let layout = Self::layout();
+        let area = unsafe { alloc_zeroed(layout) };
+
+        if area.is_null() {
+            handle_alloc_error(layout);
+        }
+
+        // an all-zero xsave header means "initial state" for everything but the legacy control
+        // words, which are always taken from the area
+        unsafe {
+            area.cast::<u16>().write(FCW_DEFAULT);
+            area.add(MXCSR_OFFSET).cast::<u32>().write(MXCSR_DEFAULT);
+        }
+
+        FpuState { area }
+    }
+
+    unsafe fn save(&mut self) {
+        match config().mode {
+            SaveMode::Fxsave => unsafe {
+                asm!("fxsave64 [{}]", in(reg) self.area, options(nostack, preserves_flags))
+            },
+            SaveMode::Xsave { mask } => unsafe {
+                asm!(
+                    "xsave64 [{}]",
+                    in(reg) self.area,
+                    in("eax") mask.bits() as u32,
+                    in("edx") (mask.bits() >> 32) as u32,
+                    options(nostack, preserves_flags)
+                )
+            },
+        }
+    }
+
+    unsafe fn restore(&self) {
+        match config().mode {
+            SaveMode::Fxsave => unsafe {
+                asm!("fxrstor64 [{}]", in(reg) self.area, options(nostack, preserves_flags))
+            },
+            SaveMode::Xsave { mask } => unsafe {
+                asm!(
+                    "xrstor64 [{}]",
+                    in(reg) self.area,
+                    in("eax") mask.bits() as u32,
+                    in("edx") (mask.bits() >> 32) as u32,
+                    options(nostack, preserves_flags)
+                )
+            },
+        }
+    }
+}
+
+impl Default for FpuState {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Drop for FpuState {
+    fn drop(&mut self) {
+        let this = self as *mut FpuState;
+
+        // the registers still hold our state, which nobody will ask for anymore; a state that
+        // was switched away from has been saved, so no other core can still have it loaded
+        if OWNER.get() == this {
+            OWNER.set(ptr::null_mut());
+        }
+
+        if CURRENT.get() == this {
+            CURRENT.set(ptr::null_mut());
+        }
+
+        unsafe { dealloc(self.area, Self::layout()) };
+    }
+}
+
+// makes `state` the one the next FPU/SIMD instruction on this core operates on; called with
+// interrupts disabled when switching between execution contexts
+// safety: `state` must stay alive (and not move) until it is switched away from
+pub unsafe fn switch_to(state: Option<&mut FpuState>) {
+    let next = state.map_or(ptr::null_mut(), |state| state as *mut FpuState);
+    let owner = OWNER.get();
+
+    // save whoever is loaded now, while it is still known to be on this core
+    if !owner.is_null() && owner != next {
+        clear_task_switched();
+        unsafe { (*owner).save() };
+        OWNER.set(ptr::null_mut());
+    }
+
+    CURRENT.set(next);
+    set_task_switched();
+}
+
+// #NM: the current context touched the FPU for the first time since it was switched to
+pub(super) fn handle_device_not_available() {
+    clear_task_switched();
+
+    let owner = OWNER.get();
+    let current = CURRENT.get();
+
+    assert!(
+        !current.is_null(),
+        "x86::fpu: FPU used outside of an FPU context"
+    );
+
+    if owner == current {
+        return;
+    }
+
+    // switch_to saves the previous owner
+    debug_assert!(owner.is_null(), "x86::fpu: stale FPU owner");
+
+    unsafe { (*current).restore() };
+    OWNER.set(current);
+}
@@ -1,19 +1,51 @@
-use core::arch::naked_asm;
-
-use log::info;
+use core::{arch::naked_asm, time::Duration};
+
+use super::{
+    apic,
+    debug::{self, Trap, TrapKind},
+    fault_report::FaultReport,
+    unwind::{INTERRUPT_FRAME_MARKER, UnwindContext},
+};
+use crate::{
+    gdb,
+    log::log_rate_limited,
+    mem::{self, PageFault, VirtualAddress},
+    mp::{self, per_cpu_counter},
+    profile,
+    sync::irq_arc,
+};
+use log::Level;
+use x86::controlregs::cr2;
+
+const DEBUG_VECTOR: u64 = 1;
+const NMI_VECTOR: u64 = 2;
+const BREAKPOINT_VECTOR: u64 = 3;
+const DEVICE_NOT_AVAILABLE_VECTOR: u64 = 7;
+pub(super) const PAGE_FAULT_VECTOR: u64 = 14;
+
+const PF_PRESENT: u64 = 1 << 0;
+const PF_WRITE: u64 = 1 << 1;
+const PF_USER: u64 = 1 << 2;
+
+per_cpu_counter!(INTERRUPTS);
+
+// interrupts and exceptions taken so far, across all cores
+pub fn interrupt_count() -> u64 {
+    INTERRUPTS.sum()
+}
 
 #[repr(C)]
-struct InterruptContext {
-    regs: [u64; 14],
+pub(super) struct InterruptContext {
+    pub(super) regs: [u64; 14],
 
-    id: u64,
-    err: u64,
+    pub(super) id: u64,
+    pub(super) err: u64,
 
-    rip: u64,
-    cs: u64,
-    rflags: u64,
-    rsp: u64,
-    ss: u64,
+    pub(super) rip: u64,
+    pub(super) cs: u64,
+    pub(super) rflags: u64,
+    pub(super) rsp: u64,
+    pub(super) ss: u64,
 }
 
 const fn error_code_offset(int_no: u8) -> u64 {
@@ -45,6 +77,13 @@ pub unsafe extern "C" fn irq_handler_entry<const I: u8>() -> ! {
 #[unsafe(naked)]
 pub unsafe extern "C" fn irq_handler_t0() -> ! {
     naked_asm!(
+        // coming from user mode, GS_BASE still holds the user value; the saved cs sits right
+        // above the vector and error code
+        "testb $3, 24(%rsp)",
+        "jz 1f",
+        "swapgs",
+        "1:",
+
         "pushq %rax",
         "pushq %rcx",
         "pushq %rdx",
@@ -63,8 +102,10 @@ pub unsafe extern "C" fn irq_handler_t0() -> ! {
         // point to top of stack
         "movq %rsp, %rdi",
 
-        // simulate the call frame
-        "pushq $0",
+        // simulate the call frame; the marker in place of the return address lets the unwinder
+        // find the interrupted context right above it
+        "movabsq ${marker}, %rax",
+        "pushq %rax",
         "pushq %rbp",
         "movq %rsp, %rbp",
 
@@ -93,15 +134,112 @@ pub unsafe extern "C" fn irq_handler_t0() -> ! {
         "popq %rcx",
         "popq %rax",
 
+        // TODO: an NMI between this swapgs and the iretq runs with the user GS_BASE
+        "testb $3, 24(%rsp)",
+        "jz 2f",
+        "swapgs",
+        "2:",
+
         "addq $16, %rsp",
         "iretq",
         options(att_syntax),
-        sym irq_handler_t1
+        sym irq_handler_t1,
+        marker = const INTERRUPT_FRAME_MARKER,
     );
 }
 
+fn handle_page_fault(context: &InterruptContext) {
+    let fault = PageFault {
+        addr: VirtualAddress::new(unsafe { cr2() } as u64),
+        present: context.err & PF_PRESENT != 0,
+        write: context.err & PF_WRITE != 0,
+        user: context.err & PF_USER != 0,
+    };
+
+    if !mem::handle_page_fault(&fault) {
+        panic!("{}", FaultReport::new(context));
+    }
+}
+
+// the end of every device interrupt. these only come in while interrupts are enabled, so the code
+// they interrupted holds no IntMutex, and deferred IrqArc drops can be destroyed here just as in
+// thread context; a core that never goes idle would otherwise never get to them
+fn end_of_interrupt() {
+    apic::eoi();
+    irq_arc::reap();
+}
+
 unsafe extern "C" fn irq_handler_t1(addr: *mut InterruptContext) {
     let context = unsafe { &*addr };
-    info!("hi: {} #{}", context.err, context.id);
-    panic!();
+
+    INTERRUPTS.inc();
+
+    if context.id == NMI_VECTOR {
+        super::mp::handle_nmi(addr);
+        return;
+    }
+
+    if context.id == DEVICE_NOT_AVAILABLE_VECTOR {
+        super::fpu::handle_device_not_available();
+        return;
+    }
+
+    if context.id == PAGE_FAULT_VECTOR {
+        handle_page_fault(context);
+        return;
+    }
+
+    // without the gdb stub, these are as unexpected as any other exception
+    if context.id == BREAKPOINT_VECTOR
+        && gdb::handle_trap(unsafe { &mut Trap::new(addr) }, TrapKind::Breakpoint)
+    {
+        return;
+    }
+
+    if context.id == DEBUG_VECTOR
+        && debug::take_single_step()
+        && gdb::handle_trap(unsafe { &mut Trap::new(addr) }, TrapKind::Step)
+    {
+        return;
+    }
+
+    // spurious interrupts must not be acknowledged
+    if context.id == apic::SPURIOUS_VECTOR as u64 {
+        log_rate_limited!(
+            Duration::from_secs(1),
+            Level::Debug,
+            "x86::irq_handler_t1(): spurious interrupt"
+        );
+        return;
+    }
+
+    if context.id == apic::IPI_VECTOR as u64 {
+        mp::handle_ipi();
+        end_of_interrupt();
+        return;
+    }
+
+    if context.id == apic::SERIAL_VECTOR as u64 {
+        super::serial::handle_rx_interrupt();
+        end_of_interrupt();
+        return;
+    }
+
+    if context.id == apic::KEYBOARD_VECTOR as u64 {
+        super::keyboard::handle_keyboard_interrupt();
+        end_of_interrupt();
+        return;
+    }
+
+    // user frames can't be trusted to unwind through, so only kernel code is sampled
+    if context.id == apic::TIMER_VECTOR as u64 {
+        if context.cs & 0b11 == 0 {
+            profile::sample(context.rip, unsafe { UnwindContext::interrupted(context) });
+        }
+
+        end_of_interrupt();
+        return;
+    }
+
+    panic!("{}", FaultReport::new(context));
 }
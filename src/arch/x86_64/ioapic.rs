@@ -0,0 +1,111 @@
+// I/O APIC, just enough to route legacy ISA interrupts to a core
+//
+// the I/O APICs, and which of their inputs each ISA IRQ is wired to, come from the MADT.
+
+extern crate alloc;
+
+use alloc::vec::Vec;
+use spin::Once;
+
+use crate::{
+    firmware::acpi,
+    mem::{ByteSize, VolatileRegion, map_mmio},
+    sync::IntMutex,
+};
+
+const REG_SELECT: usize = 0x00;
+const REG_WINDOW: usize = 0x10;
+
+const REG_VERSION: u32 = 0x01;
+const REG_REDIRECTION: u32 = 0x10;
+
+const REDIRECTION_ACTIVE_LOW: u32 = 1 << 13;
+const REDIRECTION_LEVEL: u32 = 1 << 15;
+
+// MPS INTI flags, from an interrupt source override; anything else conforms to the bus, which for
+// ISA is active high and edge triggered
+const INTI_POLARITY_MASK: u16 = 0b11;
+const INTI_ACTIVE_LOW: u16 = 0b11;
+const INTI_TRIGGER_MASK: u16 = 0b11 << 2;
+const INTI_LEVEL: u16 = 0b11 << 2;
+
+struct IoApic {
+    // registers are reached through a select/window pair, so every access has to be serialized
+    region: IntMutex<VolatileRegion>,
+    gsi_base: u32,
+    inputs: u32,
+}
+
+static IOAPICS: Once<Vec<IoApic>> = Once::new();
+
+fn read(region: &VolatileRegion, reg: u32) -> u32 {
+    region.write::<u32>(REG_SELECT, reg);
+    region.read::<u32>(REG_WINDOW)
+}
+
+fn write(region: &VolatileRegion, reg: u32, value: u32) {
+    region.write::<u32>(REG_SELECT, reg);
+    region.write::<u32>(REG_WINDOW, value);
+}
+
+fn ioapics() -> &'static [IoApic] {
+    IOAPICS.call_once(|| {
+        let Some(madt) = acpi::madt() else {
+            return Vec::new();
+        };
+
+        madt.io_apics
+            .iter()
+            .map(|ioapic| {
+                let region = map_mmio(ioapic.address, ByteSize::new(0x20))
+                    .expect("x86::ioapic: failed to map I/O APIC");
+                // the index of the last redirection entry
+                let inputs = ((read(&region, REG_VERSION) >> 16) & 0xff) + 1;
+
+                IoApic {
+                    region: IntMutex::new(region),
+                    gsi_base: ioapic.gsi_base,
+                    inputs,
+                }
+            })
+            .collect()
+    })
+}
+
+// delivers ISA `irq` as `vector` to the LAPIC with id `apic_id`; fails if there is no MADT, if no
+// I/O APIC has the input the IRQ is wired to, or if `apic_id` doesn't fit in the 8 bits of a
+// physical destination
+pub fn route_isa_irq(irq: u8, vector: u8, apic_id: u32) -> Result<(), ()> {
+    let apic_id = u8::try_from(apic_id).map_err(|_| ())?;
+
+    let (gsi, flags) = acpi::madt()
+        .ok_or(())?
+        .overrides
+        .iter()
+        .find(|o| o.irq == irq)
+        .map_or((irq as u32, 0), |o| (o.gsi, o.flags));
+
+    let ioapic = ioapics()
+        .iter()
+        .find(|ioapic| gsi.wrapping_sub(ioapic.gsi_base) < ioapic.inputs)
+        .ok_or(())?;
+
+    // fixed delivery to a physical destination, unmasked
+    let mut entry = vector as u32;
+
+    if flags & INTI_POLARITY_MASK == INTI_ACTIVE_LOW {
+        entry |= REDIRECTION_ACTIVE_LOW;
+    }
+
+    if flags & INTI_TRIGGER_MASK == INTI_LEVEL {
+        entry |= REDIRECTION_LEVEL;
+    }
+
+    let reg = REG_REDIRECTION + (gsi - ioapic.gsi_base) * 2;
+    let region = ioapic.region.lock();
+
+    write(&region, reg + 1, (apic_id as u32) << 24);
+    write(&region, reg, entry);
+
+    Ok(())
+}
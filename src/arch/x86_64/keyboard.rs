@@ -0,0 +1,160 @@
+// PS/2 keyboard input, for the console
+//
+// the controller is left as the firmware set it up, which on a PC means scancode set 1, translated
+// from whatever the keyboard speaks. keys are turned into what a terminal would send for them, so
+// that serial and keyboard input go through the same line discipline; see console::keys.
+
+use arrayvec::ArrayVec;
+use core::mem;
+use x86::io::inb;
+
+use super::{IrqState, apic, ioapic, irq_disable};
+use crate::{console, sync::IntMutex};
+
+const KEYBOARD_IRQ: u8 = 1;
+
+const DATA_PORT: u16 = 0x60;
+const STATUS_PORT: u16 = 0x64;
+
+const STATUS_OUTPUT_FULL: u8 = 1 << 0;
+// the byte waiting is from the mouse
+const STATUS_AUX: u8 = 1 << 5;
+
+const EXTENDED: u8 = 0xe0;
+const RELEASED: u8 = 0x80;
+
+const CTRL: u8 = 0x1d;
+const ALT: u8 = 0x38;
+const LEFT_SHIFT: u8 = 0x2a;
+const RIGHT_SHIFT: u8 = 0x36;
+const F1: u8 = 0x3b;
+const F10: u8 = 0x44;
+
+// the US layout, by make code, up to the space bar; zero for keys that don't type anything
+const NORMAL: &[u8; 58] =
+    b"\0\x1b1234567890-=\x7f\tqwertyuiop[]\r\0asdfghjkl;'`\0\\zxcvbnm,./\0*\0 ";
+const SHIFTED: &[u8; 58] =
+    b"\0\x1b!@#$%^&*()_+\x7f\tQWERTYUIOP{}\r\0ASDFGHJKL:\"~\0|ZXCVBNM<>?\0*\0 ";
+
+struct Keyboard {
+    // the last byte was the 0xe0 prefix
+    extended: bool,
+    shift: bool,
+    ctrl: bool,
+    alt: bool,
+}
+
+static KEYBOARD: IntMutex<Keyboard> = IntMutex::new(Keyboard {
+    extended: false,
+    shift: false,
+    ctrl: false,
+    alt: false,
+});
+
+// the escape sequence a terminal sends for an extended key
+fn extended_key(code: u8) -> Option<&'static [u8]> {
+    match code {
+        0x48 => Some(b"\x1b[A"),
+        0x50 => Some(b"\x1b[B"),
+        0x4d => Some(b"\x1b[C"),
+        0x4b => Some(b"\x1b[D"),
+        0x47 => Some(b"\x1b[H"),
+        0x4f => Some(b"\x1b[F"),
+        0x53 => Some(b"\x1b[3~"),
+        // keypad enter and slash
+        0x1c => Some(b"\r"),
+        0x35 => Some(b"/"),
+        _ => None,
+    }
+}
+
+impl Keyboard {
+    // what the key that `scancode` completes types, if anything
+    fn feed(&mut self, scancode: u8) -> ArrayVec<u8, 4> {
+        let mut out = ArrayVec::new();
+
+        if scancode == EXTENDED {
+            self.extended = true;
+            return out;
+        }
+
+        let extended = mem::take(&mut self.extended);
+        let pressed = scancode & RELEASED == 0;
+        let code = scancode & !RELEASED;
+
+        match (extended, code) {
+            (_, CTRL) => self.ctrl = pressed,
+            (_, ALT) => self.alt = pressed,
+            (false, LEFT_SHIFT | RIGHT_SHIFT) => self.shift = pressed,
+            _ if !pressed => {}
+            // alt+F<n> switches to console n - 1 right away, like on Linux, even with a line
+            // half typed
+            (false, F1..=F10) if self.alt => {
+                let _ = console::switch_to((code - F1) as usize);
+            }
+            (true, code) => out.extend(extended_key(code).unwrap_or_default().iter().copied()),
+            (false, code) => {
+                let table = if self.shift { SHIFTED } else { NORMAL };
+
+                let Some(&ch) = table.get(code as usize).filter(|&&ch| ch != 0) else {
+                    return out;
+                };
+
+                // alt+<key> is escape followed by the key, and ctrl+<letter> its control code
+                if self.alt {
+                    out.push(0x1b);
+                }
+
+                out.push(match ch {
+                    b'a'..=b'z' | b'A'..=b'Z' if self.ctrl => ch & 0x1f,
+                    _ => ch,
+                });
+            }
+        }
+
+        out
+    }
+}
+
+// handles KEYBOARD_VECTOR
+pub(super) fn handle_keyboard_interrupt() {
+    let mut keyboard = KEYBOARD.lock();
+
+    loop {
+        let status = unsafe { inb(STATUS_PORT) };
+
+        if status & STATUS_OUTPUT_FULL == 0 {
+            break;
+        }
+
+        let byte = unsafe { inb(DATA_PORT) };
+
+        if status & STATUS_AUX != 0 {
+            continue;
+        }
+
+        for ch in keyboard.feed(byte) {
+            console::input(ch, None);
+        }
+    }
+}
+
+// routes keyboard interrupts to the current core, which needs its LAPIC and IDT set up, and feeds
+// the keys to the console; fails if there is no PS/2 controller, or if the IRQ can't be routed
+pub fn enable_keyboard() -> Result<(), ()> {
+    // nothing drives the bus without a controller
+    if unsafe { inb(STATUS_PORT) } == 0xff {
+        return Err(());
+    }
+
+    ioapic::route_isa_irq(KEYBOARD_IRQ, apic::KEYBOARD_VECTOR, apic::id())?;
+
+    // the interrupt is edge triggered, so anything that arrived before it was routed has to be
+    // read out by hand, or the line stays raised for good
+    let state = IrqState::save();
+    irq_disable();
+    handle_keyboard_interrupt();
+    state.restore();
+
+    Ok(())
+}
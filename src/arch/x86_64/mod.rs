@@ -1,9 +1,22 @@
+pub mod apic;
+pub mod cpu;
+pub mod debug;
+pub mod fpu;
 pub mod paging;
+pub mod pat;
+pub mod pcid;
+pub mod tsc;
+pub mod user;
 
 mod dt;
+mod fault_report;
 mod interrupt;
+mod ioapic;
+mod keyboard;
 pub mod mp;
+pub mod msr;
 mod serial;
+mod topology;
 mod unwind;
 
 extern crate alloc;
@@ -21,6 +34,8 @@ use x86::bits64::paging::PAddr;
 use x86::bits64::paging::VAddr;
 use x86::bits64::rflags::{self, RFlags};
 
+pub use interrupt::interrupt_count;
+pub use keyboard::enable_keyboard;
 pub use serial::*;
 pub use unwind::*;
 
@@ -34,6 +49,18 @@ pub fn halt() -> ! {
     }
 }
 
+// qemu's isa-debug-exit device, as `buildtool test` attaches it
+#[cfg(feature = "ktest")]
+const QEMU_EXIT_PORT: u16 = 0xf4;
+
+// ends the run when under qemu with isa-debug-exit, which exits with `(code << 1) | 1`; halts
+// anywhere else
+#[cfg(feature = "ktest")]
+pub fn qemu_exit(code: u32) -> ! {
+    unsafe { x86::io::outl(QEMU_EXIT_PORT, code) };
+    halt()
+}
+
 #[unsafe(naked)]
 #[unsafe(no_mangle)]
 pub unsafe extern "C" fn memcpy(dest: *mut u8, src: *const u8, len: usize) -> *mut u8 {
@@ -108,6 +135,14 @@ pub fn irq_disable() {
     disable_interrupts();
 }
 
+// sleeps until the next interrupt, which is handled before this returns; must be called with
+// interrupts disabled, so that a wakeup can't slip in between checking for work and halting
+#[inline(always)]
+pub fn wait_for_interrupt() {
+    // sti only takes effect after the next instruction, so nothing can be delivered before hlt
+    unsafe { asm!("sti", "hlt", "cli") };
+}
+
 pub const HIGHER_HALF_VIRTUAL_ADDRESS_BASE_PML4: VirtualAddress =
     VirtualAddress::new(0xffff800000000000u64);
 pub const HIGHER_HALF_VIRTUAL_ADDRESS_BASE_PML5: VirtualAddress =
@@ -1,23 +1,40 @@
 extern crate alloc;
 
-use super::{dt::InterruptDescriptorTable, paging::PageTableSet};
+use super::{
+    apic::{self, IpiTarget},
+    debug::{REGISTERS_SIZE, Trap},
+    dt::InterruptDescriptorTable,
+    halt,
+    interrupt::InterruptContext,
+    msr::Msr,
+    paging::PageTableSet,
+    tsc,
+};
 use crate::{
     arch::{
         paging::PageFlags,
         x86_64::{GlobalDescriptorTable, InterruptStackTable},
     },
+    cmdline::get_cmdline,
     ksmp,
-    mem::{AddressRange, LOCAL_PAGE_TABLE, PMM, PageSize, VirtualAddress, Wrapper, vpa},
-    mp::{CORE_ID, CoreId, core_local, get_cpu_local_offset, init_cpu_local_table},
+    mem::{AddressRange, LOCAL_PAGE_TABLE, PMM, PageSize, VirtualAddress, Wrapper, stack, vpa},
+    mp::{
+        CORE_ID, CoreId, MP_STATE, MpState, bringup, core_local, get_cpu_local_offset,
+        init_cpu_local_table, ipi, park, topology,
+    },
+    sync::rcu,
 };
+use alloc::vec::Vec;
 use core::{
     arch::{asm, naked_asm},
-    sync::atomic::Ordering,
+    cell::UnsafeCell,
+    hint,
+    sync::atomic::{AtomicBool, AtomicUsize, Ordering},
+    time::Duration,
 };
 use limine::{mp::Cpu, request::MpRequest};
-use log::info;
+use log::{info, warn};
 use spin::Once;
-use x86::msr::{IA32_GS_BASE, wrmsr};
 
 #[used]
 #[unsafe(link_section = ".limine_requests")]
@@ -37,20 +54,190 @@ pub fn get_cpu_local_pointer() -> VirtualAddress {
     VirtualAddress::new(val)
 }
 
+// adds `value` to the u64 core local `offset` bytes into the current core's block; the add itself
+// is a single instruction, so an interrupt on this core can't lose an update to it
+pub fn cpu_local_add(offset: u64, value: u64) {
+    unsafe {
+        asm!(
+            "movq %gs:0, {block}",
+            "addq {value}, ({block}, {offset})",
+            block = out(reg) _,
+            offset = in(reg) offset,
+            value = in(reg) value,
+            options(nostack, att_syntax),
+        );
+    }
+}
+
+// whether core locals can be accessed on the current core yet; the BSP sets them up before leaving
+// KInit and the APs first thing in initialize_core, so this doesn't need to read GS_BASE
+pub fn cpu_local_ready() -> bool {
+    MP_STATE.load(Ordering::Relaxed) != MpState::KInit
+}
+
+// the kernel always runs with the core local block in GS_BASE; KERNEL_GS_BASE holds the user
+// value while in the kernel, and the two are exchanged with swapgs at every ring transition
 fn init_cpu_local_ptr(core_id: CoreId) {
     let ptr = get_cpu_local_offset(core_id).value();
-    unsafe { wrmsr(IA32_GS_BASE, ptr) };
+
+    unsafe {
+        Msr::GS_BASE.write(ptr);
+        Msr::KERNEL_GS_BASE.write(0);
+    }
 }
 
 static BOOTSTRAP_PT: Once<PageTableSet> = Once::new();
 
+// LAPIC id of every core, indexed by core id
+static LAPIC_IDS: Once<Vec<u32>> = Once::new();
+
+// raises an IPI on `core`, which ends up in mp::handle_ipi there
+pub fn send_ipi(core: CoreId) {
+    let lapic_id = LAPIC_IDS
+        .get()
+        .expect("x86::send_ipi(): MP not initialized")[core.0];
+    apic::send_ipi(IpiTarget::Apic(lapic_id), apic::IPI_VECTOR);
+}
+
+// whether each core has been sent to initialize_core, indexed by core id
+static STARTED: Once<Vec<AtomicBool>> = Once::new();
+
+fn cpu(core: CoreId) -> Option<&'static Cpu> {
+    let lapic_id = *LAPIC_IDS.get()?.get(core.0)?;
+
+    MP_REQUEST
+        .get_response()?
+        .cpus()
+        .iter()
+        .copied()
+        .find(|cpu| cpu.lapic_id == lapic_id)
+}
+
+// sends a core into initialize_core. limine keeps APs spinning on their goto address until then,
+// which is why cores held back at boot can be started at any later point, as long as bootloader
+// memory isn't reclaimed
+pub fn start_core(core: CoreId) -> Result<(), ()> {
+    let started = STARTED.get().ok_or(())?.get(core.0).ok_or(())?;
+
+    if started.swap(true, Ordering::SeqCst) {
+        return Err(());
+    }
+
+    cpu(core).ok_or(())?.goto_address.write(initialize_core);
+    Ok(())
+}
+
+pub fn started_cores() -> usize {
+    STARTED.get().map_or(1, |started| {
+        started
+            .iter()
+            .filter(|started| started.load(Ordering::SeqCst))
+            .count()
+    })
+}
+
+// set per core once its IDT is loaded; an NMI sent any earlier would triple fault it
+static NMI_READY: Once<Vec<AtomicBool>> = Once::new();
+
+static HALT_REQUESTED: AtomicBool = AtomicBool::new(false);
+static HALTED_CORES: AtomicUsize = AtomicUsize::new(0);
+
+// where each core was when halt_other_cores stopped it, laid out as by Trap::read_registers
+struct HaltedRegisters {
+    saved: AtomicBool,
+    regs: UnsafeCell<[u8; REGISTERS_SIZE]>,
+}
+
+// only written by the core itself, before `saved` is set, and only read after
+unsafe impl Sync for HaltedRegisters {}
+
+// indexed by core id
+static HALTED_REGISTERS: Once<Vec<HaltedRegisters>> = Once::new();
+
+// stops every other core with an NMI, waiting up to `timeout` for them to acknowledge. only the
+// first caller gets to do this; everyone after that gets Err and should halt themselves.
+pub fn halt_other_cores(timeout: Duration) -> Result<(), ()> {
+    if HALT_REQUESTED.swap(true, Ordering::SeqCst) {
+        return Err(());
+    }
+
+    // nothing to stop before the APs are started
+    let (Some(lapic_ids), Some(ready)) = (LAPIC_IDS.get(), NMI_READY.get()) else {
+        return Ok(());
+    };
+
+    if !apic::is_initialized() {
+        return Ok(());
+    }
+
+    let this = apic::id();
+    let mut sent = 0;
+
+    for (&lapic_id, ready) in lapic_ids.iter().zip(ready) {
+        if lapic_id != this && ready.load(Ordering::SeqCst) {
+            apic::send_nmi(IpiTarget::Apic(lapic_id));
+            sent += 1;
+        }
+    }
+
+    let deadline = tsc::now() + timeout;
+
+    while HALTED_CORES.load(Ordering::SeqCst) < sent && tsc::now() < deadline {
+        hint::spin_loop();
+    }
+
+    Ok(())
+}
+
+// NMIs are only ever sent by halt_other_cores, but may also come from the platform
+pub(super) fn handle_nmi(context: *mut InterruptContext) {
+    if HALT_REQUESTED.load(Ordering::SeqCst) {
+        // going by the LAPIC id, since the NMI may have come in before a swapgs
+        let this = apic::id();
+
+        if let (Some(lapic_ids), Some(halted)) = (LAPIC_IDS.get(), HALTED_REGISTERS.get())
+            && let Some(core) = lapic_ids.iter().position(|&id| id == this)
+        {
+            let slot = &halted[core];
+            unsafe { Trap::new(context).read_registers(&mut *slot.regs.get()) };
+            slot.saved.store(true, Ordering::Release);
+        }
+
+        HALTED_CORES.fetch_add(1, Ordering::SeqCst);
+        halt();
+    }
+}
+
+// the registers of a core that halt_other_cores stopped, if it got to save them
+pub fn halted_registers(core: CoreId) -> Option<[u8; REGISTERS_SIZE]> {
+    let slot = HALTED_REGISTERS.get()?.get(core.0)?;
+
+    slot.saved
+        .load(Ordering::Acquire)
+        .then(|| unsafe { *slot.regs.get() })
+}
+
 pub fn initialize_mp(tables: &PageTableSet) -> ! {
     let response = MP_REQUEST.get_response().expect("mp response not received");
 
     let n_cores = response.cpus().len();
-    info!("x86::initialize_mp(): bootstrapping {} cores", n_cores);
+    info!(
+        target: "init_smp",
+        "x86::initialize_mp(): bootstrapping {} cores",
+        n_cores
+    );
 
     init_cpu_local_table(tables, n_cores);
+    rcu::init(n_cores);
+    topology::init(n_cores);
+    park::init(n_cores);
+    bringup::init(n_cores);
+    ipi::init(n_cores);
+
+    // core locals are usable on the BSP from here on, which anything that checks MP_STATE relies
+    // on; the APs only look at theirs once they have set them up in initialize_core
+    init_cpu_local_ptr(CoreId(0));
+    MP_STATE.store(MpState::MPInit, Ordering::SeqCst);
 
     let mut core_id: u64 = 1;
     let bsp_id = response.bsp_lapic_id();
@@ -61,16 +248,57 @@ pub fn initialize_mp(tables: &PageTableSet) -> ! {
 
     BOOTSTRAP_PT.call_once(|| *tables);
 
+    // core ids are handed out in the same order as below
+    LAPIC_IDS.call_once(|| {
+        core::iter::once(bsp_id)
+            .chain(
+                response
+                    .cpus()
+                    .iter()
+                    .map(|cpu| cpu.lapic_id)
+                    .filter(|&id| id != bsp_id),
+            )
+            .collect()
+    });
+
+    NMI_READY.call_once(|| (0..n_cores).map(|_| AtomicBool::new(false)).collect());
+    HALTED_REGISTERS.call_once(|| {
+        (0..n_cores)
+            .map(|_| HaltedRegisters {
+                saved: AtomicBool::new(false),
+                regs: UnsafeCell::new([0; REGISTERS_SIZE]),
+            })
+            .collect()
+    });
+
     for cpu in response.cpus() {
         if bsp_id != cpu.lapic_id {
             cpu.extra.store(core_id, Ordering::SeqCst);
             core_id += 1;
-            cpu.goto_address.write(initialize_core);
         } else {
             core_self = Some(cpu);
         }
     }
 
+    STARTED.call_once(|| (0..n_cores).map(|i| AtomicBool::new(i == 0)).collect());
+
+    let boot_cores = match get_cmdline().mp.max_cores {
+        Some(max) => (max as usize).min(n_cores),
+        None => n_cores,
+    };
+
+    if boot_cores < n_cores {
+        info!(
+            target: "init_smp",
+            "x86::initialize_mp(): holding back {} cores",
+            n_cores - boot_cores
+        );
+    }
+
+    for id in 1..boot_cores {
+        start_core(CoreId(id)).expect("x86::initialize_mp(): failed to start core");
+    }
+
     unsafe { initialize_core(core_self.expect("limine did not give current CPU in MP response")) };
 }
 
@@ -81,28 +309,37 @@ core_local! {
 }
 
 unsafe extern "C" fn initialize_core(cpu: &Cpu) -> ! {
-    fn allocate_sp(size: PageSize, msg: &str) -> u64 {
-        vpa::get_global_vpa()
+    // interrupt stacks must always be populated, since the page fault handler runs on them
+    fn allocate_sp(name: &'static str, size: PageSize, lazy: bool, msg: &str) -> u64 {
+        let pages = vpa::get_global_vpa()
             .allocate_backed_padded(
                 &PMM::get(),
                 LOCAL_PAGE_TABLE.get().unwrap(),
                 size,
                 PageSize::new(1),
                 PageFlags::KERNEL_RW,
+                lazy,
             )
             .expect(msg)
-            .leak()
-            .as_va_range()
-            .end()
-            .value()
+            .leak();
+
+        unsafe { stack::register(name, pages, lazy) };
+        pages.as_va_range().end().value()
     }
 
     let id = CoreId(cpu.extra.load(Ordering::SeqCst) as usize);
 
     let pt = if id != CoreId(0) {
-        // swap page tables for other cores
+        // swap page tables for other cores; the core locals are mapped in the bootstrap ones, and
+        // are set up before anything else, since counting, logging and locking use them as soon as
+        // MP_STATE is past KInit
         let early_pt = BOOTSTRAP_PT.get().unwrap();
         unsafe { early_pt.set_current() };
+        init_cpu_local_ptr(id);
+
+        // the BSP programmed its PAT in kmain, before any mappings were made
+        super::pat::init();
+
         let pt = early_pt.duplicate(&PMM::get());
         unsafe { pt.set_current() };
         pt
@@ -112,41 +349,73 @@ unsafe extern "C" fn initialize_core(cpu: &Cpu) -> ! {
         *BOOTSTRAP_PT.get().unwrap()
     };
 
-    info!("hi from core (early): {}", id.0);
-
-    init_cpu_local_ptr(id);
+    info!(target: "init_smp", "hi from core (early): {}", id.0);
 
     CORE_ID.replace(id);
     LOCAL_PAGE_TABLE.call_once(|| pt);
+    rcu::online();
+    topology::register(id, super::topology::detect());
+    super::apic::init();
+    super::fpu::init();
+    super::pcid::init();
 
-    info!("hi from core: {}", CORE_ID.get());
+    info!(target: "init_smp", "hi from core: {}", CORE_ID.get());
 
     let ist = IST.call_once(|| {
         let mut ist = InterruptStackTable::default();
 
-        ist.ist1 = allocate_sp(PageSize::new(32), "failed to allocate IST");
-        ist.ist2 = allocate_sp(PageSize::new(32), "failed to allocate IST");
-        ist.ist3 = allocate_sp(PageSize::new(32), "failed to allocate IST");
-        ist.ist4 = allocate_sp(PageSize::new(32), "failed to allocate IST");
-        ist.ist5 = allocate_sp(PageSize::new(32), "failed to allocate IST");
-        ist.ist6 = allocate_sp(PageSize::new(32), "failed to allocate IST");
-        ist.ist7 = allocate_sp(PageSize::new(32), "failed to allocate IST");
+        // where the CPU switches to when coming in from user mode
+        ist.rsp0 = allocate_sp(
+            "kernel entry",
+            PageSize::new(32),
+            false,
+            "failed to allocate kernel entry stack",
+        );
+
+        ist.ist1 = allocate_sp("ist1", PageSize::new(32), false, "failed to allocate IST");
+        // ist2-5 are for nmi, #DF, #MC and #PF only, see InterruptDescriptorTable::DEDICATED_STACKS
+        ist.ist2 = allocate_sp("ist2", PageSize::new(32), false, "failed to allocate IST");
+        ist.ist3 = allocate_sp("ist3", PageSize::new(32), false, "failed to allocate IST");
+        ist.ist4 = allocate_sp("ist4", PageSize::new(32), false, "failed to allocate IST");
+        ist.ist5 = allocate_sp("ist5", PageSize::new(32), false, "failed to allocate IST");
+        ist.ist6 = allocate_sp("ist6", PageSize::new(32), false, "failed to allocate IST");
+        ist.ist7 = allocate_sp("ist7", PageSize::new(32), false, "failed to allocate IST");
 
         ist
     });
     let gdt = GDT.call_once(|| GlobalDescriptorTable::new(ist));
     let idt = IDT.call_once(InterruptDescriptorTable::new);
 
+    // loading gs clears GS_BASE, so the core local pointer has to be put back before anything can
+    // look at it
     unsafe { gdt.load() };
+    init_cpu_local_ptr(id);
     unsafe { idt.load() };
 
-    // we need to re-load the core local, for Reasons
-    init_cpu_local_ptr(id);
+    NMI_READY.get().unwrap()[id.0].store(true, Ordering::SeqCst);
+
+    bringup::mark_online();
+
+    // the BSP holds off until everyone else is up, so that anything after this can rely on all
+    // cores being there
+    if id == CoreId(0) {
+        let n_cores = started_cores();
+
+        if !bringup::wait_for_cores(n_cores, Duration::from_secs(1)) {
+            warn!(
+                "x86::initialize_core(): only {} of {} cores came up",
+                bringup::online_cores(),
+                n_cores
+            );
+        }
+    }
 
     // 8MB stack
     unsafe {
         switch_stack_to_ksmp(allocate_sp(
+            "ksmp",
             PageSize::new(2048),
+            true,
             "failed to allocate kernel smp init stack",
         ))
     };
@@ -0,0 +1,122 @@
+// model specific and control registers
+//
+// MSRs know which cpu feature they depend on, so touching one the CPU doesn't have is a clear
+// panic rather than a #GP in the middle of nowhere. writes that have to happen on every core go
+// through mp::run_on.
+
+use x86::{
+    controlregs::{self, Cr0, Cr4},
+    msr::{
+        IA32_APIC_BASE, IA32_BIOS_SIGN_ID, IA32_EFER, IA32_FS_BASE, IA32_GS_BASE,
+        IA32_KERNEL_GSBASE, IA32_PAT, IA32_TSC_DEADLINE, rdmsr, wrmsr,
+    },
+};
+
+use super::cpu::{CpuFeatures, features};
+use crate::mp::{self, CoreId, core_count, is_online};
+
+#[derive(Clone, Copy)]
+pub struct Msr {
+    address: u32,
+    name: &'static str,
+    supported: fn(&CpuFeatures) -> bool,
+}
+
+fn always(_: &CpuFeatures) -> bool {
+    true
+}
+
+fn has_pat(features: &CpuFeatures) -> bool {
+    features.pat
+}
+
+fn has_tsc_deadline(features: &CpuFeatures) -> bool {
+    features.tsc_deadline
+}
+
+fn has_x2apic(features: &CpuFeatures) -> bool {
+    features.x2apic
+}
+
+impl Msr {
+    pub const APIC_BASE: Msr = Msr::new(IA32_APIC_BASE, "IA32_APIC_BASE", always);
+    pub const PAT: Msr = Msr::new(IA32_PAT, "IA32_PAT", has_pat);
+    pub const TSC_DEADLINE: Msr =
+        Msr::new(IA32_TSC_DEADLINE, "IA32_TSC_DEADLINE", has_tsc_deadline);
+    pub const EFER: Msr = Msr::new(IA32_EFER, "IA32_EFER", always);
+    pub const FS_BASE: Msr = Msr::new(IA32_FS_BASE, "IA32_FS_BASE", always);
+    pub const GS_BASE: Msr = Msr::new(IA32_GS_BASE, "IA32_GS_BASE", always);
+    pub const KERNEL_GS_BASE: Msr = Msr::new(IA32_KERNEL_GSBASE, "IA32_KERNEL_GS_BASE", always);
+    pub const BIOS_SIGN_ID: Msr = Msr::new(IA32_BIOS_SIGN_ID, "IA32_BIOS_SIGN_ID", always);
+
+    // x2APIC registers are at 0x800 plus the xAPIC mmio offset / 16
+    pub const fn x2apic(offset: usize) -> Msr {
+        Msr::new(0x800 + (offset >> 4) as u32, "x2APIC register", has_x2apic)
+    }
+
+    const fn new(address: u32, name: &'static str, supported: fn(&CpuFeatures) -> bool) -> Msr {
+        Msr {
+            address,
+            name,
+            supported,
+        }
+    }
+
+    pub fn is_supported(self) -> bool {
+        (self.supported)(features())
+    }
+
+    fn check(self) {
+        assert!(self.is_supported(), "x86::msr: {} not supported", self.name);
+    }
+
+    pub fn read(self) -> u64 {
+        self.check();
+        unsafe { rdmsr(self.address) }
+    }
+
+    pub unsafe fn write(self, value: u64) {
+        self.check();
+        unsafe { wrmsr(self.address, value) };
+    }
+
+    // writes `value` on `core`, waiting for it to happen
+    pub unsafe fn write_on(self, core: CoreId, value: u64) -> Result<(), ()> {
+        mp::run_on(core, move || unsafe { self.write(value) })
+    }
+
+    // writes `value` on every online core
+    pub unsafe fn write_all(self, value: u64) {
+        for core in (0..core_count())
+            .map(CoreId)
+            .filter(|&core| is_online(core))
+        {
+            // a core going offline in between has nothing left to update
+            let _ = unsafe { self.write_on(core, value) };
+        }
+    }
+}
+
+pub fn cr0() -> Cr0 {
+    unsafe { controlregs::cr0() }
+}
+
+pub unsafe fn write_cr0(value: Cr0) {
+    unsafe { controlregs::cr0_write(value) };
+}
+
+pub unsafe fn update_cr0(update: impl FnOnce(Cr0) -> Cr0) {
+    unsafe { write_cr0(update(cr0())) };
+}
+
+pub fn cr4() -> Cr4 {
+    unsafe { controlregs::cr4() }
+}
+
+pub unsafe fn write_cr4(value: Cr4) {
+    unsafe { controlregs::cr4_write(value) };
+}
+
+pub unsafe fn update_cr4(update: impl FnOnce(Cr4) -> Cr4) {
+    unsafe { write_cr4(update(cr4())) };
+}
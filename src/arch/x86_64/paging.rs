@@ -2,22 +2,25 @@ use core::ptr;
 
 use super::{
     HIGHER_HALF_VIRTUAL_ADDRESS_BASE_PML4, HIGHER_HALF_VIRTUAL_ADDRESS_BASE_PML5, PAGE_SMALL_SIZE,
-    SMALL_PAGE_PAGE_SIZE,
+    SMALL_PAGE_PAGE_SIZE, cpu, pat, pcid,
 };
 use crate::{
     arch::{LARGE_PAGE_PAGE_SIZE, MEDIUM_PAGE_PAGE_SIZE},
     mem::{
-        PageFrameAllocator, PageFrameNumber, PageSize, PhysicalAddress, VirtualAddress,
-        VirtualPageFrameNumber, Wrapper,
-    }, sync::IntMutex,
+        AddressRange, PageFrameAllocator, PageFrameNumber, PageSize, PhysicalAddress, VFRange,
+        VirtualAddress, VirtualPageFrameNumber, Wrapper,
+    },
+    mp::{self, CoreId},
+    sync::IntMutex,
 };
 use limine::{paging::Mode, request::PagingModeRequest};
 use x86::{
     bits64::paging::{
         PAGE_SIZE_ENTRIES, PAddr, PD, PDEntry, PDFlags, PDPT, PDPTEntry, PDPTFlags, PML4,
-        PML4Entry, PML4Flags, PT, PTEntry, PTFlags, pd_index, pdpt_index, pml4_index, pt_index,
+        PML4Entry, PML4Flags, PML5, PML5Entry, PML5Flags, PT, PTEntry, PTFlags, pd_index,
+        pdpt_index, pml4_index, pml5_index, pt_index,
     },
-    controlregs::cr3_write,
+    controlregs::cr3,
 };
 
 #[used]
@@ -25,28 +28,47 @@ use x86::{
 static PAGING_MODE_REQUEST: PagingModeRequest =
     PagingModeRequest::new().with_mode(Mode::FIVE_LEVEL);
 
+pub fn is_five_level() -> bool {
+    PAGING_MODE_REQUEST
+        .get_response()
+        .is_some_and(|res| res.mode() == Mode::FIVE_LEVEL)
+}
+
 pub fn get_higher_half_addr() -> VirtualAddress {
-    if let Some(res) = PAGING_MODE_REQUEST.get_response()
-        && res.mode() == Mode::FIVE_LEVEL {
-            return HIGHER_HALF_VIRTUAL_ADDRESS_BASE_PML5;
-        }
+    if is_five_level() {
+        return HIGHER_HALF_VIRTUAL_ADDRESS_BASE_PML5;
+    }
 
     HIGHER_HALF_VIRTUAL_ADDRESS_BASE_PML4
 }
 
-// TODO: this should really be dynamic based on the current paging mode
+// end of the region covered by the lower 256 entries of the top level table
+pub fn get_lower_half_end() -> VirtualAddress {
+    if is_five_level() {
+        return VirtualAddress::new(0x0100_0000_0000_0000);
+    }
+
+    VirtualAddress::new(0x0000_8000_0000_0000)
+}
+
+// the root table is a PML5 when limine enabled LA57, and a PML4 otherwise
 #[derive(Clone, Copy)]
 pub struct PageTableSet {
     pml_addr: PageFrameNumber,
+    // see pcid.rs; shared by copies of the same set
+    pcid: u16,
 }
 
 trait PageTableEntry: Copy {
     fn create_page_map(addr: PageFrameNumber) -> Self;
     fn address(self) -> PAddr;
     fn present(self) -> bool;
+    fn is_leaf(self) -> bool;
+    fn writable(self) -> bool;
+    fn executable(self) -> bool;
 }
 
-macro impl_pte($ident:ident, $flags:ident) {
+macro impl_pte($ident:ident, $flags:ident, $leaf:expr) {
     impl PageTableEntry for $ident {
         fn create_page_map(addr: PageFrameNumber) -> Self {
             return $ident::new(
@@ -62,20 +84,66 @@ macro impl_pte($ident:ident, $flags:ident) {
         fn present(self) -> bool {
             return self.is_present();
         }
+
+        fn is_leaf(self) -> bool {
+            return ($leaf)(self);
+        }
+
+        fn writable(self) -> bool {
+            return self.is_writeable();
+        }
+
+        fn executable(self) -> bool {
+            return !self.is_instruction_fetching_disabled();
+        }
     }
 }
 
-impl_pte!(PML4Entry, PML4Flags);
-impl_pte!(PDPTEntry, PDPTFlags);
-impl_pte!(PDEntry, PDFlags);
-impl_pte!(PTEntry, PTFlags);
+impl_pte!(PML5Entry, PML5Flags, |_| false);
+impl_pte!(PML4Entry, PML4Flags, |_| false);
+impl_pte!(PDPTEntry, PDPTFlags, PDPTEntry::is_page);
+impl_pte!(PDEntry, PDFlags, PDEntry::is_page);
+impl_pte!(PTEntry, PTFlags, |_| true);
+
+// memory type of a mapping, encoded via the PWT/PCD/PAT bits (see pat::init for the layout)
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum CacheMode {
+    WriteBack,
+    WriteThrough,
+    // degrades to uncached if the CPU has no PAT
+    WriteCombining,
+    Uncached,
+}
+
+impl CacheMode {
+    fn bits(self) -> (bool, bool, bool) {
+        // (pwt, pcd, pat)
+        match self {
+            CacheMode::WriteBack => (false, false, false),
+            CacheMode::WriteThrough => (true, false, false),
+            CacheMode::WriteCombining if pat::is_enabled() => (true, false, true),
+            CacheMode::WriteCombining | CacheMode::Uncached => (true, true, false),
+        }
+    }
+}
+
+// the PAT bit of a 4k PTE shares its position with PS, so the x86 crate doesn't expose it
+const PT_PAT: u64 = 1 << 7;
+
+// software-defined: the mapping is write-protected and gets copied on the first write
+const PT_COW: u64 = PTFlags::USER_9.bits();
+
+const PT_ADDRESS_MASK: u64 = 0x000f_ffff_ffff_f000;
+const CR3_PCID_MASK: u64 = 0xfff;
 
 // TODO: make this bitflags?
+#[derive(Clone, Copy)]
 pub struct PageFlags {
     pub write: bool,
     pub user: bool,
     pub execute: bool,
     pub global: bool,
+    pub cache: CacheMode,
 }
 
 impl PageFlags {
@@ -84,6 +152,7 @@ impl PageFlags {
         user: false,
         execute: false,
         global: true,
+        cache: CacheMode::WriteBack,
     };
 
     pub const KERNEL_RO: PageFlags = PageFlags {
@@ -91,6 +160,7 @@ impl PageFlags {
         user: false,
         execute: false,
         global: true,
+        cache: CacheMode::WriteBack,
     };
 
     pub const KERNEL_X: PageFlags = PageFlags {
@@ -98,7 +168,31 @@ impl PageFlags {
         user: false,
         execute: true,
         global: true,
+        cache: CacheMode::WriteBack,
     };
+
+    pub const fn with_cache(self, cache: CacheMode) -> PageFlags {
+        PageFlags { cache, ..self }
+    }
+
+    // executable mappings must go through mem::make_executable, which never leaves them writable
+    fn assert_wx(&self, virt: VirtualPageFrameNumber) {
+        assert!(
+            !(self.write && self.execute),
+            "PageTableSet: refusing to create writable and executable mapping at {}",
+            virt.address()
+        );
+    }
+}
+
+// a leaf mapping found while walking a PageTableSet, with the effective permissions of the
+// whole walk
+#[derive(Clone, Copy)]
+pub struct LeafMapping {
+    pub virt: VirtualPageFrameNumber,
+    pub size: PageSize,
+    pub write: bool,
+    pub execute: bool,
 }
 
 macro tl_flag($expr:expr, $type:ident::$flag_name:ident) {
@@ -115,14 +209,40 @@ impl PageTableSet {
     pub fn new<T: PageFrameAllocator>(alloc: &T) -> PageTableSet {
         PageTableSet {
             pml_addr: alloc.allocate_zeroed_page(),
+            pcid: pcid::allocate(),
         }
     }
 
+    // only valid if the root table is a PML4
     fn pml4(&self) -> &mut PML4 {
         let pml4_ptr = self.pml_addr.address().to_virtual().as_ptr_mut();
         unsafe { &mut *pml4_ptr }
     }
 
+    // only valid if the root table is a PML5
+    fn pml5(&self) -> &mut PML5 {
+        let pml5_ptr = self.pml_addr.address().to_virtual().as_ptr_mut();
+        unsafe { &mut *pml5_ptr }
+    }
+
+    // the PML4 covering `addr`, if there is one
+    fn lookup_pml4(&self, addr: u64) -> Option<&mut PML4> {
+        if is_five_level() {
+            Self::lookup_entry::<_, PML4>(self.pml5(), pml5_index(addr.into()))
+        } else {
+            Some(self.pml4())
+        }
+    }
+
+    // the PML4 covering `addr`, allocating it if needed
+    fn walk_pml4<T: PageFrameAllocator>(&self, alloc: &T, addr: u64) -> &mut PML4 {
+        if is_five_level() {
+            Self::walk_entry::<T, _, PML4>(alloc, self.pml5(), pml5_index(addr.into()))
+        } else {
+            self.pml4()
+        }
+    }
+
     fn walk_entry<'a, T: PageFrameAllocator, U: PageTableEntry, P>(
         alloc: &T,
         table: &'a mut [U; PAGE_SIZE_ENTRIES],
@@ -139,6 +259,33 @@ impl PageTableSet {
         unsafe { &mut *ptr }
     }
 
+    // like walk_entry, but never allocates and refuses to descend into leaf entries
+    fn lookup_entry<'a, U: PageTableEntry, P>(
+        table: &'a mut [U; PAGE_SIZE_ENTRIES],
+        index: usize,
+    ) -> Option<&'a mut P> {
+        let entry = table[index];
+
+        if !entry.present() || entry.is_leaf() {
+            return None;
+        }
+
+        let ptr = PhysicalAddress::new(entry.address().0)
+            .to_virtual()
+            .as_ptr_mut();
+
+        Some(unsafe { &mut *ptr })
+    }
+
+    fn leaf_frame<U: PageTableEntry>(
+        entry: U,
+        virt: VirtualPageFrameNumber,
+        size: PageSize,
+    ) -> PageFrameNumber {
+        PhysicalAddress::new(entry.address().0).frame_aligned()
+            + PageSize::new(virt.value() % size.value())
+    }
+
     // TODO: figure out semantics for overwriting entries
 
     fn do_action<T: FnOnce()>(needs_lock: bool, action: T) {
@@ -150,8 +297,270 @@ impl PageTableSet {
         }
     }
 
-    pub fn translate(&self, _virt: VirtualPageFrameNumber) -> Option<PageFrameNumber> {
-        todo!();
+    pub fn translate(&self, virt: VirtualPageFrameNumber) -> Option<PageFrameNumber> {
+        let addr = virt.address().into();
+
+        let pml4 = self.lookup_pml4(virt.address().value())?;
+        let pdpt = Self::lookup_entry::<_, PDPT>(pml4, pml4_index(addr))?;
+        let pdpte = pdpt[pdpt_index(addr)];
+        if pdpte.is_present() && pdpte.is_page() {
+            return Some(Self::leaf_frame(pdpte, virt, LARGE_PAGE_PAGE_SIZE));
+        }
+
+        let pd = Self::lookup_entry::<_, PD>(pdpt, pdpt_index(addr))?;
+        let pde = pd[pd_index(addr)];
+        if pde.is_present() && pde.is_page() {
+            return Some(Self::leaf_frame(pde, virt, MEDIUM_PAGE_PAGE_SIZE));
+        }
+
+        let pt = Self::lookup_entry::<_, PT>(pd, pd_index(addr))?;
+        let pte = pt[pt_index(addr)];
+        pte.is_present()
+            .then(|| Self::leaf_frame(pte, virt, SMALL_PAGE_PAGE_SIZE))
+    }
+
+    // write and execute permissions, narrowed down by one more level of the walk
+    fn narrow<U: PageTableEntry>(entry: U, (write, execute): (bool, bool)) -> (bool, bool) {
+        (write && entry.writable(), execute && entry.executable())
+    }
+
+    fn leaf_mapping<U: PageTableEntry>(
+        entry: U,
+        virt: VirtualPageFrameNumber,
+        size: PageSize,
+        (write, execute): (bool, bool),
+    ) -> (LeafMapping, PageFrameNumber) {
+        let start = virt.value() / size.value() * size.value() * PAGE_SMALL_SIZE;
+
+        (
+            LeafMapping {
+                virt: VirtualAddress::new(start).frame_containing(),
+                size,
+                write,
+                execute,
+            },
+            Self::leaf_frame(entry, virt, size),
+        )
+    }
+
+    // like translate, but with the leaf mapping `virt` is in, as for_each_mapping would give it
+    pub fn lookup_mapping(
+        &self,
+        virt: VirtualPageFrameNumber,
+    ) -> Option<(LeafMapping, PageFrameNumber)> {
+        let addr = virt.address().into();
+        let mut perms = (true, true);
+
+        if is_five_level() {
+            perms = Self::narrow(self.pml5()[pml5_index(addr)], perms);
+        }
+
+        let pml4 = self.lookup_pml4(virt.address().value())?;
+        perms = Self::narrow(pml4[pml4_index(addr)], perms);
+
+        let pdpt = Self::lookup_entry::<_, PDPT>(pml4, pml4_index(addr))?;
+        let pdpte = pdpt[pdpt_index(addr)];
+        perms = Self::narrow(pdpte, perms);
+        if pdpte.is_present() && pdpte.is_page() {
+            return Some(Self::leaf_mapping(pdpte, virt, LARGE_PAGE_PAGE_SIZE, perms));
+        }
+
+        let pd = Self::lookup_entry::<_, PD>(pdpt, pdpt_index(addr))?;
+        let pde = pd[pd_index(addr)];
+        perms = Self::narrow(pde, perms);
+        if pde.is_present() && pde.is_page() {
+            return Some(Self::leaf_mapping(pde, virt, MEDIUM_PAGE_PAGE_SIZE, perms));
+        }
+
+        let pt = Self::lookup_entry::<_, PT>(pd, pd_index(addr))?;
+        let pte = pt[pt_index(addr)];
+        pte.is_present()
+            .then(|| Self::leaf_mapping(pte, virt, SMALL_PAGE_PAGE_SIZE, Self::narrow(pte, perms)))
+    }
+
+    fn lookup_pt_entry(&self, virt: VirtualPageFrameNumber) -> Option<&mut PTEntry> {
+        let addr = virt.address().into();
+
+        let pt = self
+            .lookup_pml4(virt.address().value())
+            .and_then(|pml4| Self::lookup_entry::<_, PDPT>(pml4, pml4_index(addr)))
+            .and_then(|pdpt| Self::lookup_entry::<_, PD>(pdpt, pdpt_index(addr)))
+            .and_then(|pd| Self::lookup_entry::<_, PT>(pd, pd_index(addr)))?;
+
+        Some(&mut pt[pt_index(addr)])
+    }
+
+    // changes the access permissions of a present 4k mapping, keeping everything else
+    // this only invalidates the local TLB; taking access away needs a shootdown on the other cores
+    pub fn set_access_small(
+        &self,
+        virt: VirtualPageFrameNumber,
+        write: bool,
+        execute: bool,
+    ) -> Result<(), ()> {
+        assert!(
+            !(write && execute),
+            "PageTableSet::set_access_small(): refusing to make {} writable and executable",
+            virt.address()
+        );
+
+        let mut res = Err(());
+
+        Self::do_action(virt.is_higher_half(), || {
+            let Some(entry) = self
+                .lookup_pt_entry(virt)
+                .filter(|entry| entry.is_present())
+            else {
+                return;
+            };
+
+            let mut bits = entry.0 & !(PTFlags::RW.bits() | PTFlags::XD.bits());
+
+            if write {
+                bits |= PTFlags::RW.bits();
+            }
+
+            if !execute && cpu::features().nx {
+                bits |= PTFlags::XD.bits();
+            }
+
+            entry.0 = bits;
+            self.flush(virt);
+
+            res = Ok(());
+        });
+
+        res
+    }
+
+    fn walk_leaves<U: PageTableEntry>(
+        table: &[U],
+        base: u64,
+        level: u32,
+        top_level: u32,
+        write: bool,
+        execute: bool,
+        f: &mut impl FnMut(LeafMapping),
+    ) {
+        let entry_size = PAGE_SMALL_SIZE << (9 * level);
+
+        for (index, &entry) in table.iter().enumerate().filter(|(_, e)| e.present()) {
+            let mut addr = base + index as u64 * entry_size;
+
+            // sign-extend the top level into canonical form
+            if level == top_level && index >= 256 {
+                addr |= !(entry_size * 512 - 1);
+            }
+
+            let write = write && entry.writable();
+            let execute = execute && entry.executable();
+
+            if level == 0 || entry.is_leaf() {
+                f(LeafMapping {
+                    virt: VirtualAddress::new(addr).frame_containing(),
+                    size: PageSize::new(entry_size / PAGE_SMALL_SIZE),
+                    write,
+                    execute,
+                });
+                continue;
+            }
+
+            let child = PhysicalAddress::new(entry.address().0).to_virtual();
+
+            match level {
+                4 => {
+                    let pml4 = unsafe { &*child.as_ptr::<PML4>() };
+                    Self::walk_leaves(&pml4[..], addr, 3, top_level, write, execute, f)
+                }
+                3 => {
+                    let pdpt = unsafe { &*child.as_ptr::<PDPT>() };
+                    Self::walk_leaves(&pdpt[..], addr, 2, top_level, write, execute, f)
+                }
+                2 => {
+                    let pd = unsafe { &*child.as_ptr::<PD>() };
+                    Self::walk_leaves(&pd[..], addr, 1, top_level, write, execute, f)
+                }
+                _ => {
+                    let pt = unsafe { &*child.as_ptr::<PT>() };
+                    Self::walk_leaves(&pt[..], addr, 0, top_level, write, execute, f)
+                }
+            }
+        }
+    }
+
+    // calls `f` for every present leaf mapping, in both halves
+    pub fn for_each_mapping(&self, mut f: impl FnMut(LeafMapping)) {
+        if is_five_level() {
+            Self::walk_leaves(&self.pml5()[..], 0, 4, 4, true, true, &mut f);
+        } else {
+            Self::walk_leaves(&self.pml4()[..], 0, 3, 3, true, true, &mut f);
+        }
+    }
+
+    // removes a 4k mapping, returning the frame that was mapped there
+    // this only invalidates the local TLB, so the frame can't be reused before a shootdown
+    pub fn unmap_page_small(&self, virt: VirtualPageFrameNumber) -> Option<PageFrameNumber> {
+        let mut res = None;
+
+        Self::do_action(virt.is_higher_half(), || {
+            let Some(entry) = self.lookup_pt_entry(virt) else {
+                return;
+            };
+
+            if entry.is_present() {
+                res = Some(PhysicalAddress::new(entry.address().0).frame_aligned());
+                *entry = PTEntry(0);
+                self.flush(virt);
+            }
+        });
+
+        res
+    }
+
+    // write-protects a writable 4k mapping and marks it copy-on-write; returns the mapped frame
+    // for any present mapping, whether or not it was writable
+    pub fn mark_cow(&self, virt: VirtualPageFrameNumber) -> Option<PageFrameNumber> {
+        let mut res = None;
+
+        Self::do_action(virt.is_higher_half(), || {
+            let Some(entry) = self
+                .lookup_pt_entry(virt)
+                .filter(|entry| entry.is_present())
+            else {
+                return;
+            };
+
+            if entry.0 & PTFlags::RW.bits() != 0 {
+                entry.0 = (entry.0 & !PTFlags::RW.bits()) | PT_COW;
+                self.flush(virt);
+            }
+
+            res = Some(PhysicalAddress::new(entry.address().0).frame_aligned());
+        });
+
+        res
+    }
+
+    // returns the frame behind a copy-on-write mapping
+    pub fn cow_frame(&self, virt: VirtualPageFrameNumber) -> Option<PageFrameNumber> {
+        self.lookup_pt_entry(virt)
+            .filter(|entry| entry.is_present() && entry.0 & PT_COW != 0)
+            .map(|entry| PhysicalAddress::new(entry.address().0).frame_aligned())
+    }
+
+    // turns a copy-on-write mapping back into a regular writable one, backed by `frame`
+    pub fn resolve_cow(&self, virt: VirtualPageFrameNumber, frame: PageFrameNumber) {
+        Self::do_action(virt.is_higher_half(), || {
+            let entry = self
+                .lookup_pt_entry(virt)
+                .filter(|entry| entry.is_present() && entry.0 & PT_COW != 0)
+                .expect("PageTableSet::resolve_cow(): not a copy-on-write mapping");
+
+            let flags = entry.0 & !(PT_ADDRESS_MASK | PT_COW);
+            entry.0 = frame.address().value() | flags | PTFlags::RW.bits();
+
+            self.flush(virt);
+        });
     }
 
     pub fn map_page_small<T: PageFrameAllocator>(
@@ -161,24 +570,30 @@ impl PageTableSet {
         phys: PageFrameNumber,
         flags: &PageFlags,
     ) {
+        flags.assert_wx(virt);
+
+        let (pwt, pcd, pat) = flags.cache.bits();
+        let has_nx = cpu::features().nx;
+
         Self::do_action(virt.is_higher_half(), || {
-            let pdpt = Self::walk_entry::<T, _, PDPT>(
-                alloc,
-                self.pml4(),
-                pml4_index(virt.address().into()),
-            );
+            let pml4 = self.walk_pml4(alloc, virt.address().value());
+            let pdpt =
+                Self::walk_entry::<T, _, PDPT>(alloc, pml4, pml4_index(virt.address().into()));
             let pd = Self::walk_entry::<T, _, PD>(alloc, pdpt, pdpt_index(virt.address().into()));
             let pt = Self::walk_entry::<T, _, PT>(alloc, pd, pd_index(virt.address().into()));
 
-            // TODO: we can't be sure XD exists, so maybe we need to check that?
-            pt[pt_index(virt.address().into())] = PTEntry::new(
+            let entry = PTEntry::new(
                 PAddr(phys.address().value()),
                 PTFlags::P
                     | tl_flag!(flags.write, PTFlags::RW)
                     | tl_flag!(flags.user, PTFlags::US)
-                    | tl_flag!(!flags.execute, PTFlags::XD)
-                    | tl_flag!(flags.global, PTFlags::G),
+                    | tl_flag!(!flags.execute && has_nx, PTFlags::XD)
+                    | tl_flag!(flags.global, PTFlags::G)
+                    | tl_flag!(pwt, PTFlags::PWT)
+                    | tl_flag!(pcd, PTFlags::PCD),
             );
+
+            pt[pt_index(virt.address().into())] = PTEntry(entry.0 | if pat { PT_PAT } else { 0 });
         });
     }
 
@@ -192,23 +607,28 @@ impl PageTableSet {
         assert!(virt.is_aligned(MEDIUM_PAGE_PAGE_SIZE));
         assert!(phys.is_aligned(MEDIUM_PAGE_PAGE_SIZE));
 
+        flags.assert_wx(virt);
+
+        let (pwt, pcd, pat) = flags.cache.bits();
+        let has_nx = cpu::features().nx;
+
         Self::do_action(virt.is_higher_half(), || {
-            let pdpt = Self::walk_entry::<T, _, PDPT>(
-                alloc,
-                self.pml4(),
-                pml4_index(virt.address().into()),
-            );
+            let pml4 = self.walk_pml4(alloc, virt.address().value());
+            let pdpt =
+                Self::walk_entry::<T, _, PDPT>(alloc, pml4, pml4_index(virt.address().into()));
             let pd = Self::walk_entry::<T, _, PD>(alloc, pdpt, pdpt_index(virt.address().into()));
 
-            // TODO: we can't be sure XD exists, so maybe we need to check that?
             pd[pd_index(virt.address().into())] = PDEntry::new(
                 PAddr(phys.address().value()),
                 PDFlags::P
                     | PDFlags::PS
                     | tl_flag!(flags.write, PDFlags::RW)
                     | tl_flag!(flags.user, PDFlags::US)
-                    | tl_flag!(!flags.execute, PDFlags::XD)
-                    | tl_flag!(flags.global, PDFlags::G),
+                    | tl_flag!(!flags.execute && has_nx, PDFlags::XD)
+                    | tl_flag!(flags.global, PDFlags::G)
+                    | tl_flag!(pwt, PDFlags::PWT)
+                    | tl_flag!(pcd, PDFlags::PCD)
+                    | tl_flag!(pat, PDFlags::PAT),
             );
         });
     }
@@ -223,22 +643,27 @@ impl PageTableSet {
         assert!(virt.is_aligned(LARGE_PAGE_PAGE_SIZE));
         assert!(phys.is_aligned(LARGE_PAGE_PAGE_SIZE));
 
+        flags.assert_wx(virt);
+
+        let (pwt, pcd, pat) = flags.cache.bits();
+        let has_nx = cpu::features().nx;
+
         Self::do_action(virt.is_higher_half(), || {
-            let pdpt = Self::walk_entry::<T, _, PDPT>(
-                alloc,
-                self.pml4(),
-                pml4_index(virt.address().into()),
-            );
+            let pml4 = self.walk_pml4(alloc, virt.address().value());
+            let pdpt =
+                Self::walk_entry::<T, _, PDPT>(alloc, pml4, pml4_index(virt.address().into()));
 
-            // TODO: we can't be sure XD exists, so maybe we need to check that?
             pdpt[pdpt_index(virt.address().into())] = PDPTEntry::new(
                 PAddr(phys.address().value()),
                 PDPTFlags::P
                     | PDPTFlags::PS
                     | tl_flag!(flags.write, PDPTFlags::RW)
                     | tl_flag!(flags.user, PDPTFlags::US)
-                    | tl_flag!(!flags.execute, PDPTFlags::XD)
-                    | tl_flag!(flags.global, PDPTFlags::G),
+                    | tl_flag!(!flags.execute && has_nx, PDPTFlags::XD)
+                    | tl_flag!(flags.global, PDPTFlags::G)
+                    | tl_flag!(pwt, PDPTFlags::PWT)
+                    | tl_flag!(pcd, PDPTFlags::PCD)
+                    | tl_flag!(pat, PDPTFlags::PAT),
             );
         });
     }
@@ -271,7 +696,7 @@ impl PageTableSet {
             phys += MEDIUM_PAGE_PAGE_SIZE;
         }
 
-        while base + LARGE_PAGE_PAGE_SIZE <= end {
+        while cpu::features().gib_pages && base + LARGE_PAGE_PAGE_SIZE <= end {
             self.map_page_large(alloc, base, phys, flags);
             base += LARGE_PAGE_PAGE_SIZE;
             phys += LARGE_PAGE_PAGE_SIZE;
@@ -294,7 +719,11 @@ impl PageTableSet {
         // we can get away with not locking here
         // higher half is always the last 256 of the first layer page table
         for idx in 256..512 {
-            Self::walk_entry::<T, _, PDPT>(alloc, self.pml4(), idx);
+            if is_five_level() {
+                Self::walk_entry::<T, _, PML4>(alloc, self.pml5(), idx);
+            } else {
+                Self::walk_entry::<T, _, PDPT>(alloc, self.pml4(), idx);
+            }
         }
     }
 
@@ -309,12 +738,125 @@ impl PageTableSet {
             )
         };
 
-        PageTableSet { pml_addr: page }
+        PageTableSet {
+            pml_addr: page,
+            pcid: pcid::allocate(),
+        }
+    }
+
+    // creates a table set with an empty lower half that shares the kernel half with `kernel`
+    // this relies on map_kernel_pages having been called on `kernel`, so that every kernel mapping
+    // goes through the shared second-level tables
+    pub fn clone_kernel_half<T: PageFrameAllocator>(
+        kernel: &PageTableSet,
+        alloc: &T,
+    ) -> PageTableSet {
+        let res = PageTableSet {
+            pml_addr: alloc.allocate_zeroed_page(),
+            pcid: pcid::allocate(),
+        };
+
+        if is_five_level() {
+            res.pml5()[256..512].copy_from_slice(&kernel.pml5()[256..512]);
+        } else {
+            res.pml4()[256..512].copy_from_slice(&kernel.pml4()[256..512]);
+        }
+
+        res
+    }
+
+    fn child_tables<'a, U: PageTableEntry, P>(
+        table: &'a [U],
+    ) -> impl Iterator<Item = (PageFrameNumber, &'a mut P)> {
+        table
+            .iter()
+            .filter(|entry| entry.present() && !entry.is_leaf())
+            .map(|entry| {
+                let frame = PhysicalAddress::new(entry.address().0).frame_aligned();
+                (frame, unsafe { &mut *frame.to_virtual().as_ptr_mut() })
+            })
+    }
+
+    fn destroy_pml4_entries(entries: &[PML4Entry], free_frame: &mut impl FnMut(PageFrameNumber)) {
+        for (pdpt_frame, pdpt) in Self::child_tables::<_, PDPT>(entries) {
+            for (pd_frame, pd) in Self::child_tables::<_, PD>(&pdpt[..]) {
+                for (pt_frame, _) in Self::child_tables::<_, PT>(&pd[..]) {
+                    free_frame(pt_frame);
+                }
+
+                free_frame(pd_frame);
+            }
+
+            free_frame(pdpt_frame);
+        }
+    }
+
+    // frees every page table backing the lower half, along with the top level table itself
+    // leaf frames are not touched; the caller is responsible for unmapping them first
+    pub unsafe fn destroy(self, mut free_frame: impl FnMut(PageFrameNumber)) {
+        if is_five_level() {
+            for (pml4_frame, pml4) in Self::child_tables::<_, PML4>(&self.pml5()[0..256]) {
+                Self::destroy_pml4_entries(&pml4[..], &mut free_frame);
+                free_frame(pml4_frame);
+            }
+        } else {
+            Self::destroy_pml4_entries(&self.pml4()[0..256], &mut free_frame);
+        }
+
+        free_frame(self.pml_addr);
+        pcid::release(self.pcid);
+    }
+
+    pub fn root(&self) -> PageFrameNumber {
+        self.pml_addr
+    }
+
+    // the table set currently loaded on this core
+    pub fn current() -> PageTableSet {
+        let cr3 = unsafe { cr3() };
+
+        PageTableSet {
+            pml_addr: PhysicalAddress::new(cr3 & PT_ADDRESS_MASK).frame_aligned(),
+            pcid: (cr3 & CR3_PCID_MASK) as u16,
+        }
+    }
+
+    pub fn is_current(&self) -> bool {
+        unsafe { cr3() }
+        &PT_ADDRESS_MASK == self.pml_addr.address().value()
     }
 
     pub unsafe fn set_current(&self) {
-        unsafe {
-            cr3_write(self.pml_addr.address().value());
+        unsafe { pcid::load(self.pml_addr.address().value(), self.pcid) };
+    }
+
+    // invalidates `range` in the TLB of every online core, including this one
+    // this waits for each core to get to it, so it must not be called with interrupts disabled
+    pub fn shootdown(&self, range: VFRange) {
+        for core in (0..mp::core_count()).map(CoreId) {
+            // cores that aren't online yet have nothing cached
+            let _ = mp::run_on(core, || {
+                let current = self.is_current();
+
+                for page in range.as_rust_range() {
+                    pcid::flush_page(
+                        self.pcid,
+                        current,
+                        page.address().value(),
+                        page.is_higher_half(),
+                    );
+                }
+            });
         }
     }
+
+    // invalidates the TLB entry for `virt` after its mapping was changed
+    fn flush(&self, virt: VirtualPageFrameNumber) {
+        pcid::flush_page(
+            self.pcid,
+            self.is_current(),
+            virt.address().value(),
+            virt.is_higher_half(),
+        );
+    }
 }
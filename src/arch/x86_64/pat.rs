@@ -0,0 +1,80 @@
+// page attribute table setup
+//
+// the layout matches the one limine hands over (WB, WT, UC-, UC, WP, WC), so mappings created by
+// the bootloader keep their memory type across the switch. we still program it ourselves since
+// the firmware default has no WC entry, and every core must agree on the same layout.
+
+use core::{
+    arch::asm,
+    sync::atomic::{AtomicBool, Ordering},
+};
+use log::warn;
+use x86::{controlregs::Cr0, tlb};
+
+use super::{
+    IrqState, cpu,
+    msr::{self, Msr},
+};
+
+const PAT_UC: u64 = 0x00;
+const PAT_WC: u64 = 0x01;
+const PAT_WT: u64 = 0x04;
+const PAT_WP: u64 = 0x05;
+const PAT_WB: u64 = 0x06;
+const PAT_UC_MINUS: u64 = 0x07;
+
+const PAT_LAYOUT: [u64; 8] = [
+    PAT_WB,
+    PAT_WT,
+    PAT_UC_MINUS,
+    PAT_UC,
+    PAT_WP,
+    PAT_WC,
+    PAT_UC_MINUS,
+    PAT_UC,
+];
+
+static PAT_ENABLED: AtomicBool = AtomicBool::new(false);
+
+pub fn is_enabled() -> bool {
+    PAT_ENABLED.load(Ordering::Relaxed)
+}
+
+fn wbinvd() {
+    unsafe { asm!("wbinvd", options(nostack, preserves_flags)) };
+}
+
+// programs the PAT on the current core; must run on every core before it touches any mapping
+// that uses a non-default memory type
+pub fn init() {
+    if !cpu::features().pat {
+        warn!("x86::pat::init(): PAT not supported, write-combining will fall back to uncached");
+        return;
+    }
+
+    let value = PAT_LAYOUT
+        .iter()
+        .enumerate()
+        .fold(0u64, |acc, (i, ty)| acc | (ty << (i * 8)));
+
+    // SDM 11.12.4: disable caching and flush everything before switching memory types
+    let state = IrqState::save();
+    super::irq_disable();
+
+    unsafe {
+        let old_cr0 = msr::cr0();
+        msr::write_cr0((old_cr0 | Cr0::CR0_CACHE_DISABLE) - Cr0::CR0_NOT_WRITE_THROUGH);
+        wbinvd();
+        tlb::flush_all();
+
+        Msr::PAT.write(value);
+
+        wbinvd();
+        tlb::flush_all();
+        msr::write_cr0(old_cr0);
+    }
+
+    state.restore();
+
+    PAT_ENABLED.store(true, Ordering::Relaxed);
+}
@@ -0,0 +1,141 @@
+// process-context identifiers
+//
+// every address space gets its own PCID, so switching between them doesn't throw away the TLB
+// entries of the others. PCID 0 is shared by everything that couldn't get one, and is always
+// flushed on load. we only use PCIDs when INVPCID is there too, since without it there is no
+// cheap way to invalidate a context that isn't loaded.
+//
+// PCIDs are recycled once their address space is destroyed, but other cores may still hold
+// entries for them. each PCID has a generation that is bumped when it is released, and a core
+// that loads a PCID with a generation it hasn't seen flushes it first.
+
+extern crate alloc;
+
+use alloc::vec::Vec;
+use core::{
+    arch::asm,
+    cell::Cell,
+    sync::atomic::{AtomicU16, AtomicU32, Ordering},
+};
+use log::info;
+use x86::controlregs::{Cr4, cr3_write};
+
+use super::{cpu, msr};
+use crate::{mp::core_local, sync::IntMutex};
+
+// the hardware has 4096, but every core tracks a generation for each one handed out, and there are
+// rarely this many address spaces alive at once; any more share PCID 0
+const MAX_PCIDS: usize = 256;
+const CR3_NOFLUSH: u64 = 1 << 63;
+
+const INVPCID_ADDRESS: u64 = 0;
+
+static NEXT_PCID: AtomicU16 = AtomicU16::new(1);
+static FREE_PCIDS: IntMutex<Vec<u16>> = IntMutex::new(Vec::new());
+static GENERATIONS: [AtomicU32; MAX_PCIDS] = [const { AtomicU32::new(0) }; MAX_PCIDS];
+
+core_local! {
+    // the generation of each PCID this core has last flushed
+    SEEN: [Cell<u32>; MAX_PCIDS] = [const { Cell::new(0) }; MAX_PCIDS];
+}
+
+fn supported() -> bool {
+    let features = cpu::features();
+    features.pcid && features.invpcid
+}
+
+// whether PCIDs are enabled on the current core
+fn enabled() -> bool {
+    msr::cr4().contains(Cr4::CR4_ENABLE_PCID)
+}
+
+// enables PCIDs on the current core; the loaded CR3 must be using PCID 0
+pub fn init() {
+    if !supported() {
+        return;
+    }
+
+    unsafe { msr::update_cr4(|cr4| cr4 | Cr4::CR4_ENABLE_PCID) };
+    info!("x86::pcid::init(): PCIDs enabled");
+}
+
+pub fn allocate() -> u16 {
+    if !supported() {
+        return 0;
+    }
+
+    if let Some(pcid) = FREE_PCIDS.lock().pop() {
+        return pcid;
+    }
+
+    NEXT_PCID
+        .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |next| {
+            ((next as usize) < MAX_PCIDS).then_some(next + 1)
+        })
+        .unwrap_or(0)
+}
+
+// the caller must make sure nothing has the PCID loaded anymore
+pub fn release(pcid: u16) {
+    if pcid == 0 {
+        return;
+    }
+
+    GENERATIONS[pcid as usize].fetch_add(1, Ordering::SeqCst);
+    FREE_PCIDS.lock().push(pcid);
+}
+
+unsafe fn invpcid(kind: u64, pcid: u16, addr: u64) {
+    let descriptor: [u64; 2] = [pcid as u64, addr];
+
+    unsafe {
+        asm!(
+            "invpcid {}, [{}]",
+            in(reg) kind,
+            in(reg) &descriptor,
+            options(nostack, preserves_flags)
+        )
+    };
+}
+
+// loads `root` into CR3, keeping the TLB entries of `pcid` if they are still good
+pub unsafe fn load(root: u64, pcid: u16) {
+    if pcid == 0 || !enabled() {
+        unsafe { cr3_write(root) };
+        return;
+    }
+
+    let generation = GENERATIONS[pcid as usize].load(Ordering::SeqCst);
+    let seen = &SEEN[pcid as usize];
+
+    if seen.get() == generation {
+        unsafe { cr3_write(root | pcid as u64 | CR3_NOFLUSH) };
+    } else {
+        unsafe { cr3_write(root | pcid as u64) };
+        seen.set(generation);
+    }
+}
+
+// invalidates the local TLB entry for `addr` in the context `pcid`; `current` is whether that
+// context is the one loaded right now
+pub fn flush_page(pcid: u16, current: bool, addr: u64, kernel: bool) {
+    if !enabled() {
+        // other contexts don't survive a CR3 load anyway
+        unsafe { x86::tlb::flush(addr as usize) };
+        return;
+    }
+
+    if kernel {
+        // the kernel half is shared by every context, and its mappings aren't necessarily global,
+        // so the page goes from each context that was ever handed out, and from the global ones
+        unsafe { x86::tlb::flush(addr as usize) };
+
+        for pcid in 0..NEXT_PCID.load(Ordering::Relaxed) {
+            unsafe { invpcid(INVPCID_ADDRESS, pcid, addr) };
+        }
+    } else if current {
+        unsafe { x86::tlb::flush(addr as usize) };
+    } else {
+        unsafe { invpcid(INVPCID_ADDRESS, pcid, addr) };
+    }
+}
@@ -1,21 +1,70 @@
 use core::cell::SyncUnsafeCell;
 
+use spin::Once;
 use uart_16550::SerialPort;
 
-use crate::log::CharSink;
+use super::{IrqState, apic, ioapic, irq_disable};
+use crate::{console, log::CharSink};
 
 pub struct SerialCharSink {
+    port: u16,
     serial: SyncUnsafeCell<SerialPort>,
+    // a second handle for the receive side, so the interrupt handler never aliases the one that
+    // is sending
+    rx: SyncUnsafeCell<SerialPort>,
 }
 
+// the port whose input goes to the console
+static RX: Once<&'static SerialCharSink> = Once::new();
+
 impl SerialCharSink {
     pub fn open(port: u16) -> SerialCharSink {
         let mut serial = unsafe { SerialPort::new(port) };
+        // this also enables receive interrupts on the UART
         serial.init();
         SerialCharSink {
+            port,
             serial: SyncUnsafeCell::new(serial),
+            rx: SyncUnsafeCell::new(unsafe { SerialPort::new(port) }),
         }
     }
+
+    // the ISA IRQ of the standard COM ports
+    fn isa_irq(&self) -> Option<u8> {
+        match self.port {
+            0x3f8 | 0x3e8 => Some(4),
+            0x2f8 | 0x2e8 => Some(3),
+            _ => None,
+        }
+    }
+
+    // routes receive interrupts to the current core, which needs its LAPIC and IDT set up, and
+    // feeds whatever comes in to the console; fails for non-standard ports, if some port is
+    // already receiving, or if the IRQ can't be routed
+    pub fn enable_rx(&'static self) -> Result<(), ()> {
+        let irq = self.isa_irq().ok_or(())?;
+
+        if RX.is_completed() {
+            return Err(());
+        }
+
+        ioapic::route_isa_irq(irq, apic::SERIAL_VECTOR, apic::id())?;
+        RX.call_once(|| self);
+
+        // the interrupt is edge triggered, so anything that arrived before it was routed has to
+        // be read out by hand, or the line stays raised for good
+        let state = IrqState::save();
+        irq_disable();
+        handle_rx_interrupt();
+        state.restore();
+
+        Ok(())
+    }
+
+    // waits for a byte; only for ports whose input doesn't go to the console
+    pub fn receive(&self) -> u8 {
+        unsafe { &mut *self.rx.get() }.receive()
+    }
 }
 
 impl CharSink for SerialCharSink {
@@ -27,3 +76,16 @@ impl CharSink for SerialCharSink {
         // no-op
     }
 }
+
+// handles SERIAL_VECTOR
+pub(super) fn handle_rx_interrupt() {
+    let Some(&serial) = RX.get() else {
+        return;
+    };
+
+    let rx = unsafe { &mut *serial.rx.get() };
+
+    while let Ok(byte) = rx.try_receive() {
+        console::input(byte, Some(serial));
+    }
+}
@@ -0,0 +1,89 @@
+// cpuid based topology detection
+//
+// this has to run on the core being described, since the APIC id and the leaves derived from it
+// are per logical processor.
+
+use arrayvec::ArrayVec;
+use x86::cpuid::{CacheType, CpuId, TopologyType};
+
+use crate::mp::topology::{CacheInfo, CacheKind, CoreTopology};
+
+// number of bits needed to give `count` logical processors distinct ids
+fn id_bits(count: usize) -> u32 {
+    count.max(1).next_power_of_two().trailing_zeros()
+}
+
+fn caches(cpuid: &CpuId, apic_id: u32) -> ArrayVec<CacheInfo, 8> {
+    let Some(params) = cpuid.get_cache_parameters() else {
+        return ArrayVec::new();
+    };
+
+    params
+        .filter_map(|cache| {
+            let kind = match cache.cache_type() {
+                CacheType::Data => CacheKind::Data,
+                CacheType::Instruction => CacheKind::Instruction,
+                CacheType::Unified => CacheKind::Unified,
+                _ => return None,
+            };
+
+            Some(CacheInfo {
+                level: cache.level(),
+                kind,
+                size: cache.associativity()
+                    * cache.physical_line_partitions()
+                    * cache.coherency_line_size()
+                    * cache.sets(),
+                line_size: cache.coherency_line_size(),
+                // every logical processor sharing the cache has the same upper APIC id bits
+                id: apic_id >> id_bits(cache.max_cores_for_cache()),
+            })
+        })
+        .take(8)
+        .collect()
+}
+
+pub fn detect() -> CoreTopology {
+    let cpuid = CpuId::new();
+
+    let (apic_id, smt_bits, package_bits) = match cpuid.get_extended_topology_info() {
+        Some(levels) => {
+            let mut apic_id = 0;
+            let mut smt_bits = 0;
+            let mut package_bits = 0;
+
+            // each level's shift covers every level below it, so the last one gives the package
+            for level in levels {
+                apic_id = level.x2apic_id();
+
+                if level.level_type() == TopologyType::SMT {
+                    smt_bits = level.shift_right_for_next_apic_id();
+                }
+
+                package_bits = level.shift_right_for_next_apic_id();
+            }
+
+            (apic_id, smt_bits, package_bits)
+        }
+        None => {
+            // no way to tell threads and cores apart, so treat every logical processor as a core
+            let info = cpuid
+                .get_feature_info()
+                .expect("x86::topology::detect(): cpuid leaf 1 missing");
+
+            (
+                info.initial_local_apic_id() as u32,
+                0,
+                id_bits(info.max_logical_processor_ids() as usize),
+            )
+        }
+    };
+
+    CoreTopology {
+        apic_id,
+        package: apic_id >> package_bits,
+        core: (apic_id & ((1 << package_bits) - 1)) >> smt_bits,
+        thread: apic_id & ((1 << smt_bits) - 1),
+        caches: caches(&cpuid, apic_id),
+    }
+}
@@ -0,0 +1,97 @@
+// tsc based timekeeping
+//
+// the TSC is assumed to be invariant and synchronized between cores, which holds on anything
+// recent and under QEMU/KVM. the frequency comes from CPUID when the CPU reports it, and is
+// calibrated against the PIT otherwise.
+
+use core::{hint, time::Duration};
+use log::info;
+use x86::{
+    cpuid::CpuId,
+    io::{inb, outb},
+    time::rdtsc,
+};
+
+use crate::sync::SeqLock;
+
+#[derive(Clone, Copy)]
+struct TscClock {
+    // TSC value at calibration time, which counts as time 0
+    offset: u64,
+    hz: u64,
+}
+
+static CLOCK: SeqLock<TscClock> = SeqLock::new(TscClock { offset: 0, hz: 0 });
+
+const PIT_FREQUENCY: u64 = 1_193_182;
+const PIT_CALIBRATION_MS: u64 = 10;
+
+fn cpuid_frequency() -> Option<u64> {
+    let cpuid = CpuId::new();
+
+    cpuid
+        .get_tsc_info()
+        .and_then(|info| info.tsc_frequency())
+        .or_else(|| {
+            cpuid
+                .get_processor_frequency_info()
+                .map(|info| info.processor_base_frequency() as u64 * 1_000_000)
+        })
+        .filter(|&hz| hz != 0)
+}
+
+// runs channel 2 of the PIT for a fixed interval and counts TSC ticks across it
+fn pit_frequency() -> u64 {
+    let count = PIT_FREQUENCY * PIT_CALIBRATION_MS / 1000;
+
+    unsafe {
+        // gate channel 2 on, with the speaker output off
+        let port61 = inb(0x61);
+        outb(0x61, (port61 & !0x02) | 0x01);
+
+        // channel 2, lobyte/hibyte, mode 0 (interrupt on terminal count)
+        outb(0x43, 0b1011_0000);
+        outb(0x42, count as u8);
+        outb(0x42, (count >> 8) as u8);
+
+        let start = rdtsc();
+
+        while inb(0x61) & 0x20 == 0 {
+            hint::spin_loop();
+        }
+
+        let end = rdtsc();
+
+        outb(0x61, port61);
+
+        (end - start) * 1000 / PIT_CALIBRATION_MS
+    }
+}
+
+// calibrates the TSC; only needs to run once, on the BSP
+pub fn init() {
+    let hz = cpuid_frequency().unwrap_or_else(pit_frequency);
+
+    CLOCK.set(TscClock {
+        offset: unsafe { rdtsc() },
+        hz,
+    });
+
+    info!("x86::tsc::init(): TSC running at {} kHz", hz / 1000);
+}
+
+pub fn frequency_hz() -> u64 {
+    CLOCK.read().hz
+}
+
+// time since the TSC was calibrated, or zero before that
+pub fn now() -> Duration {
+    let clock = CLOCK.read();
+
+    if clock.hz == 0 {
+        return Duration::ZERO;
+    }
+
+    let ticks = unsafe { rdtsc() }.saturating_sub(clock.offset);
+    Duration::from_nanos((ticks as u128 * 1_000_000_000 / clock.hz as u128) as u64)
+}
@@ -1,29 +1,161 @@
-use x86::bits64::registers::rbp;
+use core::arch::asm;
 
+use super::interrupt::InterruptContext;
+use crate::modules::symbols::{self, CfaRegister};
+
+// pushed by irq_handler_t0 in place of a return address; non-canonical, so it can't be a real one
+pub(super) const INTERRUPT_FRAME_MARKER: u64 = 0x1e7f_1e7f_1e7f_1e7f;
+
+// frames are recovered from the unwind table in the debug module when it covers the current pc,
+// and from the frame pointer chain otherwise (code without CFI, such as the interrupt entry
+// stubs, or before the debug module is loaded)
 #[derive(Clone, Copy)]
 pub struct UnwindContext {
-    ptr: *const u64,
+    pc: u64,
+    rsp: u64,
+    rbp: u64,
+    // whether pc is a return address, which may already point past the end of the calling
+    // function, rather than the instruction that was executing
+    after_call: bool,
 }
 
 impl UnwindContext {
+    const END: UnwindContext = UnwindContext {
+        pc: 0,
+        rsp: 0,
+        rbp: 0,
+        after_call: false,
+    };
+
     #[inline(always)]
     pub unsafe fn get() -> UnwindContext {
+        let (pc, rsp, rbp): (u64, u64, u64);
+
+        unsafe {
+            asm!(
+                "leaq 0(%rip), {}",
+                "movq %rsp, {}",
+                "movq %rbp, {}",
+                out(reg) pc,
+                out(reg) rsp,
+                out(reg) rbp,
+                options(att_syntax, nomem, nostack, preserves_flags)
+            );
+        }
+
+        UnwindContext {
+            pc,
+            rsp,
+            rbp,
+            after_call: false,
+        }
+    }
+
+    // starting at the code `context` interrupted, rather than the handler; the interrupted rbp is
+    // saved by the entry stub right below the frame marker
+    pub(super) unsafe fn interrupted(context: &InterruptContext) -> UnwindContext {
+        let frame = (context as *const InterruptContext as *const u64).wrapping_sub(2);
+
         UnwindContext {
-            ptr: rbp() as *const u64,
+            pc: context.rip,
+            rsp: context.rsp,
+            rbp: unsafe { frame.read() },
+            after_call: false,
+        }
+    }
+
+    fn rule(&self) -> Option<symbols::UnwindRule> {
+        symbols::unwind_rule(if self.after_call {
+            self.pc.wrapping_sub(1)
+        } else {
+            self.pc
+        })
+    }
+
+    // the interrupt entry frame is laid out as [saved rbp][marker][InterruptContext]
+    unsafe fn interrupt_context(&self) -> Option<*const InterruptContext> {
+        if self.rule().is_some() || self.rbp == 0 {
+            return None;
+        }
+
+        let frame = self.rbp as *const u64;
+
+        if unsafe { frame.wrapping_add(1).read() } != INTERRUPT_FRAME_MARKER {
+            return None;
+        }
+
+        Some(frame.wrapping_add(2) as *const InterruptContext)
+    }
+
+    unsafe fn caller(&self) -> Option<UnwindContext> {
+        if let Some(rule) = self.rule() {
+            let cfa = match rule.cfa_register {
+                CfaRegister::Rsp => self.rsp,
+                CfaRegister::Rbp => self.rbp,
+            }
+            .wrapping_add_signed(rule.cfa_offset as i64);
+
+            if cfa == 0 {
+                return None;
+            }
+
+            return Some(UnwindContext {
+                pc: unsafe { ((cfa - 8) as *const u64).read() },
+                rsp: cfa,
+                rbp: match rule.rbp_offset {
+                    Some(offset) => unsafe {
+                        (cfa.wrapping_add_signed(offset as i64) as *const u64).read()
+                    },
+                    None => self.rbp,
+                },
+                after_call: true,
+            });
+        }
+
+        if self.rbp == 0 {
+            return None;
         }
+
+        let frame = self.rbp as *const u64;
+
+        // the saved rbp of an interrupt entry frame is the interrupted code's, so the chain simply
+        // continues from there
+        if let Some(context) = unsafe { self.interrupt_context() } {
+            // the frames of user code can't be trusted, so stop at the ring boundary
+            if unsafe { (*context).cs } & 0b11 != 0 {
+                return None;
+            }
+
+            return Some(UnwindContext {
+                pc: unsafe { (*context).rip },
+                rsp: unsafe { (*context).rsp },
+                rbp: unsafe { frame.read() },
+                after_call: false,
+            });
+        }
+
+        Some(UnwindContext {
+            pc: unsafe { frame.wrapping_add(1).read() },
+            rsp: self.rbp + 16,
+            rbp: unsafe { frame.read() },
+            after_call: true,
+        })
     }
 
     pub unsafe fn valid(&self) -> bool {
         (unsafe { self.return_address() }) != 0
     }
 
+    // the vector of the interrupt this frame was interrupted by, if it is an interrupt entry frame
+    pub unsafe fn interrupt_vector(&self) -> Option<u64> {
+        unsafe { self.interrupt_context().map(|context| (*context).id) }
+    }
+
     pub unsafe fn return_address(&self) -> u64 {
-        unsafe { self.ptr.wrapping_add(1).read() }
+        unsafe { self.caller() }.map_or(0, |caller| caller.pc)
     }
 
     pub unsafe fn next(&self) -> UnwindContext {
-        UnwindContext {
-            ptr: unsafe { self.ptr.read() } as *const u64,
-        }
+        unsafe { self.caller() }.unwrap_or(Self::END)
     }
 }
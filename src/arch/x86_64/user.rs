@@ -0,0 +1,62 @@
+// dropping to ring 3
+//
+// coming back is the usual interrupt path: every IDT entry switches to an IST stack, and the TSS
+// rsp0 set up in initialize_core covers anything else that changes privilege level.
+
+use core::arch::asm;
+use x86::{Ring, segmentation::SegmentSelector};
+
+use super::{dt::GlobalDescriptorTable, paging::PageTableSet};
+use crate::mem::{VirtualAddress, Wrapper};
+
+// IF, plus the always-one bit
+const USER_RFLAGS: u64 = 0x202;
+
+// switches to `address_space` and starts running user code at `entry` with the stack at `stack`
+// safety: both must be mapped as user accessible in `address_space`, which must also contain the
+// kernel half
+pub unsafe fn enter_user(
+    entry: VirtualAddress,
+    stack: VirtualAddress,
+    address_space: &PageTableSet,
+) -> ! {
+    let cs = SegmentSelector::new(GlobalDescriptorTable::USER_CS, Ring::Ring3).bits() as u64;
+    let ss = SegmentSelector::new(GlobalDescriptorTable::USER_DS, Ring::Ring3).bits() as u64;
+
+    unsafe {
+        address_space.set_current();
+
+        asm!(
+            "push {ss}",
+            "push {rsp}",
+            "push {rflags}",
+            "push {cs}",
+            "push {rip}",
+            // don't hand kernel values to user code
+            "xor eax, eax",
+            "xor ebx, ebx",
+            "xor ecx, ecx",
+            "xor edx, edx",
+            "xor esi, esi",
+            "xor edi, edi",
+            "xor ebp, ebp",
+            "xor r8d, r8d",
+            "xor r9d, r9d",
+            "xor r10d, r10d",
+            "xor r11d, r11d",
+            "xor r12d, r12d",
+            "xor r13d, r13d",
+            "xor r14d, r14d",
+            "xor r15d, r15d",
+            // user code gets its own GS_BASE; the core local block is swapped back in on entry
+            "swapgs",
+            "iretq",
+            ss = in(reg) ss,
+            rsp = in(reg) stack.value(),
+            rflags = in(reg) USER_RFLAGS,
+            cs = in(reg) cs,
+            rip = in(reg) entry.value(),
+            options(noreturn)
+        );
+    }
+}
@@ -4,39 +4,79 @@ use core::{
     ops::Range,
 };
 
+use arrayvec::ArrayVec;
 use derive_more::Display;
 use logos::{Lexer, Logos};
 
 use super::CmdlineParsable;
 
-fn parse_int(mut str: &str) -> i64 {
+// None if the literal doesn't fit in an i64
+fn parse_int(mut str: &str) -> Option<i64> {
     let mut neg = false;
     if str.starts_with("-") {
         str = &str[1..];
         neg = true;
     }
 
-    let res;
-
-    if let Some(str) = str.strip_prefix("0x") {
-        res = i64::from_str_radix(str, 16).unwrap();
+    let (radix, digits) = if let Some(str) = str.strip_prefix("0x") {
+        (16, str)
     } else if let Some(str) = str.strip_prefix("0o") {
-        res = i64::from_str_radix(str, 8).unwrap();
+        (8, str)
     } else if let Some(str) = str.strip_prefix("0") {
-        if str.is_empty() {
-            res = 0;
+        (8, str)
+    } else {
+        (10, str)
+    };
+
+    // accumulated with the sign already applied, so that i64::MIN fits too
+    digits.chars().try_fold(0i64, |res, ch| {
+        let digit = ch.to_digit(radix)? as i64;
+        let res = res.checked_mul(radix as i64)?;
+
+        if neg {
+            res.checked_sub(digit)
         } else {
-            res = i64::from_str_radix(str, 8).unwrap();
+            res.checked_add(digit)
         }
-    } else {
-        res = str.parse::<i64>().unwrap();
+    })
+}
+
+// integers may carry a binary size suffix (K, M, G or T); None if the result doesn't fit
+fn parse_sized_int(str: &str) -> Option<i64> {
+    let shift = match str.as_bytes().last()? {
+        b'k' | b'K' => 10,
+        b'm' | b'M' => 20,
+        b'g' | b'G' => 30,
+        b't' | b'T' => 40,
+        _ => return parse_int(str),
+    };
+
+    parse_int(&str[..str.len() - 1])?.checked_mul(1 << shift)
+}
+
+// an inclusive `lo-hi` range of non-negative integers, e.g. `0-3` or `1M-16M`
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Default)]
+pub struct NumberRange {
+    pub start: u64,
+    pub end: u64,
+}
+
+impl Display for NumberRange {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+        write!(f, "{}-{}", self.start, self.end)
     }
+}
+
+fn parse_range(str: &str) -> Option<NumberRange> {
+    let (start, end) = str.split_once('-')?;
+    let start = parse_sized_int(start)?.try_into().ok()?;
+    let end = parse_sized_int(end)?.try_into().ok()?;
 
-    if neg { -res } else { res }
+    (start <= end).then_some(NumberRange { start, end })
 }
 
 #[derive(Logos, Debug, PartialEq, Clone, Copy, Display)]
-#[logos(skip r"[ \t\n\f]+")]
+#[logos(skip r"[ \t\r\n\f]+")]
 pub enum CmdlineTokenData<'a> {
     #[token(",")]
     Comma,
@@ -56,8 +96,20 @@ pub enum CmdlineTokenData<'a> {
     ClosedParen,
     #[regex("[a-zA-Z_][a-zA-Z0-9_]*")]
     Identifier(&'a str),
-    #[regex("-?([1-9][0-9]*|0[0-7]*|0o[0-7]+|0x[0-9a-fA-F]+)", |lex| parse_int(lex.slice()))]
+    #[regex("-?([1-9][0-9]*|0[0-7]*|0o[0-7]+|0x[0-9a-fA-F]+)[kKmMgGtT]?", |lex| parse_sized_int(lex.slice()))]
     Number(i64),
+    // longer than a number followed by a negative one, so `0-3` is always a range
+    #[regex("([1-9][0-9]*|0[0-7]*|0o[0-7]+|0x[0-9a-fA-F]+)[kKmMgGtT]?-([1-9][0-9]*|0[0-7]*|0o[0-7]+|0x[0-9a-fA-F]+)[kKmMgGtT]?", |lex| parse_range(lex.slice()))]
+    Range(NumberRange),
+    // colon separated hex bytes, e.g. a mac address; at least three, so that `ab:cd` is still a
+    // field and its value
+    #[regex("[0-9a-fA-F]{2}(:[0-9a-fA-F]{2}){2,}")]
+    HexBytes(&'a str),
+    #[regex("[0-9a-fA-F]{8}-[0-9a-fA-F]{4}-[0-9a-fA-F]{4}-[0-9a-fA-F]{4}-[0-9a-fA-F]{12}")]
+    Uuid(&'a str),
+    // no escapes, so a string can't contain a double quote
+    #[regex(r#""[^"]*""#, |lex| { let str = lex.slice(); &str[1..str.len() - 1] })]
+    String(&'a str),
     Eof,
 }
 
@@ -74,6 +126,17 @@ pub enum CmdlineErrorCode<'a> {
     BadToken,
     BadBoolean(CmdlineTokenData<'a>),
     BadInt(CmdlineTokenData<'a>),
+    BadString(CmdlineTokenData<'a>),
+    BadRange(CmdlineTokenData<'a>),
+    BadHex(CmdlineTokenData<'a>),
+    StringTooLong(usize),
+    // from #[validate(min = .., max = ..)]
+    OutOfRange {
+        min: Option<i64>,
+        max: Option<i64>,
+    },
+    // from #[validate(with = "..")]
+    Invalid(&'static str),
 }
 
 #[derive(Debug)]
@@ -103,15 +166,87 @@ impl<'a> Display for CmdlineParseError<'a> {
             CmdlineErrorCode::BadToken => f.write_str("bad token")?,
             CmdlineErrorCode::BadBoolean(tok) => write!(f, "bad boolean token: {} ", tok)?,
             CmdlineErrorCode::BadInt(tok) => write!(f, "bad int token: {} ", tok)?,
+            CmdlineErrorCode::BadString(tok) => write!(f, "bad string token: {} ", tok)?,
+            CmdlineErrorCode::BadRange(tok) => write!(f, "bad range token: {} ", tok)?,
+            CmdlineErrorCode::BadHex(tok) => write!(f, "bad hex token: {} ", tok)?,
+            CmdlineErrorCode::StringTooLong(max) => {
+                write!(f, "string too long; at most {} bytes", max)?
+            }
+            CmdlineErrorCode::OutOfRange { min, max } => match (min, max) {
+                (Some(min), Some(max)) => write!(f, "value out of range; {} to {}", min, max)?,
+                (Some(min), None) => write!(f, "value out of range; at least {}", min)?,
+                (None, Some(max)) => write!(f, "value out of range; at most {}", max)?,
+                (None, None) => f.write_str("value out of range")?,
+            },
+            CmdlineErrorCode::Invalid(reason) => write!(f, "invalid value: {}", reason)?,
         };
 
         write!(f, " at {:?}", self.1)
     }
 }
 
+impl<'a> CmdlineParseError<'a> {
+    // the cmdline the error came from, with the rejected token underlined
+    pub fn caret<'b>(&'b self, text: &'b str) -> Caret<'b> {
+        Caret {
+            text,
+            range: self.1.clone(),
+        }
+    }
+}
+
+pub struct Caret<'a> {
+    text: &'a str,
+    range: Range<usize>,
+}
+
+impl Display for Caret<'_> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+        // only the line the token starts on is shown, which matters for config modules
+        let token_start = self.range.start.min(self.text.len());
+        let line_start = self.text[..token_start].rfind('\n').map_or(0, |i| i + 1);
+        let line_end = self.text[token_start..]
+            .find('\n')
+            .map_or(self.text.len(), |i| token_start + i);
+        let line = &self.text[line_start..line_end];
+
+        // the range is in bytes, but the underline is in columns
+        let columns = |end: usize| {
+            let end = end.clamp(line_start, line_end) - line_start;
+            line.get(..end).map_or(end, |s| s.chars().count())
+        };
+
+        let start = columns(self.range.start);
+        // eof and other empty tokens still get a caret
+        let width = (columns(self.range.end) - start).max(1);
+
+        writeln!(f, "{}", line)?;
+
+        for _ in 0..start {
+            f.write_str(" ")?;
+        }
+
+        f.write_str("^")?;
+
+        for _ in 1..width {
+            f.write_str("~")?;
+        }
+
+        Ok(())
+    }
+}
+
+// how many skipped options are remembered in lenient mode; any more are still skipped
+pub const MAX_SKIPPED: usize = 8;
+
 pub struct CmdlineLexer<'a> {
     lexer: Lexer<'a, CmdlineTokenData<'a>>,
     current: CmdlineToken<'a>,
+    // skip unknown fields instead of failing the whole parse
+    lenient: bool,
+    skipped: ArrayVec<CmdlineParseError<'a>, MAX_SKIPPED>,
+    // end of the last token returned by next
+    prev_end: usize,
 }
 
 impl<'a> CmdlineToken<'a> {
@@ -152,9 +287,61 @@ impl<'a> CmdlineLexer<'a> {
         Ok(CmdlineLexer {
             lexer,
             current: tok,
+            lenient: false,
+            skipped: ArrayVec::new(),
+            prev_end: 0,
         })
     }
 
+    pub fn set_lenient(&mut self, lenient: bool) {
+        self.lenient = lenient;
+    }
+
+    // the unknown fields that were skipped in lenient mode
+    pub fn take_skipped(&mut self) -> ArrayVec<CmdlineParseError<'a>, MAX_SKIPPED> {
+        mem::take(&mut self.skipped)
+    }
+
+    // called right after an unknown field name, enumerator or flag; fails with `err` normally, but
+    // in lenient mode remembers it and skips over the field's value or the enumerator's payload,
+    // if it has one
+    pub fn skip_unknown(
+        &mut self,
+        err: CmdlineParseError<'a>,
+    ) -> Result<(), CmdlineParseError<'a>> {
+        if !self.lenient {
+            return Err(err);
+        }
+
+        let _ = self.skipped.try_push(err);
+
+        match self.peek().0 {
+            CmdlineTokenData::Colon => {
+                self.next()?;
+            }
+            CmdlineTokenData::OpenBrace | CmdlineTokenData::OpenParen => {}
+            _ => return Ok(()),
+        }
+
+        // a value ends at the next delimiter that isn't nested inside it
+        let mut depth = 0usize;
+
+        loop {
+            match self.peek().0 {
+                CmdlineTokenData::OpenBrace | CmdlineTokenData::OpenParen => depth += 1,
+                CmdlineTokenData::ClosedBrace | CmdlineTokenData::ClosedParen if depth == 0 => {
+                    return Ok(());
+                }
+                CmdlineTokenData::ClosedBrace | CmdlineTokenData::ClosedParen => depth -= 1,
+                CmdlineTokenData::Comma if depth == 0 => return Ok(()),
+                CmdlineTokenData::Eof => return Ok(()),
+                _ => {}
+            }
+
+            self.next()?;
+        }
+    }
+
     pub fn parse<T: CmdlineParsable>(
         data: &'a str,
         out: &mut T,
@@ -181,6 +368,7 @@ impl<'a> CmdlineLexer<'a> {
         }
 
         mem::swap(&mut self.current, &mut tok);
+        self.prev_end = tok.1.end;
 
         Ok(tok)
     }
@@ -189,6 +377,12 @@ impl<'a> CmdlineLexer<'a> {
         &self.current
     }
 
+    // the input from `start` up to the end of the last token taken, e.g. a whole value once it
+    // has been parsed
+    pub fn span_from(&self, start: usize) -> Range<usize> {
+        start..self.prev_end.max(start)
+    }
+
     pub fn expect(&mut self, tok: CmdlineTokenData<'static>) -> Result<(), CmdlineParseError<'a>> {
         let CmdlineToken(data, range) = self.next()?;
 
@@ -235,26 +429,45 @@ mod test {
 
     #[test]
     fn test_parse_int_decimal() {
-        assert_eq!(parse_int("123"), 123);
-        assert_eq!(parse_int("-123"), -123);
+        assert_eq!(parse_int("123"), Some(123));
+        assert_eq!(parse_int("-123"), Some(-123));
     }
 
     #[test]
     fn test_parse_int_hex() {
-        assert_eq!(parse_int("0x1a3"), 0x1a3);
-        assert_eq!(parse_int("-0x1a3"), -0x1a3);
+        assert_eq!(parse_int("0x1a3"), Some(0x1a3));
+        assert_eq!(parse_int("-0x1a3"), Some(-0x1a3));
     }
 
     #[test]
     fn test_parse_int_octal() {
-        assert_eq!(parse_int("075"), 0o75);
-        assert_eq!(parse_int("-075"), -0o75);
+        assert_eq!(parse_int("075"), Some(0o75));
+        assert_eq!(parse_int("-075"), Some(-0o75));
     }
 
     #[test]
     fn test_parse_int_zero() {
-        assert_eq!(parse_int("0"), 0);
-        assert_eq!(parse_int("-0"), 0);
+        assert_eq!(parse_int("0"), Some(0));
+        assert_eq!(parse_int("-0"), Some(0));
+    }
+
+    #[test]
+    fn test_parse_int_overflow() {
+        assert_eq!(parse_int("9223372036854775807"), Some(i64::MAX));
+        assert_eq!(parse_int("-9223372036854775808"), Some(i64::MIN));
+        assert_eq!(parse_int("9223372036854775808"), None);
+        assert_eq!(parse_int("0x10000000000000000"), None);
+        assert_eq!(parse_sized_int("99999999999999999999K"), None);
+    }
+
+    #[test]
+    fn test_parse_sized_int() {
+        assert_eq!(parse_sized_int("123"), Some(123));
+        assert_eq!(parse_sized_int("4K"), Some(4 << 10));
+        assert_eq!(parse_sized_int("16m"), Some(16 << 20));
+        assert_eq!(parse_sized_int("0x10G"), Some(16 << 30));
+        assert_eq!(parse_sized_int("-1T"), Some(-(1 << 40)));
+        assert_eq!(parse_sized_int("0x10000000T"), None);
     }
 
     #[test]
@@ -291,6 +504,45 @@ mod test {
         assert_eq!(lexer.next().unwrap().0, CmdlineTokenData::Number(-42));
     }
 
+    #[test]
+    fn test_cmdline_tokenizer_strings() {
+        let data = r#""hello, world" "" name:"a:b""#;
+        let mut lexer = CmdlineLexer::new(data).unwrap();
+
+        assert_eq!(
+            lexer.next().unwrap().0,
+            CmdlineTokenData::String("hello, world")
+        );
+        assert_eq!(lexer.next().unwrap().0, CmdlineTokenData::String(""));
+        assert_eq!(
+            lexer.next().unwrap().0,
+            CmdlineTokenData::Identifier("name")
+        );
+        assert_eq!(lexer.next().unwrap().0, CmdlineTokenData::Colon);
+        assert_eq!(lexer.next().unwrap().0, CmdlineTokenData::String("a:b"));
+    }
+
+    #[test]
+    fn test_cmdline_tokenizer_unterminated_string() {
+        let data = r#""unterminated"#;
+        let mut lexer = CmdlineLexer::new(data);
+
+        assert_eq!(lexer.err().unwrap().0, CmdlineErrorCode::BadToken);
+    }
+
+    #[test]
+    fn test_cmdline_tokenizer_sizes() {
+        let data = "4K 1G size";
+        let mut lexer = CmdlineLexer::new(data).unwrap();
+
+        assert_eq!(lexer.next().unwrap().0, CmdlineTokenData::Number(4096));
+        assert_eq!(lexer.next().unwrap().0, CmdlineTokenData::Number(1 << 30));
+        assert_eq!(
+            lexer.next().unwrap().0,
+            CmdlineTokenData::Identifier("size")
+        );
+    }
+
     #[test]
     fn test_cmdline_tokenizer_commas_and_colons() {
         let data = "cmd1, cmd2:cmd3";
@@ -383,6 +635,151 @@ mod test {
             .unwrap();
     }
 
+    #[test]
+    fn test_skip_unknown_strict() {
+        let data = "unknown:{a, b}, known";
+        let mut lexer = CmdlineLexer::new(data).unwrap();
+
+        let tok = lexer.next().unwrap();
+        let err = tok.make_error(CmdlineErrorCode::BadToken);
+
+        assert!(lexer.skip_unknown(err).is_err());
+    }
+
+    #[test]
+    fn test_skip_unknown_lenient() {
+        let data = "unknown:{a, (b, c)}, flag, known";
+        let mut lexer = CmdlineLexer::new(data).unwrap();
+        lexer.set_lenient(true);
+
+        for _ in 0..2 {
+            let tok = lexer.next().unwrap();
+            let err = tok.make_error(CmdlineErrorCode::BadToken);
+            lexer.skip_unknown(err).unwrap();
+            assert_eq!(lexer.next().unwrap().0, CmdlineTokenData::Comma);
+        }
+
+        assert_eq!(
+            lexer.next().unwrap().0,
+            CmdlineTokenData::Identifier("known")
+        );
+        assert_eq!(lexer.take_skipped().len(), 2);
+    }
+
+    #[test]
+    fn test_skip_unknown_payload() {
+        let data = "unknown { a: (b, c) }, known";
+        let mut lexer = CmdlineLexer::new(data).unwrap();
+        lexer.set_lenient(true);
+
+        let tok = lexer.next().unwrap();
+        let err = tok.make_error(CmdlineErrorCode::BadToken);
+        lexer.skip_unknown(err).unwrap();
+
+        assert_eq!(lexer.next().unwrap().0, CmdlineTokenData::Comma);
+        assert_eq!(
+            lexer.next().unwrap().0,
+            CmdlineTokenData::Identifier("known")
+        );
+    }
+
+    #[test]
+    fn test_parse_range() {
+        assert_eq!(parse_range("0-3"), Some(NumberRange { start: 0, end: 3 }));
+        assert_eq!(
+            parse_range("1M-0x2M"),
+            Some(NumberRange {
+                start: 1 << 20,
+                end: 2 << 20
+            })
+        );
+        assert_eq!(parse_range("3-3"), Some(NumberRange { start: 3, end: 3 }));
+        assert_eq!(parse_range("4-3"), None);
+    }
+
+    #[test]
+    fn test_range_token() {
+        let data = "0-3, 1-, 5";
+        let mut lexer = CmdlineLexer::new(data).unwrap();
+
+        assert_eq!(
+            lexer.next().unwrap().0,
+            CmdlineTokenData::Range(NumberRange { start: 0, end: 3 })
+        );
+        assert_eq!(lexer.next().unwrap().0, CmdlineTokenData::Comma);
+
+        // a dangling `-` is rejected rather than read as a negative number
+        assert_eq!(lexer.next().unwrap_err().1, 6..7);
+    }
+
+    #[test]
+    fn test_hex_tokens() {
+        let data = "mac:52:54:00:12:34:56, ab:cd, fb:{id:0b5d2e4c-09a8-4f5e-9d2b-3c0e6a8f7b21}";
+        let mut lexer = CmdlineLexer::new(data).unwrap();
+
+        assert_eq!(lexer.next().unwrap().0, CmdlineTokenData::Identifier("mac"));
+        assert_eq!(lexer.next().unwrap().0, CmdlineTokenData::Colon);
+        assert_eq!(
+            lexer.next().unwrap().0,
+            CmdlineTokenData::HexBytes("52:54:00:12:34:56")
+        );
+        assert_eq!(lexer.next().unwrap().0, CmdlineTokenData::Comma);
+        assert_eq!(lexer.next().unwrap().0, CmdlineTokenData::Identifier("ab"));
+        assert_eq!(lexer.next().unwrap().0, CmdlineTokenData::Colon);
+        assert_eq!(lexer.next().unwrap().0, CmdlineTokenData::Identifier("cd"));
+        assert_eq!(lexer.next().unwrap().0, CmdlineTokenData::Comma);
+        assert_eq!(lexer.next().unwrap().0, CmdlineTokenData::Identifier("fb"));
+        assert_eq!(lexer.next().unwrap().0, CmdlineTokenData::Colon);
+        assert_eq!(lexer.next().unwrap().0, CmdlineTokenData::OpenBrace);
+        assert_eq!(lexer.next().unwrap().0, CmdlineTokenData::Identifier("id"));
+        assert_eq!(lexer.next().unwrap().0, CmdlineTokenData::Colon);
+        assert_eq!(
+            lexer.next().unwrap().0,
+            CmdlineTokenData::Uuid("0b5d2e4c-09a8-4f5e-9d2b-3c0e6a8f7b21")
+        );
+        assert_eq!(lexer.next().unwrap().0, CmdlineTokenData::ClosedBrace);
+    }
+
+    #[test]
+    fn test_span_from() {
+        let data = "a:(1, 2), b";
+        let mut lexer = CmdlineLexer::new(data).unwrap();
+
+        lexer.next().unwrap();
+        lexer.next().unwrap();
+
+        let start = lexer.peek().1.start;
+
+        for _ in 0..5 {
+            lexer.next().unwrap();
+        }
+
+        assert_eq!(&data[lexer.span_from(start)], "(1, 2)");
+    }
+
+    #[test]
+    fn test_caret() {
+        use arrayvec::ArrayString;
+        use core::fmt::Write;
+
+        let data = "mem:{oom:crash}";
+        let err = CmdlineParseError(CmdlineErrorCode::BadToken, 9..14);
+        let mut out = ArrayString::<64>::new();
+        write!(out, "{}", err.caret(data)).unwrap();
+        assert_eq!(&out[..], "mem:{oom:crash}\n         ^~~~~");
+
+        let err = CmdlineParseError(CmdlineErrorCode::BadToken, 15..15);
+        let mut out = ArrayString::<64>::new();
+        write!(out, "{}", err.caret(data)).unwrap();
+        assert_eq!(&out[..], "mem:{oom:crash}\n               ^");
+
+        let data = "mem:{\n  oom:crash\n}";
+        let err = CmdlineParseError(CmdlineErrorCode::BadToken, 12..17);
+        let mut out = ArrayString::<64>::new();
+        write!(out, "{}", err.caret(data)).unwrap();
+        assert_eq!(&out[..], "  oom:crash\n      ^~~~~");
+    }
+
     #[test]
     fn test_parse_block_end_token() {
         let data = "{cmd1 cmd2}";
@@ -1,22 +1,42 @@
 mod lexer;
 mod parse;
+mod schema;
 
-use core::{cell::SyncUnsafeCell, str::Utf8Error};
+use core::{cell::SyncUnsafeCell, fmt, str::Utf8Error};
 
 pub use lexer::*;
 pub use parse::*;
+pub use schema::*;
 
+use arrayvec::ArrayVec;
 use limine::request::ExecutableCmdlineRequest;
+use log::info;
 use proc_macros::CmdlineParsable;
 use spin::Once;
 
-use crate::log::options::{
-    FormatOptions, FramebufferOptions, LogLevel, LogMode, LogOptions, LogSource, SerialOptions,
+use crate::{
+    crashdump::options::CrashDumpOptions,
+    gdb::options::GdbOptions,
+    log::options::{LogOptions, TargetLevels},
+    mem::options::MemOptions,
+    modules::find_config_module,
+    mp::options::MpOptions,
+    profile::options::ProfileOptions,
+    sync::{IntMutex, SeqLock},
 };
 
 #[derive(Clone, Copy)]
 pub struct KernelCmdline {
     pub logging: LogOptions,
+    pub mem: MemOptions,
+    pub mp: MpOptions,
+    pub gdb: GdbOptions,
+    pub crashdump: CrashDumpOptions,
+    pub profile: ProfileOptions,
+    // list every recognized option at boot, see dump_cmdline_schema
+    pub help: bool,
+    // skip unknown options with a warning instead of rejecting the whole cmdline
+    pub lenient: bool,
 }
 
 impl CmdlineParsable for KernelCmdline {
@@ -24,14 +44,61 @@ impl CmdlineParsable for KernelCmdline {
         lexer.parse_block(CmdlineTokenData::Eof, CmdlineTokenData::Comma, |lexer| {
             let tok = lexer.next()?;
             match tok.unwrap_ident()? {
+                "help" => {
+                    self.help = true;
+                    Ok(())
+                }
+                // already applied by parse_kernel_cmdline, before anything else was parsed
+                "lenient" => {
+                    self.lenient = true;
+                    Ok(())
+                }
                 "logging" => {
                     lexer.expect(crate::cmdline::CmdlineTokenData::Colon)?;
                     self.logging.parse(lexer)
                 }
-                _ => Err(tok.make_error(CmdlineErrorCode::UnknownFlag(&["logging"]))),
+                "mem" => {
+                    lexer.expect(crate::cmdline::CmdlineTokenData::Colon)?;
+                    self.mem.parse(lexer)
+                }
+                "mp" => {
+                    lexer.expect(crate::cmdline::CmdlineTokenData::Colon)?;
+                    self.mp.parse(lexer)
+                }
+                "gdb" => {
+                    lexer.expect(crate::cmdline::CmdlineTokenData::Colon)?;
+                    self.gdb.parse(lexer)
+                }
+                "crashdump" => {
+                    lexer.expect(crate::cmdline::CmdlineTokenData::Colon)?;
+                    self.crashdump.parse(lexer)
+                }
+                "profile" => {
+                    lexer.expect(crate::cmdline::CmdlineTokenData::Colon)?;
+                    self.profile.parse(lexer)
+                }
+                _ => lexer.skip_unknown(tok.make_error(CmdlineErrorCode::UnknownFlag(&[
+                    "logging",
+                    "mem",
+                    "mp",
+                    "gdb",
+                    "crashdump",
+                    "profile",
+                    "help",
+                    "lenient",
+                ]))),
             }
         })
     }
+
+    fn schema(&self, writer: &mut SchemaWriter) -> fmt::Result {
+        writer.field("logging", &[], &self.logging)?;
+        writer.field("mem", &[], &self.mem)?;
+        writer.field("mp", &[], &self.mp)?;
+        writer.field("gdb", &[], &self.gdb)?;
+        writer.field("crashdump", &[], &self.crashdump)?;
+        writer.field("profile", &[], &self.profile)
+    }
 }
 
 // requests
@@ -41,23 +108,14 @@ impl CmdlineParsable for KernelCmdline {
 static CMDLINE_REQUEST: ExecutableCmdlineRequest = ExecutableCmdlineRequest::new();
 
 static DEFAULT_OPTIONS: KernelCmdline = KernelCmdline {
-    logging: LogOptions {
-        serial: SerialOptions {
-            enable: false,
-            // TODO: this is definitely arch dependent
-            port: 0x3f8,
-            mode: LogMode(LogLevel::Info, LogSource::all(), LogLevel::Warn),
-        },
-        fb: FramebufferOptions {
-            mode: LogMode(LogLevel::Info, LogSource::all(), LogLevel::Warn),
-        },
-        options: FormatOptions {
-            level: true,
-            target: true,
-            mod_path: false,
-            src: false,
-        },
-    },
+    logging: LogOptions::DEFAULT,
+    mem: MemOptions::DEFAULT,
+    mp: MpOptions::DEFAULT,
+    gdb: GdbOptions::DEFAULT,
+    crashdump: CrashDumpOptions::DEFAULT,
+    profile: ProfileOptions::DEFAULT,
+    help: false,
+    lenient: false,
 };
 
 pub enum CmdlineError {
@@ -66,16 +124,68 @@ pub enum CmdlineError {
     ParseError(CmdlineParseError<'static>),
 }
 
+// how the config module went, if there was one
+pub struct ConfigResult {
+    pub text: &'static str,
+    pub error: Option<CmdlineParseError<'static>>,
+    pub skipped: ArrayVec<CmdlineParseError<'static>, MAX_SKIPPED>,
+}
+
 static CMDLINE_TEXT: Once<&'static str> = Once::new();
 // need to use SyncUnsafeCell here because we need a mutable ref for parse to avoid stack
 // allocations
 static CMDLINE_STATE: SyncUnsafeCell<KernelCmdline> = SyncUnsafeCell::new(DEFAULT_OPTIONS);
 static CMDLINE_ERROR: Once<CmdlineError> = Once::new();
+static CMDLINE_SKIPPED: Once<ArrayVec<CmdlineParseError<'static>, MAX_SKIPPED>> = Once::new();
+static CONFIG_RESULT: Once<ConfigResult> = Once::new();
+// starts out as the boot cmdline, and is then changed by reconfigure_cmdline
+static RUNTIME_CMDLINE: SeqLock<KernelCmdline> = SeqLock::new(DEFAULT_OPTIONS);
+// serializes changes to the runtime options; they are made on a copy and published with a single
+// store, so readers never wait on a parse
+static RECONFIGURE_LOCK: IntMutex<()> = IntMutex::new(());
 
+// the options the kernel was booted with; anything that is only looked at during boot should use
+// these
 pub fn get_cmdline() -> &'static KernelCmdline {
     unsafe { &*CMDLINE_STATE.get() }
 }
 
+// the options as they currently are, for the ones that can be changed after boot
+pub fn get_runtime_cmdline() -> KernelCmdline {
+    RUNTIME_CMDLINE.read()
+}
+
+// the runtime logging options, which are read for every record, without copying the rest
+pub fn get_runtime_log_options() -> LogOptions {
+    RUNTIME_CMDLINE.read_part(|options| unsafe { &raw const (*options).logging })
+}
+
+// just the per target levels of the runtime logging options
+pub fn get_runtime_target_levels() -> TargetLevels {
+    RUNTIME_CMDLINE.read_part(|options| unsafe { &raw const (*options).logging.targets })
+}
+
+// parses `text` on top of the current runtime options, e.g. `logging:{options:{src}}`; on error,
+// nothing is changed
+pub fn reconfigure_cmdline(text: &str) -> Result<(), CmdlineParseError<'_>> {
+    let _guard = RECONFIGURE_LOCK.lock();
+
+    let mut updated = RUNTIME_CMDLINE.read();
+    CmdlineLexer::parse(text, &mut updated)?;
+    RUNTIME_CMDLINE.set(updated);
+
+    Ok(())
+}
+
+// changes the runtime options in place, for callers that know exactly what they want changed
+pub fn update_runtime_cmdline(update: impl FnOnce(&mut KernelCmdline)) {
+    let _guard = RECONFIGURE_LOCK.lock();
+
+    let mut updated = RUNTIME_CMDLINE.read();
+    update(&mut updated);
+    RUNTIME_CMDLINE.set(updated);
+}
+
 pub fn get_cmdline_text() -> Option<&'static str> {
     CMDLINE_TEXT.get().map(|v| &**v)
 }
@@ -84,27 +194,113 @@ pub fn get_cmdline_error() -> Option<&'static CmdlineError> {
     CMDLINE_ERROR.get()
 }
 
+pub fn get_config_result() -> Option<&'static ConfigResult> {
+    CONFIG_RESULT.get()
+}
+
+// unknown options that were skipped because of `lenient`
+pub fn get_cmdline_skipped() -> &'static [CmdlineParseError<'static>] {
+    CMDLINE_SKIPPED.get().map_or(&[], |v| &v[..])
+}
+
+// whether `flag` appears as a top level option; lenient mode has to be known before parsing starts,
+// wherever it's written
+fn has_top_level_flag(data: &str, flag: &str) -> bool {
+    let Ok(mut lexer) = CmdlineLexer::new(data) else {
+        return false;
+    };
+
+    let mut depth = 0usize;
+
+    loop {
+        let Ok(tok) = lexer.next() else {
+            return false;
+        };
+
+        match tok.0 {
+            CmdlineTokenData::Eof => return false,
+            CmdlineTokenData::OpenBrace | CmdlineTokenData::OpenParen => depth += 1,
+            CmdlineTokenData::ClosedBrace | CmdlineTokenData::ClosedParen => {
+                depth = depth.saturating_sub(1)
+            }
+            CmdlineTokenData::Identifier(ident) if depth == 0 && ident == flag => return true,
+            _ => {}
+        }
+    }
+}
+
+// parses `text` on top of `state`, skipping unknown options if it asks to be lenient; returns
+// what was skipped
+fn parse_onto(
+    text: &'static str,
+    state: &mut KernelCmdline,
+) -> Result<ArrayVec<CmdlineParseError<'static>, MAX_SKIPPED>, CmdlineParseError<'static>> {
+    let mut lexer = CmdlineLexer::new(text)?;
+    lexer.set_lenient(has_top_level_flag(text, "lenient"));
+    state.parse(&mut lexer)?;
+    Ok(lexer.take_skipped())
+}
+
+// a config module is parsed first, so the bootloader cmdline can override it
+fn parse_config(state: &mut KernelCmdline) {
+    let Some(text) = find_config_module() else {
+        return;
+    };
+
+    let mut result = ConfigResult {
+        text,
+        error: None,
+        skipped: ArrayVec::new(),
+    };
+
+    match parse_onto(text, state) {
+        Ok(skipped) => result.skipped = skipped,
+        Err(err) => {
+            *state = DEFAULT_OPTIONS;
+            result.error = Some(err);
+        }
+    }
+
+    CONFIG_RESULT.call_once(|| result);
+}
+
 pub fn parse_kernel_cmdline() {
     let state = unsafe { &mut *CMDLINE_STATE.get() };
 
-    if let Some(res) = CMDLINE_REQUEST.get_response() {
-        let res = match res.cmdline().to_str() {
-            Ok(x) => x,
-            Err(err) => {
-                CMDLINE_ERROR.call_once(|| CmdlineError::Utf8Error(err));
-                return;
-            }
-        };
+    parse_config(state);
+
+    // a bad cmdline falls back to the config, rather than all the way to the defaults
+    let fallback = *state;
+
+    match CMDLINE_REQUEST
+        .get_response()
+        .map(|res| res.cmdline().to_str())
+    {
+        Some(Ok(res)) => {
+            CMDLINE_TEXT.call_once(|| res);
 
-        match CmdlineLexer::parse(res, state) {
-            Ok(_) => {}
-            Err(err) => {
-                // reset to default
-                *state = DEFAULT_OPTIONS;
-                CMDLINE_ERROR.call_once(|| CmdlineError::ParseError(err));
+            match parse_onto(res, state) {
+                Ok(skipped) => {
+                    CMDLINE_SKIPPED.call_once(|| skipped);
+                }
+                Err(err) => {
+                    *state = fallback;
+                    CMDLINE_ERROR.call_once(|| CmdlineError::ParseError(err));
+                }
             }
         }
-    } else {
-        CMDLINE_ERROR.call_once(|| CmdlineError::NoResponse);
+        Some(Err(err)) => {
+            CMDLINE_ERROR.call_once(|| CmdlineError::Utf8Error(err));
+        }
+        None => {
+            CMDLINE_ERROR.call_once(|| CmdlineError::NoResponse);
+        }
     }
+
+    RUNTIME_CMDLINE.set(*state);
+}
+
+// logs every option the kernel cmdline accepts, along with its default
+pub fn dump_cmdline_schema() {
+    info!("cmdline options:\n{}", Schema(&DEFAULT_OPTIONS));
 }
@@ -1,9 +1,17 @@
+use arrayvec::ArrayString;
 use bitflags::Flags;
+use core::{any::type_name, fmt};
 
-use super::{CmdlineErrorCode, CmdlineLexer, CmdlineParseError, CmdlineTokenData};
+use super::{
+    CmdlineErrorCode, CmdlineLexer, CmdlineParseError, CmdlineTokenData, NumberRange, SchemaWriter,
+};
+use crate::mem::{ByteSize, Wrapper};
 
 pub trait CmdlineParsable {
     fn parse<'a>(&mut self, lexer: &mut CmdlineLexer<'a>) -> Result<(), CmdlineParseError<'a>>;
+
+    // describes the accepted syntax, with the current value as the default
+    fn schema(&self, writer: &mut SchemaWriter) -> fmt::Result;
 }
 
 // primitive impls
@@ -22,6 +30,41 @@ impl CmdlineParsable for bool {
 
         Ok(())
     }
+
+    fn schema(&self, writer: &mut SchemaWriter) -> fmt::Result {
+        writer.value("bool", self)
+    }
+}
+
+// `field:value` sets the option, starting from the current value if there is one; absent fields
+// keep their default, which is usually None
+impl<T: CmdlineParsable + Default> CmdlineParsable for Option<T> {
+    fn parse<'a>(&mut self, lexer: &mut CmdlineLexer<'a>) -> Result<(), CmdlineParseError<'a>> {
+        self.get_or_insert_default().parse(lexer)
+    }
+
+    fn schema(&self, writer: &mut SchemaWriter) -> fmt::Result {
+        match self {
+            Some(value) => value.schema(writer),
+            None => writer.value(type_name::<T>(), "unset"),
+        }
+    }
+}
+
+// numeric values that `#[validate(min = .., max = ..)]` can check, see the CmdlineParsable derive
+pub trait CmdlineBounded {
+    fn in_bounds(&self, min: Option<i64>, max: Option<i64>) -> bool;
+}
+
+fn in_bounds(value: i128, min: Option<i64>, max: Option<i64>) -> bool {
+    min.is_none_or(|min| value >= min as i128) && max.is_none_or(|max| value <= max as i128)
+}
+
+// an unset option has nothing to check
+impl<T: CmdlineBounded> CmdlineBounded for Option<T> {
+    fn in_bounds(&self, min: Option<i64>, max: Option<i64>) -> bool {
+        self.as_ref().is_none_or(|value| value.in_bounds(min, max))
+    }
 }
 
 pub trait ParsableFlags: Flags + Copy {}
@@ -38,8 +81,8 @@ impl<T: ParsableFlags> CmdlineParsable for T {
             let id_tok = lexer.next()?;
             let id = id_tok.unwrap_ident()?;
             let Some(item) = T::FLAGS.iter().find(|f| f.name().eq_ignore_ascii_case(id)) else {
-                // TODO
-                return Err(id_tok.make_error(CmdlineErrorCode::UnknownFlag(&[])));
+                lexer.skip_unknown(id_tok.make_error(CmdlineErrorCode::UnknownFlag(&[])))?;
+                continue;
             };
 
             if neg {
@@ -49,6 +92,13 @@ impl<T: ParsableFlags> CmdlineParsable for T {
             }
         }
     }
+
+    fn schema(&self, writer: &mut SchemaWriter) -> fmt::Result {
+        writer.flags(
+            T::FLAGS.iter().map(|flag| flag.name()),
+            self.iter_names().map(|(name, _)| name),
+        )
+    }
 }
 
 macro impl_int_parsable($int_type:ident) {
@@ -68,6 +118,16 @@ macro impl_int_parsable($int_type:ident) {
 
             Ok(())
         }
+
+        fn schema(&self, writer: &mut SchemaWriter) -> fmt::Result {
+            writer.value(stringify!($int_type), self)
+        }
+    }
+
+    impl CmdlineBounded for $int_type {
+        fn in_bounds(&self, min: Option<i64>, max: Option<i64>) -> bool {
+            in_bounds(*self as i128, min, max)
+        }
     }
 }
 
@@ -79,3 +139,204 @@ impl_int_parsable!(i8);
 impl_int_parsable!(i16);
 impl_int_parsable!(i32);
 impl_int_parsable!(i64);
+
+// strings are bounded so that options stay Copy and can live in the static default cmdline; bare
+// identifiers are accepted too, so simple names don't need quoting
+impl<const N: usize> CmdlineParsable for ArrayString<N> {
+    fn parse<'a>(&mut self, lexer: &mut CmdlineLexer<'a>) -> Result<(), CmdlineParseError<'a>> {
+        let tok = lexer.next()?;
+
+        let (CmdlineTokenData::String(str) | CmdlineTokenData::Identifier(str)) = tok.0 else {
+            return Err(tok.make_error(CmdlineErrorCode::BadString(tok.0)));
+        };
+
+        *self = ArrayString::from(str)
+            .map_err(|_| tok.make_error(CmdlineErrorCode::StringTooLong(N)))?;
+
+        Ok(())
+    }
+
+    fn schema(&self, writer: &mut SchemaWriter) -> fmt::Result {
+        writer.value("string", format_args!("\"{}\"", self))
+    }
+}
+
+// written with a size suffix more often than not, e.g. `heap_size:256M`
+impl CmdlineParsable for ByteSize {
+    fn parse<'a>(&mut self, lexer: &mut CmdlineLexer<'a>) -> Result<(), CmdlineParseError<'a>> {
+        let mut bytes = 0u64;
+        bytes.parse(lexer)?;
+        *self = ByteSize::new(bytes);
+
+        Ok(())
+    }
+
+    fn schema(&self, writer: &mut SchemaWriter) -> fmt::Result {
+        writer.value("size", self)
+    }
+}
+
+impl CmdlineBounded for ByteSize {
+    fn in_bounds(&self, min: Option<i64>, max: Option<i64>) -> bool {
+        in_bounds(self.value() as i128, min, max)
+    }
+}
+
+// a single number is accepted as a range of one, e.g. `cores:2` as well as `cores:0-3`
+impl CmdlineParsable for NumberRange {
+    fn parse<'a>(&mut self, lexer: &mut CmdlineLexer<'a>) -> Result<(), CmdlineParseError<'a>> {
+        let tok = lexer.next()?;
+
+        *self = match tok.0 {
+            CmdlineTokenData::Range(range) => range,
+            CmdlineTokenData::Number(x) if x >= 0 => NumberRange {
+                start: x as u64,
+                end: x as u64,
+            },
+            _ => return Err(tok.make_error(CmdlineErrorCode::BadRange(tok.0))),
+        };
+
+        Ok(())
+    }
+
+    fn schema(&self, writer: &mut SchemaWriter) -> fmt::Result {
+        writer.value("range", self)
+    }
+}
+
+// both ends have to be in bounds
+impl CmdlineBounded for NumberRange {
+    fn in_bounds(&self, min: Option<i64>, max: Option<i64>) -> bool {
+        in_bounds(self.start as i128, min, max) && in_bounds(self.end as i128, min, max)
+    }
+}
+
+// `aa:bb:cc:...`, with exactly N bytes
+fn parse_hex_bytes<const N: usize>(str: &str) -> Option<[u8; N]> {
+    let mut bytes = [0; N];
+    let mut parts = str.split(':');
+
+    for byte in &mut bytes {
+        *byte = u8::from_str_radix(parts.next()?, 16).ok()?;
+    }
+
+    parts.next().is_none().then_some(bytes)
+}
+
+// at least three bytes, since fewer don't lex as CmdlineTokenData::HexBytes
+impl<const N: usize> CmdlineParsable for [u8; N] {
+    fn parse<'a>(&mut self, lexer: &mut CmdlineLexer<'a>) -> Result<(), CmdlineParseError<'a>> {
+        const {
+            assert!(
+                N >= 3,
+                "[u8; N] can only be parsed from the cmdline for N >= 3"
+            );
+        }
+
+        let tok = lexer.next()?;
+
+        let CmdlineTokenData::HexBytes(str) = tok.0 else {
+            return Err(tok.make_error(CmdlineErrorCode::BadHex(tok.0)));
+        };
+
+        *self =
+            parse_hex_bytes(str).ok_or_else(|| tok.make_error(CmdlineErrorCode::BadHex(tok.0)))?;
+
+        Ok(())
+    }
+
+    fn schema(&self, writer: &mut SchemaWriter) -> fmt::Result {
+        writer.value("bytes", HexBytes(self))
+    }
+}
+
+struct HexBytes<'a>(&'a [u8]);
+
+impl fmt::Display for HexBytes<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, byte) in self.0.iter().enumerate() {
+            if i != 0 {
+                f.write_str(":")?;
+            }
+
+            write!(f, "{:02x}", byte)?;
+        }
+
+        Ok(())
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Default, Debug)]
+pub struct MacAddress(pub [u8; 6]);
+
+impl fmt::Display for MacAddress {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        HexBytes(&self.0).fmt(f)
+    }
+}
+
+impl CmdlineParsable for MacAddress {
+    fn parse<'a>(&mut self, lexer: &mut CmdlineLexer<'a>) -> Result<(), CmdlineParseError<'a>> {
+        self.0.parse(lexer)
+    }
+
+    fn schema(&self, writer: &mut SchemaWriter) -> fmt::Result {
+        writer.value("mac", self)
+    }
+}
+
+// in the usual 8-4-4-4-12 form, quoted or not
+#[derive(Clone, Copy, PartialEq, Eq, Default, Debug)]
+pub struct Uuid(pub [u8; 16]);
+
+impl Uuid {
+    fn from_str(str: &str) -> Option<Uuid> {
+        if str.len() != 36 || [8, 13, 18, 23].iter().any(|&i| str.as_bytes()[i] != b'-') {
+            return None;
+        }
+
+        let mut bytes = [0; 16];
+        let mut digits = str.chars().filter(|&ch| ch != '-');
+
+        for byte in &mut bytes {
+            let hi = digits.next()?.to_digit(16)?;
+            let lo = digits.next()?.to_digit(16)?;
+            *byte = (hi << 4 | lo) as u8;
+        }
+
+        digits.next().is_none().then_some(Uuid(bytes))
+    }
+}
+
+impl fmt::Display for Uuid {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, byte) in self.0.iter().enumerate() {
+            if matches!(i, 4 | 6 | 8 | 10) {
+                f.write_str("-")?;
+            }
+
+            write!(f, "{:02x}", byte)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl CmdlineParsable for Uuid {
+    fn parse<'a>(&mut self, lexer: &mut CmdlineLexer<'a>) -> Result<(), CmdlineParseError<'a>> {
+        let tok = lexer.next()?;
+
+        let (CmdlineTokenData::Uuid(str) | CmdlineTokenData::String(str)) = tok.0 else {
+            return Err(tok.make_error(CmdlineErrorCode::BadHex(tok.0)));
+        };
+
+        *self =
+            Uuid::from_str(str).ok_or_else(|| tok.make_error(CmdlineErrorCode::BadHex(tok.0)))?;
+
+        Ok(())
+    }
+
+    fn schema(&self, writer: &mut SchemaWriter) -> fmt::Result {
+        writer.value("uuid", self)
+    }
+}
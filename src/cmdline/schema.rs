@@ -0,0 +1,108 @@
+// human readable description of the options a cmdline type accepts
+//
+// every CmdlineParsable describes itself through a SchemaWriter, using its current value as the
+// default, so describing DEFAULT_OPTIONS lists every recognized option along with its default.
+
+use core::fmt::{self, Display, Write};
+
+use super::CmdlineParsable;
+
+pub struct SchemaWriter<'a> {
+    out: &'a mut dyn Write,
+    depth: usize,
+}
+
+impl<'a> SchemaWriter<'a> {
+    pub fn new(out: &'a mut dyn Write) -> SchemaWriter<'a> {
+        SchemaWriter { out, depth: 0 }
+    }
+
+    fn indent(&mut self) -> fmt::Result {
+        for _ in 0..self.depth {
+            self.out.write_str("  ")?;
+        }
+
+        Ok(())
+    }
+
+    // a `name:value` entry, described by the value itself
+    pub fn field(
+        &mut self,
+        name: &str,
+        aliases: &[&str],
+        value: &dyn CmdlineParsable,
+    ) -> fmt::Result {
+        self.indent()?;
+        self.out.write_str(name)?;
+
+        for alias in aliases {
+            write!(self.out, "|{}", alias)?;
+        }
+
+        self.out.write_str(": ")?;
+        value.schema(self)
+    }
+
+    // a braced or parenthesized group of entries
+    pub fn group(
+        &mut self,
+        open: &str,
+        close: &str,
+        body: impl FnOnce(&mut Self) -> fmt::Result,
+    ) -> fmt::Result {
+        writeln!(self.out, "{}", open)?;
+
+        self.depth += 1;
+        let res = body(self);
+        self.depth -= 1;
+        res?;
+
+        self.indent()?;
+        writeln!(self.out, "{}", close)
+    }
+
+    pub fn value(&mut self, ty: &str, default: impl Display) -> fmt::Result {
+        writeln!(self.out, "{} = {}", ty, default)
+    }
+
+    // one identifier out of `options`
+    pub fn choice(&mut self, options: &[&str], default: &str) -> fmt::Result {
+        self.out.write_str("one of ")?;
+        self.list(options.iter().copied())?;
+        writeln!(self.out, " = {}", default)
+    }
+
+    // any combination of `options`, each of which can be negated with `!`
+    pub fn flags<'b>(
+        &mut self,
+        options: impl Iterator<Item = &'b str>,
+        default: impl Iterator<Item = &'b str>,
+    ) -> fmt::Result {
+        self.out.write_str("flags ")?;
+        self.list(options)?;
+        self.out.write_str(" = ")?;
+        self.list(default)?;
+        writeln!(self.out)
+    }
+
+    fn list<'b>(&mut self, items: impl IntoIterator<Item = &'b str>) -> fmt::Result {
+        for (i, item) in items.into_iter().enumerate() {
+            if i != 0 {
+                self.out.write_str("|")?;
+            }
+
+            self.out.write_str(item)?;
+        }
+
+        Ok(())
+    }
+}
+
+// describes `value` as a whole, e.g. for `{}` formatting
+pub struct Schema<'a, T: CmdlineParsable>(pub &'a T);
+
+impl<T: CmdlineParsable> Display for Schema<'_, T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.0.schema(&mut SchemaWriter::new(f))
+    }
+}
@@ -0,0 +1,82 @@
+// turns terminal input into keys
+//
+// terminals send most keys as their character, but the cursor and editing keys as escape
+// sequences: CSI (`ESC [`, optional numeric parameters, then a final byte) or SS3 (`ESC O`, then a
+// final byte), depending on the terminal and its mode. alt+<key> is sent as escape followed by the
+// key.
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Key {
+    Char(u8),
+    Alt(u8),
+    Up,
+    Down,
+    Left,
+    Right,
+    Home,
+    End,
+    Delete,
+}
+
+#[derive(Clone, Copy)]
+enum State {
+    Ground,
+    Escape,
+    // the first parameter, and whether it is still being read
+    Csi(u8, bool),
+    Ss3,
+}
+
+pub struct KeyDecoder(State);
+
+impl KeyDecoder {
+    pub const fn new() -> KeyDecoder {
+        KeyDecoder(State::Ground)
+    }
+
+    // a key, once `byte` completes one; unknown sequences are swallowed whole
+    pub fn feed(&mut self, byte: u8) -> Option<Key> {
+        let (state, key) = match (self.0, byte) {
+            (State::Ground, 0x1b) => (State::Escape, None),
+            (State::Ground, _) => (State::Ground, Some(Key::Char(byte))),
+            (State::Escape, b'[') => (State::Csi(0, true), None),
+            (State::Escape, b'O') => (State::Ss3, None),
+            (State::Escape, _) => (State::Ground, Some(Key::Alt(byte))),
+            (State::Csi(param, true), b'0'..=b'9') => (
+                State::Csi(param.saturating_mul(10).saturating_add(byte - b'0'), true),
+                None,
+            ),
+            // only the first parameter matters; the rest are modifiers
+            (State::Csi(param, _), b';') => (State::Csi(param, false), None),
+            (State::Csi(param, _), 0x40..=0x7e) => (State::Ground, csi_key(param, byte)),
+            (State::Csi(..), _) => (self.0, None),
+            (State::Ss3, _) => (State::Ground, final_key(byte)),
+        };
+
+        self.0 = state;
+        key
+    }
+}
+
+fn final_key(byte: u8) -> Option<Key> {
+    match byte {
+        b'A' => Some(Key::Up),
+        b'B' => Some(Key::Down),
+        b'C' => Some(Key::Right),
+        b'D' => Some(Key::Left),
+        b'H' => Some(Key::Home),
+        b'F' => Some(Key::End),
+        _ => None,
+    }
+}
+
+fn csi_key(param: u8, byte: u8) -> Option<Key> {
+    match (byte, param) {
+        // vt220 style, ESC [ <n> ~
+        (b'~', 1 | 7) => Some(Key::Home),
+        (b'~', 3) => Some(Key::Delete),
+        (b'~', 4 | 8) => Some(Key::End),
+        (b'~', _) => None,
+        _ => final_key(byte),
+    }
+}
@@ -0,0 +1,295 @@
+// console input, with a simple line discipline, and the virtual consoles on the framebuffer
+//
+// bytes come in one at a time from interrupt handlers (see SerialCharSink::enable_rx and
+// enable_keyboard), are echoed back to the serial port they came from, if any, and to the shell
+// console, and are collected into lines, which can
+// be edited with the cursor keys, and recalled from a history. finished lines are queued until
+// someone reads them with read_line; if nobody does, the oldest are dropped.
+
+use arrayvec::{ArrayString, ArrayVec};
+use core::fmt::Write;
+
+use crate::{
+    log::{self, CharSink},
+    sync::IntMutex,
+};
+
+mod keys;
+mod vt;
+
+use keys::{Key, KeyDecoder};
+pub use vt::*;
+
+pub const MAX_LINE: usize = 128;
+const QUEUED_LINES: usize = 8;
+pub const HISTORY: usize = 16;
+
+pub type Line = ArrayString<MAX_LINE>;
+
+// what goes back to the terminal for one byte of input; enough to redraw a whole line
+type Echo = ArrayString<{ 2 * MAX_LINE }>;
+
+struct LineDiscipline {
+    // the serial port input last came from, and so where replies should go; none for the keyboard,
+    // which only ever sees the shell console
+    sink: Option<&'static dyn CharSink>,
+    // the line being edited, which only ever holds printable ascii
+    current: ArrayVec<u8, MAX_LINE>,
+    cursor: usize,
+    lines: ArrayVec<Line, QUEUED_LINES>,
+    history: ArrayVec<Line, HISTORY>,
+    // the history entry being shown, and what was typed before going up to it
+    browsing: Option<usize>,
+    draft: ArrayVec<u8, MAX_LINE>,
+    // terminals send \r for enter, but some send \r\n, which shouldn't end two lines
+    last_cr: bool,
+    keys: KeyDecoder,
+}
+
+static INPUT: IntMutex<LineDiscipline> = IntMutex::new(LineDiscipline {
+    sink: None,
+    current: ArrayVec::new_const(),
+    cursor: 0,
+    lines: ArrayVec::new_const(),
+    history: ArrayVec::new_const(),
+    browsing: None,
+    draft: ArrayVec::new_const(),
+    last_cr: false,
+    keys: KeyDecoder::new(),
+});
+
+fn echo(sink: Option<&dyn CharSink>, bytes: &[u8]) {
+    // the port input comes from is a log sink as well, which is only written under the log's lock
+    if let Some(sink) = sink {
+        log::write_raw(sink, bytes);
+    }
+
+    let shell = vt(SHELL_CONSOLE);
+
+    unsafe {
+        shell.write(bytes);
+        shell.flush();
+    }
+}
+
+fn put(out: &mut Echo, bytes: &[u8]) {
+    for &ch in bytes {
+        let _ = out.try_push(ch as char);
+    }
+}
+
+fn cursor_left(out: &mut Echo, n: usize) {
+    if n != 0 {
+        let _ = write!(out, "\x1b[{}D", n);
+    }
+}
+
+fn cursor_right(out: &mut Echo, n: usize) {
+    if n != 0 {
+        let _ = write!(out, "\x1b[{}C", n);
+    }
+}
+
+impl LineDiscipline {
+    // draws the line from the cursor on, and erases whatever was after it; the terminal's cursor
+    // has to be at the cursor, and is put back there
+    fn redraw_tail(&self, out: &mut Echo) {
+        put(out, &self.current[self.cursor..]);
+        out.push_str("\x1b[K");
+        cursor_left(out, self.current.len() - self.cursor);
+    }
+
+    fn move_to(&mut self, pos: usize, out: &mut Echo) {
+        if pos < self.cursor {
+            cursor_left(out, self.cursor - pos);
+        } else {
+            cursor_right(out, pos - self.cursor);
+        }
+
+        self.cursor = pos;
+    }
+
+    fn replace(&mut self, text: &[u8], out: &mut Echo) {
+        self.move_to(0, out);
+        self.current.clear();
+        let _ = self.current.try_extend_from_slice(text);
+        self.redraw_tail(out);
+        self.move_to(self.current.len(), out);
+    }
+
+    fn insert(&mut self, ch: u8, out: &mut Echo) {
+        if self.current.is_full() {
+            return;
+        }
+
+        self.current.insert(self.cursor, ch);
+        self.cursor += 1;
+        put(out, &[ch]);
+
+        if self.cursor != self.current.len() {
+            self.redraw_tail(out);
+        }
+    }
+
+    fn backspace(&mut self, out: &mut Echo) {
+        if self.cursor == 0 {
+            return;
+        }
+
+        self.cursor -= 1;
+        self.current.remove(self.cursor);
+        cursor_left(out, 1);
+        self.redraw_tail(out);
+    }
+
+    fn delete(&mut self, out: &mut Echo) {
+        if self.cursor == self.current.len() {
+            return;
+        }
+
+        self.current.remove(self.cursor);
+        self.redraw_tail(out);
+    }
+
+    fn history_up(&mut self, out: &mut Echo) {
+        let index = match self.browsing {
+            None if self.history.is_empty() => return,
+            None => {
+                self.draft = self.current.clone();
+                self.history.len() - 1
+            }
+            Some(0) => return,
+            Some(index) => index - 1,
+        };
+
+        let line = self.history[index];
+        self.browsing = Some(index);
+        self.replace(line.as_bytes(), out);
+    }
+
+    fn history_down(&mut self, out: &mut Echo) {
+        match self.browsing {
+            None => {}
+            Some(index) if index + 1 < self.history.len() => {
+                let line = self.history[index + 1];
+                self.browsing = Some(index + 1);
+                self.replace(line.as_bytes(), out);
+            }
+            Some(_) => {
+                self.browsing = None;
+                let draft = self.draft.clone();
+                self.replace(&draft, out);
+            }
+        }
+    }
+
+    fn clear(&mut self) {
+        self.current.clear();
+        self.cursor = 0;
+        self.browsing = None;
+    }
+
+    fn finish_line(&mut self) {
+        let mut line = Line::new();
+
+        for &ch in self.current.iter() {
+            line.push(ch as char);
+        }
+
+        if !line.trim().is_empty() && self.history.last() != Some(&line) {
+            if self.history.is_full() {
+                self.history.remove(0);
+            }
+
+            self.history.push(line);
+        }
+
+        if self.lines.is_full() {
+            self.lines.remove(0);
+        }
+
+        self.lines.push(line);
+        self.clear();
+    }
+
+    fn input(&mut self, byte: u8, out: &mut Echo) {
+        let last_cr = self.last_cr;
+        self.last_cr = byte == b'\r';
+
+        let Some(key) = self.keys.feed(byte) else {
+            return;
+        };
+
+        match key {
+            Key::Char(b'\n') if last_cr => {}
+            Key::Char(b'\r' | b'\n') => {
+                self.move_to(self.current.len(), out);
+                out.push_str("\r\n");
+                self.finish_line();
+            }
+            // backspace and delete
+            Key::Char(0x08 | 0x7f) => self.backspace(out),
+            Key::Delete => self.delete(out),
+            // ^C throws the line away
+            Key::Char(0x03) => {
+                self.move_to(self.current.len(), out);
+                out.push_str("^C\r\n");
+                self.clear();
+            }
+            // ^U erases it
+            Key::Char(0x15) => self.replace(&[], out),
+            // ^A and ^E, like readline
+            Key::Home | Key::Char(0x01) => self.move_to(0, out),
+            Key::End | Key::Char(0x05) => self.move_to(self.current.len(), out),
+            Key::Left => self.move_to(self.cursor.saturating_sub(1), out),
+            Key::Right => self.move_to((self.cursor + 1).min(self.current.len()), out),
+            Key::Up => self.history_up(out),
+            Key::Down => self.history_down(out),
+            Key::Char(ch @ 0x20..=0x7e) => self.insert(ch, out),
+            // alt+<n> switches to console n - 1
+            Key::Alt(n @ b'1'..=b'9') => {
+                let _ = switch_to((n - b'1') as usize);
+            }
+            _ => {}
+        }
+    }
+}
+
+// feeds a received byte through the line discipline, echoing to `sink`, the serial port it came
+// from, if it didn't come from the keyboard
+pub fn input(byte: u8, sink: Option<&'static dyn CharSink>) {
+    let mut input = INPUT.lock();
+    let mut out = Echo::new();
+
+    input.sink = sink;
+    input.input(byte, &mut out);
+
+    if !out.is_empty() {
+        echo(sink, out.as_bytes());
+    }
+}
+
+// the serial port input last came from, if it didn't come from the keyboard
+pub fn output() -> Option<&'static dyn CharSink> {
+    INPUT.lock().sink
+}
+
+pub fn has_line() -> bool {
+    !INPUT.lock().lines.is_empty()
+}
+
+// the oldest line that hasn't been read yet, without the line ending
+pub fn read_line() -> Option<Line> {
+    let mut input = INPUT.lock();
+
+    if input.lines.is_empty() {
+        None
+    } else {
+        Some(input.lines.remove(0))
+    }
+}
+
+// earlier lines, oldest first
+pub fn history() -> ArrayVec<Line, HISTORY> {
+    INPUT.lock().history.clone()
+}
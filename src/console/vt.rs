@@ -0,0 +1,149 @@
+// virtual consoles, multiplexed on the framebuffer
+//
+// every console keeps the tail of what was written to it, but only the active one is drawn.
+// switching clears the screen and replays the new console's text, which is much cheaper than
+// keeping a framebuffer's worth of pixels per console. terminals send alt+<n> as escape followed by
+// the digit, and that switches to console n - 1 (see LineDiscipline::input); on a PS/2 keyboard,
+// so does alt+F<n> (see arch::enable_keyboard).
+
+use crate::{log::CharSink, sync::IntMutex};
+
+pub const LOG_CONSOLE: usize = 0;
+pub const SHELL_CONSOLE: usize = 1;
+// for WASM guests, once there are any
+pub const GUEST_CONSOLE: usize = 2;
+pub const CONSOLES: usize = 3;
+
+const SCROLLBACK_SIZE: usize = 8192;
+
+struct Scrollback {
+    buf: [u8; SCROLLBACK_SIZE],
+    // where the next byte goes
+    head: usize,
+    len: usize,
+}
+
+impl Scrollback {
+    const fn new() -> Scrollback {
+        Scrollback {
+            buf: [0; SCROLLBACK_SIZE],
+            head: 0,
+            len: 0,
+        }
+    }
+
+    fn push(&mut self, ch: u8) {
+        self.buf[self.head] = ch;
+        self.head = (self.head + 1) % SCROLLBACK_SIZE;
+        self.len = (self.len + 1).min(SCROLLBACK_SIZE);
+    }
+
+    fn bytes(&self) -> impl Iterator<Item = u8> + '_ {
+        let start = (self.head + SCROLLBACK_SIZE - self.len) % SCROLLBACK_SIZE;
+        let text = (0..self.len).map(move |i| self.buf[(start + i) % SCROLLBACK_SIZE]);
+
+        // once the oldest text has been overwritten, the first line is probably cut off, possibly
+        // in the middle of an escape sequence
+        let partial = self.len == SCROLLBACK_SIZE;
+        text.skip_while(move |&ch| partial && ch != b'\n')
+            .skip(partial as usize)
+    }
+}
+
+struct Consoles {
+    display: Option<&'static dyn CharSink>,
+    active: usize,
+    scrollback: [Scrollback; CONSOLES],
+}
+
+static CONSOLES_STATE: IntMutex<Consoles> = IntMutex::new(Consoles {
+    display: None,
+    active: LOG_CONSOLE,
+    scrollback: [const { Scrollback::new() }; CONSOLES],
+});
+
+pub struct VirtualConsole(usize);
+
+static VIRTUAL_CONSOLES: [VirtualConsole; CONSOLES] =
+    [VirtualConsole(0), VirtualConsole(1), VirtualConsole(2)];
+
+pub fn vt(index: usize) -> &'static VirtualConsole {
+    &VIRTUAL_CONSOLES[index]
+}
+
+impl CharSink for VirtualConsole {
+    unsafe fn putc(&self, ch: u8) {
+        unsafe { self.write(&[ch]) };
+    }
+
+    unsafe fn write(&self, bytes: &[u8]) {
+        let mut consoles = CONSOLES_STATE.lock();
+        bytes
+            .iter()
+            .for_each(|&ch| consoles.scrollback[self.0].push(ch));
+
+        if consoles.active == self.0
+            && let Some(display) = consoles.display
+        {
+            unsafe { display.write(bytes) };
+        }
+    }
+
+    unsafe fn flush(&self) {
+        let consoles = CONSOLES_STATE.lock();
+
+        if consoles.active == self.0
+            && let Some(display) = consoles.display
+        {
+            unsafe { display.flush() };
+        }
+    }
+
+    fn is_terminal(&self) -> bool {
+        true
+    }
+}
+
+// where the active console is drawn; until there is one, consoles only keep their scrollback
+pub fn attach_display(display: &'static dyn CharSink) {
+    CONSOLES_STATE.lock().display = Some(display);
+}
+
+// for the panic path; see IntMutex::force_unlock
+pub unsafe fn force_unlock() -> bool {
+    unsafe { CONSOLES_STATE.force_unlock() }
+}
+
+pub fn active() -> usize {
+    CONSOLES_STATE.lock().active
+}
+
+// fails if there is no such console
+pub fn switch_to(index: usize) -> Result<(), ()> {
+    if index >= CONSOLES {
+        return Err(());
+    }
+
+    let mut consoles = CONSOLES_STATE.lock();
+
+    if consoles.active == index {
+        return Ok(());
+    }
+
+    consoles.active = index;
+
+    if let Some(display) = consoles.display {
+        // reset attributes, clear the screen and home the cursor
+        for ch in b"\x1b[0m\x1b[2J\x1b[H" {
+            unsafe { display.putc(*ch) };
+        }
+
+        for ch in consoles.scrollback[index].bytes() {
+            unsafe { display.putc(ch) };
+        }
+
+        unsafe { display.flush() };
+    }
+
+    Ok(())
+}
@@ -0,0 +1,230 @@
+// a machine readable dump of the kernel's state on panic, streamed over serial
+//
+// with `crashdump:{enable}`, the panic handler writes, after the report, the registers of every
+// core it managed to stop, the dmesg ring, the memory map and the top of each core's stack. it all
+// goes out as lines of text, so it survives being captured along with the log:
+//   KDUMP1 <kind> <data in hex> <fnv-1a of kind and data, in hex>
+// each frame stands on its own, so a corrupt one only loses what was in it. `buildtool decode`
+// turns the frames back into a report; see buildtool/src/crashdump.rs for the payloads.
+
+pub mod options;
+
+use core::{
+    fmt::{self, Display, Write},
+    slice,
+};
+
+use arrayvec::ArrayVec;
+
+use crate::{
+    arch::{
+        PAGE_SMALL_SIZE, SerialCharSink,
+        debug::{self, REGISTERS_SIZE},
+        mp::{cpu_local_ready, halted_registers},
+        paging::{PageTableSet, get_higher_half_addr},
+    },
+    cmdline::get_cmdline,
+    log::{CharSink, with_dmesg},
+    mem::{MemoryMapView, VirtualAddress, Wrapper},
+    mp::{CORE_ID, CoreId, core_count},
+};
+
+const MAGIC: &str = "KDUMP1";
+
+// bytes of data per frame, which is twice as many characters on the line
+pub const FRAME_DATA: usize = 192;
+
+// the stack pointer, in the layout of debug::Trap::read_registers
+const RSP_OFFSET: usize = 7 * 8;
+
+// also what the profiler streams its samples with
+pub struct Writer {
+    port: SerialCharSink,
+    frames: u32,
+}
+
+impl Writer {
+    pub fn open(port: u16) -> Writer {
+        Writer {
+            port: SerialCharSink::open(port),
+            frames: 0,
+        }
+    }
+
+    fn put(&self, bytes: &[u8]) {
+        for &ch in bytes {
+            unsafe { self.port.putc(ch) };
+        }
+    }
+
+    fn put_hex(&self, byte: u8) {
+        const DIGITS: &[u8; 16] = b"0123456789abcdef";
+        self.put(&[DIGITS[(byte >> 4) as usize], DIGITS[(byte & 0xf) as usize]]);
+    }
+
+    // `data` has to fit in FRAME_DATA
+    pub fn frame(&mut self, kind: &str, data: &[u8]) {
+        // fnv-1a
+        let checksum = kind
+            .bytes()
+            .chain(data.iter().copied())
+            .fold(0x811c9dc5u32, |hash, byte| {
+                (hash ^ byte as u32).wrapping_mul(0x01000193)
+            });
+
+        // on a line of its own, whatever was written before
+        self.put(b"\n");
+        self.put(MAGIC.as_bytes());
+        self.put(b" ");
+        self.put(kind.as_bytes());
+        self.put(b" ");
+        data.iter().for_each(|&byte| self.put_hex(byte));
+        self.put(b" ");
+        checksum
+            .to_be_bytes()
+            .iter()
+            .for_each(|&byte| self.put_hex(byte));
+        self.put(b"\n");
+
+        self.frames += 1;
+    }
+
+    // as many frames as `data` takes, each starting with `header` and then the offset of its part
+    // of `data`, counting from `offset`, as a u32
+    fn frames(&mut self, kind: &str, header: &[u8], offset: usize, data: &[u8]) {
+        let chunk_size = FRAME_DATA - header.len() - 4;
+
+        for (i, chunk) in data.chunks(chunk_size).enumerate() {
+            let mut frame = ArrayVec::<u8, FRAME_DATA>::new();
+            frame.extend(header.iter().copied());
+            frame.extend(((offset + i * chunk_size) as u32).to_le_bytes());
+            frame.extend(chunk.iter().copied());
+
+            self.frame(kind, &frame);
+        }
+    }
+}
+
+// the panic report, as text split across frames
+struct ReportWriter<'a> {
+    writer: &'a mut Writer,
+    buf: ArrayVec<u8, FRAME_DATA>,
+}
+
+impl ReportWriter<'_> {
+    fn flush(&mut self) {
+        if !self.buf.is_empty() {
+            self.writer.frame("panic", &self.buf);
+            self.buf.clear();
+        }
+    }
+}
+
+impl Write for ReportWriter<'_> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        for &byte in s.as_bytes() {
+            if self.buf.is_full() {
+                self.flush();
+            }
+
+            self.buf.push(byte);
+        }
+
+        Ok(())
+    }
+}
+
+// whether `addr` can be read without faulting; only the higher half, since a stopped core may
+// have been running user code
+fn mapped(addr: u64) -> bool {
+    addr >= get_higher_half_addr().value()
+        && PageTableSet::current()
+            .translate(VirtualAddress::new(addr).frame_containing())
+            .is_some()
+}
+
+// from the stack pointer up, until `pages` pages in or the first unmapped page
+fn write_stack(writer: &mut Writer, core: CoreId, regs: &[u8; REGISTERS_SIZE], pages: u8) {
+    let rsp = u64::from_le_bytes(regs[RSP_OFFSET..RSP_OFFSET + 8].try_into().unwrap());
+    let end = (rsp / PAGE_SMALL_SIZE + pages as u64) * PAGE_SMALL_SIZE;
+
+    let mut page = rsp / PAGE_SMALL_SIZE * PAGE_SMALL_SIZE;
+
+    while page < end && mapped(page) {
+        let start = page.max(rsp);
+        let data = unsafe {
+            slice::from_raw_parts(
+                start as *const u8,
+                (page + PAGE_SMALL_SIZE - start) as usize,
+            )
+        };
+
+        let mut header = [0; 12];
+        header[..4].copy_from_slice(&(core.0 as u32).to_le_bytes());
+        header[4..].copy_from_slice(&start.to_le_bytes());
+        writer.frames("stack", &header, 0, data);
+
+        page += PAGE_SMALL_SIZE;
+    }
+}
+
+// writes the dump if it is enabled; this doesn't take any locks but the dmesg one, which the panic
+// handler has forced open by now, so it is safe to call from there
+pub fn write(report: &dyn Display) {
+    let options = get_cmdline().crashdump;
+
+    if !options.enable {
+        return;
+    }
+
+    let mut writer = Writer::open(options.port);
+
+    writer.frame("begin", &[]);
+
+    let mut report_writer = ReportWriter {
+        writer: &mut writer,
+        buf: ArrayVec::new(),
+    };
+    let _ = write!(report_writer, "{}", report);
+    report_writer.flush();
+
+    let this = if cpu_local_ready() {
+        CORE_ID.get()
+    } else {
+        CoreId(0)
+    };
+
+    let cores = (0..core_count()).map(CoreId).filter_map(|core| {
+        if core == this {
+            Some((core, debug::current_registers()))
+        } else {
+            Some((core, halted_registers(core)?))
+        }
+    });
+
+    for (core, regs) in cores {
+        let mut data = ArrayVec::<u8, FRAME_DATA>::new();
+        data.extend((core.0 as u32).to_le_bytes());
+        data.extend(regs);
+        writer.frame("cpu", &data);
+
+        write_stack(&mut writer, core, &regs, options.stack_pages);
+    }
+
+    for entry in MemoryMapView::get().iter() {
+        let mut data = ArrayVec::<u8, FRAME_DATA>::new();
+        data.extend(entry.start.address().value().to_le_bytes());
+        data.extend((entry.size.value() * PAGE_SMALL_SIZE).to_le_bytes());
+        data.push(entry.entry_type as u8);
+        writer.frame("memmap", &data);
+    }
+
+    with_dmesg(|first, second| {
+        writer.frames("dmesg", &[], 0, first);
+        writer.frames("dmesg", &[], first.len(), second);
+    });
+
+    // so the decoder can tell whether anything went missing
+    let frames = writer.frames;
+    writer.frame("end", &frames.to_le_bytes());
+}
@@ -0,0 +1,15 @@
+use proc_macros::CmdlineParsable;
+
+use crate::cmdline::CmdlineParsable;
+
+#[derive(CmdlineParsable, Clone, Copy)]
+pub struct CrashDumpOptions {
+    #[default_value(false)]
+    pub enable: bool,
+    // the logging port by default, so that the dump ends up in the same capture as the log
+    #[default_value(0x3f8)]
+    pub port: u16,
+    // how much of each core's stack to dump, from its stack pointer up
+    #[default_value(4)]
+    pub stack_pages: u8,
+}
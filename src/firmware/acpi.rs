@@ -0,0 +1,131 @@
+// just enough ACPI to route interrupts: the I/O APICs and ISA interrupt overrides in the MADT
+
+extern crate alloc;
+
+use alloc::vec::Vec;
+use spin::Once;
+
+use super::{copy_physical, read_u16, read_u32, read_u64, rsdp};
+use crate::mem::{PhysicalAddress, Wrapper};
+
+const HEADER_SIZE: usize = 36;
+// anything bigger than this is taken to be garbage rather than copied
+const MAX_TABLE_SIZE: usize = 1 << 20;
+
+const MADT_ENTRIES: usize = 44;
+const ENTRY_IO_APIC: u8 = 1;
+const ENTRY_ISA_OVERRIDE: u8 = 2;
+
+pub struct IoApic {
+    pub address: PhysicalAddress,
+    // the first global system interrupt on its inputs
+    pub gsi_base: u32,
+}
+
+// an ISA IRQ that isn't wired to the global system interrupt of the same number, or isn't edge
+// triggered and active high
+pub struct IsaOverride {
+    pub irq: u8,
+    pub gsi: u32,
+    // MPS INTI flags: polarity in bits 0-1, trigger mode in bits 2-3
+    pub flags: u16,
+}
+
+pub struct Madt {
+    pub io_apics: Vec<IoApic>,
+    pub overrides: Vec<IsaOverride>,
+}
+
+static MADT: Once<Option<Madt>> = Once::new();
+
+// a whole table, if its checksum is good
+fn copy_table(phys: PhysicalAddress) -> Option<Vec<u8>> {
+    let len = read_u32(&copy_physical(phys, HEADER_SIZE)?, 4)? as usize;
+
+    if !(HEADER_SIZE..=MAX_TABLE_SIZE).contains(&len) {
+        return None;
+    }
+
+    let table = copy_physical(phys, len)?;
+    let sum = table.iter().fold(0u8, |sum, &byte| sum.wrapping_add(byte));
+
+    (sum == 0).then_some(table)
+}
+
+// the table with `signature`, through the XSDT, or the RSDT on ACPI 1.0
+fn find_table(signature: &[u8; 4]) -> Option<Vec<u8>> {
+    let rsdp = rsdp()?;
+    let header = copy_physical(rsdp, 20)?;
+
+    if !header.starts_with(b"RSD PTR ") {
+        return None;
+    }
+
+    let (root, entry_size) = if header[15] >= 2 {
+        let header = copy_physical(rsdp, 36)?;
+        (read_u64(&header, 24)?, 8)
+    } else {
+        (read_u32(&header, 16)? as u64, 4)
+    };
+
+    let root = copy_table(PhysicalAddress::new(root))?;
+
+    root[HEADER_SIZE..]
+        .chunks_exact(entry_size)
+        .map(|entry| match entry_size {
+            8 => read_u64(entry, 0),
+            _ => read_u32(entry, 0).map(|addr| addr as u64),
+        })
+        .filter_map(|addr| Some(PhysicalAddress::new(addr?)))
+        .find(|&addr| {
+            copy_physical(addr, 4).is_some_and(|sig| sig.as_slice() == signature.as_slice())
+        })
+        .and_then(copy_table)
+}
+
+fn parse_madt(table: &[u8]) -> Madt {
+    let mut madt = Madt {
+        io_apics: Vec::new(),
+        overrides: Vec::new(),
+    };
+
+    let mut rest = table.get(MADT_ENTRIES..).unwrap_or_default();
+
+    while let [ty, len, ..] = *rest {
+        let len = len as usize;
+
+        if len < 2 || len > rest.len() {
+            break;
+        }
+
+        let (entry, tail) = rest.split_at(len);
+        rest = tail;
+
+        match ty {
+            ENTRY_IO_APIC => {
+                if let (Some(address), Some(gsi_base)) = (read_u32(entry, 4), read_u32(entry, 8)) {
+                    madt.io_apics.push(IoApic {
+                        address: PhysicalAddress::new(address as u64),
+                        gsi_base,
+                    });
+                }
+            }
+            ENTRY_ISA_OVERRIDE => {
+                if let (Some(&irq), Some(gsi), Some(flags)) =
+                    (entry.get(3), read_u32(entry, 4), read_u16(entry, 8))
+                {
+                    madt.overrides.push(IsaOverride { irq, gsi, flags });
+                }
+            }
+            _ => {}
+        }
+    }
+
+    madt
+}
+
+// read the first time it is asked for, which needs the kernel page tables
+pub fn madt() -> Option<&'static Madt> {
+    MADT.call_once(|| find_table(b"APIC").map(|table| parse_madt(&table)))
+        .as_ref()
+}
@@ -0,0 +1,171 @@
+// firmware provided tables: the MADT, for routing interrupts, and whatever tells us about the
+// machine at boot
+
+extern crate alloc;
+
+use alloc::vec::Vec;
+use core::slice;
+use limine::request::{RsdpRequest, SmbiosRequest};
+use log::{info, warn};
+
+use crate::{
+    arch::paging::CacheMode,
+    mem::{ByteSize, MemoryMapView, PhysicalAddress, Wrapper, map_mmio_with_mode},
+};
+
+pub mod acpi;
+mod smbios;
+
+#[used]
+#[unsafe(link_section = ".limine_requests")]
+static RSDP_REQUEST: RsdpRequest = RsdpRequest::new();
+
+#[used]
+#[unsafe(link_section = ".limine_requests")]
+static SMBIOS_REQUEST: SmbiosRequest = SmbiosRequest::new();
+
+// tables are copied out of firmware memory once, and then read as plain bytes. most of them are in
+// RAM (ACPI reclaimable or NVS), which the hhdm maps write-back already; the rest, such as an RSDP
+// in the BIOS area, which the memory map calls reserved, get a write-back mapping of their own
+fn copy_physical(phys: PhysicalAddress, size: usize) -> Option<Vec<u8>> {
+    let end = phys + ByteSize::new(size as u64);
+
+    let in_ram = MemoryMapView::get().iter().any(|entry| {
+        entry.entry_type.is_ram()
+            && entry.start.address() <= phys
+            && end <= (entry.start + entry.size).address()
+    });
+
+    if in_ram && let Some(virt) = phys.try_to_virtual() {
+        return Some(unsafe { slice::from_raw_parts(virt.as_ptr::<u8>(), size) }.to_vec());
+    }
+
+    let region = map_mmio_with_mode(phys, ByteSize::new(size as u64), CacheMode::WriteBack)?;
+    Some((0..size).map(|i| region.read::<u8>(i)).collect())
+}
+
+fn read_u16(buf: &[u8], offset: usize) -> Option<u16> {
+    Some(u16::from_le_bytes(
+        buf.get(offset..offset + 2)?.try_into().ok()?,
+    ))
+}
+
+fn read_u32(buf: &[u8], offset: usize) -> Option<u32> {
+    Some(u32::from_le_bytes(
+        buf.get(offset..offset + 4)?.try_into().ok()?,
+    ))
+}
+
+fn read_u64(buf: &[u8], offset: usize) -> Option<u64> {
+    Some(u64::from_le_bytes(
+        buf.get(offset..offset + 8)?.try_into().ok()?,
+    ))
+}
+
+// where the bootloader found the RSDP
+fn rsdp() -> Option<PhysicalAddress> {
+    RSDP_REQUEST
+        .get_response()
+        .map(|res| PhysicalAddress::new(res.address() as u64))
+}
+
+fn dump_rsdp() {
+    let Some(res) = RSDP_REQUEST.get_response() else {
+        warn!("no response received for RSDP request");
+        return;
+    };
+
+    let Some(rsdp) = copy_physical(PhysicalAddress::new(res.address() as u64), 20) else {
+        warn!("firmware::dump_rsdp(): failed to map RSDP");
+        return;
+    };
+
+    if &rsdp[..8] != b"RSD PTR " {
+        warn!("firmware::dump_rsdp(): bad RSDP signature");
+        return;
+    }
+
+    let oem = &rsdp[9..15];
+    let revision = rsdp[15];
+
+    info!(
+        "acpi: revision {}, oem \"{}\", rsdp at {:#x}",
+        revision,
+        str::from_utf8(oem).unwrap_or("?").trim_end(),
+        res.address()
+    );
+}
+
+fn dump_smbios() {
+    let Some(res) = SMBIOS_REQUEST.get_response() else {
+        warn!("no response received for SMBIOS request");
+        return;
+    };
+
+    // prefer the 64-bit entry point, which can describe a table above 4GiB
+    let table = res
+        .entry_64()
+        .and_then(|addr| smbios::Table::from_entry_64(PhysicalAddress::new(addr as u64)))
+        .or_else(|| {
+            res.entry_32()
+                .and_then(|addr| smbios::Table::from_entry_32(PhysicalAddress::new(addr as u64)))
+        });
+
+    let Some(table) = table else {
+        warn!("firmware::dump_smbios(): no usable SMBIOS entry point");
+        return;
+    };
+
+    info!("smbios: version {}.{}", table.version.0, table.version.1);
+
+    for entry in table.structures() {
+        match entry.ty {
+            smbios::TYPE_FIRMWARE => info!(
+                "  firmware: {} {} ({})",
+                entry.string(0x04).unwrap_or("unknown"),
+                entry.string(0x05).unwrap_or("?"),
+                entry.string(0x08).unwrap_or("?"),
+            ),
+            smbios::TYPE_SYSTEM => info!(
+                "  system: {} {}",
+                entry.string(0x04).unwrap_or("unknown"),
+                entry.string(0x05).unwrap_or("?"),
+            ),
+            smbios::TYPE_PROCESSOR => info!(
+                "  processor: {} ({})",
+                entry.string(0x10).unwrap_or("unknown"),
+                entry.string(0x04).unwrap_or("?"),
+            ),
+            smbios::TYPE_MEMORY_DEVICE => {
+                // empty slots are listed too
+                let Some(size) = smbios::memory_device_size(&entry) else {
+                    continue;
+                };
+
+                // small devices are only given in KiB
+                let (size, unit) = if size % 1024 == 0 {
+                    (size / 1024, "MiB")
+                } else {
+                    (size, "KiB")
+                };
+
+                info!(
+                    "  memory: {} {} at {}, {} MT/s, {} {}",
+                    size,
+                    unit,
+                    entry.string(0x10).unwrap_or("?"),
+                    entry.word(0x15).unwrap_or(0),
+                    entry.string(0x17).unwrap_or("unknown"),
+                    entry.string(0x1a).unwrap_or("?"),
+                );
+            }
+            _ => {}
+        }
+    }
+}
+
+// firmware tables live in memory that is only reachable once the kernel page tables are up
+pub fn dump_firmware_info() {
+    dump_rsdp();
+    dump_smbios();
+}
@@ -0,0 +1,133 @@
+// just enough SMBIOS to say what we're running on
+//
+// the structure table is copied out of firmware memory once, and then walked as plain bytes.
+
+extern crate alloc;
+
+use alloc::vec::Vec;
+use core::iter;
+
+use super::{copy_physical, read_u16, read_u32, read_u64};
+use crate::mem::{PhysicalAddress, Wrapper};
+
+pub const TYPE_FIRMWARE: u8 = 0;
+pub const TYPE_SYSTEM: u8 = 1;
+pub const TYPE_PROCESSOR: u8 = 4;
+pub const TYPE_MEMORY_DEVICE: u8 = 17;
+const TYPE_END: u8 = 127;
+
+pub struct Table {
+    pub version: (u8, u8),
+    data: Vec<u8>,
+}
+
+pub struct Structure<'a> {
+    pub ty: u8,
+    formatted: &'a [u8],
+    strings: &'a [u8],
+}
+
+impl Table {
+    // reads the structure table pointed to by a 32-bit ("_SM_") entry point
+    pub fn from_entry_32(entry: PhysicalAddress) -> Option<Table> {
+        let header = copy_physical(entry, 0x1f)?;
+
+        if !header.starts_with(b"_SM_") {
+            return None;
+        }
+
+        Some(Table {
+            version: (header[6], header[7]),
+            data: copy_physical(
+                PhysicalAddress::new(read_u32(&header, 0x18)? as u64),
+                read_u16(&header, 0x16)? as usize,
+            )?,
+        })
+    }
+
+    // reads the structure table pointed to by a 64-bit ("_SM3_") entry point
+    pub fn from_entry_64(entry: PhysicalAddress) -> Option<Table> {
+        let header = copy_physical(entry, 0x18)?;
+
+        if !header.starts_with(b"_SM3_") {
+            return None;
+        }
+
+        Some(Table {
+            version: (header[7], header[8]),
+            data: copy_physical(
+                PhysicalAddress::new(read_u64(&header, 0x10)?),
+                read_u32(&header, 0x0c)? as usize,
+            )?,
+        })
+    }
+
+    pub fn structures(&self) -> impl Iterator<Item = Structure<'_>> {
+        let mut rest = &self.data[..];
+
+        iter::from_fn(move || {
+            let ty = *rest.first()?;
+            let len = *rest.get(1)? as usize;
+
+            if ty == TYPE_END || len < 4 || len > rest.len() {
+                return None;
+            }
+
+            let (formatted, tail) = rest.split_at(len);
+
+            // the string set ends with two nuls, even when it is empty
+            let strings_len = tail.windows(2).position(|w| w == [0, 0])?;
+            let strings = &tail[..strings_len];
+            rest = &tail[strings_len + 2..];
+
+            Some(Structure {
+                ty,
+                formatted,
+                strings,
+            })
+        })
+    }
+}
+
+impl<'a> Structure<'a> {
+    pub fn byte(&self, offset: usize) -> Option<u8> {
+        self.formatted.get(offset).copied()
+    }
+
+    pub fn word(&self, offset: usize) -> Option<u16> {
+        read_u16(self.formatted, offset)
+    }
+
+    pub fn dword(&self, offset: usize) -> Option<u32> {
+        read_u32(self.formatted, offset)
+    }
+
+    // the string referenced by the index byte at `offset`; strings are numbered from one, and
+    // zero means there is none
+    pub fn string(&self, offset: usize) -> Option<&'a str> {
+        let index = self.byte(offset)? as usize;
+
+        if index == 0 {
+            return None;
+        }
+
+        self.strings
+            .split(|&ch| ch == 0)
+            .nth(index - 1)
+            .and_then(|bytes| core::str::from_utf8(bytes).ok())
+            .map(str::trim)
+            .filter(|str| !str.is_empty())
+    }
+}
+
+// in KiB, or None if the slot is empty or the size is unknown
+pub fn memory_device_size(device: &Structure) -> Option<u64> {
+    match device.word(0x0c)? {
+        0 | 0xffff => None,
+        // the extended size is always in MiB
+        0x7fff => Some((device.dword(0x1c)? as u64 & 0x7fff_ffff) * 1024),
+        // the top bit selects KiB instead of MiB
+        size if size & 0x8000 != 0 => Some((size & 0x7fff) as u64),
+        size => Some(size as u64 * 1024),
+    }
+}
@@ -0,0 +1,389 @@
+// a gdb remote stub, on a serial port of its own
+//
+// with `gdb:{enable}`, breakpoints stop in the stub, as does boot with `wait` and a panic unless
+// `!on_panic`; the debugger attaches with `target remote` to the other end of the port, e.g.
+// `-serial tcp::1235,server,nowait` for qemu's second port. the stub runs on the core that trapped,
+// with interrupts off, while the other cores keep going, unless a panic has stopped them already.
+// it only knows the basics: registers, memory, software breakpoints, continuing and stepping.
+
+pub mod options;
+
+use core::sync::atomic::{AtomicBool, Ordering};
+
+use arrayvec::ArrayVec;
+use log::{info, warn};
+use spin::Once;
+
+use crate::{
+    arch::{
+        PAGE_SMALL_SIZE, SerialCharSink,
+        debug::{self, BREAKPOINT_INSN, REGISTERS_SIZE, Trap, TrapKind},
+        paging::{PageTableSet, get_higher_half_addr, get_lower_half_end},
+    },
+    cmdline::get_cmdline,
+    log::CharSink,
+    mem::{ByteSize, MemoryMapView, VirtualAddress, Wrapper},
+    sync::IntMutex,
+};
+
+const MAX_PACKET: usize = 1024;
+const MAX_BREAKPOINTS: usize = 32;
+
+// stopped by SIGTRAP
+const STOP_REPLY: &[u8] = b"S05";
+
+type Packet = ArrayVec<u8, MAX_PACKET>;
+
+static PORT: Once<SerialCharSink> = Once::new();
+
+// held by the core in the stub, so that cores trapping at the same time take turns
+static STUB: IntMutex<()> = IntMutex::new(());
+
+// the breakpoints, and the bytes they replaced
+static BREAKPOINTS: IntMutex<ArrayVec<(u64, u8), MAX_BREAKPOINTS>> =
+    IntMutex::new(ArrayVec::new_const());
+
+// whether there is a debugger to tell about stops, which there isn't until it sends something
+static ATTACHED: AtomicBool = AtomicBool::new(false);
+
+fn hex_digit(ch: u8) -> Option<u8> {
+    (ch as char).to_digit(16).map(|digit| digit as u8)
+}
+
+fn parse_hex(text: &[u8]) -> Option<u64> {
+    u64::from_str_radix(str::from_utf8(text).ok()?, 16).ok()
+}
+
+fn push_hex(out: &mut Packet, byte: u8) {
+    const DIGITS: &[u8; 16] = b"0123456789abcdef";
+    out.push(DIGITS[(byte >> 4) as usize]);
+    out.push(DIGITS[(byte & 0xf) as usize]);
+}
+
+fn decode_hex(text: &[u8], out: &mut [u8]) -> Option<()> {
+    if text.len() != out.len() * 2 {
+        return None;
+    }
+
+    for (byte, pair) in out.iter_mut().zip(text.chunks_exact(2)) {
+        *byte = (hex_digit(pair[0])? << 4) | hex_digit(pair[1])?;
+    }
+
+    Some(())
+}
+
+// `addr,len`
+fn parse_range(text: &[u8]) -> Option<(u64, usize)> {
+    let comma = text.iter().position(|&ch| ch == b',')?;
+    Some((
+        parse_hex(&text[..comma])?,
+        parse_hex(&text[comma + 1..])? as usize,
+    ))
+}
+
+// the hhdm alias of `addr`, so that anything mapped can be read, and code written to, without
+// faulting; only for RAM, since touching a device register can have side effects, or fault if
+// nothing decodes the address
+fn alias(addr: u64) -> Option<*mut u8> {
+    if addr >= get_lower_half_end().value() && addr < get_higher_half_addr().value() {
+        return None;
+    }
+
+    let virt = VirtualAddress::new(addr);
+    let frame = PageTableSet::current().translate(virt.frame_containing())?;
+
+    MemoryMapView::get()
+        .iter()
+        .find(|entry| entry.start <= frame && frame < entry.start + entry.size)
+        .filter(|entry| entry.entry_type.is_ram())?;
+
+    let phys = frame.address() + ByteSize::new(addr % PAGE_SMALL_SIZE);
+
+    Some(phys.try_to_virtual()?.as_ptr_mut())
+}
+
+fn read_byte(addr: u64) -> Option<u8> {
+    Some(unsafe { alias(addr)?.read_volatile() })
+}
+
+fn write_byte(addr: u64, byte: u8) -> Option<()> {
+    unsafe { alias(addr)?.write_volatile(byte) };
+    Some(())
+}
+
+fn receive_packet(port: &SerialCharSink) -> Packet {
+    loop {
+        // acks, and the ^C that asks to interrupt, which can't be noticed while running anyway
+        while port.receive() != b'$' {}
+
+        let mut packet = Packet::new();
+        let mut sum = 0u8;
+        let mut overflow = false;
+
+        loop {
+            let ch = port.receive();
+
+            if ch == b'#' {
+                break;
+            }
+
+            sum = sum.wrapping_add(ch);
+            overflow |= packet.try_push(ch).is_err();
+        }
+
+        let checksum = [port.receive(), port.receive()];
+
+        if !overflow && parse_hex(&checksum) == Some(sum as u64) {
+            unsafe { port.putc(b'+') };
+            return packet;
+        }
+
+        unsafe { port.putc(b'-') };
+    }
+}
+
+fn send_packet(port: &SerialCharSink, data: &[u8]) {
+    let sum = data.iter().fold(0u8, |sum, &ch| sum.wrapping_add(ch));
+
+    let mut trailer = Packet::new();
+    trailer.push(b'#');
+    push_hex(&mut trailer, sum);
+
+    loop {
+        for &ch in b"$".iter().chain(data).chain(&trailer) {
+            unsafe { port.putc(ch) };
+        }
+
+        loop {
+            match port.receive() {
+                b'+' => return,
+                b'-' => break,
+                _ => {}
+            }
+        }
+    }
+}
+
+fn read_memory(args: &[u8], reply: &mut Packet) {
+    let Some((addr, len)) = parse_range(args) else {
+        reply.extend(*b"E01");
+        return;
+    };
+
+    // gdb asks for less, and more often, if the reply runs short
+    for addr in (addr..).take(len.min(MAX_PACKET / 2)) {
+        match read_byte(addr) {
+            Some(byte) => push_hex(reply, byte),
+            None => break,
+        }
+    }
+
+    if reply.is_empty() && len != 0 {
+        reply.extend(*b"E14");
+    }
+}
+
+fn write_memory(args: &[u8]) -> &'static [u8] {
+    let Some(colon) = args.iter().position(|&ch| ch == b':') else {
+        return b"E01";
+    };
+
+    let data = &args[colon + 1..];
+
+    let Some((addr, len)) = parse_range(&args[..colon]) else {
+        return b"E01";
+    };
+
+    if data.len() != len * 2 {
+        return b"E01";
+    }
+
+    for (addr, pair) in (addr..).zip(data.chunks_exact(2)) {
+        let mut byte = [0];
+
+        if decode_hex(pair, &mut byte).is_none() || write_byte(addr, byte[0]).is_none() {
+            return b"E14";
+        }
+    }
+
+    b"OK"
+}
+
+fn insert_breakpoint(addr: u64) -> &'static [u8] {
+    let mut breakpoints = BREAKPOINTS.lock();
+
+    if breakpoints.iter().any(|&(bp, _)| bp == addr) {
+        return b"OK";
+    }
+
+    if breakpoints.is_full() {
+        return b"E28";
+    }
+
+    let Some(original) = read_byte(addr) else {
+        return b"E14";
+    };
+
+    if write_byte(addr, BREAKPOINT_INSN).is_none() {
+        return b"E14";
+    }
+
+    breakpoints.push((addr, original));
+    b"OK"
+}
+
+fn remove_breakpoint(addr: u64) -> &'static [u8] {
+    let mut breakpoints = BREAKPOINTS.lock();
+
+    let Some(index) = breakpoints.iter().position(|&(bp, _)| bp == addr) else {
+        return b"E01";
+    };
+
+    let (addr, original) = breakpoints.remove(index);
+    write_byte(addr, original);
+    b"OK"
+}
+
+// `Z0,addr,kind` and `z0,addr,kind`; only software breakpoints are supported
+fn breakpoint_packet(packet: &[u8]) -> &'static [u8] {
+    let Some(args) = packet.get(1..).and_then(|args| args.strip_prefix(b"0,")) else {
+        return b"";
+    };
+
+    let Some((addr, _)) = parse_range(args) else {
+        return b"E01";
+    };
+
+    if packet[0] == b'Z' {
+        insert_breakpoint(addr)
+    } else {
+        remove_breakpoint(addr)
+    }
+}
+
+// talks to the debugger until it resumes the trapped code
+fn serve(port: &SerialCharSink, trap: &mut Trap) {
+    if ATTACHED.load(Ordering::Relaxed) {
+        send_packet(port, STOP_REPLY);
+    }
+
+    loop {
+        let packet = receive_packet(port);
+        let mut reply = Packet::new();
+
+        ATTACHED.store(true, Ordering::Relaxed);
+
+        match packet.first() {
+            Some(b'?') => reply.extend(STOP_REPLY.iter().copied()),
+            Some(b'g') => {
+                let mut regs = [0; REGISTERS_SIZE];
+                trap.read_registers(&mut regs);
+
+                for byte in regs {
+                    push_hex(&mut reply, byte);
+                }
+            }
+            Some(b'G') => {
+                let mut regs = [0; REGISTERS_SIZE];
+
+                match decode_hex(&packet[1..], &mut regs) {
+                    Some(()) => {
+                        trap.write_registers(&regs);
+                        reply.extend(*b"OK");
+                    }
+                    None => reply.extend(*b"E01"),
+                }
+            }
+            Some(b'm') => read_memory(&packet[1..], &mut reply),
+            Some(b'M') => reply.extend(write_memory(&packet[1..]).iter().copied()),
+            Some(b'Z' | b'z') => reply.extend(breakpoint_packet(&packet).iter().copied()),
+            Some(&command @ (b'c' | b's')) => {
+                if let Some(addr) = parse_hex(&packet[1..]) {
+                    trap.set_pc(addr);
+                }
+
+                trap.set_single_step(command == b's');
+                return;
+            }
+            // detach and kill both leave the kernel running, without breakpoints
+            Some(&command @ (b'D' | b'k')) => {
+                for (addr, original) in BREAKPOINTS.lock().drain(..) {
+                    write_byte(addr, original);
+                }
+
+                ATTACHED.store(false, Ordering::Relaxed);
+                trap.set_single_step(false);
+
+                if command == b'D' {
+                    send_packet(port, b"OK");
+                }
+
+                return;
+            }
+            Some(b'H') => reply.extend(*b"OK"),
+            Some(b'q') if packet.starts_with(b"qSupported") => {
+                // in hex, without the framing
+                reply.extend(*b"PacketSize=3fc");
+            }
+            Some(b'q') if packet.starts_with(b"qAttached") => reply.push(b'1'),
+            // an empty reply is what tells gdb that something isn't supported
+            _ => {}
+        }
+
+        send_packet(port, &reply);
+    }
+}
+
+// called for breakpoint and single step exceptions; false if the stub isn't open, in which case
+// the exception is the kernel's problem
+pub fn handle_trap(trap: &mut Trap, kind: TrapKind) -> bool {
+    let Some(port) = PORT.get() else {
+        return false;
+    };
+
+    let _stub = STUB.lock();
+
+    // int3 leaves the pc past itself, which has to be undone for the breakpoints that were put in
+    // place of an instruction
+    let addr = trap.pc().wrapping_sub(1);
+
+    if kind == TrapKind::Breakpoint && BREAKPOINTS.lock().iter().any(|&(bp, _)| bp == addr) {
+        trap.set_pc(addr);
+    }
+
+    serve(port, trap);
+    true
+}
+
+// breakpoints are written through the hhdm, so this has to wait for the kernel page tables
+pub fn init() {
+    let options = get_cmdline().gdb;
+    let serial = get_cmdline().logging.serial;
+
+    if !options.enable {
+        return;
+    }
+
+    if serial.enable && serial.port == options.port {
+        warn!(
+            "gdb::init(): port {:#x} is already used for logging",
+            options.port
+        );
+        return;
+    }
+
+    PORT.call_once(|| SerialCharSink::open(options.port));
+    info!("gdb::init(): stub on port {:#x}", options.port);
+
+    if options.wait {
+        info!("gdb::init(): waiting for the debugger");
+        debug::breakpoint();
+    }
+}
+
+// lets the debugger look at a core that panicked, once the report is out
+pub fn enter_on_panic() {
+    if PORT.is_completed() && get_cmdline().gdb.on_panic {
+        debug::breakpoint();
+    }
+}
@@ -0,0 +1,18 @@
+use proc_macros::CmdlineParsable;
+
+use crate::cmdline::CmdlineParsable;
+
+#[derive(CmdlineParsable, Clone, Copy)]
+pub struct GdbOptions {
+    #[default_value(false)]
+    pub enable: bool,
+    // COM2, since COM1 usually has the log on it
+    #[default_value(0x2f8)]
+    pub port: u16,
+    // stop at boot, until the debugger continues
+    #[default_value(false)]
+    pub wait: bool,
+    // hand a panicking core to the debugger, instead of just halting it
+    #[default_value(true)]
+    pub on_panic: bool,
+}
@@ -0,0 +1,332 @@
+// the commands kshell always has
+
+extern crate alloc;
+
+use alloc::string::String;
+use core::fmt::{self, Write};
+
+use rustc_demangle::demangle;
+
+use super::{COMMANDS, Command};
+use crate::{
+    cmdline::{Schema, get_runtime_cmdline, reconfigure_cmdline},
+    console,
+    log::{Symbolized, dmesg, dropped_records},
+    mem::{heap_stats, stack::stack_usage},
+    modules::{self, symbols},
+    mp::{self, CoreId},
+};
+
+pub(super) const BUILTIN: &[Command] = &[
+    Command {
+        name: "help",
+        help: "list commands",
+        run: help,
+    },
+    Command {
+        name: "mem",
+        help: "heap statistics",
+        run: mem,
+    },
+    Command {
+        name: "stacks",
+        help: "how deep each kernel stack has gone",
+        run: stacks,
+    },
+    Command {
+        name: "dmesg",
+        help: "print the kernel log",
+        run: dmesg_command,
+    },
+    Command {
+        name: "ps",
+        help: "list cores and what they are doing",
+        run: ps,
+    },
+    Command {
+        name: "symbols",
+        help: "symbols <addr>: look up a code address",
+        run: symbols,
+    },
+    Command {
+        name: "addr2line",
+        help: "addr2line <addr>...: function, inline chain and source line of hex addresses",
+        run: addr2line,
+    },
+    Command {
+        name: "set",
+        help: "set <options>: change runtime options, e.g. set logging:{options:{src}}",
+        run: set,
+    },
+    Command {
+        name: "options",
+        help: "list options, with their current values",
+        run: options,
+    },
+    Command {
+        name: "park",
+        help: "park <core>: stop a core from taking work",
+        run: park,
+    },
+    Command {
+        name: "unpark",
+        help: "unpark <core>: resume a parked core",
+        run: unpark,
+    },
+    Command {
+        name: "start",
+        help: "start <core>: start a core held back at boot",
+        run: start,
+    },
+    Command {
+        name: "modules",
+        help: "list the modules that were loaded",
+        run: modules_command,
+    },
+    Command {
+        name: "history",
+        help: "list earlier lines; up and down recall them",
+        run: history,
+    },
+    Command {
+        name: "vt",
+        help: "vt [n]: which virtual console is shown, or switch to n (also alt+<n + 1>)",
+        run: vt,
+    },
+];
+
+// decimal, or hex with 0x
+fn parse_number(str: &str) -> Option<u64> {
+    match str.strip_prefix("0x") {
+        Some(hex) => u64::from_str_radix(hex, 16).ok(),
+        None => str.parse().ok(),
+    }
+}
+
+fn parse_core(args: &str, out: &mut dyn Write) -> Result<Option<CoreId>, fmt::Error> {
+    match parse_number(args) {
+        Some(core) if (core as usize) < mp::core_count() => Ok(Some(CoreId(core as usize))),
+        _ => {
+            writeln!(out, "expected a core number below {}", mp::core_count())?;
+            Ok(None)
+        }
+    }
+}
+
+fn help(_args: &str, out: &mut dyn Write) -> fmt::Result {
+    let registered = COMMANDS.lock().clone();
+
+    for command in BUILTIN.iter().chain(registered.iter()) {
+        writeln!(out, "{:10} {}", command.name, command.help)?;
+    }
+
+    Ok(())
+}
+
+fn mem(_args: &str, out: &mut dyn Write) -> fmt::Result {
+    let stats = heap_stats();
+
+    writeln!(
+        out,
+        "heap: {} allocations, {} frees, {} live",
+        stats.allocations,
+        stats.frees,
+        stats.allocations.saturating_sub(stats.frees)
+    )
+}
+
+fn stacks(_args: &str, out: &mut dyn Write) -> fmt::Result {
+    for usage in stack_usage() {
+        writeln!(
+            out,
+            "core {} {:12} {:>8} / {:>8} bytes ({}%){}",
+            usage.core,
+            usage.name,
+            usage.used,
+            usage.size,
+            usage.used * 100 / usage.size,
+            if usage.lazy { ", in pages" } else { "" }
+        )?;
+    }
+
+    Ok(())
+}
+
+fn dmesg_command(_args: &str, out: &mut dyn Write) -> fmt::Result {
+    let text = dmesg();
+    out.write_str(&String::from_utf8_lossy(&text))?;
+
+    let dropped = dropped_records();
+
+    if dropped != 0 {
+        writeln!(out, "({} records dropped)", dropped)?;
+    }
+
+    Ok(())
+}
+
+// there are no tasks yet, so this is about cores
+fn ps(_args: &str, out: &mut dyn Write) -> fmt::Result {
+    for core in (0..mp::core_count()).map(CoreId) {
+        let state = if mp::is_parked(core) {
+            "parked"
+        } else if mp::is_online(core) {
+            "online"
+        } else {
+            "offline"
+        };
+
+        writeln!(out, "core {}: {}", core, state)?;
+    }
+
+    Ok(())
+}
+
+fn symbols(args: &str, out: &mut dyn Write) -> fmt::Result {
+    let Some(addr) = parse_number(args) else {
+        return writeln!(out, "usage: symbols <addr>");
+    };
+
+    writeln!(out, "{:#016x}:", addr)?;
+    write!(out, "{}", Symbolized(addr))
+}
+
+// one line per frame, innermost first, each with where in its caller it was inlined, e.g.
+//   0xffffffff80012345
+//     core::fmt::write at /rustc/.../fmt/mod.rs:1180:17
+//     inlined into kernel::kmain at src/main.rs:42:5
+fn addr2line(args: &str, out: &mut dyn Write) -> fmt::Result {
+    if args.trim().is_empty() {
+        return writeln!(out, "usage: addr2line <addr>...");
+    }
+
+    for arg in args.split_whitespace() {
+        let hex = arg.strip_prefix("0x").unwrap_or(arg);
+
+        let Ok(addr) = u64::from_str_radix(hex, 16) else {
+            writeln!(out, "{}: not a hex address", arg)?;
+            continue;
+        };
+
+        writeln!(out, "{:#018x}", addr)?;
+
+        let (functions, loc) = symbols::symbolize(addr);
+
+        let Some(functions) = functions else {
+            writeln!(out, "  ??")?;
+            continue;
+        };
+
+        // the location of an inlined function is its call site in the next one out
+        let mut loc = loc;
+        let mut prefix = "";
+
+        for function in functions {
+            write!(
+                out,
+                "  {}{:#}",
+                prefix,
+                demangle(function.name.unwrap_or("??"))
+            )?;
+
+            match loc {
+                Some(loc) => writeln!(
+                    out,
+                    " at {}:{}:{}",
+                    loc.file.unwrap_or("??"),
+                    loc.row,
+                    loc.col
+                )?,
+                None => writeln!(out)?,
+            }
+
+            loc = Some(function.location);
+            prefix = "inlined into ";
+        }
+    }
+
+    Ok(())
+}
+
+fn set(args: &str, out: &mut dyn Write) -> fmt::Result {
+    match reconfigure_cmdline(args) {
+        Ok(()) => Ok(()),
+        Err(err) => writeln!(out, "{}\n{}", err, err.caret(args)),
+    }
+}
+
+fn options(_args: &str, out: &mut dyn Write) -> fmt::Result {
+    write!(out, "{}", Schema(&get_runtime_cmdline()))
+}
+
+fn park(args: &str, out: &mut dyn Write) -> fmt::Result {
+    if let Some(core) = parse_core(args, out)?
+        && mp::park(core).is_err()
+    {
+        writeln!(out, "core {} can't be parked", core)?;
+    }
+
+    Ok(())
+}
+
+fn unpark(args: &str, out: &mut dyn Write) -> fmt::Result {
+    if let Some(core) = parse_core(args, out)?
+        && mp::unpark(core).is_err()
+    {
+        writeln!(out, "core {} can't be unparked", core)?;
+    }
+
+    Ok(())
+}
+
+fn start(args: &str, out: &mut dyn Write) -> fmt::Result {
+    if let Some(core) = parse_core(args, out)?
+        && mp::start_core(core).is_err()
+    {
+        writeln!(out, "core {} didn't come up", core)?;
+    }
+
+    Ok(())
+}
+
+fn modules_command(_args: &str, out: &mut dyn Write) -> fmt::Result {
+    for module in modules::loaded() {
+        if module.released {
+            writeln!(
+                out,
+                "{:10} {:>16}  {}",
+                module.kind, "released", module.path
+            )?;
+        } else {
+            writeln!(
+                out,
+                "{:10} {:10} bytes  {}",
+                module.kind,
+                module.data.len(),
+                module.path
+            )?;
+        }
+    }
+
+    Ok(())
+}
+
+fn history(_args: &str, out: &mut dyn Write) -> fmt::Result {
+    for (i, line) in console::history().iter().enumerate() {
+        writeln!(out, "{:3} {}", i + 1, line)?;
+    }
+
+    Ok(())
+}
+
+fn vt(args: &str, out: &mut dyn Write) -> fmt::Result {
+    let Some(index) = parse_number(args) else {
+        return writeln!(out, "on console {}", console::active());
+    };
+
+    if console::switch_to(index as usize).is_err() {
+        writeln!(out, "there are only {} consoles", console::CONSOLES)?;
+    }
+
+    Ok(())
+}
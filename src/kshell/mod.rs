@@ -0,0 +1,119 @@
+// interactive kernel shell
+//
+// runs on the BSP once everything else is up, reading lines from the console and answering on
+// whatever they came from, as well as on the shell console. besides the built-in commands (see
+// commands.rs), subsystems can add their own with `register`.
+
+use arrayvec::ArrayVec;
+use core::fmt::{self, Write};
+
+use crate::{
+    console,
+    log::{self, CharSink},
+    mp,
+    sync::IntMutex,
+};
+
+mod commands;
+
+pub const MAX_COMMANDS: usize = 32;
+
+#[derive(Clone, Copy)]
+pub struct Command {
+    pub name: &'static str,
+    // a single line, shown by `help`
+    pub help: &'static str,
+    // gets everything after the command name, trimmed
+    pub run: fn(args: &str, out: &mut dyn Write) -> fmt::Result,
+}
+
+static COMMANDS: IntMutex<ArrayVec<Command, MAX_COMMANDS>> = IntMutex::new(ArrayVec::new_const());
+
+// adds a command; fails if the name is taken, or if there are already MAX_COMMANDS of them
+pub fn register(command: Command) -> Result<(), ()> {
+    let mut commands = COMMANDS.lock();
+
+    if find(command.name).is_some() || commands.iter().any(|cmd| cmd.name == command.name) {
+        return Err(());
+    }
+
+    commands.try_push(command).map_err(|_| ())
+}
+
+fn find(name: &str) -> Option<Command> {
+    commands::BUILTIN
+        .iter()
+        .find(|cmd| cmd.name == name)
+        .copied()
+}
+
+// console output, to the serial port the line came from, if any, and the shell console; terminals
+// want \r\n
+struct Output(Option<&'static dyn CharSink>);
+
+impl Output {
+    fn write(&self, bytes: &[u8]) {
+        if bytes.is_empty() {
+            return;
+        }
+
+        // the port is a log sink as well, which is only written under the log's lock
+        if let Some(sink) = self.0 {
+            log::write_raw(sink, bytes);
+        }
+
+        unsafe { console::vt(console::SHELL_CONSOLE).write(bytes) };
+    }
+
+    fn flush(&self) {
+        unsafe { console::vt(console::SHELL_CONSOLE).flush() };
+    }
+}
+
+impl Write for Output {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        for (i, line) in s.split('\n').enumerate() {
+            if i != 0 {
+                self.write(b"\r\n");
+            }
+
+            self.write(line.as_bytes());
+        }
+
+        Ok(())
+    }
+}
+
+fn execute(line: &str, out: &mut dyn Write) -> fmt::Result {
+    let line = line.trim();
+
+    if line.is_empty() {
+        return Ok(());
+    }
+
+    let (name, args) = line.split_once(' ').unwrap_or((line, ""));
+
+    // copied out, so the command runs without the lock held
+    let command =
+        find(name).or_else(|| COMMANDS.lock().iter().find(|cmd| cmd.name == name).copied());
+
+    match command {
+        Some(command) => (command.run)(args.trim(), out),
+        None => writeln!(out, "unknown command '{}', try 'help'", name),
+    }
+}
+
+pub fn run() -> ! {
+    loop {
+        mp::idle_until(console::has_line);
+
+        let Some(line) = console::read_line() else {
+            continue;
+        };
+
+        let mut out = Output(console::output());
+        let _ = execute(&line, &mut out);
+        let _ = out.write_str("> ");
+        out.flush();
+    }
+}
@@ -0,0 +1,51 @@
+// checks that the basics the other tests lean on work at all
+
+extern crate alloc;
+
+use alloc::{boxed::Box, vec::Vec};
+
+use super::ktest;
+use crate::{
+    arch::paging::PageTableSet,
+    mem::{VirtualAddress, Wrapper, heap_stats, stack::stack_usage},
+};
+
+static MAPPED: u64 = 0x1234;
+
+ktest! {
+    fn heap_round_trip() {
+        let before = heap_stats();
+
+        let values: Vec<u64> = (0..1024).collect();
+        let boxed = Box::new(values.iter().sum::<u64>());
+        assert_eq!(*boxed, 1023 * 1024 / 2);
+
+        drop(boxed);
+        drop(values);
+
+        // other cores may be allocating too, so only lower bounds hold
+        let after = heap_stats();
+        assert!(after.allocations >= before.allocations + 2);
+        assert!(after.frees >= before.frees + 2);
+    }
+
+    fn kernel_statics_are_mapped() {
+        let addr = VirtualAddress::new(&raw const MAPPED as u64);
+
+        assert!(
+            PageTableSet::current()
+                .translate(addr.frame_containing())
+                .is_some()
+        );
+    }
+
+    fn stacks_are_tracked() {
+        let stacks = stack_usage();
+
+        assert!(!stacks.is_empty());
+
+        for stack in stacks {
+            assert!(stack.used <= stack.size, "{} overflowed", stack.name);
+        }
+    }
+}
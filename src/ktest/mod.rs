@@ -0,0 +1,119 @@
+// kernel mode tests, built in with the `ktest` feature and run by `buildtool test`
+//
+// tests are declared with ktest!, and the linker gathers them in .ktests. once every core is up,
+// the BSP runs them in link order instead of starting the shell, and reports on the logging serial
+// port, each on a line of its own:
+//   KTEST start <name>
+//   KTEST pass <name>
+//   KTEST fail <name>
+//   KTEST done <number of tests>
+// and then exits qemu through isa-debug-exit. a test fails by panicking, which ends the run, since
+// there is no unwinding; a panic outside of any test is reported as `KTEST panic`.
+
+mod basic;
+
+use core::{
+    ffi::c_void,
+    fmt::{self, Write},
+    slice,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+use arrayvec::ArrayString;
+use spin::Once;
+
+use crate::{
+    arch::{SerialCharSink, qemu_exit},
+    cmdline::get_cmdline,
+    log::{self, CharSink},
+};
+
+const MAGIC: &str = "KTEST";
+
+// what the harness exits qemu with, see `buildtool test`
+const EXIT_SUCCESS: u32 = 0x10;
+const EXIT_FAILURE: u32 = 0x11;
+
+pub struct KernelTest {
+    pub name: &'static str,
+    pub run: fn(),
+}
+
+// ktest! { fn name() { ... } ... }
+pub macro ktest($(fn $name:ident() $body:block)*) {
+    $(
+        fn $name() $body
+
+        const _: () = {
+            #[used]
+            #[unsafe(link_section = ".ktests")]
+            static TEST: crate::ktest::KernelTest = crate::ktest::KernelTest {
+                name: concat!(module_path!(), "::", stringify!($name)),
+                run: $name,
+            };
+        };
+    )*
+}
+
+unsafe extern "C" {
+    static _marker_ktests_start: c_void;
+    static _marker_ktests_end: c_void;
+}
+
+// the index of the test that is running, if there is one
+static CURRENT: AtomicUsize = AtomicUsize::new(usize::MAX);
+
+fn tests() -> &'static [KernelTest] {
+    let start = &raw const _marker_ktests_start as *const KernelTest;
+    let end = &raw const _marker_ktests_end as *const KernelTest;
+
+    unsafe { slice::from_raw_parts(start, end.offset_from_unsigned(start)) }
+}
+
+// opened on first use, since that resets the UART; only if the log doesn't go to serial already
+static PORT: Once<SerialCharSink> = Once::new();
+
+fn port() -> &'static dyn CharSink {
+    match log::serial_sink() {
+        Some(serial) => serial,
+        None => PORT.call_once(|| SerialCharSink::open(get_cmdline().logging.serial.port)),
+    }
+}
+
+fn report(args: fmt::Arguments) {
+    // on a line of its own, whatever was written before
+    let mut line = ArrayString::<256>::new();
+    let _ = write!(line, "\n{} {}\n", MAGIC, args);
+
+    // under the log's sink lock, so that another core's log output can't split the line
+    log::write_raw(port(), line.as_bytes());
+}
+
+pub fn run() -> ! {
+    let tests = tests();
+
+    for (i, test) in tests.iter().enumerate() {
+        CURRENT.store(i, Ordering::SeqCst);
+        report(format_args!("start {}", test.name));
+
+        (test.run)();
+
+        report(format_args!("pass {}", test.name));
+    }
+
+    CURRENT.store(usize::MAX, Ordering::SeqCst);
+
+    report(format_args!("done {}", tests.len()));
+
+    qemu_exit(EXIT_SUCCESS)
+}
+
+// called by the panic handler, once the report is out
+pub fn fail() -> ! {
+    match tests().get(CURRENT.load(Ordering::SeqCst)) {
+        Some(test) => report(format_args!("fail {}", test.name)),
+        None => report(format_args!("panic")),
+    }
+
+    qemu_exit(EXIT_FAILURE)
+}
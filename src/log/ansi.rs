@@ -1,6 +1,6 @@
 use core::fmt::{
     Binary, Debug, Display, Formatter, LowerExp, LowerHex, Octal, Pointer, Result, UpperExp,
-    UpperHex,
+    UpperHex, Write,
 };
 
 use bitflags::bitflags;
@@ -109,3 +109,61 @@ impl_for!(Pointer);
 impl_for!(Binary);
 impl_for!(LowerExp);
 impl_for!(UpperExp);
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum StripState {
+    Text,
+    Escape,
+    Sequence,
+}
+
+// passes text through with escape sequences removed, e.g. for logs that end up in a file; a
+// sequence may be split across writes
+pub struct ANSIStripper<W: Write> {
+    inner: W,
+    state: StripState,
+}
+
+impl<W: Write> ANSIStripper<W> {
+    pub fn new(inner: W) -> ANSIStripper<W> {
+        ANSIStripper {
+            inner,
+            state: StripState::Text,
+        }
+    }
+}
+
+impl<W: Write> Write for ANSIStripper<W> {
+    fn write_str(&mut self, s: &str) -> Result {
+        // start of the current run of plain text
+        let mut run = 0;
+
+        for (i, ch) in s.bytes().enumerate() {
+            match (self.state, ch) {
+                (StripState::Text, 0x1b) => {
+                    self.inner.write_str(&s[run..i])?;
+                    self.state = StripState::Escape;
+                }
+                (StripState::Text, _) => continue,
+                (StripState::Escape, b'[') => self.state = StripState::Sequence,
+                // only control sequences are ever written; anything else after an escape is kept
+                (StripState::Escape, _) | (StripState::Sequence, 0x80..) => {
+                    self.state = StripState::Text;
+                    run = i;
+                    continue;
+                }
+                // the final byte of a control sequence
+                (StripState::Sequence, 0x40..=0x7e) => self.state = StripState::Text,
+                (StripState::Sequence, _) => {}
+            }
+
+            run = i + 1;
+        }
+
+        if self.state == StripState::Text {
+            self.inner.write_str(&s[run..])?;
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,78 @@
+// the tail of the kernel log, kept in memory so it can be read back later
+//
+// this is just another sink, attached ahead of the boot sinks so that it also gets the records
+// from before they were up. once it fills up, the oldest text is overwritten.
+
+extern crate alloc;
+
+use alloc::vec::Vec;
+
+use super::CharSink;
+use crate::sync::IntMutex;
+
+const DMESG_SIZE: usize = 16384;
+
+struct Ring {
+    buf: [u8; DMESG_SIZE],
+    // where the next byte goes
+    head: usize,
+    len: usize,
+}
+
+pub(super) struct DmesgSink {
+    ring: IntMutex<Ring>,
+}
+
+pub(super) static DMESG: DmesgSink = DmesgSink {
+    ring: IntMutex::new(Ring {
+        buf: [0; DMESG_SIZE],
+        head: 0,
+        len: 0,
+    }),
+};
+
+impl Ring {
+    fn push(&mut self, ch: u8) {
+        self.buf[self.head] = ch;
+        self.head = (self.head + 1) % DMESG_SIZE;
+        self.len = (self.len + 1).min(DMESG_SIZE);
+    }
+}
+
+impl CharSink for DmesgSink {
+    unsafe fn putc(&self, ch: u8) {
+        self.ring.lock().push(ch);
+    }
+
+    unsafe fn write(&self, bytes: &[u8]) {
+        let mut ring = self.ring.lock();
+        bytes.iter().for_each(|&ch| ring.push(ch));
+    }
+
+    unsafe fn flush(&self) {
+        // no-op
+    }
+}
+
+// for the panic path; see IntMutex::force_unlock
+pub(super) unsafe fn force_unlock() -> bool {
+    unsafe { DMESG.ring.force_unlock() }
+}
+
+// the log, oldest first, in up to two pieces; for the panic path, where copying it into the heap
+// isn't an option
+pub fn with_dmesg<R>(f: impl FnOnce(&[u8], &[u8]) -> R) -> R {
+    let ring = DMESG.ring.lock();
+    let start = (ring.head + DMESG_SIZE - ring.len) % DMESG_SIZE;
+
+    if start + ring.len <= DMESG_SIZE {
+        f(&ring.buf[start..start + ring.len], &[])
+    } else {
+        f(&ring.buf[start..], &ring.buf[..ring.head])
+    }
+}
+
+// a copy of the log, oldest first; copied so that nothing slow happens with the lock held
+pub fn dmesg() -> Vec<u8> {
+    with_dmesg(|first, second| [first, second].concat())
+}
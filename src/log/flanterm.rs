@@ -1,16 +1,47 @@
+// the framebuffer terminal
+//
+// drawing is what makes the framebuffer slow, so output is batched twice over: bytes are handed to
+// flanterm a buffer at a time rather than one FFI call each, and what flanterm has drawn to its
+// back buffer only goes to the screen when the sink is flushed (which the logger does for records
+// at or above a backend's flush level), or at the end of a line once FLUSH_INTERVAL has passed
+// since the last time.
+
 use super::{CharSink, ansi::Color};
-use core::ptr;
+use crate::{arch::tsc, modules::font::Font};
+use arrayvec::ArrayVec;
+use core::{cell::SyncUnsafeCell, ptr, time::Duration};
 use flanterm::{
     flanterm_context, flanterm_fb_init, flanterm_flush, flanterm_set_autoflush, flanterm_write,
 };
 use limine::framebuffer::Framebuffer;
 
+const BUFFER_SIZE: usize = 512;
+const FLUSH_INTERVAL: Duration = Duration::from_millis(50);
+
+struct Pending {
+    buf: ArrayVec<u8, BUFFER_SIZE>,
+    // whether anything was written since the last flush, which the idle loop asks for every time
+    // it wakes up
+    dirty: bool,
+    // tsc::now() at the last flush
+    last_flush: Duration,
+}
+
 pub struct FlanTermTTY {
     context: *mut flanterm_context,
+    // callers serialize putc and flush, like for any other sink
+    pending: SyncUnsafeCell<Pending>,
 }
 
 impl FlanTermTTY {
-    pub fn from_framebuffer(fb: &Framebuffer) -> FlanTermTTY {
+    // without a font, flanterm uses its built-in 8x16 one; fails if flanterm can't set up the
+    // terminal, which can happen with a large font, since everything has to fit in its static
+    // allocation pool
+    pub fn from_framebuffer(
+        fb: &Framebuffer,
+        font: Option<Font>,
+        scale: u8,
+    ) -> Option<FlanTermTTY> {
         let context: *mut flanterm_context;
         let mut ansi_colors = [
             Color::BLACK.rgb(),
@@ -36,7 +67,16 @@ impl FlanTermTTY {
 
         let mut default_bg = Color::BACKGROUND.rgb();
         let mut default_fg = Color::FOREGROUND.rgb();
-        
+
+        let (font_ptr, font_width, font_height) = match font {
+            Some(font) => (
+                font.glyphs.as_ptr().cast_mut().cast(),
+                font.width,
+                font.height,
+            ),
+            None => (ptr::null_mut(), 0, 0),
+        };
+
         unsafe {
             context = flanterm_fb_init(
                 None,
@@ -58,35 +98,78 @@ impl FlanTermTTY {
                 &raw mut default_fg,
                 ptr::null_mut(),
                 ptr::null_mut(),
-                ptr::null_mut(),
-                0usize,
-                0usize,
+                font_ptr,
+                font_width,
+                font_height,
                 1usize,
-                0usize,
-                0usize,
+                scale as usize,
+                scale as usize,
                 0usize,
             );
 
+            if context.is_null() {
+                return None;
+            }
+
             flanterm_set_autoflush(context, false);
         }
 
-        FlanTermTTY { context }
+        Some(FlanTermTTY {
+            context,
+            pending: SyncUnsafeCell::new(Pending {
+                buf: ArrayVec::new_const(),
+                dirty: false,
+                last_flush: Duration::ZERO,
+            }),
+        })
+    }
+
+    // hands the buffered bytes to flanterm, which draws them to its back buffer
+    unsafe fn write_out(&self, pending: &mut Pending) {
+        unsafe { flanterm_write(self.context, pending.buf.as_ptr().cast(), pending.buf.len()) };
+        pending.buf.clear();
     }
 }
 
 impl CharSink for FlanTermTTY {
     unsafe fn putc(&self, ch: u8) {
-        unsafe {
-            flanterm_write(self.context, ptr::from_ref(&(ch as i8)), 1);
+        let pending = unsafe { &mut *self.pending.get() };
 
-            if ch == b'\n' {
-                flanterm_flush(self.context);
-            }
+        if pending.buf.is_full() {
+            unsafe { self.write_out(pending) };
+        }
+
+        pending.buf.push(ch);
+        pending.dirty = true;
+
+        // before the TSC is calibrated there is no telling how long it has been, so every line is
+        // shown right away, like it used to be
+        let now = tsc::now();
+        if ch == b'\n'
+            && (now == Duration::ZERO || now.saturating_sub(pending.last_flush) >= FLUSH_INTERVAL)
+        {
+            unsafe { self.flush() };
         }
     }
 
     unsafe fn flush(&self) {
-        unsafe { flanterm_flush(self.context) };
+        let pending = unsafe { &mut *self.pending.get() };
+
+        if !pending.dirty {
+            return;
+        }
+
+        unsafe {
+            self.write_out(pending);
+            flanterm_flush(self.context);
+        }
+
+        pending.dirty = false;
+        pending.last_flush = tsc::now();
+    }
+
+    fn is_terminal(&self) -> bool {
+        true
     }
 }
 
@@ -0,0 +1,71 @@
+// hex dumps of raw bytes, e.g. for looking at device registers or descriptors during bring-up
+
+use core::fmt::{self, Display, Formatter};
+use log::{Level, log};
+
+const BYTES_PER_LINE: usize = 16;
+
+struct Line<'a> {
+    offset: usize,
+    bytes: &'a [u8],
+}
+
+impl Display for Line<'_> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "{:08x} ", self.offset)?;
+
+        for i in 0..BYTES_PER_LINE {
+            // an extra space splits the line in two halves
+            if i == BYTES_PER_LINE / 2 {
+                f.write_str(" ")?;
+            }
+
+            match self.bytes.get(i) {
+                Some(byte) => write!(f, " {:02x}", byte)?,
+                None => f.write_str("   ")?,
+            }
+        }
+
+        f.write_str("  |")?;
+
+        for &byte in self.bytes {
+            let ch = if byte.is_ascii_graphic() || byte == b' ' {
+                byte as char
+            } else {
+                '.'
+            };
+
+            write!(f, "{}", ch)?;
+        }
+
+        f.write_str("|")
+    }
+}
+
+// logs `data` at `level` with `target` as the record target, one record per line of 16 bytes, e.g.
+// `label: 00000000  7f 45 4c 46 02 01 01 00  00 00 00 00 00 00 00 00  |.ELF............|`
+pub fn hexdump(target: &str, level: Level, label: &str, data: &[u8]) {
+    if data.is_empty() {
+        log!(target: target, level, "{}: (empty)", label);
+        return;
+    }
+
+    for (i, bytes) in data.chunks(BYTES_PER_LINE).enumerate() {
+        log!(
+            target: target,
+            level,
+            "{}: {}",
+            label,
+            Line {
+                offset: i * BYTES_PER_LINE,
+                bytes,
+            }
+        );
+    }
+}
+
+// hexdump() from the calling module, so its per-target level applies, e.g.
+// `hexdump!(Level::Debug, "madt", bytes)`
+pub macro hexdump($level:expr, $label:expr, $data:expr $(,)?) {
+    crate::log::hexdump(module_path!(), $level, $label, $data)
+}
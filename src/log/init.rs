@@ -1,48 +1,115 @@
 use super::flanterm::FlanTermTTY;
-use crate::{arch::SerialCharSink, cmdline::get_cmdline, log::CharSink, sync::IntMutex};
+use crate::{
+    arch::SerialCharSink,
+    cmdline::get_cmdline,
+    console::{self, LOG_CONSOLE},
+    log::CharSink,
+    modules::find_font_module,
+};
 use limine::request::FramebufferRequest;
-use log::{LevelFilter, info, set_logger};
+use log::{LevelFilter, info, set_logger, warn};
 use spin::Once;
 
-use super::log::LogImpl;
+use super::{
+    dmesg::DMESG,
+    log::{LogImpl, SinkMode},
+    options::LogMode,
+};
 
 #[used]
 #[unsafe(link_section = ".limine_requests")]
 static FRAMEBUFFER_REQUEST: FramebufferRequest = FramebufferRequest::new();
 
-static LOGGER: Once<LogImpl> = Once::new();
+static LOGGER: LogImpl = LogImpl::new();
 
 static SERIAL: Once<SerialCharSink> = Once::new();
 
 static FLANTERM: Once<FlanTermTTY> = Once::new();
 
+// installs the logger with no sinks yet, so that records from before init_tty are buffered
+// rather than lost; this should be the first thing the kernel does
+pub fn init_early_log() {
+    set_logger(&LOGGER)
+        .map(|()| log::set_max_level(LevelFilter::Trace))
+        .unwrap();
+}
+
+// attaches another sink, e.g. a network console, which gets every record `mode` lets through;
+// fails if there are already MAX_SINKS of them
+pub fn add_sink(sink: &'static dyn CharSink, mode: LogMode) -> Result<(), ()> {
+    LOGGER.add_sink(sink, SinkMode::Fixed(mode))
+}
+
+// writes `bytes` to `sink`, such as an echo of console input, under the same lock as the log
+// records going to it
+pub fn write_raw(sink: &dyn CharSink, bytes: &[u8]) {
+    LOGGER.write_raw(sink, bytes);
+}
+
+// the serial port the log goes to, if it goes to one
+pub fn serial_sink() -> Option<&'static SerialCharSink> {
+    SERIAL.get()
+}
+
+// for the panic handler, once the other cores are stopped; everything logged after this is
+// written out and flushed right away, without regard for who held the log locks before
+pub unsafe fn enter_panic_mode() {
+    unsafe { LOGGER.enter_panic_mode() };
+}
+
+// lets commands be typed over the serial connection, if there is one
+pub fn init_serial_input() {
+    if let Some(serial) = SERIAL.get()
+        && serial.enable_rx().is_err()
+    {
+        warn!("log::init_serial_input(): can't take input from this serial port");
+    }
+}
+
 pub fn init_tty() {
-    let mut serial: Option<&'static dyn CharSink> = None;
-    let mut framebuffer: Option<&'static dyn CharSink> = None;
+    let mut font_rejected = false;
+
+    let _ = LOGGER.add_sink(&DMESG, SinkMode::Fixed(LogMode::DEFAULT));
 
     if get_cmdline().logging.serial.enable {
-        serial = Some(SERIAL.call_once(|| SerialCharSink::open(get_cmdline().logging.serial.port)));
+        let serial = SERIAL.call_once(|| SerialCharSink::open(get_cmdline().logging.serial.port));
+        let _ = LOGGER.add_sink(serial, SinkMode::Cmdline(|options| options.serial.mode));
     }
 
     if let Some(res) = FRAMEBUFFER_REQUEST.get_response()
         && let Some(ref fb) = res.framebuffers().next()
     {
-        framebuffer = Some(FLANTERM.call_once(|| FlanTermTTY::from_framebuffer(fb)));
+        let scale = get_cmdline().logging.fb.font_scale;
+        let font = find_font_module();
+
+        // if flanterm can't take the font, the built-in one is better than nothing
+        let framebuffer = FlanTermTTY::from_framebuffer(fb, font, scale)
+            .map(|tty| (tty, font.is_some()))
+            .or_else(|| FlanTermTTY::from_framebuffer(fb, None, scale).map(|tty| (tty, false)));
+
+        if let Some((tty, font_used)) = framebuffer {
+            // the log goes to its own console, which is what's shown until something switches away
+            console::attach_display(FLANTERM.call_once(|| tty));
+            let _ = LOGGER.add_sink(
+                console::vt(LOG_CONSOLE),
+                SinkMode::Cmdline(|options| options.fb.mode),
+            );
+
+            font_rejected = font.is_some() && !font_used;
+        }
     }
 
-    set_logger(LOGGER.call_once(|| LogImpl {
-        lock: IntMutex::new(()),
-        serial,
-        framebuffer,
-    }))
-    .map(|()| log::set_max_level(LevelFilter::Trace))
-    .unwrap();
+    LOGGER.attach();
 
-    info!("kmain(): tty initialized");
+    info!(target: "init", "kmain(): tty initialized");
+
+    if font_rejected {
+        warn!("log::init_tty(): the console font is too large, using the built-in one");
+    }
 
     if let Some(res) = FRAMEBUFFER_REQUEST.get_response()
         && let Some(ref fb) = res.framebuffers().next()
     {
-        info!("kmain(): framebuffer: {}x{}", fb.width(), fb.height());
+        info!(target: "init", "kmain(): framebuffer: {}x{}", fb.width(), fb.height());
     }
 }
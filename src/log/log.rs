@@ -1,34 +1,166 @@
 use core::fmt::Write;
 
-use super::CharSink;
+use super::{
+    CharSink, dmesg,
+    staging::{StagedRecord, StagingRing},
+};
 use crate::{
-    cmdline::get_cmdline,
-    log::ansi::{ANSIFormatter, Color},
+    cmdline::{
+        CmdlineLexer, get_runtime_log_options, get_runtime_target_levels, update_runtime_cmdline,
+    },
+    console::{self, LOG_CONSOLE},
+    kshell::{self, Command},
+    log::{
+        ansi::{ANSIFormatter, ANSIStripper, Color},
+        options::{ColorMode, FormatOptions, LogLevel, LogMode, LogOptions, LogSource},
+    },
+    mp::{CoreId, MP_STATE, MpState, core_count, core_local, per_cpu_counter},
     sync::IntMutex,
 };
-use core::fmt::Result;
-use log::Log;
+use arrayvec::ArrayVec;
+use core::{
+    fmt::Result,
+    sync::atomic::{AtomicBool, Ordering},
+};
+use log::{Level, Log, error};
+
+per_cpu_counter!(DROPPED_RECORDS);
+
+// records that went nowhere, because no sink was attached or because there was no room to stage
+// them
+pub fn dropped_records() -> u64 {
+    DROPPED_RECORDS.sum()
+}
+
+// records logged before the sinks are attached, or before core locals are up, are staged here
+const BOOT_RECORDS: usize = 64;
+const STAGED_RECORDS: usize = 16;
+
+static BOOT_RING: StagingRing<BOOT_RECORDS> = StagingRing::new();
+
+core_local! {
+    RING: StagingRing<STAGED_RECORDS> = StagingRing::new();
+}
+
+fn stage(record: &log::Record) -> bool {
+    if MP_STATE.load(Ordering::Relaxed) == MpState::KInit {
+        BOOT_RING.push(record)
+    } else {
+        RING.push(record)
+    }
+}
+
+// the per-core rings, which only exist once core locals are set up
+fn core_rings() -> impl Iterator<Item = &'static StagingRing<STAGED_RECORDS>> {
+    let cores = match MP_STATE.load(Ordering::Relaxed) {
+        MpState::KInit => 0,
+        _ => core_count(),
+    };
+
+    (0..cores).map(|core| unsafe { &*RING.addr_on(CoreId(core)).as_ptr() })
+}
+
+fn pending() -> bool {
+    BOOT_RING.pending() || core_rings().any(|ring| ring.pending())
+}
+
+pub const MAX_SINKS: usize = 8;
+
+// where a sink gets its LogMode from
+#[derive(Clone, Copy)]
+pub(super) enum SinkMode {
+    Fixed(LogMode),
+    // one of the backends set up from the cmdline, which follows the runtime options
+    Cmdline(fn(&LogOptions) -> LogMode),
+}
+
+struct Sink {
+    backend: &'static dyn CharSink,
+    mode: SinkMode,
+}
 
 pub struct LogImpl {
-    pub(super) lock: IntMutex<()>,
-    pub(super) serial: Option<&'static dyn CharSink>,
-    pub(super) framebuffer: Option<&'static dyn CharSink>,
+    // also serializes writes, so it is only ever taken with try_lock on the logging path
+    sinks: IntMutex<ArrayVec<Sink, MAX_SINKS>>,
+    // set once the boot sinks are added, before which everything is staged
+    attached: AtomicBool,
+    // set by the panic handler, after which every record is written and flushed right away
+    panicking: AtomicBool,
 }
 
 impl Write for &'static dyn CharSink {
     fn write_str(&mut self, s: &str) -> Result {
-        for ch in s.bytes() {
-            unsafe {
-                self.putc(ch);
+        unsafe { self.write(s.as_bytes()) };
+        Ok(())
+    }
+}
+
+// which LogSource a record belongs to, going by its target; records from anywhere else aren't
+// subject to the source mask
+fn record_source(record: &log::Record) -> Option<LogSource> {
+    LogSource::FLAGS
+        .iter()
+        .find(|flag| flag.name().eq_ignore_ascii_case(record.target()))
+        .map(|flag| *flag.value())
+}
+
+// `target_level` is the level set for the record's target, if there is one, which takes the place
+// of the backend level
+fn accepts(mode: &LogMode, target_level: Option<Level>, record: &log::Record) -> bool {
+    record.level() <= target_level.unwrap_or(Level::from(mode.0))
+        && record_source(record).is_none_or(|source| mode.1.contains(source))
+}
+
+// sets or, with None, clears the level for records from `target` and everything below it; fails,
+// saying why, if there is no room for it
+pub fn set_target_level(
+    target: &str,
+    level: Option<LogLevel>,
+) -> core::result::Result<(), &'static str> {
+    let mut result = Ok(());
+    update_runtime_cmdline(|options| result = options.logging.targets.set(target, level));
+    result
+}
+
+// `loglevel <target> <level>`, or `loglevel <target> default` to clear it
+fn loglevel_command(args: &str, out: &mut dyn Write) -> Result {
+    let Some((target, level)) = args.split_once(' ') else {
+        return writeln!(
+            out,
+            "usage: loglevel <target> <error|warn|info|debug|trace|default>"
+        );
+    };
+
+    let level = match level.trim() {
+        "default" => None,
+        level => {
+            let mut parsed = LogLevel::Info;
+
+            if let Err(err) = CmdlineLexer::parse(level, &mut parsed) {
+                return writeln!(out, "{}", err);
             }
+
+            Some(parsed)
         }
+    };
 
-        Ok(())
+    if let Err(err) = set_target_level(target, level) {
+        writeln!(out, "{}", err)?;
     }
+
+    Ok(())
+}
+
+pub fn register_commands() {
+    let _ = kshell::register(Command {
+        name: "loglevel",
+        help: "loglevel <target> <level>: set the log level for a target",
+        run: loglevel_command,
+    });
 }
 
-fn do_write<T: Write>(record: &log::Record, backend: &mut T) {
-    if get_cmdline().logging.options.level {
+fn do_write<T: Write>(record: &log::Record, options: &FormatOptions, backend: &mut T) {
+    if options.level {
         let _ = match record.level() {
             log::Level::Error => write!(
                 backend,
@@ -50,17 +182,17 @@ fn do_write<T: Write>(record: &log::Record, backend: &mut T) {
         };
     }
 
-    if get_cmdline().logging.options.target && !record.target().is_empty() {
+    if options.target && !record.target().is_empty() {
         let _ = write!(backend, "{} | ", record.target());
     }
 
-    if get_cmdline().logging.options.mod_path
+    if options.mod_path
         && let Some(path) = record.module_path()
     {
         let _ = write!(backend, "{} | ", path);
     }
 
-    if get_cmdline().logging.options.src {
+    if options.src {
         let _ = write!(
             backend,
             "{}:{} | ",
@@ -73,33 +205,191 @@ fn do_write<T: Write>(record: &log::Record, backend: &mut T) {
     let _ = backend.write_char('\n');
 }
 
+impl LogImpl {
+    pub(super) const fn new() -> LogImpl {
+        LogImpl {
+            sinks: IntMutex::new(ArrayVec::new_const()),
+            attached: AtomicBool::new(false),
+            panicking: AtomicBool::new(false),
+        }
+    }
+
+    // for the panic handler, once the other cores are stopped: breaks the locks on the way to the
+    // sinks, since whoever holds them won't let go, and switches to writing records out directly,
+    // so that the panic report can't be lost to staging or to a framebuffer that is never flushed.
+    // the runtime options need no such treatment: they are only ever held for a single store, see
+    // cmdline::reconfigure_cmdline
+    pub(super) unsafe fn enter_panic_mode(&self) {
+        let broken = unsafe { self.sinks.force_unlock() }
+            | unsafe { dmesg::force_unlock() }
+            | unsafe { console::force_unlock() };
+
+        // whatever sinks there are by now, even if the boot ones aren't all there
+        self.attached.store(true, Ordering::Release);
+        self.panicking.store(true, Ordering::Release);
+
+        let _ = console::switch_to(LOG_CONSOLE);
+
+        if broken {
+            error!("--- panic: broke the log locks, output above may be interleaved ---");
+        }
+    }
+
+    // fails if there are already MAX_SINKS sinks
+    pub(super) fn add_sink(
+        &self,
+        backend: &'static dyn CharSink,
+        mode: SinkMode,
+    ) -> core::result::Result<(), ()> {
+        self.sinks
+            .lock()
+            .try_push(Sink { backend, mode })
+            .map_err(|_| ())
+    }
+
+    // writes `bytes` to `backend`, which is usually one of the sinks too, without interleaving
+    // with a record; anything staged goes out first, to keep the output in order
+    pub(super) fn write_raw(&self, backend: &dyn CharSink, bytes: &[u8]) {
+        let sinks = self.sinks.lock();
+
+        if self.attached.load(Ordering::Acquire) {
+            self.drain(&sinks);
+        }
+
+        unsafe {
+            backend.write(bytes);
+            backend.flush();
+        }
+    }
+
+    // called once the boot sinks are added; writes out everything that was logged before
+    pub(super) fn attach(&self) {
+        self.attached.store(true, Ordering::Release);
+        self.drain_pending();
+    }
+
+    // drains the rings until they are empty, or until someone else holds the sink lock, in which
+    // case it's up to them
+    fn drain_pending(&self) {
+        while self.attached.load(Ordering::Acquire)
+            && pending()
+            && let Some(sinks) = self.sinks.try_lock()
+        {
+            self.drain(&sinks);
+        }
+    }
+
+    fn drain(&self, sinks: &[Sink]) {
+        let mut write = |staged: &StagedRecord| {
+            self.write(
+                sinks,
+                &log::Record::builder()
+                    .level(staged.level)
+                    .target(&staged.target)
+                    .file_static(staged.file)
+                    .line(staged.line)
+                    .module_path_static(staged.module_path)
+                    .args(format_args!("{}", staged.message))
+                    .build(),
+            )
+        };
+
+        while BOOT_RING.pop(&mut write) {}
+
+        for ring in core_rings() {
+            while ring.pop(&mut write) {}
+        }
+    }
+
+    fn write(&self, sinks: &[Sink], record: &log::Record) {
+        let logging = get_runtime_log_options();
+        let target_level = logging.targets.level_for(record.target());
+
+        if sinks.is_empty() {
+            DROPPED_RECORDS.inc();
+            return;
+        }
+
+        for sink in sinks.iter() {
+            let mode = match sink.mode {
+                SinkMode::Fixed(mode) => mode,
+                SinkMode::Cmdline(mode) => mode(&logging),
+            };
+
+            if !accepts(&mode, target_level, record) {
+                continue;
+            }
+
+            let mut backend = sink.backend;
+            let color = match logging.options.color {
+                ColorMode::Auto => backend.is_terminal(),
+                ColorMode::Always => true,
+                ColorMode::Never => false,
+            };
+
+            if color {
+                do_write(record, &logging.options, &mut backend);
+            } else {
+                do_write(record, &logging.options, &mut ANSIStripper::new(backend));
+            }
+
+            // anything at least this severe is pushed out right away
+            if record.level() <= Level::from(mode.2) {
+                unsafe { backend.flush() };
+            }
+        }
+    }
+}
+
 impl Log for LogImpl {
-    fn enabled(&self, _metadata: &log::Metadata) -> bool {
-        // filtering is done per-backend anyway
-        true
+    fn enabled(&self, metadata: &log::Metadata) -> bool {
+        // the rest of the filtering is done per-backend
+        get_runtime_target_levels()
+            .level_for(metadata.target())
+            .is_none_or(|level| metadata.level() <= level)
     }
 
     fn log(&self, record: &log::Record) {
-        let _guard = self.lock.lock();
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+
+        // only the panicking core is left, so it can wait for the lock
+        if self.panicking.load(Ordering::Acquire) {
+            let sinks = self.sinks.lock();
+            self.drain(&sinks);
+            self.write(&sinks, record);
 
-        if let Some(mut serial) = self.serial {
-            do_write(record, &mut serial);
+            for sink in sinks.iter() {
+                unsafe { sink.backend.flush() };
+            }
+
+            return;
         }
 
-        if let Some(mut framebuffer) = self.framebuffer {
-            do_write(record, &mut framebuffer);
+        // anything staged goes first, to keep records in order
+        if self.attached.load(Ordering::Acquire)
+            && let Some(sinks) = self.sinks.try_lock()
+        {
+            self.drain(&sinks);
+            self.write(&sinks, record);
+        } else if !stage(record) {
+            DROPPED_RECORDS.inc();
         }
+
+        // the lock holder may have let go before this record was staged
+        self.drain_pending();
     }
 
     fn flush(&self) {
-        let _guard = self.lock.lock();
+        let Some(sinks) = self.sinks.try_lock() else {
+            return;
+        };
 
-        if let Some(serial) = self.serial {
-            unsafe { serial.flush() };
-        }
+        self.drain(&sinks);
 
-        if let Some(framebuffer) = self.framebuffer {
-            unsafe { framebuffer.flush() };
+        for sink in sinks.iter() {
+            unsafe { sink.backend.flush() };
         }
     }
 }
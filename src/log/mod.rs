@@ -2,18 +2,40 @@ use crate::{arch::UnwindContext, modules::symbols};
 use core::fmt::{self, Display, Result};
 
 pub mod ansi;
+mod dmesg;
 mod flanterm;
+mod hexdump;
 mod init;
 mod log;
 pub mod options;
+pub mod pstore;
+mod ratelimit;
+mod staging;
 
+pub use dmesg::{dmesg, with_dmesg};
+pub use hexdump::hexdump;
 pub use init::*;
+pub use log::{MAX_SINKS, dropped_records, register_commands, set_target_level};
+pub use ratelimit::{RateLimiter, log_rate_limited};
 use rustc_demangle::demangle;
 
 pub trait CharSink: Send + Sync {
     unsafe fn putc(&self, ch: u8);
 
+    // putc for each byte; sinks with a lock of their own take it once for all of them
+    unsafe fn write(&self, bytes: &[u8]) {
+        for &ch in bytes {
+            unsafe { self.putc(ch) };
+        }
+    }
+
     unsafe fn flush(&self);
+
+    // whether escape sequences are rendered rather than shown as-is, which decides if
+    // `color:auto` logs in color
+    fn is_terminal(&self) -> bool {
+        false
+    }
 }
 
 pub struct StackTrace(UnwindContext);
@@ -29,45 +51,77 @@ impl StackTrace {
     }
 }
 
-impl Display for StackTrace {
+// where `addr` is in the source, as far as the symbol module knows, one line each for the location
+// and every function it is inlined into
+pub struct Symbolized(pub u64);
+
+impl Display for Symbolized {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> Result {
-        let StackTrace(mut context) = *self;
+        let (fn_iter, loc) = symbols::symbolize(self.0);
 
-        let mut i = 0;
-        while unsafe { context.valid() } {
-            let addr = unsafe { context.return_address() };
-            writeln!(f, "#{}: {:#016x}", i, addr)?;
+        if let Some(loc) = loc {
+            writeln!(
+                f,
+                "  at {}:{}:{}",
+                loc.file.unwrap_or("unk"),
+                loc.row,
+                loc.col
+            )?;
+        }
 
-            let (fn_iter, loc) = symbols::symbolize(addr);
+        if let Some(mut iter) = fn_iter {
+            if let Some(first) = iter.next() {
+                writeln!(f, "  in {:#}", demangle(first.name.unwrap_or("unk")))?;
+            }
 
-            if let Some(loc) = loc {
+            for inl in iter.by_ref() {
+                let loc = inl.location;
                 writeln!(
                     f,
-                    "  at {}:{}:{}",
+                    "    inlined at {}:{}:{}",
                     loc.file.unwrap_or("unk"),
                     loc.row,
                     loc.col
                 )?;
+                writeln!(f, "    into {:#}", demangle(inl.name.unwrap_or("unk")))?;
             }
+        }
+
+        Ok(())
+    }
+}
 
-            if let Some(mut iter) = fn_iter {
-                if let Some(first) = iter.next() {
-                    writeln!(f, "  in {:#}", demangle(first.name.unwrap_or("unk")))?;
-                }
-
-                for inl in iter.by_ref() {
-                    let loc = inl.location;
-                    writeln!(
-                        f,
-                        "    inlined at {}:{}:{}",
-                        loc.file.unwrap_or("unk"),
-                        loc.row,
-                        loc.col
-                    )?;
-                    writeln!(f, "    into {:#}", demangle(inl.name.unwrap_or("unk")))?;
-                }
+// `addr`, along with the static it points into if the symbol module knows of one, e.g.
+// `0xffffffff80123458 (CMDLINE_STATE+0x8)`
+pub struct DataSymbolized(pub u64);
+
+impl Display for DataSymbolized {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> Result {
+        write!(f, "{:#016x}", self.0)?;
+
+        if let Some((name, offset)) = symbols::symbolize_data(self.0) {
+            write!(f, " ({:#}+{:#x})", demangle(name), offset)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl Display for StackTrace {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> Result {
+        let StackTrace(mut context) = *self;
+
+        let mut i = 0;
+        while unsafe { context.valid() } {
+            if let Some(vector) = unsafe { context.interrupt_vector() } {
+                writeln!(f, "-- interrupt {:#x} --", vector)?;
             }
 
+            let addr = unsafe { context.return_address() };
+            writeln!(f, "#{}: {:#016x}", i, addr)?;
+
+            write!(f, "{}", Symbolized(addr))?;
+
             i += 1;
             context = unsafe { context.next() };
         }
@@ -1,8 +1,13 @@
+use arrayvec::ArrayString;
 use bitflags::bitflags;
+use core::fmt;
 use log::Level;
 use proc_macros::CmdlineParsable;
 
-use crate::cmdline::{CmdlineParsable, ParsableFlags};
+use crate::cmdline::{
+    CmdlineErrorCode, CmdlineLexer, CmdlineParsable, CmdlineParseError, CmdlineTokenData,
+    ParsableFlags, SchemaWriter,
+};
 
 bitflags! {
     #[derive(Clone, Copy)]
@@ -37,12 +42,117 @@ impl From<LogLevel> for Level {
     }
 }
 
+// per backend filtering: the least severe level that is shown, the sources that are shown (going
+// by record target), and the least severe level that flushes the backend right away
 #[derive(CmdlineParsable, Clone, Copy)]
-pub struct LogMode(pub LogLevel, pub LogSource, pub LogLevel);
+pub struct LogMode(
+    #[default_value(LogLevel::Info)] pub LogLevel,
+    #[default_value(LogSource::all())] pub LogSource,
+    #[default_value(LogLevel::Warn)] pub LogLevel,
+);
+
+pub const MAX_TARGET_LEVELS: usize = 16;
+// enough for a module path a few levels into an arch directory
+pub const MAX_TARGET_LEN: usize = 64;
+
+// levels for individual record targets, e.g. `{init:warn, "wasm_kernel::mem":error}`; a target
+// also covers everything below it in the module path, and the most specific entry wins. an entry
+// takes the place of the backend level for the records it covers, so it can make a target more
+// verbose as well as quieter
+#[derive(Clone, Copy)]
+pub struct TargetLevels {
+    entries: [(ArrayString<MAX_TARGET_LEN>, LogLevel); MAX_TARGET_LEVELS],
+    len: usize,
+}
+
+impl TargetLevels {
+    pub const DEFAULT: TargetLevels = TargetLevels {
+        entries: [(ArrayString::new_const(), LogLevel::Info); MAX_TARGET_LEVELS],
+        len: 0,
+    };
+
+    pub fn entries(&self) -> &[(ArrayString<MAX_TARGET_LEN>, LogLevel)] {
+        &self.entries[..self.len]
+    }
+
+    pub fn level_for(&self, target: &str) -> Option<Level> {
+        self.entries()
+            .iter()
+            .filter(|(name, _)| {
+                target
+                    .strip_prefix(name.as_str())
+                    .is_some_and(|rest| rest.is_empty() || rest.starts_with("::"))
+            })
+            .max_by_key(|(name, _)| name.len())
+            .map(|(_, level)| Level::from(*level))
+    }
+
+    // sets or, with None, removes the entry for `target`; fails, saying why, if the target name is
+    // too long or the table is full
+    pub fn set(&mut self, target: &str, level: Option<LogLevel>) -> Result<(), &'static str> {
+        let index = self.entries().iter().position(|(name, _)| name == target);
+
+        match (index, level) {
+            (Some(index), Some(level)) => self.entries[index].1 = level,
+            (Some(index), None) => {
+                self.entries[index..self.len].rotate_left(1);
+                self.len -= 1;
+            }
+            (None, Some(level)) => {
+                if self.len == MAX_TARGET_LEVELS {
+                    return Err("too many target levels");
+                }
+
+                let target = ArrayString::from(target).map_err(|_| "target name too long")?;
+                self.entries[self.len] = (target, level);
+                self.len += 1;
+            }
+            (None, None) => {}
+        }
+
+        Ok(())
+    }
+}
+
+impl CmdlineParsable for TargetLevels {
+    fn parse<'a>(&mut self, lexer: &mut CmdlineLexer<'a>) -> Result<(), CmdlineParseError<'a>> {
+        lexer.expect(CmdlineTokenData::OpenBrace)?;
+        lexer.parse_block(
+            CmdlineTokenData::ClosedBrace,
+            CmdlineTokenData::Comma,
+            |lexer| {
+                let start = lexer.peek().1.start;
+                let mut target = ArrayString::<MAX_TARGET_LEN>::new();
+                target.parse(lexer)?;
+                let target_span = lexer.span_from(start);
+
+                lexer.expect(CmdlineTokenData::Colon)?;
+                let mut level = LogLevel::Info;
+                level.parse(lexer)?;
+
+                self.set(&target, Some(level))
+                    .map_err(|err| CmdlineParseError(CmdlineErrorCode::Invalid(err), target_span))
+            },
+        )
+    }
+
+    fn schema(&self, writer: &mut SchemaWriter) -> fmt::Result {
+        writer.group("{", "}", |writer| {
+            for (name, level) in self.entries() {
+                writer.field(name, &[], level)?;
+            }
+
+            Ok(())
+        })
+    }
+}
 
 #[derive(CmdlineParsable, Clone, Copy)]
 pub struct SerialOptions {
+    #[default_value(false)]
     pub enable: bool,
+    // TODO: this is definitely arch dependent
+    #[default_value(0x3f8)]
     pub port: u16,
     pub mode: LogMode,
 }
@@ -50,14 +160,32 @@ pub struct SerialOptions {
 #[derive(CmdlineParsable, Clone, Copy)]
 pub struct FramebufferOptions {
     pub mode: LogMode,
+    // how many times the font is magnified; 0 picks what suits the screen size
+    #[default_value(0)]
+    pub font_scale: u8,
+}
+
+// `auto` only uses color on sinks that are terminals, so serial logs, which usually end up in a
+// file, come out plain
+#[derive(CmdlineParsable, Clone, Copy)]
+pub enum ColorMode {
+    Auto,
+    Always,
+    Never,
 }
 
 #[derive(CmdlineParsable, Clone, Copy)]
 pub struct FormatOptions {
+    #[default_value(true)]
     pub level: bool,
+    #[default_value(true)]
     pub target: bool,
+    #[default_value(false)]
     pub mod_path: bool,
+    #[default_value(false)]
     pub src: bool,
+    #[default_value(ColorMode::Auto)]
+    pub color: ColorMode,
 }
 
 #[derive(CmdlineParsable, Clone, Copy)]
@@ -65,4 +193,5 @@ pub struct LogOptions {
     pub serial: SerialOptions,
     pub fb: FramebufferOptions,
     pub options: FormatOptions,
+    pub targets: TargetLevels,
 }
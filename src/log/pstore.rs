@@ -0,0 +1,149 @@
+// panic reports that survive a warm reboot
+//
+// the last few pages of the highest usable memory map entry are kept away from the page
+// allocators, so nothing but a panic ever writes to them. the report goes there behind a header,
+// and the next boot picks it up. this only works as long as the memory map comes out the same and
+// the firmware leaves memory alone across the reset, which holds for QEMU's system_reset.
+
+extern crate alloc;
+
+use alloc::string::String;
+use core::{
+    fmt::{self, Display, Write},
+    mem::size_of,
+    ptr,
+};
+use log::warn;
+use spin::Once;
+
+use crate::{
+    arch::PAGE_SMALL_SIZE,
+    mem::{MemoryMapType, MemoryMapView, PageFrameNumber, PageSize, Wrapper},
+};
+
+const PSTORE_PAGES: u64 = 16;
+const MAGIC: u64 = u64::from_le_bytes(*b"KPSTORE1");
+
+#[repr(C)]
+struct Header {
+    magic: u64,
+    len: u32,
+    checksum: u32,
+}
+
+static REGION: Once<PageFrameNumber> = Once::new();
+
+fn checksum(data: &[u8]) -> u32 {
+    // fnv-1a
+    data.iter().fold(0x811c9dc5, |hash, &byte| {
+        (hash ^ byte as u32).wrapping_mul(0x01000193)
+    })
+}
+
+fn capacity() -> usize {
+    (PSTORE_PAGES * PAGE_SMALL_SIZE) as usize - size_of::<Header>()
+}
+
+fn header() -> Option<*mut Header> {
+    REGION
+        .get()
+        .map(|frame| frame.to_virtual().as_ptr_mut::<Header>())
+}
+
+fn data() -> Option<*mut u8> {
+    header().map(|header| unsafe { header.add(1) as *mut u8 })
+}
+
+// picks the region; has to run before the page allocators are set up
+pub fn init() {
+    let region = MemoryMapView::get()
+        .iter()
+        .filter(|entry| {
+            entry.entry_type == MemoryMapType::Usable && entry.size.value() >= PSTORE_PAGES * 2
+        })
+        .map(|entry| entry.start + entry.size - PageSize::new(PSTORE_PAGES))
+        .max_by_key(|frame| frame.value());
+
+    match region {
+        Some(frame) => {
+            REGION.call_once(|| frame);
+        }
+        None => warn!("log::pstore::init(): no usable memory to keep panic reports in"),
+    }
+}
+
+// whether `frame` belongs to the region, and so must not be handed out
+pub fn contains(frame: PageFrameNumber) -> bool {
+    REGION.get().is_some_and(|start| {
+        frame.value() >= start.value() && frame.value() < start.value() + PSTORE_PAGES
+    })
+}
+
+// writes as much as fits into the region
+struct RegionWriter {
+    data: *mut u8,
+    len: usize,
+}
+
+impl Write for RegionWriter {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        let count = s.len().min(capacity() - self.len);
+
+        unsafe {
+            ptr::copy_nonoverlapping(s.as_ptr(), self.data.add(self.len), count);
+        }
+
+        self.len += count;
+        Ok(())
+    }
+}
+
+// keeps `report` for the next boot; this doesn't take any locks, so it is safe to call from the
+// panic handler
+pub fn record(report: &dyn Display) {
+    let (Some(header), Some(data)) = (header(), data()) else {
+        return;
+    };
+
+    let mut writer = RegionWriter { data, len: 0 };
+    let _ = write!(writer, "{}", report);
+
+    let text = unsafe { core::slice::from_raw_parts(data, writer.len) };
+
+    unsafe {
+        header.write_volatile(Header {
+            magic: MAGIC,
+            len: writer.len as u32,
+            checksum: checksum(text),
+        });
+    }
+}
+
+// logs the report left behind by the previous boot, if there is one, and clears it
+pub fn dump_previous() {
+    let (Some(header), Some(data)) = (header(), data()) else {
+        return;
+    };
+
+    let Header {
+        magic,
+        len,
+        checksum: sum,
+    } = unsafe { header.read_volatile() };
+
+    if magic != MAGIC || len as usize > capacity() {
+        return;
+    }
+
+    let text = unsafe { core::slice::from_raw_parts(data, len as usize) };
+
+    if checksum(text) != sum {
+        warn!("log::pstore: found a corrupted panic report from the previous boot");
+    } else {
+        warn!("previous boot panicked: {}", String::from_utf8_lossy(text));
+    }
+
+    unsafe {
+        (&raw mut (*header).magic).write_volatile(0);
+    }
+}
@@ -0,0 +1,67 @@
+// rate limited logging, for diagnostics on paths that can run many times a second
+//
+// every call site of log_rate_limited! gets its own limiter, so one noisy site doesn't silence the
+// others. records that are held back are counted, and the count is attached to the next record
+// that gets through.
+
+use core::{
+    sync::atomic::{AtomicU64, Ordering},
+    time::Duration,
+};
+
+use crate::arch::tsc;
+
+pub struct RateLimiter {
+    // tsc::now() in nanoseconds before which nothing else is let through
+    next: AtomicU64,
+    suppressed: AtomicU64,
+}
+
+impl RateLimiter {
+    pub const fn new() -> RateLimiter {
+        RateLimiter {
+            next: AtomicU64::new(0),
+            suppressed: AtomicU64::new(0),
+        }
+    }
+
+    // Some(number of records held back since the last one) if a record may go out now
+    pub fn check(&self, interval: Duration) -> Option<u64> {
+        let now = tsc::now().as_nanos() as u64;
+        let next = self.next.load(Ordering::Relaxed);
+
+        if now < next
+            || self
+                .next
+                .compare_exchange(
+                    next,
+                    now + interval.as_nanos() as u64,
+                    Ordering::Relaxed,
+                    Ordering::Relaxed,
+                )
+                .is_err()
+        {
+            self.suppressed.fetch_add(1, Ordering::Relaxed);
+            return None;
+        }
+
+        Some(self.suppressed.swap(0, Ordering::Relaxed))
+    }
+}
+
+// `log!`, but at most once per `interval` from each call site, e.g.
+// `log_rate_limited!(Duration::from_secs(1), Level::Warn, "spurious irq {}", vector)`
+pub macro log_rate_limited($interval:expr, $level:expr, $($arg:tt)+) {{
+    static LIMITER: crate::log::RateLimiter = crate::log::RateLimiter::new();
+
+    match LIMITER.check($interval) {
+        Some(0) => ::log::log!($level, $($arg)+),
+        Some(suppressed) => ::log::log!(
+            $level,
+            "{} ({} similar records suppressed)",
+            format_args!($($arg)+),
+            suppressed
+        ),
+        None => {}
+    }
+}}
@@ -0,0 +1,146 @@
+// lock-free staging for records that can't be written out right away
+//
+// a record goes straight to the sinks when the sink lock is free. when it isn't, because another
+// core is writing or because the record comes from an exception or NMI that hit while this core
+// was writing, it is formatted into a ring instead, and whoever holds the sink lock next drains
+// every ring before writing anything else. the rings are bounded MPMC queues (Vyukov's), so
+// nested contexts on the same core can all push to one without waiting on each other.
+
+use arrayvec::ArrayString;
+use core::{
+    cell::UnsafeCell,
+    fmt::{Result, Write},
+    mem::MaybeUninit,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+use log::Level;
+
+use super::options::MAX_TARGET_LEN;
+
+// targets are as long as options::TargetLevels takes them, so that replayed records are filtered
+// the same; the source location is always static when it comes from the log macros
+pub(super) struct StagedRecord {
+    pub(super) level: Level,
+    pub(super) target: ArrayString<MAX_TARGET_LEN>,
+    pub(super) file: Option<&'static str>,
+    pub(super) line: Option<u32>,
+    pub(super) module_path: Option<&'static str>,
+    pub(super) message: ArrayString<256>,
+}
+
+// writes as much as fits, instead of failing on the first piece that doesn't
+struct Truncating<'a, const N: usize>(&'a mut ArrayString<N>);
+
+impl<const N: usize> Write for Truncating<'_, N> {
+    fn write_str(&mut self, s: &str) -> Result {
+        for ch in s.chars() {
+            if self.0.try_push(ch).is_err() {
+                break;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+struct Slot {
+    // `pos` when free for the push at `pos`, and `pos + 1` once that push is complete
+    seq: AtomicUsize,
+    record: UnsafeCell<MaybeUninit<StagedRecord>>,
+}
+
+pub(super) struct StagingRing<const N: usize> {
+    slots: [Slot; N],
+    head: AtomicUsize,
+    // only ever touched with the sink lock held
+    tail: AtomicUsize,
+}
+
+unsafe impl<const N: usize> Sync for StagingRing<N> {}
+
+impl<const N: usize> StagingRing<N> {
+    pub(super) const fn new() -> StagingRing<N> {
+        let mut slots = [const {
+            Slot {
+                seq: AtomicUsize::new(0),
+                record: UnsafeCell::new(MaybeUninit::uninit()),
+            }
+        }; N];
+
+        let mut i = 0;
+        while i < N {
+            slots[i].seq = AtomicUsize::new(i);
+            i += 1;
+        }
+
+        StagingRing {
+            slots,
+            head: AtomicUsize::new(0),
+            tail: AtomicUsize::new(0),
+        }
+    }
+
+    // false if the ring is full
+    pub(super) fn push(&self, record: &log::Record) -> bool {
+        let mut pos = self.head.load(Ordering::Relaxed);
+
+        let slot = loop {
+            let slot = &self.slots[pos % N];
+            let seq = slot.seq.load(Ordering::Acquire);
+
+            if seq == pos {
+                match self.head.compare_exchange_weak(
+                    pos,
+                    pos + 1,
+                    Ordering::Relaxed,
+                    Ordering::Relaxed,
+                ) {
+                    Ok(_) => break slot,
+                    Err(head) => pos = head,
+                }
+            } else if seq < pos {
+                // still holds the record from the last time around
+                return false;
+            } else {
+                pos = self.head.load(Ordering::Relaxed);
+            }
+        };
+
+        let staged = unsafe { &mut *slot.record.get() }.write(StagedRecord {
+            level: record.level(),
+            target: ArrayString::new(),
+            file: record.file_static(),
+            line: record.line(),
+            module_path: record.module_path_static(),
+            message: ArrayString::new(),
+        });
+
+        let _ = Truncating(&mut staged.target).write_str(record.target());
+        let _ = Truncating(&mut staged.message).write_fmt(*record.args());
+
+        slot.seq.store(pos + 1, Ordering::Release);
+        true
+    }
+
+    // hands the oldest record to `action`; false if there is none, or if it is still being
+    // written. must only be called with the sink lock held
+    pub(super) fn pop(&self, action: impl FnOnce(&StagedRecord)) -> bool {
+        let pos = self.tail.load(Ordering::Relaxed);
+        let slot = &self.slots[pos % N];
+
+        if slot.seq.load(Ordering::Acquire) != pos + 1 {
+            return false;
+        }
+
+        action(unsafe { (*slot.record.get()).assume_init_ref() });
+
+        slot.seq.store(pos + N, Ordering::Release);
+        self.tail.store(pos + 1, Ordering::Relaxed);
+        true
+    }
+
+    pub(super) fn pending(&self) -> bool {
+        let pos = self.tail.load(Ordering::Relaxed);
+        self.slots[pos % N].seq.load(Ordering::Acquire) == pos + 1
+    }
+}
@@ -18,24 +18,33 @@
 
 mod arch;
 mod cmdline;
+mod console;
+mod crashdump;
+mod firmware;
+mod gdb;
+mod kshell;
+#[cfg(feature = "ktest")]
+mod ktest;
 mod log;
 mod mem;
 mod modules;
 mod mp;
+mod profile;
 mod sync;
 
 use ::log::{info, warn};
-use arch::halt;
 use arch::mp::initialize_mp;
-use cmdline::{get_cmdline_error, get_cmdline_text, parse_kernel_cmdline};
+use cmdline::{
+    dump_cmdline_schema, get_cmdline, get_cmdline_error, get_cmdline_skipped, get_cmdline_text,
+    get_config_result, parse_kernel_cmdline,
+};
 use limine::BaseRevision;
 use limine::firmware_type::FirmwareType;
 use limine::request::{
     BootloaderInfoRequest, FirmwareTypeRequest, RequestsEndMarker, RequestsStartMarker,
-    RsdpRequest, SmbiosRequest,
 };
-use log::{StackTrace, init_tty};
-use modules::load_modules_early;
+use log::{StackTrace, init_early_log, init_serial_input, init_tty};
+use modules::{load_modules_early, load_modules_late, release_modules};
 
 #[used]
 #[unsafe(link_section = ".limine_requests")]
@@ -49,14 +58,6 @@ static BOOTLOADER_INFO_REQUEST: BootloaderInfoRequest = BootloaderInfoRequest::n
 #[unsafe(link_section = ".limine_requests")]
 static FIRMWARE_TYPE_REQUEST: FirmwareTypeRequest = FirmwareTypeRequest::new();
 
-#[used]
-#[unsafe(link_section = ".limine_requests")]
-static RSDP_REQUEST: RsdpRequest = RsdpRequest::new();
-
-#[used]
-#[unsafe(link_section = ".limine_requests")]
-static SMBIOS_REQUEST: SmbiosRequest = SmbiosRequest::new();
-
 #[used]
 #[unsafe(link_section = ".limine_requests_start")]
 static _START_MARKER: RequestsStartMarker = RequestsStartMarker::new();
@@ -67,25 +68,65 @@ static _END_MARKER: RequestsEndMarker = RequestsEndMarker::new();
 
 fn dump_boot_info() {
     if let Some(res) = BOOTLOADER_INFO_REQUEST.get_response() {
-        info!("bootloader: {} v{}", res.name(), res.version());
+        info!(
+            target: "init_limine",
+            "bootloader: {} v{}",
+            res.name(),
+            res.version()
+        );
+    }
+
+    if let Some(config) = get_config_result() {
+        info!("config: \"{}\"", config.text);
+
+        if let Some(err) = &config.error {
+            warn!(
+                "failed to parse config: {}\n{}",
+                err,
+                err.caret(config.text)
+            );
+        }
+
+        for err in &config.skipped {
+            warn!("skipped config option: {}\n{}", err, err.caret(config.text));
+        }
     }
 
     if let Some(res) = get_cmdline_text() {
         info!("cmdline: \"{}\"", res);
     }
 
+    log::pstore::dump_previous();
+
     if let Some(err) = get_cmdline_error() {
         match err {
             cmdline::CmdlineError::NoResponse => warn!("no response received for cmdline request"),
             cmdline::CmdlineError::Utf8Error(err) => {
                 warn!("failed to convert cmdline to utf8: {}", err)
             }
-            cmdline::CmdlineError::ParseError(err) => warn!("failed to parse cmdline: {}", err),
+            cmdline::CmdlineError::ParseError(err) => warn!(
+                "failed to parse cmdline: {}\n{}",
+                err,
+                err.caret(get_cmdline_text().unwrap_or(""))
+            ),
         }
     }
 
+    for err in get_cmdline_skipped() {
+        warn!(
+            "skipped cmdline option: {}\n{}",
+            err,
+            err.caret(get_cmdline_text().unwrap_or(""))
+        );
+    }
+
+    if get_cmdline().help {
+        dump_cmdline_schema();
+    }
+
     if let Some(res) = FIRMWARE_TYPE_REQUEST.get_response() {
         info!(
+            target: "init_limine",
             "firmware: {}",
             match res.firmware_type() {
                 FirmwareType::X86_BIOS => "bios",
@@ -98,24 +139,61 @@ fn dump_boot_info() {
     }
 
     mem::dump_memory_info();
+
+    arch::cpu::dump_cpu_info();
+    firmware::dump_firmware_info();
 }
 
 #[unsafe(no_mangle)]
 unsafe extern "C" fn kmain() -> ! {
+    init_early_log();
     parse_kernel_cmdline();
     init_tty();
     load_modules_early();
-    dump_boot_info();
 
+    arch::cpu::init();
+    arch::pat::init();
+    arch::tsc::init();
+
+    // the panic store has to be set aside before the page allocators take everything else
+    log::pstore::init();
     let addr_space = mem::init();
+    load_modules_late();
+    release_modules();
+    gdb::init();
+
+    // the firmware tables have to be mapped in, so this waits for the kernel page tables
+    dump_boot_info();
 
     initialize_mp(&addr_space);
 }
 
 pub extern "C" fn ksmp() -> ! {
+    profile::start();
+
+    // input interrupts all go to the BSP, which by now has its LAPIC and IDT
+    if mp::CORE_ID.get() == mp::CoreId(0) {
+        init_serial_input();
+
+        if arch::enable_keyboard().is_err() {
+            info!("ksmp(): no PS/2 keyboard, input is only taken over serial");
+        }
+    }
+
     info!("hello from ksmp: {}", StackTrace::current());
     info!("i did not halt!");
-    halt();
+
+    #[cfg(feature = "ktest")]
+    if mp::CORE_ID.get() == mp::CoreId(0) {
+        ktest::run();
+    }
+
+    if mp::CORE_ID.get() == mp::CoreId(0) {
+        log::register_commands();
+        kshell::run();
+    }
+
+    mp::idle();
 }
 
 #[cfg(not(test))]
@@ -123,23 +201,63 @@ pub extern "C" fn ksmp() -> ! {
 fn rust_panic(info: &core::panic::PanicInfo) -> ! {
     use ::log::error;
     use arch::halt;
+    use core::time::Duration;
     use log::StackTrace;
 
-    match info.location() {
-        Some(location) => error!(
-            "panic: {}\nat {}:{}:{}\n{}",
-            info.message(),
-            location.file(),
-            location.line(),
-            location.column(),
-            StackTrace::current()
-        ),
-        None => error!(
-            "panic: {}\nat unknown location\n{}",
-            info.message(),
-            StackTrace::current()
-        ),
+    // stop everyone else first, so the report isn't interleaved with their output
+    if arch::mp::halt_other_cores(Duration::from_millis(100)).is_err() {
+        // another core is already panicking, and will report
+        halt();
+    }
+
+    // a stopped core, or the code that panicked, may be holding the log locks
+    unsafe { log::enter_panic_mode() };
+
+    struct PanicReport<'a> {
+        info: &'a core::panic::PanicInfo<'a>,
+        trace: StackTrace,
+    }
+
+    impl core::fmt::Display for PanicReport<'_> {
+        fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+            match self.info.location() {
+                Some(location) => write!(
+                    f,
+                    "panic: {}\nat {}:{}:{}\n{}",
+                    self.info.message(),
+                    location.file(),
+                    location.line(),
+                    location.column(),
+                    self.trace
+                ),
+                None => write!(
+                    f,
+                    "panic: {}\nat unknown location\n{}",
+                    self.info.message(),
+                    self.trace
+                ),
+            }
+        }
+    }
+
+    let report = PanicReport {
+        info,
+        trace: StackTrace::current(),
     };
 
-    halt()
+    // stash the report first, since logging it can still deadlock
+    log::pstore::record(&report);
+    error!("{}", report);
+
+    crashdump::write(&report);
+
+    #[cfg(feature = "ktest")]
+    ktest::fail();
+
+    #[cfg(not(feature = "ktest"))]
+    {
+        gdb::enter_on_panic();
+
+        halt()
+    }
 }
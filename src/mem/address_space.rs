@@ -0,0 +1,213 @@
+extern crate alloc;
+
+use super::{
+    AddressRange, PMM, PageFrameAllocator, PageFrameNumber, PageSize, VFRange,
+    VirtualPageFrameNumber, kernel_page_table, lazy,
+};
+use crate::arch::paging::{PageFlags, PageTableSet, get_lower_half_end};
+use alloc::collections::BTreeMap;
+use core::ops::Bound;
+
+#[derive(Clone, Copy)]
+pub enum RegionBacking {
+    // frames allocated by the address space, released when the region is unmapped; these may be
+    // shared copy-on-write with clones of the address space
+    Anonymous,
+    // like anonymous, but each page is only allocated when it is first touched
+    Lazy,
+    // frames owned by someone else (mmio, shared buffers, ...), never released here
+    Physical(PageFrameNumber),
+}
+
+#[derive(Clone, Copy)]
+pub struct MappedRegion {
+    pub range: VFRange,
+    pub flags: PageFlags,
+    pub backing: RegionBacking,
+}
+
+// an owning set of page tables covering the lower half, with the kernel half shared with every
+// other address space
+pub struct AddressSpace {
+    tables: PageTableSet,
+    regions: BTreeMap<VirtualPageFrameNumber, MappedRegion>,
+    pmm: PMM,
+}
+
+impl AddressSpace {
+    pub fn new() -> AddressSpace {
+        let pmm = PMM::get();
+
+        AddressSpace {
+            tables: PageTableSet::clone_kernel_half(kernel_page_table(), &pmm),
+            regions: BTreeMap::new(),
+            pmm,
+        }
+    }
+
+    pub fn tables(&self) -> &PageTableSet {
+        &self.tables
+    }
+
+    pub fn regions(&self) -> impl Iterator<Item = &MappedRegion> {
+        self.regions.values()
+    }
+
+    pub fn region_containing(&self, addr: VirtualPageFrameNumber) -> Option<&MappedRegion> {
+        self.regions
+            .range(..=addr)
+            .next_back()
+            .map(|(_, region)| region)
+            .filter(|region| region.range.contains(addr))
+    }
+
+    fn is_free(&self, range: VFRange) -> bool {
+        if range.empty() || range.end().address() > get_lower_half_end() {
+            return false;
+        }
+
+        let prev = self.regions.range(..range.start()).next_back();
+        let next = self
+            .regions
+            .range((Bound::Included(range.start()), Bound::Unbounded))
+            .next();
+
+        prev.into_iter()
+            .chain(next)
+            .all(|(_, region)| !region.range.intersects(&range))
+    }
+
+    fn insert(&mut self, region: MappedRegion) -> Result<(), ()> {
+        if !self.is_free(region.range) {
+            return Err(());
+        }
+
+        if let RegionBacking::Lazy = region.backing {
+            lazy::register(&self.tables, region.range, region.flags);
+        }
+
+        for (i, virt) in region.range.as_rust_range().enumerate() {
+            let phys = match region.backing {
+                RegionBacking::Anonymous => self.pmm.allocate_zeroed_page(),
+                RegionBacking::Lazy => break,
+                RegionBacking::Physical(base) => base + PageSize::new(i as u64),
+            };
+
+            self.tables
+                .map_page_small(&self.pmm, virt, phys, &region.flags);
+        }
+
+        self.regions.insert(region.range.start(), region);
+
+        Ok(())
+    }
+
+    // maps zeroed memory over `range`
+    pub fn map_anonymous(&mut self, range: VFRange, flags: PageFlags) -> Result<(), ()> {
+        self.insert(MappedRegion {
+            range,
+            flags,
+            backing: RegionBacking::Anonymous,
+        })
+    }
+
+    // reserves `range` for zeroed memory that is populated on first touch
+    pub fn map_lazy(&mut self, range: VFRange, flags: PageFlags) -> Result<(), ()> {
+        self.insert(MappedRegion {
+            range,
+            flags,
+            backing: RegionBacking::Lazy,
+        })
+    }
+
+    // maps `range` to the physical frames starting at `phys`, which stay owned by the caller
+    pub fn map_physical(
+        &mut self,
+        range: VFRange,
+        phys: PageFrameNumber,
+        flags: PageFlags,
+    ) -> Result<(), ()> {
+        self.insert(MappedRegion {
+            range,
+            flags,
+            backing: RegionBacking::Physical(phys),
+        })
+    }
+
+    // removes the region starting at `base`
+    pub fn unmap(&mut self, base: VirtualPageFrameNumber) -> Result<(), ()> {
+        let region = self.regions.remove(&base).ok_or(())?;
+
+        if let RegionBacking::Lazy = region.backing {
+            lazy::unregister(&self.tables, base);
+        }
+
+        for virt in region.range.as_rust_range() {
+            let frame = self.tables.unmap_page_small(virt);
+
+            if let (RegionBacking::Anonymous | RegionBacking::Lazy, Some(frame)) =
+                (region.backing, frame)
+            {
+                self.pmm.release_page(frame);
+            }
+        }
+
+        Ok(())
+    }
+
+    // creates a copy of this address space, with anonymous memory shared copy-on-write between
+    // the two until either side writes to it
+    // TODO: this only invalidates the local TLB for the source, other cores need a shootdown
+    pub fn clone_cow(&mut self) -> AddressSpace {
+        let mut res = AddressSpace::new();
+
+        for region in self.regions.values() {
+            if let RegionBacking::Lazy = region.backing {
+                lazy::register(&res.tables, region.range, region.flags);
+            }
+
+            for (i, virt) in region.range.as_rust_range().enumerate() {
+                let frame = match region.backing {
+                    // pages of a lazy region that weren't touched yet are skipped here
+                    RegionBacking::Anonymous | RegionBacking::Lazy => {
+                        let Some(frame) = self.tables.mark_cow(virt) else {
+                            continue;
+                        };
+
+                        self.pmm.share_page(frame);
+                        frame
+                    }
+                    RegionBacking::Physical(base) => base + PageSize::new(i as u64),
+                };
+
+                res.tables
+                    .map_page_small(&res.pmm, virt, frame, &region.flags);
+
+                if let RegionBacking::Anonymous | RegionBacking::Lazy = region.backing {
+                    res.tables.mark_cow(virt);
+                }
+            }
+
+            res.regions.insert(region.range.start(), *region);
+        }
+
+        res
+    }
+
+    pub unsafe fn set_current(&self) {
+        unsafe { self.tables.set_current() };
+    }
+}
+
+// the address space must not be active on any core when it is dropped
+impl Drop for AddressSpace {
+    fn drop(&mut self) {
+        while let Some(&base) = self.regions.keys().next() {
+            self.unmap(base).unwrap();
+        }
+
+        lazy::forget_space(&self.tables);
+
+        unsafe { self.tables.destroy(|frame| self.pmm.free_page(frame)) };
+    }
+}
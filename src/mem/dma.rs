@@ -0,0 +1,96 @@
+// dma-coherent buffers: physically contiguous memory behind a non write-back kernel mapping, for
+// device rings and descriptors
+
+use super::{
+    AddressRange, ByteSize, PMM, PageFrameNumber, PageSize, PhysicalAddress, SizeType,
+    VirtualAddress, Wrapper, kernel_page_table,
+    vpa::{TreeAllocator, VirtualAllocation, get_global_vpa},
+};
+use crate::arch::paging::{CacheMode, PageFlags};
+use core::ptr;
+
+pub struct DmaBuffer {
+    allocation: VirtualAllocation<'static, TreeAllocator>,
+    phys: PageFrameNumber,
+    size: ByteSize,
+}
+
+unsafe impl Send for DmaBuffer {}
+unsafe impl Sync for DmaBuffer {}
+
+impl DmaBuffer {
+    pub fn virt_addr(&self) -> VirtualAddress {
+        self.allocation.range().start().address()
+    }
+
+    pub fn phys_addr(&self) -> PhysicalAddress {
+        self.phys.address()
+    }
+
+    pub fn size(&self) -> ByteSize {
+        self.size
+    }
+
+    pub fn as_ptr<T>(&self) -> *const T {
+        self.virt_addr().as_ptr()
+    }
+
+    pub fn as_ptr_mut<T>(&self) -> *mut T {
+        self.virt_addr().as_ptr_mut()
+    }
+}
+
+impl Drop for DmaBuffer {
+    fn drop(&mut self) {
+        let tables = kernel_page_table();
+        let pmm = PMM::get();
+
+        for page in self.allocation.range().as_rust_range() {
+            tables.unmap_page_small(page);
+        }
+
+        for frame in self.phys..self.phys + self.allocation.range().size() {
+            pmm.free_page(frame);
+        }
+
+        // the virtual range is released when `allocation` is dropped
+    }
+}
+
+// allocates an uncached, physically contiguous, zeroed buffer
+pub fn alloc(bytes: ByteSize, align: ByteSize) -> Option<DmaBuffer> {
+    alloc_with_mode(bytes, align, CacheMode::Uncached)
+}
+
+pub fn alloc_with_mode(bytes: ByteSize, align: ByteSize, mode: CacheMode) -> Option<DmaBuffer> {
+    let pages = bytes.page_size_roundup();
+    let align = align.page_size_roundup().max(PageSize::new(1));
+    let pmm = PMM::get();
+
+    let phys = pmm.allocate_contiguous(pages, align)?;
+
+    let Some(allocation) = get_global_vpa().allocate(pages) else {
+        for frame in phys..phys + pages {
+            pmm.free_page(frame);
+        }
+
+        return None;
+    };
+
+    let tables = kernel_page_table();
+    let flags = PageFlags::KERNEL_RW.with_cache(mode);
+
+    for (virt, frame) in allocation.range().as_rust_range().zip(phys..phys + pages) {
+        tables.map_page_small(&pmm, virt, frame, &flags);
+    }
+
+    let buffer = DmaBuffer {
+        allocation,
+        phys,
+        size: bytes,
+    };
+
+    unsafe { ptr::write_bytes(buffer.as_ptr_mut::<u8>(), 0, pages.size_bytes() as usize) };
+
+    Some(buffer)
+}
@@ -0,0 +1,58 @@
+use super::{PMM, PageFrameAllocator, VirtualAddress, lazy};
+use crate::arch::{PAGE_SMALL_SIZE, paging::PageTableSet};
+use core::ptr;
+
+#[derive(Clone, Copy, Debug)]
+pub struct PageFault {
+    pub addr: VirtualAddress,
+    pub present: bool,
+    pub write: bool,
+    pub user: bool,
+}
+
+fn handle_cow_fault(tables: &PageTableSet, fault: &PageFault) -> bool {
+    let virt = fault.addr.frame_containing();
+
+    let Some(frame) = tables.cow_frame(virt) else {
+        return false;
+    };
+
+    let pmm = PMM::get();
+
+    // if every other mapping has already been copied, this one can simply take over the frame
+    if pmm.page_refcount(frame) == 1 {
+        tables.resolve_cow(virt, frame);
+        return true;
+    }
+
+    let copy = pmm.allocate_single_page();
+
+    unsafe {
+        ptr::copy_nonoverlapping(
+            frame.to_virtual().as_ptr::<u8>(),
+            copy.to_virtual().as_ptr_mut::<u8>(),
+            PAGE_SMALL_SIZE as usize,
+        )
+    };
+
+    tables.resolve_cow(virt, copy);
+    pmm.release_page(frame);
+
+    true
+}
+
+// called by the arch fault handler; returns false if the fault could not be resolved
+// TODO: two cores faulting on the same cow page of one address space race here
+pub fn handle_page_fault(fault: &PageFault) -> bool {
+    let tables = PageTableSet::current();
+
+    if !fault.present {
+        return lazy::handle_fault(&tables, fault.addr.frame_containing());
+    }
+
+    if fault.write {
+        return handle_cow_fault(&tables, fault);
+    }
+
+    false
+}
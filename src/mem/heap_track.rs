@@ -0,0 +1,322 @@
+// heap allocation tracking
+//
+// when enabled on the cmdline, every live heap allocation is recorded along with a short
+// backtrace of where it came from, so that leaks can be found by dumping what is still alive
+// grouped by call site. the table lives in its own page-backed region, since it obviously can't
+// be allocated from the heap it is tracking.
+
+use super::{AddressRange, PMM, PageSize, VFRange, kernel_page_table, vpa};
+use crate::{
+    arch::{UnwindContext, paging::PageFlags},
+    modules::symbols,
+    sync::IntMutex,
+};
+use core::{
+    mem::size_of,
+    slice,
+    sync::atomic::{AtomicBool, Ordering},
+};
+use log::info;
+use rustc_demangle::demangle;
+
+const TABLE_CAPACITY: usize = 1 << 15;
+const TRACE_DEPTH: usize = 6;
+const MAX_REPORTED_SITES: usize = 64;
+
+const EMPTY: u64 = 0;
+
+type Site = [u64; TRACE_DEPTH];
+
+#[derive(Clone, Copy)]
+struct Entry {
+    ptr: u64,
+    size: u64,
+    site: Site,
+}
+
+struct Table {
+    entries: &'static mut [Entry],
+    live: usize,
+    dropped: usize,
+}
+
+static TABLE: IntMutex<Option<Table>> = IntMutex::new(None);
+
+// checked before taking the table lock, so that the allocator fast path stays lock-free when
+// tracking is off
+static ENABLED: AtomicBool = AtomicBool::new(false);
+
+#[inline(always)]
+pub(super) fn is_enabled() -> bool {
+    ENABLED.load(Ordering::Relaxed)
+}
+
+// the splitmix64 finalizer; the table is indexed by the low bits, which a plain multiply leaves
+// depending on nothing but the low bits of the pointer
+fn hash(ptr: u64) -> usize {
+    let mut x = ptr;
+    x = (x ^ (x >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+    x = (x ^ (x >> 27)).wrapping_mul(0x94d049bb133111eb);
+    (x ^ (x >> 31)) as usize
+}
+
+// linear probing with backward shift deletion, so that there are no tombstones to build up and
+// every probe ends at the first empty slot
+impl Table {
+    fn home(ptr: u64) -> usize {
+        hash(ptr) % TABLE_CAPACITY
+    }
+
+    fn slots(&self, ptr: u64) -> impl Iterator<Item = usize> {
+        let start = Self::home(ptr);
+        (0..TABLE_CAPACITY).map(move |i| (start + i) % TABLE_CAPACITY)
+    }
+
+    fn insert(&mut self, entry: Entry) {
+        // keep some headroom, otherwise probing degenerates into a full scan
+        if self.live >= TABLE_CAPACITY * 3 / 4 {
+            self.dropped += 1;
+            return;
+        }
+
+        let slot = self
+            .slots(entry.ptr)
+            .find(|&i| self.entries[i].ptr == EMPTY)
+            .unwrap();
+
+        self.entries[slot] = entry;
+        self.live += 1;
+    }
+
+    fn find_slot(&self, ptr: u64) -> Option<usize> {
+        self.slots(ptr)
+            .take_while(|&i| self.entries[i].ptr != EMPTY)
+            .find(|&i| self.entries[i].ptr == ptr)
+    }
+
+    fn find(&mut self, ptr: u64) -> Option<&mut Entry> {
+        let slot = self.find_slot(ptr)?;
+        Some(&mut self.entries[slot])
+    }
+
+    fn remove(&mut self, ptr: u64) -> Option<Entry> {
+        let mut hole = self.find_slot(ptr)?;
+        let res = self.entries[hole];
+
+        // pull back every entry after the hole that would no longer be found past it, i.e. whose
+        // home slot isn't cyclically within (hole, slot]
+        let mut slot = hole;
+
+        loop {
+            slot = (slot + 1) % TABLE_CAPACITY;
+
+            let next = self.entries[slot].ptr;
+
+            if next == EMPTY {
+                break;
+            }
+
+            let home = Self::home(next);
+            let stays = if hole <= slot {
+                hole < home && home <= slot
+            } else {
+                hole < home || home <= slot
+            };
+
+            if !stays {
+                self.entries[hole] = self.entries[slot];
+                hole = slot;
+            }
+        }
+
+        self.entries[hole].ptr = EMPTY;
+        self.live -= 1;
+        Some(res)
+    }
+}
+
+// captures the backtrace of the caller of the GlobalAlloc method this is inlined into
+#[inline(always)]
+pub(super) fn current_site() -> Site {
+    let mut site = [0; TRACE_DEPTH];
+    let mut context = unsafe { UnwindContext::get() };
+
+    for slot in site.iter_mut() {
+        if !unsafe { context.valid() } {
+            break;
+        }
+
+        *slot = unsafe { context.return_address() };
+        context = unsafe { context.next() };
+    }
+
+    site
+}
+
+pub(super) fn init() {
+    let size = (size_of::<Entry>() * TABLE_CAPACITY) as u64;
+    let pages = PageSize::new(size.div_ceil(crate::arch::PAGE_SMALL_SIZE));
+
+    let range: VFRange = vpa::get_global_vpa()
+        .allocate_backed(
+            &PMM::get(),
+            kernel_page_table(),
+            pages,
+            PageFlags::KERNEL_RW,
+            false,
+        )
+        .expect("mem::heap_track::init(): failed to allocate allocation table")
+        .leak();
+
+    // the backing pages are not guaranteed to be zeroed, and EMPTY is 0
+    let entries = unsafe {
+        let ptr = range.start().as_ptr_mut::<Entry>();
+        ptr.write_bytes(0, TABLE_CAPACITY);
+        slice::from_raw_parts_mut(ptr, TABLE_CAPACITY)
+    };
+
+    *TABLE.lock() = Some(Table {
+        entries,
+        live: 0,
+        dropped: 0,
+    });
+
+    ENABLED.store(true, Ordering::Relaxed);
+
+    info!(
+        "mem::heap_track::init(): tracking up to {} live allocations",
+        TABLE_CAPACITY * 3 / 4
+    );
+}
+
+pub(super) fn record_alloc(ptr: *mut u8, size: usize, site: Site) {
+    if ptr.is_null() {
+        return;
+    }
+
+    if let Some(table) = TABLE.lock().as_mut() {
+        table.insert(Entry {
+            ptr: ptr as u64,
+            size: size as u64,
+            site,
+        });
+    }
+}
+
+pub(super) fn record_free(ptr: *mut u8) {
+    if let Some(table) = TABLE.lock().as_mut() {
+        // allocations made before tracking was enabled are simply not found
+        table.remove(ptr as u64);
+    }
+}
+
+pub(super) fn record_realloc(old: *mut u8, new: *mut u8, size: usize, site: Site) {
+    if new.is_null() {
+        return;
+    }
+
+    let mut table = TABLE.lock();
+    let Some(table) = table.as_mut() else {
+        return;
+    };
+
+    if old == new
+        && let Some(entry) = table.find(old as u64)
+    {
+        entry.size = size as u64;
+        return;
+    }
+
+    table.remove(old as u64);
+    table.insert(Entry {
+        ptr: new as u64,
+        size: size as u64,
+        site,
+    });
+}
+
+#[derive(Clone, Copy)]
+struct SiteSummary {
+    site: Site,
+    count: usize,
+    bytes: u64,
+}
+
+// logs every live tracked allocation, grouped by call site
+pub fn dump_heap_allocations() {
+    // this must not allocate, since the table lock is held while the sites are gathered; logging
+    // and symbolizing them only happens once it is released
+    let mut sites = [SiteSummary {
+        site: [0; TRACE_DEPTH],
+        count: 0,
+        bytes: 0,
+    }; MAX_REPORTED_SITES];
+    let mut n_sites = 0;
+    let mut other = SiteSummary {
+        site: [0; TRACE_DEPTH],
+        count: 0,
+        bytes: 0,
+    };
+
+    // ENABLED is only set once the table is there
+    if !is_enabled() {
+        info!("mem::dump_heap_allocations(): tracking disabled, enable with mem:{{track_allocs}}");
+        return;
+    }
+
+    let (live, dropped) = {
+        let table = TABLE.lock();
+        let table = table.as_ref().unwrap();
+
+        for entry in table.entries.iter().filter(|entry| entry.ptr != EMPTY) {
+            let summary = match sites[..n_sites]
+                .iter()
+                .position(|summary| summary.site == entry.site)
+            {
+                Some(index) => &mut sites[index],
+                None if n_sites < MAX_REPORTED_SITES => {
+                    sites[n_sites].site = entry.site;
+                    n_sites += 1;
+                    &mut sites[n_sites - 1]
+                }
+                None => &mut other,
+            };
+
+            summary.count += 1;
+            summary.bytes += entry.size;
+        }
+
+        (table.live, table.dropped)
+    };
+
+    sites[..n_sites].sort_unstable_by_key(|summary| core::cmp::Reverse(summary.bytes));
+
+    info!(
+        "mem::dump_heap_allocations(): {} live allocations from {} sites ({} untracked)",
+        live, n_sites, dropped
+    );
+
+    for summary in &sites[..n_sites] {
+        info!(
+            "  {} allocations, {:#x} bytes",
+            summary.count, summary.bytes
+        );
+
+        for &addr in summary.site.iter().take_while(|&&addr| addr != 0) {
+            let name = symbols::symbolize(addr)
+                .0
+                .and_then(|mut iter| iter.next())
+                .and_then(|func| func.name)
+                .unwrap_or("unk");
+
+            info!("    {:#016x} in {:#}", addr, demangle(name));
+        }
+    }
+
+    if other.count > 0 {
+        info!(
+            "  {} allocations, {:#x} bytes from other sites",
+            other.count, other.bytes
+        );
+    }
+}
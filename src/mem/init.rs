@@ -4,11 +4,15 @@ use super::{
     vpa::{EarlyAllocator, VirtualAllocator},
 };
 use crate::{
-    arch::paging::{PageFlags, PageTableSet, get_higher_half_addr},
-    log::ansi::{ANSIFormatter, Color},
+    arch::paging::{CacheMode, PageFlags, PageTableSet, get_higher_half_addr},
+    cmdline::get_cmdline,
+    log::{
+        ansi::{ANSIFormatter, Color},
+        pstore,
+    },
     mem::{
         AddressRange, MEMORY_MAP_REQUEST, VFRange, get_hhdm_start, get_kernel_physical_base,
-        get_kernel_virtual_base, init_pdt, malloc::init_malloc, vpa,
+        get_kernel_virtual_base, heap_track, init_pdt, malloc::init_malloc, vpa, wx::audit_wx,
     },
 };
 use core::{cell::RefCell, ffi::c_void};
@@ -53,8 +57,11 @@ impl PageFrameAllocator for EarlyPMM {
         let entries = MemoryMapView::get();
 
         loop {
+            // the panic store sits at the end of an entry, so running into it means the entry is
+            // used up
             if state.offset < entries.at(state.index).size
                 && entries.at(state.index).entry_type == MemoryMapType::Usable
+                && !pstore::contains(entries.at(state.index).start + state.offset)
             {
                 break;
             }
@@ -90,7 +97,7 @@ impl EarlyPMM {
 pub fn dump_memory_info() {
     let mem_map = MEMORY_MAP_REQUEST.get_response().unwrap();
 
-    info!("memory map: ");
+    info!(target: "init_memmap", "memory map: ");
     for entries in mem_map.entries() {
         let (str, color) = match entries.entry_type {
             EntryType::USABLE => ("usable", Color::GREEN),
@@ -105,6 +112,7 @@ pub fn dump_memory_info() {
         };
 
         info!(
+            target: "init_memmap",
             "[{:12 }] {:#016x}-{:#016x} len = {:#x}",
             ANSIFormatter::new(&str).color(color),
             entries.base,
@@ -130,6 +138,16 @@ pub(super) struct VirtualMemoryLayout {
 
 pub(super) static VM_LAYOUT: Once<VirtualMemoryLayout> = Once::new();
 
+// the page tables set up by mem::init; the higher half is shared by every core, so kernel
+// mappings made through this are globally visible
+static KERNEL_PAGE_TABLE: Once<PageTableSet> = Once::new();
+
+pub fn kernel_page_table() -> &'static PageTableSet {
+    KERNEL_PAGE_TABLE
+        .get()
+        .expect("mem::kernel_page_table(): mm not initialized")
+}
+
 fn init_vm_layout(
     memory_map: &MemoryMapResponse,
 ) -> (
@@ -169,7 +187,7 @@ fn init_vm_layout(
     let pdt_size =
         (ByteSize::size_of::<page_info::PageState>() * hhdm_size.value()).page_size_roundup();
 
-    let heap_size = PageSize::new(1 << 28);
+    let heap_size = get_cmdline().mem.heap_size.page_size_roundup();
 
     let (pdt_base, pdt_end) = allocator
         .allocate_padded(pdt_size, padding)
@@ -225,9 +243,13 @@ fn transition_paging(pmm: &EarlyPMM, layout: &VirtualMemoryLayout, space: &mut P
         if let Some(traits) = match entry.entry_type {
             MemoryMapType::Usable
             | MemoryMapType::KernelBinaries
-            | MemoryMapType::Framebuffer
             | MemoryMapType::ACPIReclaimable
             | MemoryMapType::BootloaderReclaimable => Some(PageFlags::KERNEL_RW),
+            // the terminal scrolls by rewriting the whole framebuffer, which is painfully slow
+            // over an uncached or write-back mapping
+            MemoryMapType::Framebuffer => {
+                Some(PageFlags::KERNEL_RW.with_cache(CacheMode::WriteCombining))
+            }
             MemoryMapType::BadMemory | MemoryMapType::ACPINVS => Some(PageFlags::KERNEL_RO),
             _ => None,
         } {
@@ -342,6 +364,8 @@ pub fn init() -> PageTableSet {
 
     transition_paging(&early_pmm, layout, &mut root_space);
 
+    audit_wx(&root_space);
+
     init_pdt(
         &early_pmm,
         &mut root_space,
@@ -353,5 +377,11 @@ pub fn init() -> PageTableSet {
 
     vpa::initialize(VirtualAllocator::tree(early_allocator));
 
+    KERNEL_PAGE_TABLE.call_once(|| root_space);
+
+    if get_cmdline().mem.track_allocs {
+        heap_track::init();
+    }
+
     root_space
 }
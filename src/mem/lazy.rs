@@ -0,0 +1,120 @@
+// demand-paged mappings
+//
+// a lazy region only reserves virtual address space up front; physical pages are allocated and
+// zeroed by the page fault handler the first time each page is touched.
+
+extern crate alloc;
+
+use super::{
+    AddressRange, PMM, PageFrameAllocator, PageFrameNumber, VFRange, VirtualPageFrameNumber,
+};
+use crate::{
+    arch::paging::{PageFlags, PageTableSet},
+    sync::IntMutex,
+};
+use alloc::collections::BTreeMap;
+
+#[derive(Clone, Copy)]
+struct LazyRegion {
+    range: VFRange,
+    flags: PageFlags,
+}
+
+struct LazyRegionTree {
+    regions: BTreeMap<VirtualPageFrameNumber, LazyRegion>,
+}
+
+impl LazyRegionTree {
+    const fn new() -> LazyRegionTree {
+        LazyRegionTree {
+            regions: BTreeMap::new(),
+        }
+    }
+
+    fn find(&self, virt: VirtualPageFrameNumber) -> Option<LazyRegion> {
+        self.regions
+            .range(..=virt)
+            .next_back()
+            .map(|(_, region)| *region)
+            .filter(|region| region.range.contains(virt))
+    }
+}
+
+// the higher half is shared by every address space, so its lazy regions live in a single tree
+static KERNEL_REGIONS: IntMutex<LazyRegionTree> = IntMutex::new(LazyRegionTree::new());
+
+// lower half regions, keyed by the root table of the owning address space
+static USER_REGIONS: IntMutex<BTreeMap<PageFrameNumber, LazyRegionTree>> =
+    IntMutex::new(BTreeMap::new());
+
+fn with_tree<R>(
+    tables: &PageTableSet,
+    higher_half: bool,
+    action: impl FnOnce(&mut LazyRegionTree) -> R,
+) -> R {
+    if higher_half {
+        action(&mut KERNEL_REGIONS.lock())
+    } else {
+        action(
+            USER_REGIONS
+                .lock()
+                .entry(tables.root())
+                .or_insert_with(LazyRegionTree::new),
+        )
+    }
+}
+
+// reserves `range` in `tables` as a lazily populated mapping
+pub fn register(tables: &PageTableSet, range: VFRange, flags: PageFlags) {
+    with_tree(tables, range.start().is_higher_half(), |tree| {
+        let prev = tree
+            .regions
+            .insert(range.start(), LazyRegion { range, flags });
+        assert!(
+            prev.is_none(),
+            "mem::lazy::register(): region at {} already registered",
+            range.start()
+        );
+    });
+}
+
+// drops the lazy region starting at `base`; pages that were already populated stay mapped
+pub fn unregister(tables: &PageTableSet, base: VirtualPageFrameNumber) {
+    with_tree(tables, base.is_higher_half(), |tree| {
+        tree.regions.remove(&base);
+    });
+}
+
+// drops the kernel lazy regions that start inside `range`, which is being given back to the vpa
+pub(super) fn forget_kernel_range(range: VFRange) {
+    KERNEL_REGIONS
+        .lock()
+        .regions
+        .retain(|&base, _| !range.contains(base));
+}
+
+// drops every lower half lazy region of an address space that is being torn down
+pub fn forget_space(tables: &PageTableSet) {
+    USER_REGIONS.lock().remove(&tables.root());
+}
+
+// populates the page containing a not-present fault, if it belongs to a lazy region
+pub(super) fn handle_fault(tables: &PageTableSet, virt: VirtualPageFrameNumber) -> bool {
+    // the tree lock is held while mapping, so two cores touching the same page can't both
+    // populate it
+    with_tree(tables, virt.is_higher_half(), |tree| {
+        let Some(region) = tree.find(virt) else {
+            return false;
+        };
+
+        if tables.translate(virt).is_some() {
+            return true;
+        }
+
+        let pmm = PMM::get();
+        let frame = pmm.allocate_zeroed_page();
+        tables.map_page_small(&pmm, virt, frame, &region.flags);
+
+        true
+    })
+}
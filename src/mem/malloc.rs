@@ -1,15 +1,19 @@
 use super::{
     AddressRange, ByteSize, PMM, PageFrameAllocator, PageSize, VFRange, VirtualPageFrameNumber,
-    Wrapper,
+    Wrapper, heap_track, oom,
 };
 use crate::{
-    arch::paging::{PageFlags, PageTableSet},
+    arch::{
+        MEDIUM_PAGE_PAGE_SIZE, SMALL_PAGE_PAGE_SIZE,
+        paging::{PageFlags, PageTableSet},
+    },
+    mp::per_cpu_counter,
     sync::IntMutex,
 };
 use core::{
     alloc::{GlobalAlloc, Layout},
     cmp::Ordering,
-    ptr::{NonNull, null_mut},
+    ptr::NonNull,
 };
 use log::info;
 use spin::Once;
@@ -22,6 +26,79 @@ struct BumpHeap {
     addr: PageTableSet,
 }
 
+impl BumpHeap {
+    // growth requests at least this large are backed by 2MiB pages where possible
+    const HUGE_PAGE_THRESHOLD: PageSize = MEDIUM_PAGE_PAGE_SIZE;
+
+    fn map_small(&mut self) {
+        let phys_frame = self.pmm.allocate_single_page();
+
+        self.addr
+            .map_page_small(&self.pmm, self.limit, phys_frame, &PageFlags::KERNEL_RW);
+
+        self.limit += SMALL_PAGE_PAGE_SIZE;
+    }
+
+    fn map_medium(&mut self) -> bool {
+        let Some(phys_frame) = self
+            .pmm
+            .allocate_contiguous(MEDIUM_PAGE_PAGE_SIZE, MEDIUM_PAGE_PAGE_SIZE)
+        else {
+            return false;
+        };
+
+        self.addr
+            .map_page_medium(&self.pmm, self.limit, phys_frame, &PageFlags::KERNEL_RW);
+
+        self.limit += MEDIUM_PAGE_PAGE_SIZE;
+        true
+    }
+
+    fn grow(&mut self, size: PageSize) -> Result<(), ()> {
+        if size < Self::HUGE_PAGE_THRESHOLD {
+            if self.limit + size > self.range.end() {
+                return Err(());
+            }
+
+            for _ in 0..size.value() {
+                self.map_small();
+            }
+
+            return Ok(());
+        }
+
+        // large growth: fill up to the next 2MiB boundary with small pages, then round the rest
+        // up to whole huge pages
+        let misalignment = self.limit.value() % MEDIUM_PAGE_PAGE_SIZE.value();
+        let head = PageSize::new(
+            (MEDIUM_PAGE_PAGE_SIZE.value() - misalignment) % MEDIUM_PAGE_PAGE_SIZE.value(),
+        );
+        let end = self.limit
+            + head
+            + PageSize::new(size.value().next_multiple_of(MEDIUM_PAGE_PAGE_SIZE.value()));
+
+        if end > self.range.end() {
+            return Err(());
+        }
+
+        for _ in 0..head.value() {
+            self.map_small();
+        }
+
+        while self.limit < end {
+            // physical memory may be too fragmented for a huge page, in which case we fall back
+            // to mapping this chunk with small pages
+            if !self.map_medium() {
+                for _ in 0..MEDIUM_PAGE_PAGE_SIZE.value() {
+                    self.map_small();
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
 impl OomHandler for BumpHeap {
     fn handle_oom(talc: &mut Talc<Self>, layout: core::alloc::Layout) -> Result<(), ()> {
         let this = &mut talc.oom_handler;
@@ -29,18 +106,7 @@ impl OomHandler for BumpHeap {
         let initial_span = Span::new(base.as_ptr_mut(), this.limit.as_ptr_mut());
         let size = ByteSize::new(layout.pad_to_align().size() as u64).page_size_roundup();
 
-        if base + size > this.range.end() {
-            return Err(());
-        }
-
-        for _ in 0..size.value() {
-            let phys_frame = this.pmm.allocate_single_page();
-
-            this.addr
-                .map_page_small(&this.pmm, this.limit, phys_frame, &PageFlags::KERNEL_RW);
-
-            this.limit += PageSize::new(1);
-        }
+        this.grow(size)?;
 
         let final_span = Span::new(base.as_ptr_mut(), this.limit.as_ptr_mut());
 
@@ -54,21 +120,79 @@ impl OomHandler for BumpHeap {
     }
 }
 
+per_cpu_counter!(ALLOCATIONS);
+per_cpu_counter!(FREES);
+
+pub struct HeapStats {
+    pub allocations: u64,
+    pub frees: u64,
+}
+
+pub fn heap_stats() -> HeapStats {
+    HeapStats {
+        allocations: ALLOCATIONS.sum(),
+        frees: FREES.sum(),
+    }
+}
+
 // TODO: this should delegate stuff, but im lazy
 
 struct GlobalAllocImpl {
     delegate: Once<IntMutex<Talc<BumpHeap>>>,
 }
 
+impl GlobalAllocImpl {
+    // talc only fails once the heap's range is used up, since running out of frames is already
+    // handled by the PMM; that goes through the OOM policy just the same. this runs without the
+    // heap lock, so that a victim can give its heap memory back before the retry
+    fn malloc(&self, layout: Layout) -> NonNull<u8> {
+        let delegate = self.delegate.get().expect("alloc not initialized");
+
+        loop {
+            if let Ok(ptr) = unsafe { delegate.lock().malloc(layout) } {
+                return ptr;
+            }
+
+            oom::out_of_memory(ByteSize::new(layout.size() as u64).page_size_roundup());
+        }
+    }
+
+    // must be called without the heap lock held
+    unsafe fn release(&self, ptr: NonNull<u8>, layout: Layout) {
+        // in debug builds, freed chunks sit in a poisoned quarantine before being reused; a
+        // use-after-free is only reported here, so the panic path can still allocate
+        #[cfg(debug_assertions)]
+        let (ptr, layout) = match super::poison::quarantine_chunk(ptr, layout) {
+            Ok(Some(chunk)) => chunk,
+            Ok(None) => return,
+            Err(err) => panic!("{}", err),
+        };
+
+        let mut delegate = self.delegate.get().expect("alloc not initialized").lock();
+        unsafe { delegate.free(ptr, layout) };
+    }
+}
+
 unsafe impl GlobalAlloc for GlobalAllocImpl {
     unsafe fn alloc(&self, layout: core::alloc::Layout) -> *mut u8 {
-        let mut delegate = self.delegate.get().expect("alloc not initialized").lock();
-        unsafe { delegate.malloc(layout).map_or(null_mut(), |nn| nn.as_ptr()) }
+        let res = self.malloc(layout).as_ptr();
+        ALLOCATIONS.inc();
+
+        if heap_track::is_enabled() {
+            heap_track::record_alloc(res, layout.size(), heap_track::current_site());
+        }
+
+        res
     }
 
     unsafe fn dealloc(&self, ptr: *mut u8, layout: core::alloc::Layout) {
-        let mut delegate = self.delegate.get().expect("alloc not initialized").lock();
-        unsafe { delegate.free(NonNull::new_unchecked(ptr), layout) };
+        FREES.inc();
+
+        if heap_track::is_enabled() {
+            heap_track::record_free(ptr);
+        }
+
+        unsafe { self.release(NonNull::new_unchecked(ptr), layout) };
     }
 
     unsafe fn realloc(
@@ -76,6 +200,23 @@ unsafe impl GlobalAlloc for GlobalAllocImpl {
         ptr: *mut u8,
         old_layout: core::alloc::Layout,
         new_size: usize,
+    ) -> *mut u8 {
+        let res = unsafe { self.realloc_untracked(ptr, old_layout, new_size) };
+
+        if heap_track::is_enabled() {
+            heap_track::record_realloc(ptr, res, new_size, heap_track::current_site());
+        }
+
+        res
+    }
+}
+
+impl GlobalAllocImpl {
+    unsafe fn realloc_untracked(
+        &self,
+        ptr: *mut u8,
+        old_layout: core::alloc::Layout,
+        new_size: usize,
     ) -> *mut u8 {
         let delegate = self.delegate.get().expect("alloc not initialized");
 
@@ -92,29 +233,15 @@ unsafe impl GlobalAlloc for GlobalAllocImpl {
                 let new_layout =
                     unsafe { Layout::from_size_align_unchecked(new_size, old_layout.align()) };
 
-                let mut lock = delegate.lock();
-                let allocation = match unsafe { lock.malloc(new_layout) } {
-                    Ok(ptr) => ptr,
-                    Err(_) => return null_mut(),
-                };
+                let allocation = self.malloc(new_layout);
 
-                if old_layout.size() > 0x10000 {
-                    drop(lock);
-                    unsafe {
-                        allocation
-                            .as_ptr()
-                            .copy_from_nonoverlapping(ptr, old_layout.size())
-                    };
-                    lock = delegate.lock();
-                } else {
-                    unsafe {
-                        allocation
-                            .as_ptr()
-                            .copy_from_nonoverlapping(ptr, old_layout.size())
-                    };
-                }
+                unsafe {
+                    allocation
+                        .as_ptr()
+                        .copy_from_nonoverlapping(ptr, old_layout.size())
+                };
 
-                unsafe { lock.free(nn_ptr, old_layout) };
+                unsafe { self.release(nn_ptr, old_layout) };
                 allocation.as_ptr()
             }
 
@@ -140,12 +267,35 @@ static GLOBAL_ALLOC: GlobalAllocImpl = GlobalAllocImpl {
 pub(super) fn init_malloc(heap_range: VFRange, addr: PageTableSet) {
     info!("mem::init_malloc(): initializing heap");
 
+    // start the heap on a 2MiB boundary so that large growth can be backed by huge pages
+    let start = VirtualPageFrameNumber::new(
+        heap_range
+            .start()
+            .value()
+            .next_multiple_of(MEDIUM_PAGE_PAGE_SIZE.value()),
+    );
+
+    assert!(
+        start < heap_range.end(),
+        "mem::init_malloc(): heap range too small"
+    );
+
+    let mut heap = BumpHeap {
+        range: VFRange::new(start, heap_range.end()),
+        limit: start,
+        pmm: PMM::get(),
+        addr,
+    };
+
+    // back the first chunk of the heap with a huge page up front, since early boot allocates a
+    // lot of small objects
+    heap.grow(BumpHeap::HUGE_PAGE_THRESHOLD)
+        .expect("mem::init_malloc(): failed to map initial heap");
+
     GLOBAL_ALLOC.delegate.call_once(|| {
-        IntMutex::new(Talc::new(BumpHeap {
-            range: heap_range,
-            limit: heap_range.start(),
-            pmm: PMM::get(),
-            addr,
-        }))
+        let mut talc = Talc::new(heap);
+        let span = Span::new(start.as_ptr_mut(), talc.oom_handler.limit.as_ptr_mut());
+        unsafe { talc.claim(span).expect("initial heap claim failed") };
+        IntMutex::new(talc)
     });
 }
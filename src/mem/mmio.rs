@@ -0,0 +1,102 @@
+// uncached kernel mappings of device registers; drivers should go through this rather than
+// poking at the (write-back) hhdm
+
+use super::{
+    AddressRange, ByteSize, PMM, PhysicalAddress, VirtualAddress, Wrapper, kernel_page_table,
+    vpa::{TreeAllocator, VirtualAllocation, get_global_vpa},
+};
+use crate::arch::{
+    PAGE_SMALL_SIZE,
+    paging::{CacheMode, PageFlags},
+};
+
+pub struct VolatileRegion {
+    allocation: VirtualAllocation<'static, TreeAllocator>,
+    base: VirtualAddress,
+    size: ByteSize,
+}
+
+unsafe impl Send for VolatileRegion {}
+unsafe impl Sync for VolatileRegion {}
+
+impl VolatileRegion {
+    pub fn base(&self) -> VirtualAddress {
+        self.base
+    }
+
+    pub fn size(&self) -> ByteSize {
+        self.size
+    }
+
+    fn register_ptr<T>(&self, offset: usize) -> *mut T {
+        assert!(
+            (offset + size_of::<T>()) as u64 <= self.size.value(),
+            "VolatileRegion: access at {:#x} out of bounds (size {})",
+            offset,
+            self.size
+        );
+
+        let addr = self.base + ByteSize::new(offset as u64);
+        assert!(
+            addr.value().is_multiple_of(align_of::<T>() as u64),
+            "VolatileRegion: unaligned access at {:#x}",
+            offset
+        );
+
+        addr.as_ptr_mut()
+    }
+
+    pub fn read<T: Copy>(&self, offset: usize) -> T {
+        unsafe { self.register_ptr::<T>(offset).read_volatile() }
+    }
+
+    pub fn write<T: Copy>(&self, offset: usize, value: T) {
+        unsafe { self.register_ptr::<T>(offset).write_volatile(value) }
+    }
+}
+
+impl Drop for VolatileRegion {
+    fn drop(&mut self) {
+        let tables = kernel_page_table();
+
+        for page in self.allocation.range().as_rust_range() {
+            tables.unmap_page_small(page);
+        }
+    }
+}
+
+pub fn map_mmio(phys: PhysicalAddress, size: ByteSize) -> Option<VolatileRegion> {
+    map_mmio_with_mode(phys, size, CacheMode::Uncached)
+}
+
+pub fn map_mmio_with_mode(
+    phys: PhysicalAddress,
+    size: ByteSize,
+    mode: CacheMode,
+) -> Option<VolatileRegion> {
+    let page_offset = ByteSize::new(phys.value() % PAGE_SMALL_SIZE);
+    let first_frame = phys.frame_containing();
+    let pages = (page_offset + size).page_size_roundup();
+
+    let allocation = get_global_vpa().allocate(pages)?;
+
+    let tables = kernel_page_table();
+    let pmm = PMM::get();
+    let flags = PageFlags::KERNEL_RW.with_cache(mode);
+
+    for (virt, frame) in allocation
+        .range()
+        .as_rust_range()
+        .zip(first_frame..first_frame + pages)
+    {
+        tables.map_page_small(&pmm, virt, frame, &flags);
+    }
+
+    let base = allocation.range().start().address() + page_offset;
+
+    Some(VolatileRegion {
+        allocation,
+        base,
+        size,
+    })
+}
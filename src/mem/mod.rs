@@ -1,15 +1,35 @@
+mod address_space;
+pub mod dma;
+mod fault;
+mod heap_track;
 mod init;
+pub mod lazy;
 mod malloc;
+mod mmio;
+pub mod oom;
+pub mod options;
 mod pmm;
+#[cfg(debug_assertions)]
+mod poison;
 mod requests;
+pub mod stack;
 mod types;
+mod valloc;
 pub mod vpa;
+mod wx;
 
+pub use address_space::*;
+pub use fault::*;
+pub use heap_track::dump_heap_allocations;
 pub use init::*;
+pub use malloc::{HeapStats, heap_stats};
+pub use mmio::*;
 pub use pmm::*;
 pub use requests::*;
 use spin::Once;
 pub use types::*;
+pub use valloc::*;
+pub use wx::make_executable;
 
 use crate::{arch::paging::PageTableSet, mp::core_local};
 
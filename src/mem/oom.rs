@@ -0,0 +1,131 @@
+// out-of-memory handling
+//
+// when the PMM runs dry, the configured policy decides how hard we try to make room before
+// panicking. reclaimers and victims are invoked from whatever context hit the OOM, which may hold
+// the heap or page table locks, so they must only hand frames straight back to the PMM and must
+// never allocate.
+
+use super::{PageSize, Wrapper, options::OomPolicy};
+use crate::{
+    cmdline::get_cmdline,
+    sync::{IntMutex, RwIntLock},
+};
+use arrayvec::ArrayVec;
+use core::cmp::Reverse;
+use log::{error, warn};
+
+// a cache that can drop some of its memory on demand
+pub trait Reclaimer: Sync {
+    fn name(&self) -> &'static str;
+
+    // releases up to `target` pages, returning how many were actually freed
+    fn reclaim(&self, target: PageSize) -> PageSize;
+}
+
+// something that can be killed to free its memory, such as a WASM instance
+pub trait OomVictim: Sync {
+    fn name(&self) -> &'static str;
+
+    fn footprint(&self) -> PageSize;
+
+    // tears the consumer down and releases its memory, returning how many pages were freed
+    fn kill(&self) -> PageSize;
+}
+
+const MAX_RECLAIMERS: usize = 16;
+const MAX_VICTIMS: usize = 64;
+
+// reclaimers are registered once at boot, but walked on every OOM from any core
+static RECLAIMERS: RwIntLock<ArrayVec<&'static dyn Reclaimer, MAX_RECLAIMERS>> =
+    RwIntLock::new(ArrayVec::new_const());
+static VICTIMS: IntMutex<ArrayVec<&'static dyn OomVictim, MAX_VICTIMS>> =
+    IntMutex::new(ArrayVec::new_const());
+
+pub fn register_reclaimer(reclaimer: &'static dyn Reclaimer) {
+    RECLAIMERS
+        .write()
+        .try_push(reclaimer)
+        .expect("mem::oom: too many reclaimers");
+}
+
+pub fn register_victim(victim: &'static dyn OomVictim) -> Result<(), ()> {
+    VICTIMS.lock().try_push(victim).map_err(|_| ())
+}
+
+pub fn unregister_victim(victim: &'static dyn OomVictim) {
+    VICTIMS.lock().retain(|v| !core::ptr::addr_eq(*v, victim));
+}
+
+fn try_reclaim(target: PageSize) -> PageSize {
+    let mut freed = PageSize::new(0);
+
+    for reclaimer in RECLAIMERS.read().iter() {
+        if freed >= target {
+            break;
+        }
+
+        let res = reclaimer.reclaim(PageSize::new(target.value() - freed.value()));
+
+        if res.value() > 0 {
+            warn!(
+                "mem::oom: reclaimed {} pages from {}",
+                res,
+                reclaimer.name()
+            );
+        }
+
+        freed += res;
+    }
+
+    freed
+}
+
+// kills the biggest victim that actually frees something; one that doesn't stays registered, since
+// it may well have more to give up later
+fn try_kill() -> PageSize {
+    // a copy, since a victim may unregister itself while being killed
+    let mut victims = VICTIMS.lock().clone();
+    victims.sort_unstable_by_key(|victim| Reverse(victim.footprint()));
+
+    for victim in victims {
+        warn!(
+            "mem::oom: killing {} ({} pages)",
+            victim.name(),
+            victim.footprint()
+        );
+
+        let freed = victim.kill();
+
+        if freed.value() > 0 {
+            unregister_victim(victim);
+            return freed;
+        }
+
+        warn!("mem::oom: killing {} freed nothing", victim.name());
+    }
+
+    PageSize::new(0)
+}
+
+// called by the PMM when an allocation of `request` pages failed, and by the heap when its range is
+// used up; returns once some memory was freed and the allocation should be retried, and panics if
+// the policy can't make any progress
+pub fn out_of_memory(request: PageSize) {
+    let policy = get_cmdline().mem.oom;
+
+    if policy != OomPolicy::Panic {
+        if try_reclaim(request).value() > 0 {
+            return;
+        }
+
+        if policy == OomPolicy::Kill && try_kill().value() > 0 {
+            return;
+        }
+    }
+
+    error!(
+        "mem::oom: failed to satisfy allocation of {} pages",
+        request
+    );
+    panic!("out of memory");
+}
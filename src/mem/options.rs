@@ -0,0 +1,27 @@
+use proc_macros::CmdlineParsable;
+
+use super::ByteSize;
+use crate::cmdline::CmdlineParsable;
+
+// what to do once physical memory runs out
+#[derive(CmdlineParsable, Clone, Copy, PartialEq, Eq)]
+pub enum OomPolicy {
+    // give up immediately
+    Panic,
+    // ask registered caches to release memory, then give up
+    Reclaim,
+    // reclaim, then kill the largest registered memory consumer, then give up
+    Kill,
+}
+
+#[derive(CmdlineParsable, Clone, Copy)]
+pub struct MemOptions {
+    #[default_value(OomPolicy::Kill)]
+    pub oom: OomPolicy,
+    // record every live heap allocation and its call site, see mem::dump_heap_allocations
+    #[default_value(false)]
+    pub track_allocs: bool,
+    // size of the kernel heap's virtual range
+    #[default_value(ByteSize::new(1 << 28))]
+    pub heap_size: ByteSize,
+}
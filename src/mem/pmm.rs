@@ -1,13 +1,17 @@
-use super::{EarlyPMM, MemoryMapView, PageFrameNumber, PageSize, VirtualAddress};
+use super::{EarlyPMM, MemoryMapView, PageFrameNumber, PageSize, VirtualAddress, oom};
 use crate::{
     arch::{
         PAGE_SMALL_SIZE, SMALL_PAGE_PAGE_SIZE,
         paging::{PageFlags, PageTableSet},
     },
+    log::pstore,
     mem::{ByteSize, MemoryMapType, Wrapper},
     sync::IntMutex,
 };
-use core::ptr;
+use core::{
+    ptr,
+    sync::atomic::{AtomicU32, Ordering},
+};
 use log::info;
 use page_info::PageState;
 use spin::Once;
@@ -33,15 +37,26 @@ pub trait PageFrameAllocator {
 
 pub mod page_info {
     use crate::mem::PageFrameNumber;
+    use core::sync::atomic::AtomicU32;
 
     pub enum PageState {
         Free(Option<PageFrameNumber>),
         Used,
+        // freed, but held back by the use-after-free quarantine
+        #[cfg(debug_assertions)]
+        Quarantined,
     }
 
     #[repr(align(64))]
     pub struct Page {
         pub state: PageState,
+
+        // number of mappings referencing this frame; frames are shared by copy-on-write mappings
+        pub refcount: AtomicU32,
+
+        // set when the frame was filled with the poison pattern on free
+        #[cfg(debug_assertions)]
+        pub poisoned: bool,
     }
 }
 
@@ -119,9 +134,15 @@ pub(super) fn init_pdt(
         for offset in PageSize::new(0)..entry.size {
             let frame = entry.start + offset;
             let info = get_page_info(frame);
-            *info = if entry.entry_type == MemoryMapType::Usable && !pmm.is_used(index, offset) {
+            *info = if entry.entry_type == MemoryMapType::Usable
+                && !pmm.is_used(index, offset)
+                && !pstore::contains(frame)
+            {
                 let result = page_info::Page {
                     state: PageState::Free(next_free),
+                    refcount: AtomicU32::new(0),
+                    #[cfg(debug_assertions)]
+                    poisoned: false,
                 };
 
                 next_free = Some(frame);
@@ -130,6 +151,9 @@ pub(super) fn init_pdt(
             } else {
                 page_info::Page {
                     state: PageState::Used,
+                    refcount: AtomicU32::new(0),
+                    #[cfg(debug_assertions)]
+                    poisoned: false,
                 }
             }
         }
@@ -137,18 +161,51 @@ pub(super) fn init_pdt(
 
     *pdt.free_list.lock() = next_free;
 
+    #[cfg(debug_assertions)]
+    oom::register_reclaimer(&PageQuarantineReclaimer);
+
     info!("mem::init_pdt(): wrote physical page data table");
 }
 
+// quarantined frames are only held back to catch use-after-free, so they are the first thing to go
+// under memory pressure
+#[cfg(debug_assertions)]
+struct PageQuarantineReclaimer;
+
+#[cfg(debug_assertions)]
+impl oom::Reclaimer for PageQuarantineReclaimer {
+    fn name(&self) -> &'static str {
+        "page quarantine"
+    }
+
+    fn reclaim(&self, target: PageSize) -> PageSize {
+        let pmm = PMM::get();
+        let mut freed = PageSize::new(0);
+
+        while freed < target
+            && let Some(frame) = super::poison::take_quarantined_page()
+        {
+            pmm.release_quarantined(frame);
+            freed += PageSize::new(1);
+        }
+
+        freed
+    }
+}
+
 pub struct PMM {
     pdt: &'static PDTData,
 }
 
 impl PageFrameAllocator for PMM {
     fn allocate_single_page(&self) -> PageFrameNumber {
-        // TODO: maybe use results more
-        self.allocate_pages(PageSize::new(1))
-            .expect("out of memory")
+        loop {
+            if let Some(frame) = self.allocate_pages(PageSize::new(1)) {
+                return frame;
+            }
+
+            oom::out_of_memory(PageSize::new(1));
+        }
     }
 }
 
@@ -159,6 +216,22 @@ impl PMM {
         }
     }
 
+    // like allocate_zeroed_page, but fails instead of going through the oom policy, for callers
+    // that have a way to back out
+    pub fn try_allocate_zeroed_page(&self) -> Option<PageFrameNumber> {
+        let frame = self.allocate_pages(PageSize::new(1))?;
+
+        unsafe {
+            ptr::write_bytes(
+                frame.to_virtual().as_ptr_mut::<u8>(),
+                0,
+                PAGE_SMALL_SIZE as usize,
+            )
+        };
+
+        Some(frame)
+    }
+
     fn allocate_pages(&self, count: PageSize) -> Option<PageFrameNumber> {
         // TODO
         assert!(count.value() == 1);
@@ -172,6 +245,168 @@ impl PMM {
             } else {
                 panic!("free list points to non-free page")
             }
+
+            free_page.state = PageState::Used;
+            free_page.refcount.store(1, Ordering::Relaxed);
+
+            #[cfg(debug_assertions)]
+            if free_page.poisoned {
+                free_page.poisoned = false;
+                super::poison::verify_page(free_page_number);
+            }
         })
     }
+
+    // finds a physically contiguous run of free frames; this scans the pdt, so it is only meant
+    // for rare allocations such as dma buffers or huge heap pages
+    pub fn allocate_contiguous(&self, count: PageSize, align: PageSize) -> Option<PageFrameNumber> {
+        let mut free_list = self.pdt.free_list.lock();
+
+        let is_free =
+            |frame: PageFrameNumber| matches!(get_page_info(frame).state, PageState::Free(_));
+
+        let start = MemoryMapView::get()
+            .iter()
+            .filter(|entry| entry.entry_type == MemoryMapType::Usable)
+            .find_map(|entry| {
+                let end = entry.start + entry.size;
+                let mut base =
+                    PageFrameNumber::new(entry.start.value().next_multiple_of(align.value()));
+
+                while base + count <= end {
+                    match (base..base + count).find(|&frame| !is_free(frame)) {
+                        Some(used) => {
+                            base = PageFrameNumber::new(
+                                (used.value() + 1).next_multiple_of(align.value()),
+                            )
+                        }
+                        None => return Some(base),
+                    }
+                }
+
+                None
+            })?;
+
+        let end = start + count;
+
+        // unlink the run from the free list
+        let mut prev: Option<PageFrameNumber> = None;
+        let mut cursor = *free_list;
+
+        while let Some(frame) = cursor {
+            let PageState::Free(next) = get_page_info(frame).state else {
+                panic!("free list points to non-free page")
+            };
+
+            if start <= frame && frame < end {
+                match prev {
+                    Some(prev) => get_page_info(prev).state = PageState::Free(next),
+                    None => *free_list = next,
+                }
+
+                let info = get_page_info(frame);
+                info.state = PageState::Used;
+                info.refcount.store(1, Ordering::Relaxed);
+
+                #[cfg(debug_assertions)]
+                if info.poisoned {
+                    info.poisoned = false;
+                    super::poison::verify_page(frame);
+                }
+            } else {
+                prev = Some(frame);
+            }
+
+            cursor = next;
+        }
+
+        Some(start)
+    }
+
+    pub fn free_page(&self, frame: PageFrameNumber) {
+        assert!(
+            matches!(get_page_info(frame).state, PageState::Used),
+            "PMM::free_page(): double free of frame {}",
+            frame
+        );
+
+        assert!(
+            get_page_info(frame).refcount.swap(0, Ordering::Relaxed) <= 1,
+            "PMM::free_page(): frame {} is still shared",
+            frame
+        );
+
+        self.push_free(frame);
+    }
+
+    fn push_free(&self, frame: PageFrameNumber) {
+        #[cfg(debug_assertions)]
+        {
+            // a quarantined frame is neither used nor free, so freeing it again trips the double
+            // free assert above instead of linking it into the free list twice
+            get_page_info(frame).state = PageState::Quarantined;
+
+            if let Some(evicted) = super::poison::quarantine_page(frame) {
+                self.release_quarantined(evicted);
+            }
+        }
+
+        #[cfg(not(debug_assertions))]
+        self.insert_free(frame);
+    }
+
+    #[cfg(debug_assertions)]
+    fn release_quarantined(&self, frame: PageFrameNumber) {
+        assert!(
+            matches!(get_page_info(frame).state, PageState::Quarantined),
+            "PMM::release_quarantined(): frame {} left the quarantine in another state",
+            frame
+        );
+
+        self.insert_free(frame);
+    }
+
+    fn insert_free(&self, frame: PageFrameNumber) {
+        let mut free_list = self.pdt.free_list.lock();
+        let info = get_page_info(frame);
+
+        info.state = PageState::Free(*free_list);
+
+        #[cfg(debug_assertions)]
+        {
+            info.poisoned = true;
+        }
+
+        *free_list = Some(frame);
+    }
+
+    pub fn page_refcount(&self, frame: PageFrameNumber) -> u32 {
+        get_page_info(frame).refcount.load(Ordering::Acquire)
+    }
+
+    // adds a reference to an allocated frame that is about to be mapped a second time
+    pub fn share_page(&self, frame: PageFrameNumber) {
+        let prev = get_page_info(frame)
+            .refcount
+            .fetch_add(1, Ordering::Relaxed);
+        assert!(
+            prev != 0,
+            "PMM::share_page(): frame {} is not allocated",
+            frame
+        );
+    }
+
+    // drops a reference to a frame, freeing it once the last one is gone
+    pub fn release_page(&self, frame: PageFrameNumber) {
+        let prev = get_page_info(frame).refcount.fetch_sub(1, Ordering::AcqRel);
+        assert!(
+            prev != 0,
+            "PMM::release_page(): frame {} is not allocated",
+            frame
+        );
+
+        if prev == 1 {
+            self.push_free(frame);
+        }
+    }
 }
@@ -0,0 +1,132 @@
+// debug-only use-after-free detection
+//
+// freed memory is filled with a poison pattern and parked in a quarantine before it is handed
+// back to the underlying allocator. once an entry is evicted from the quarantine (i.e. right
+// before it can be reused), the pattern is verified, and any modification is reported as a
+// use-after-free.
+
+use super::PageFrameNumber;
+use crate::{arch::PAGE_SMALL_SIZE, sync::IntMutex};
+use core::{alloc::Layout, fmt, ptr::NonNull, slice};
+
+pub const POISON_BYTE: u8 = 0x6b;
+
+const HEAP_QUARANTINE_SIZE: usize = 64;
+const PAGE_QUARANTINE_SIZE: usize = 32;
+
+pub struct Quarantine<T: Copy, const N: usize> {
+    entries: [Option<T>; N],
+    head: usize,
+}
+
+impl<T: Copy, const N: usize> Quarantine<T, N> {
+    pub const fn new() -> Quarantine<T, N> {
+        Quarantine {
+            entries: [None; N],
+            head: 0,
+        }
+    }
+
+    // inserts an entry, returning the oldest one if the quarantine is full
+    pub fn push(&mut self, value: T) -> Option<T> {
+        let evicted = self.entries[self.head].replace(value);
+        self.head = (self.head + 1) % N;
+        evicted
+    }
+
+    // removes the oldest entry
+    pub fn pop(&mut self) -> Option<T> {
+        (0..N).find_map(|i| self.entries[(self.head + i) % N].take())
+    }
+}
+
+#[derive(Clone, Copy)]
+struct HeapChunk(NonNull<u8>, Layout);
+
+// chunks are only ever touched with the quarantine lock held
+unsafe impl Send for HeapChunk {}
+
+static HEAP_QUARANTINE: IntMutex<Quarantine<HeapChunk, HEAP_QUARANTINE_SIZE>> =
+    IntMutex::new(Quarantine::new());
+
+static PAGE_QUARANTINE: IntMutex<Quarantine<PageFrameNumber, PAGE_QUARANTINE_SIZE>> =
+    IntMutex::new(Quarantine::new());
+
+pub fn poison(ptr: *mut u8, len: usize) {
+    unsafe { ptr.write_bytes(POISON_BYTE, len) };
+}
+
+// returns the offset of the first byte that no longer matches the poison pattern
+pub fn find_corruption(ptr: *const u8, len: usize) -> Option<usize> {
+    unsafe { slice::from_raw_parts(ptr, len) }
+        .iter()
+        .position(|&b| b != POISON_BYTE)
+}
+
+// a quarantined chunk that was written to after it was freed
+pub struct UseAfterFree {
+    ptr: NonNull<u8>,
+    size: usize,
+    offset: usize,
+}
+
+impl fmt::Display for UseAfterFree {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "mem::poison: use-after-free detected: heap chunk {:p} (size {:#x}) modified at offset {:#x} after free",
+            self.ptr, self.size, self.offset
+        )
+    }
+}
+
+// poisons a freed heap chunk and quarantines it; returns the chunk that should actually be
+// released to the heap, if any. a use-after-free is returned rather than panicked on, so the caller
+// can report it with no allocator lock held
+pub(super) fn quarantine_chunk(
+    ptr: NonNull<u8>,
+    layout: Layout,
+) -> Result<Option<(NonNull<u8>, Layout)>, UseAfterFree> {
+    poison(ptr.as_ptr(), layout.size());
+
+    let Some(HeapChunk(ptr, layout)) = HEAP_QUARANTINE.lock().push(HeapChunk(ptr, layout)) else {
+        return Ok(None);
+    };
+
+    match find_corruption(ptr.as_ptr(), layout.size()) {
+        Some(offset) => Err(UseAfterFree {
+            ptr,
+            size: layout.size(),
+            offset,
+        }),
+        None => Ok(Some((ptr, layout))),
+    }
+}
+
+// poisons a freed page frame and quarantines it; returns the frame that should actually be
+// released to the free list, if any
+pub(super) fn quarantine_page(frame: PageFrameNumber) -> Option<PageFrameNumber> {
+    poison(
+        frame.to_virtual().as_ptr_mut::<u8>(),
+        PAGE_SMALL_SIZE as usize,
+    );
+
+    PAGE_QUARANTINE.lock().push(frame)
+}
+
+// takes a frame out of the quarantine early, e.g. to satisfy an allocation under memory pressure
+pub(super) fn take_quarantined_page() -> Option<PageFrameNumber> {
+    PAGE_QUARANTINE.lock().pop()
+}
+
+// verifies a previously poisoned frame that is about to be handed out again
+pub(super) fn verify_page(frame: PageFrameNumber) {
+    if let Some(offset) =
+        find_corruption(frame.to_virtual().as_ptr::<u8>(), PAGE_SMALL_SIZE as usize)
+    {
+        panic!(
+            "mem::poison: use-after-free detected: page frame {} modified at offset {:#x} after free",
+            frame, offset
+        );
+    }
+}
@@ -60,6 +60,20 @@ pub enum MemoryMapType {
     Unknown,
 }
 
+impl MemoryMapType {
+    // plain RAM, which can be read and written without side effects, unlike device memory
+    pub fn is_ram(self) -> bool {
+        matches!(
+            self,
+            MemoryMapType::Usable
+                | MemoryMapType::ACPIReclaimable
+                | MemoryMapType::ACPINVS
+                | MemoryMapType::BootloaderReclaimable
+                | MemoryMapType::KernelBinaries
+        )
+    }
+}
+
 pub struct MemoryMapEntry {
     pub start: PageFrameNumber,
     pub size: PageSize,
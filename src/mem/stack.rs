@@ -0,0 +1,144 @@
+// how deep the kernel stacks have gone, so that their sizes can be picked from data
+//
+// a stack that is populated up front is filled with a canary pattern before it is first used, and
+// its high-water mark is where the pattern stops, looking up from the bottom. a lazy stack can't be
+// filled without populating all of it, so its high-water mark is the lowest page that was touched.
+// the idle loop goes over the stacks every so often, and warns about any that comes close to its
+// end.
+
+extern crate alloc;
+
+use alloc::vec::Vec;
+use core::time::Duration;
+
+use log::warn;
+
+use super::{AddressRange, VFRange, Wrapper};
+use crate::{
+    arch::{PAGE_SMALL_SIZE, paging::PageTableSet},
+    log::RateLimiter,
+    mp::{CORE_ID, CoreId},
+    sync::IntMutex,
+};
+
+const CANARY: u64 = 0x57ac_c0de_57ac_c0de;
+
+const CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+// how full a stack has to get, in percent, before it is warned about
+const WARN_PERCENT: u64 = 75;
+
+struct Stack {
+    core: CoreId,
+    name: &'static str,
+    pages: VFRange,
+    lazy: bool,
+    // the deepest use warned about so far, so that the same mark isn't reported again
+    warned: u64,
+}
+
+#[derive(Clone, Copy)]
+pub struct StackUsage {
+    pub core: CoreId,
+    pub name: &'static str,
+    pub size: u64,
+    // the high-water mark, in bytes from the top; page granular for lazy stacks
+    pub used: u64,
+    pub lazy: bool,
+}
+
+static STACKS: IntMutex<Vec<Stack>> = IntMutex::new(Vec::new());
+
+static CHECK: RateLimiter = RateLimiter::new();
+
+impl Stack {
+    fn used(&self) -> u64 {
+        let range = self.pages.as_va_range();
+        let start = range.start().value();
+        let end = range.end().value();
+
+        let unused = if self.lazy {
+            let tables = PageTableSet::current();
+
+            self.pages
+                .as_rust_range()
+                .position(|page| tables.translate(page).is_some())
+                .map_or(end - start, |pages| pages as u64 * PAGE_SMALL_SIZE)
+        } else {
+            let words = (end - start) as usize / size_of::<u64>();
+            let bottom = start as *const u64;
+
+            // the stack may be in use on another core, hence the volatile reads
+            (0..words)
+                .position(|i| unsafe { bottom.add(i).read_volatile() } != CANARY)
+                .unwrap_or(words) as u64
+                * size_of::<u64>() as u64
+        };
+
+        end - start - unused
+    }
+
+    fn usage(&self) -> StackUsage {
+        StackUsage {
+            core: self.core,
+            name: self.name,
+            size: self.pages.as_va_range().size().value(),
+            used: self.used(),
+            lazy: self.lazy,
+        }
+    }
+}
+
+// records a freshly allocated stack of the current core, filling it with the canary unless it is
+// lazy
+//
+// # Safety
+// `pages` must be mapped (or lazily mappable) and not in use yet
+pub unsafe fn register(name: &'static str, pages: VFRange, lazy: bool) {
+    let range = pages.as_va_range();
+
+    if !lazy {
+        let words = range.size().value() as usize / size_of::<u64>();
+        let bottom = range.start().as_ptr_mut::<u64>();
+
+        for i in 0..words {
+            unsafe { bottom.add(i).write(CANARY) };
+        }
+    }
+
+    STACKS.lock().push(Stack {
+        core: CORE_ID.get(),
+        name,
+        pages,
+        lazy,
+        warned: 0,
+    });
+}
+
+// the high-water mark of every stack registered so far
+pub fn stack_usage() -> Vec<StackUsage> {
+    STACKS.lock().iter().map(Stack::usage).collect()
+}
+
+// warns about stacks past WARN_PERCENT, once for each new high-water mark; cheap enough to call
+// often, since it only looks at the stacks once every CHECK_INTERVAL
+pub fn check() {
+    if CHECK.check(CHECK_INTERVAL).is_none() {
+        return;
+    }
+
+    for stack in STACKS.lock().iter_mut() {
+        let usage = stack.usage();
+
+        if usage.used * 100 < usage.size * WARN_PERCENT || usage.used <= stack.warned {
+            continue;
+        }
+
+        stack.warned = usage.used;
+
+        warn!(
+            "mem::stack::check(): {} stack of core {} reached {} of {} bytes",
+            usage.name, usage.core, usage.used, usage.size
+        );
+    }
+}
@@ -385,6 +385,13 @@ impl PhysicalAddress {
         res
     }
 
+    // like to_virtual, for addresses that might not be in the hhdm, such as mmio
+    pub fn try_to_virtual(self) -> Option<VirtualAddress> {
+        let layout = VM_LAYOUT.get()?;
+        let res = layout.hhdm_base + (self - PhysicalAddress::new(0));
+        (res < layout.hhdm_end).then_some(res)
+    }
+
     pub fn frame_containing(self) -> PageFrameNumber {
         PageFrameNumber(self.0 / PAGE_SMALL_SIZE)
     }
@@ -0,0 +1,85 @@
+// virtually contiguous kernel buffers backed by whatever physical pages are available, for large
+// allocations that don't need physical contiguity
+
+extern crate alloc;
+
+use super::{
+    AddressRange, ByteSize, PMM, PageSize, VirtualAddress, kernel_page_table,
+    vpa::{TreeAllocator, VirtualAllocation, get_global_vpa},
+};
+use crate::arch::paging::PageFlags;
+use alloc::vec::Vec;
+
+pub struct VAllocation {
+    allocation: VirtualAllocation<'static, TreeAllocator>,
+    size: ByteSize,
+}
+
+unsafe impl Send for VAllocation {}
+unsafe impl Sync for VAllocation {}
+
+impl VAllocation {
+    pub fn addr(&self) -> VirtualAddress {
+        self.allocation.usable().start().address()
+    }
+
+    pub fn size(&self) -> ByteSize {
+        self.size
+    }
+
+    pub fn as_ptr<T>(&self) -> *const T {
+        self.addr().as_ptr()
+    }
+
+    pub fn as_ptr_mut<T>(&self) -> *mut T {
+        self.addr().as_ptr_mut()
+    }
+}
+
+impl Drop for VAllocation {
+    fn drop(&mut self) {
+        let tables = kernel_page_table();
+        let pmm = PMM::get();
+        let range = self.allocation.usable();
+
+        let frames: Vec<_> = range
+            .as_rust_range()
+            .filter_map(|page| tables.unmap_page_small(page))
+            .collect();
+
+        // other cores may still have the pages cached, so the frames can only be reused once
+        // they're gone from every TLB
+        tables.shootdown(range);
+
+        for frame in frames {
+            pmm.free_page(frame);
+        }
+
+        // the virtual range is released when `allocation` is dropped
+    }
+}
+
+// allocates a zeroed, virtually contiguous buffer, surrounded by unmapped guard pages
+// dropping the result shoots down its pages on every core, so it must not happen with interrupts
+// disabled
+pub fn valloc(bytes: ByteSize) -> Option<VAllocation> {
+    let pages = bytes.page_size_roundup();
+    let allocation = get_global_vpa().allocate_padded(pages, PageSize::new(1))?;
+    let range = allocation.usable();
+
+    // if we run out of frames part way, dropping this unmaps and frees the ones mapped so far
+    let valloc = VAllocation {
+        allocation,
+        size: bytes,
+    };
+
+    let tables = kernel_page_table();
+    let pmm = PMM::get();
+
+    for virt in range.as_rust_range() {
+        let frame = pmm.try_allocate_zeroed_page()?;
+        tables.map_page_small(&pmm, virt, frame, &PageFlags::KERNEL_RW);
+    }
+
+    Some(valloc)
+}
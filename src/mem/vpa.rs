@@ -2,7 +2,7 @@
 
 extern crate alloc;
 
-use super::{AddressRange, PageFrameAllocator, PageSize, VFRange, VirtualPageFrameNumber};
+use super::{AddressRange, PageFrameAllocator, PageSize, VFRange, VirtualPageFrameNumber, lazy};
 use crate::{arch::paging::{PageFlags, PageTableSet}, sync::IntMutex};
 use alloc::boxed::Box;
 use arrayvec::ArrayVec;
@@ -40,6 +40,11 @@ impl<'a, T: VirtualAllocatorHandler> VirtualAllocation<'a, T> {
     pub fn range(&self) -> VFRange {
         self.range
     }
+
+    // the range without padding
+    pub fn usable(&self) -> VFRange {
+        self.usable
+    }
 }
 
 impl<'a, T: VirtualAllocatorHandler> Drop for VirtualAllocation<'a, T> {
@@ -119,6 +124,7 @@ impl<T: VirtualAllocatorHandler> VirtualAllocator<T> {
         self.allocate_padded(size, PageSize::new(0))
     }
 
+    // if `lazy` is set, only the usable range is reserved and pages are populated on first touch
     pub fn allocate_backed_padded<P: PageFrameAllocator>(
         &self,
         pmm: &P,
@@ -126,11 +132,17 @@ impl<T: VirtualAllocatorHandler> VirtualAllocator<T> {
         size: PageSize,
         padding: PageSize,
         flags: PageFlags,
+        lazy: bool,
     ) -> Option<BackedVirtualAllocation<'_, T>> {
         let range = self.allocate_padded(size, padding)?;
-        for addr in range.range().as_rust_range() {
-            let phys = pmm.allocate_single_page();
-            tables.map_page_small(pmm, addr, phys, &flags);
+
+        if lazy {
+            lazy::register(tables, range.usable, flags);
+        } else {
+            for addr in range.range().as_rust_range() {
+                let phys = pmm.allocate_single_page();
+                tables.map_page_small(pmm, addr, phys, &flags);
+            }
         }
 
         Some(BackedVirtualAllocation {
@@ -144,12 +156,20 @@ impl<T: VirtualAllocatorHandler> VirtualAllocator<T> {
         tables: &PageTableSet,
         size: PageSize,
         flags: PageFlags,
+        lazy: bool,
     ) -> Option<BackedVirtualAllocation<'_, T>> {
-        self.allocate_backed_padded(pmm, tables, size, PageSize::new(0), flags)
+        self.allocate_backed_padded(pmm, tables, size, PageSize::new(0), flags, lazy)
     }
 
     pub fn free(&self, range: VFRange) -> Result<(), ()> {
-        self.inner.lock().free(range)
+        self.inner.lock().free(range)?;
+
+        // otherwise whatever gets the range next would have it populated behind its back
+        if range.start().is_higher_half() {
+            lazy::forget_kernel_range(range);
+        }
+
+        Ok(())
     }
 }
 
@@ -0,0 +1,65 @@
+// W^X enforcement: no mapping may ever be writable and executable at the same time
+
+use super::{AddressRange, VFRange, VirtualPageFrameNumber};
+use crate::arch::{SMALL_PAGE_PAGE_SIZE, cpu, paging::PageTableSet};
+use log::{info, warn};
+
+// walks every mapping in `tables` and panics if any of them is both writable and executable
+pub(super) fn audit_wx(tables: &PageTableSet) {
+    // without nx every mapping is executable, so W^X can't be enforced and every writable mapping
+    // would be reported
+    if !cpu::features().nx {
+        warn!("mem::audit_wx(): nx is not supported, skipping W^X audit");
+        return;
+    }
+
+    let mut violations = 0;
+
+    tables.for_each_mapping(|mapping| {
+        if mapping.write && mapping.execute {
+            warn!(
+                "mem::audit_wx(): {}-{} is writable and executable",
+                mapping.virt.address(),
+                (mapping.virt + mapping.size).address()
+            );
+            violations += 1;
+        }
+    });
+
+    assert!(
+        violations == 0,
+        "mem::audit_wx(): found {} W+X mappings",
+        violations
+    );
+
+    info!("mem::audit_wx(): no W+X mappings found");
+}
+
+// remaps every page in `range` read-only and executable in the current address space; this is
+// the only sanctioned way to create executable mappings after boot (e.g. for AOT-compiled wasm)
+// every page must already be mapped with 4k pages, otherwise this fails without changing any of
+// them; it shoots down the range on every core, so it must not be called with interrupts disabled
+pub fn make_executable(range: VFRange) -> Result<(), ()> {
+    let tables = PageTableSet::current();
+
+    let mapped = range.as_rust_range().all(|page: VirtualPageFrameNumber| {
+        tables
+            .lookup_mapping(page)
+            .is_some_and(|(mapping, _)| mapping.size == SMALL_PAGE_PAGE_SIZE)
+    });
+
+    if !mapped {
+        return Err(());
+    }
+
+    for page in range.as_rust_range() {
+        tables
+            .set_access_small(page, false, true)
+            .expect("mem::make_executable(): mapping changed while it was being remapped");
+    }
+
+    // other cores may still have the pages cached as writable
+    tables.shootdown(range);
+
+    Ok(())
+}
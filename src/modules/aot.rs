@@ -0,0 +1,39 @@
+// ahead-of-time compiled WASM programs
+//
+// these are native code, so unlike anything else that comes in as a module, they are only taken
+// with a valid signature.
+
+use log::warn;
+
+use super::{Module, ModuleCmdline, ModuleHandler, signature};
+
+pub(super) const HANDLER: ModuleHandler = ModuleHandler {
+    kind: "aot",
+    multiple: true,
+    load,
+    load_late: None,
+    resident: |_| true,
+};
+
+fn load(module: &Module) -> Result<(), ()> {
+    let ModuleCmdline::Aot { signature: sig, .. } = module.cmdline else {
+        return Err(());
+    };
+
+    if !signature::has_key() {
+        warn!(
+            "mod({}): this kernel has no module signing key",
+            module.path
+        );
+        return Err(());
+    }
+
+    if signature::verify(module.data, sig).is_none() {
+        warn!("mod({}): bad or missing signature", module.path);
+        return Err(());
+    }
+
+    // TODO: load it, once there is a runtime to hand it to, and start it if it asks for autostart
+    warn!("mod({}): AOT modules aren't supported yet", module.path);
+    Err(())
+}
@@ -0,0 +1,94 @@
+// a table of function names built into the kernel image, for when there is no symbol module
+//
+// with the `builtin-symbols` feature, the kernel sets aside BUILTIN_SYMBOLS_SIZE zeroed bytes in
+// .builtin_symbols, which `buildtool --builtin-symbols` fills in after linking, so nothing moves.
+// the table is:
+// - TABLE_MAGIC and the number of functions, as a u32
+// - for each function, sorted by address: its offset from KERNEL_BASE, its size and the offset of
+//   its name from the start of the table, all u32s
+// - the names, NUL terminated and already demangled
+// this only gets the function, without inlining or line numbers.
+
+use core::{
+    ffi::{CStr, c_void},
+    slice,
+};
+
+use super::symbols::KERNEL_BASE;
+
+const TABLE_MAGIC: &[u8; 4] = b"KSYM";
+const HEADER_SIZE: usize = 8;
+const ENTRY_SIZE: usize = 12;
+
+#[cfg(feature = "builtin-symbols")]
+const BUILTIN_SYMBOLS_SIZE: usize = 1 << 20;
+
+#[cfg(feature = "builtin-symbols")]
+#[used]
+#[unsafe(link_section = ".builtin_symbols")]
+static BUILTIN_SYMBOLS: [u8; BUILTIN_SYMBOLS_SIZE] = [0; BUILTIN_SYMBOLS_SIZE];
+
+// read through the markers, since the compiler only knows the table as zeroes
+unsafe extern "C" {
+    static _marker_builtin_symbols_start: c_void;
+    static _marker_builtin_symbols_end: c_void;
+}
+
+fn read_u32(buf: &[u8], offset: usize) -> Option<u32> {
+    let bytes = buf.get(offset..offset + 4)?;
+    Some(u32::from_le_bytes(bytes.try_into().ok()?))
+}
+
+// the table, if the buildtool filled one in
+fn table() -> Option<(&'static [u8], usize)> {
+    let start = &raw const _marker_builtin_symbols_start as *const u8;
+    let end = &raw const _marker_builtin_symbols_end as *const u8;
+
+    let data = unsafe { slice::from_raw_parts(start, end.offset_from_unsigned(start)) };
+
+    if !data.starts_with(TABLE_MAGIC) {
+        return None;
+    }
+
+    let count = read_u32(data, 4)? as usize;
+    (HEADER_SIZE + count * ENTRY_SIZE <= data.len()).then_some((data, count))
+}
+
+fn entry(data: &[u8], index: usize) -> Option<(u32, u32, u32)> {
+    let offset = HEADER_SIZE + index * ENTRY_SIZE;
+    Some((
+        read_u32(data, offset)?,
+        read_u32(data, offset + 4)?,
+        read_u32(data, offset + 8)?,
+    ))
+}
+
+// the name of the function containing `addr`
+pub fn find(addr: u64) -> Option<&'static str> {
+    let (data, count) = table()?;
+    let offset: u32 = addr.checked_sub(KERNEL_BASE)?.try_into().ok()?;
+
+    // the last function starting at or before `offset`
+    let mut lo = 0;
+    let mut hi = count;
+
+    while lo < hi {
+        let mid = lo + (hi - lo) / 2;
+        if entry(data, mid)?.0 <= offset {
+            lo = mid + 1;
+        } else {
+            hi = mid;
+        }
+    }
+
+    let (start, size, name) = entry(data, lo.checked_sub(1)?)?;
+
+    if offset - start >= size {
+        return None;
+    }
+
+    CStr::from_bytes_until_nul(data.get(name as usize..)?)
+        .ok()?
+        .to_str()
+        .ok()
+}
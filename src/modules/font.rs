@@ -0,0 +1,90 @@
+// PSF console fonts, version 1 and 2
+//
+// flanterm draws glyphs as single bytes per row, so only fonts 8 pixels wide can be used, and
+// only the first 256 glyphs, without the unicode table.
+
+use log::warn;
+
+use super::{Module, ModuleHandler};
+
+const PSF1_MAGIC: [u8; 2] = [0x36, 0x04];
+const PSF2_MAGIC: [u8; 4] = [0x72, 0xb5, 0x4a, 0x86];
+
+const GLYPHS: usize = 256;
+
+#[derive(Clone, Copy)]
+pub struct Font {
+    // GLYPHS glyphs, `height` bytes each
+    pub glyphs: &'static [u8],
+    pub width: usize,
+    pub height: usize,
+}
+
+fn read_u32(buf: &[u8], offset: usize) -> Option<usize> {
+    let bytes = buf.get(offset..offset + 4)?;
+    Some(u32::from_le_bytes(bytes.try_into().ok()?) as usize)
+}
+
+fn parse_psf1(data: &'static [u8]) -> Option<Font> {
+    // magic, mode, bytes per glyph; there are always at least 256 glyphs
+    let height = *data.get(3)? as usize;
+
+    Some(Font {
+        glyphs: data.get(4..4 + GLYPHS * height)?,
+        width: 8,
+        height,
+    })
+}
+
+fn parse_psf2(data: &'static [u8]) -> Option<Font> {
+    let header_size = read_u32(data, 8)?;
+    let count = read_u32(data, 16)?;
+    let glyph_size = read_u32(data, 20)?;
+    let height = read_u32(data, 24)?;
+    let width = read_u32(data, 28)?;
+
+    if width != 8 || glyph_size != height || count < GLYPHS {
+        return None;
+    }
+
+    Some(Font {
+        glyphs: data.get(header_size..header_size + GLYPHS * glyph_size)?,
+        width,
+        height,
+    })
+}
+
+pub fn parse(data: &'static [u8]) -> Option<Font> {
+    let font = if data.starts_with(&PSF2_MAGIC) {
+        parse_psf2(data)?
+    } else if data.starts_with(&PSF1_MAGIC) {
+        parse_psf1(data)?
+    } else {
+        return None;
+    };
+
+    (font.height != 0).then_some(font)
+}
+
+// the font is already handed to the framebuffer console by now (see find_font_module), so this
+// only checks it
+pub(super) const HANDLER: ModuleHandler = ModuleHandler {
+    kind: "font",
+    multiple: false,
+    load,
+    load_late: None,
+    // flanterm keeps its own copy of the glyphs
+    resident: |_| false,
+};
+
+fn load(module: &Module) -> Result<(), ()> {
+    if parse(module.data).is_none() {
+        warn!(
+            "mod({}): not a PSF font with 256 glyphs, 8 pixels wide",
+            module.path
+        );
+        return Err(());
+    }
+
+    Ok(())
+}
@@ -0,0 +1,205 @@
+// the initrd: a USTAR archive, read in place
+//
+// each file is a 512 byte header followed by its contents, padded to a multiple of 512 bytes; the
+// archive ends with zeroed blocks. paths are looked up relative to the root of the archive, so
+// `/boot/config`, `boot/config` and `./boot/config` are all the same file. once there is a heap,
+// the files are indexed, so that lookups don't have to walk the whole archive.
+
+extern crate alloc;
+
+use alloc::vec::Vec;
+use arrayvec::ArrayString;
+use log::warn;
+use spin::Once;
+
+use super::{Module, ModuleCmdline, ModuleHandler, find_module};
+
+const BLOCK_SIZE: usize = 512;
+
+const NAME: (usize, usize) = (0, 100);
+const SIZE: (usize, usize) = (124, 12);
+const CHECKSUM: (usize, usize) = (148, 8);
+const TYPE: usize = 156;
+const MAGIC: (usize, usize) = (257, 6);
+const PREFIX: (usize, usize) = (345, 155);
+
+// the longest a prefix, a slash and a name can be
+pub type Path = ArrayString<256>;
+
+fn field(header: &[u8], (start, len): (usize, usize)) -> &[u8] {
+    let field = &header[start..start + len];
+    let end = field.iter().position(|&ch| ch == 0).unwrap_or(len);
+    &field[..end]
+}
+
+fn octal(field: &[u8]) -> Option<usize> {
+    let digits = str::from_utf8(field).ok()?.trim_matches(' ');
+    usize::from_str_radix(digits, 8).ok()
+}
+
+// the checksum is the sum of the header bytes, with the checksum field taken as spaces
+fn checksum_ok(header: &[u8]) -> bool {
+    let (start, len) = CHECKSUM;
+    let sum: usize = header
+        .iter()
+        .enumerate()
+        .map(|(i, &ch)| {
+            if (start..start + len).contains(&i) {
+                b' ' as usize
+            } else {
+                ch as usize
+            }
+        })
+        .sum();
+
+    octal(field(header, CHECKSUM)) == Some(sum)
+}
+
+fn normalize(path: &str) -> &str {
+    let mut path = path;
+
+    loop {
+        path = match path.strip_prefix('/').or_else(|| path.strip_prefix("./")) {
+            Some(rest) => rest,
+            None => return path,
+        };
+    }
+}
+
+#[derive(Clone, Copy)]
+pub struct Entry {
+    header: &'static [u8],
+    pub data: &'static [u8],
+}
+
+impl Entry {
+    // bytes that aren't ascii come out as latin-1, and whatever doesn't fit is cut off
+    pub fn path(&self) -> Path {
+        let mut path = Path::new();
+        let prefix = field(self.header, PREFIX);
+
+        let slash: &[u8] = if prefix.is_empty() { b"" } else { b"/" };
+
+        for &ch in prefix.iter().chain(slash).chain(field(self.header, NAME)) {
+            let _ = path.try_push(ch as char);
+        }
+
+        path
+    }
+
+    // regular files; older archivers mark them with a NUL
+    pub fn is_file(&self) -> bool {
+        matches!(self.header[TYPE], b'0' | 0)
+    }
+}
+
+#[derive(Clone, Copy)]
+pub struct Archive(&'static [u8]);
+
+impl Archive {
+    // only checks the first header; a damaged entry further in ends the archive early
+    pub fn parse(data: &'static [u8]) -> Option<Archive> {
+        let archive = Archive(data);
+        archive.entries().next()?;
+        Some(archive)
+    }
+
+    pub fn entries(&self) -> impl Iterator<Item = Entry> {
+        let data = self.0;
+        let mut offset = 0;
+
+        core::iter::from_fn(move || {
+            let header = data.get(offset..offset + BLOCK_SIZE)?;
+
+            if header.iter().all(|&ch| ch == 0)
+                || !field(header, MAGIC).starts_with(b"ustar")
+                || !checksum_ok(header)
+            {
+                return None;
+            }
+
+            let size = octal(field(header, SIZE))?;
+            let contents = data.get(offset + BLOCK_SIZE..offset + BLOCK_SIZE + size)?;
+
+            offset += BLOCK_SIZE + size.div_ceil(BLOCK_SIZE) * BLOCK_SIZE;
+
+            Some(Entry {
+                header,
+                data: contents,
+            })
+        })
+    }
+
+    pub fn find(&self, path: &str) -> Option<&'static [u8]> {
+        let path = normalize(path);
+
+        self.entries()
+            .find(|entry| entry.is_file() && normalize(&entry.path()) == path)
+            .map(|entry| entry.data)
+    }
+}
+
+// the archive is read in place whenever something looks for a file, so this only checks it
+pub(super) const HANDLER: ModuleHandler = ModuleHandler {
+    kind: "initrd",
+    multiple: false,
+    load,
+    load_late: Some(build_index),
+    resident: |_| true,
+};
+
+fn load(module: &Module) -> Result<(), ()> {
+    if Archive::parse(module.data).is_none() {
+        warn!("mod({}): not a USTAR archive", module.path);
+        return Err(());
+    }
+
+    Ok(())
+}
+
+static INITRD: Once<Option<Archive>> = Once::new();
+
+// every file, by normalized path, sorted
+static INDEX: Once<Vec<(Path, &'static [u8])>> = Once::new();
+
+fn build_index(module: &Module) -> Result<(), ()> {
+    let archive = Archive::parse(module.data).ok_or(())?;
+
+    let mut index: Vec<_> = archive
+        .entries()
+        .filter(|entry| entry.is_file())
+        .filter_map(|entry| Some((Path::from(normalize(&entry.path())).ok()?, entry.data)))
+        .collect();
+
+    // the sort is stable, so like with Archive::find, the first of several files with the same
+    // path wins
+    index.sort_by(|a, b| a.0.cmp(&b.0));
+    index.dedup_by(|a, b| a.0 == b.0);
+
+    INDEX.call_once(|| index);
+    Ok(())
+}
+
+// the first initrd module that is a valid archive; this works before logging is up
+pub fn archive() -> Option<Archive> {
+    *INITRD.call_once(|| {
+        find_module(|cmdline, data| match cmdline {
+            ModuleCmdline::Initrd => Archive::parse(data),
+            _ => None,
+        })
+    })
+}
+
+// the contents of a file in the initrd
+pub fn find(path: &str) -> Option<&'static [u8]> {
+    let Some(index) = INDEX.get() else {
+        return archive()?.find(path);
+    };
+
+    let path = normalize(path);
+
+    index
+        .binary_search_by(|(name, _)| name.as_str().cmp(path))
+        .ok()
+        .map(|i| index[i].1)
+}
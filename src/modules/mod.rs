@@ -1,69 +1,377 @@
-use core::ptr::slice_from_raw_parts;
+use core::{
+    ptr::{self, slice_from_raw_parts},
+    sync::atomic::{AtomicBool, Ordering},
+};
 
-use crate::cmdline::{CmdlineLexer, CmdlineParsable};
-use limine::request::ModuleRequest;
-use log::warn;
+use crate::{
+    arch::PAGE_SMALL_SIZE,
+    cmdline::{CmdlineLexer, CmdlineParsable},
+    mem::{PMM, PageFrameNumber, PageSize, VirtualAddress, Wrapper},
+    sync::IntMutex,
+};
+use arrayvec::{ArrayString, ArrayVec};
+use limine::{file::File, request::ModuleRequest};
+use log::{info, warn};
 use proc_macros::CmdlineParsable;
+use signature::ModuleSignature;
+mod aot;
+mod builtin_symbols;
+pub mod font;
+pub mod initrd;
+pub mod signature;
 pub mod symbols;
 
-// the main command line types
-#[derive(CmdlineParsable)]
-enum ModuleCmdline {
+// what a module can be called by, instead of its file name
+pub type ModuleName = ArrayString<32>;
+
+// the module types, which a module string starts with, and their options, e.g.
+// `symbols{required}`; without the braces, the options keep their defaults
+#[derive(CmdlineParsable, Clone, Copy)]
+pub enum ModuleCmdline {
     InternalNull,
-    Symbols,
+    Symbols {
+        // panic if the symbols don't load, instead of going on without backtraces
+        #[default_value(false)]
+        required: bool,
+    },
+    // more kernel options, in the cmdline grammar; see cmdline::parse_kernel_cmdline
+    Config,
+    // a PSF console font, for the framebuffer
+    Font,
+    // a USTAR archive of more files; see initrd.rs
+    Initrd,
+    // ahead-of-time compiled WASM programs, which are native code, so they have to be signed; see
+    // signature.rs
+    Aot {
+        signature: ModuleSignature,
+        #[default_value(None)]
+        name: Option<ModuleName>,
+        // start the program once it is loaded
+        #[default_value(false)]
+        autostart: bool,
+    },
 }
 
+// where things that could also be modules of their own are looked for in the initrd
+const INITRD_CONFIG: &str = "boot/config";
+const INITRD_FONT: &str = "boot/font.psf";
+
 #[used]
 #[unsafe(link_section = ".limine_requests")]
 static MODULE_REQUEST: ModuleRequest = ModuleRequest::new();
 
+fn module_data(module: &File) -> &'static [u8] {
+    unsafe { &*slice_from_raw_parts(module.addr(), module.size() as usize) }
+}
+
+#[derive(Clone, Copy)]
+pub struct Module {
+    pub path: &'static str,
+    // the type, as written in the module string
+    pub kind: &'static str,
+    // the whole module string; handlers that were registered parse their options out of it into a
+    // CmdlineParsable of their own, since `cmdline` only knows the builtin types
+    pub string: &'static str,
+    // InternalNull for the types of registered handlers
+    pub cmdline: ModuleCmdline,
+    // empty once the module is released
+    pub data: &'static [u8],
+    pub released: bool,
+}
+
+impl Module {
+    // the name given in the module string or, failing that, the file name without the directories
+    pub fn name(&self) -> &str {
+        match &self.cmdline {
+            ModuleCmdline::Aot {
+                name: Some(name), ..
+            } => name,
+            _ => self.path.rsplit('/').next().unwrap_or(self.path),
+        }
+    }
+}
+
+pub struct ModuleHandler {
+    pub kind: &'static str,
+    // if not, modules of this type after the first one that loads are ignored
+    pub multiple: bool,
+    // takes in a module, or warns about what is wrong with it; this runs before there is a heap
+    pub load: fn(&Module) -> Result<(), ()>,
+    // the rest of the work on a module that loaded, once the allocator is up; if it fails, the
+    // module is dropped
+    pub load_late: Option<fn(&Module) -> Result<(), ()>>,
+    // whether the memory of a module with this data is still used after loading; if not, it is
+    // given back once modules are loaded (see release_modules)
+    pub resident: fn(&[u8]) -> bool,
+}
+
+pub const MAX_HANDLERS: usize = 16;
+pub const MAX_MODULES: usize = 32;
+
+const BUILTIN: &[ModuleHandler] = &[
+    symbols::HANDLER,
+    CONFIG_HANDLER,
+    font::HANDLER,
+    initrd::HANDLER,
+    aot::HANDLER,
+];
+
+static HANDLERS: IntMutex<ArrayVec<&'static ModuleHandler, MAX_HANDLERS>> =
+    IntMutex::new(ArrayVec::new_const());
+
+static LOADED: IntMutex<ArrayVec<Module, MAX_MODULES>> = IntMutex::new(ArrayVec::new_const());
+
+// adds a handler for another module type, which has to happen before modules are loaded; fails if
+// the type is taken, or if there are already MAX_HANDLERS of them. the handler gets the module
+// string as is, see Module::string
+pub fn register_handler(handler: &'static ModuleHandler) -> Result<(), ()> {
+    let mut handlers = HANDLERS.lock();
+
+    if find_handler(handler.kind).is_some() || handlers.iter().any(|h| h.kind == handler.kind) {
+        return Err(());
+    }
+
+    handlers.try_push(handler).map_err(|_| ())
+}
+
+fn find_handler(kind: &str) -> Option<&'static ModuleHandler> {
+    BUILTIN.iter().find(|handler| handler.kind == kind)
+}
+
+fn handler_for(kind: &str) -> Option<&'static ModuleHandler> {
+    find_handler(kind).or_else(|| HANDLERS.lock().iter().find(|h| h.kind == kind).copied())
+}
+
+// the modules that were loaded, in the order they were passed
+pub fn loaded() -> ArrayVec<Module, MAX_MODULES> {
+    LOADED.lock().clone()
+}
+
+// a loaded module of some type and, if given, with some file name
+pub fn find_loaded(kind: &str, name: Option<&str>) -> Option<Module> {
+    LOADED
+        .lock()
+        .iter()
+        .find(|module| module.kind == kind && name.is_none_or(|name| module.name() == name))
+        .copied()
+}
+
+// config modules are applied along with the kernel cmdline, so this only checks them
+const CONFIG_HANDLER: ModuleHandler = ModuleHandler {
+    kind: "config",
+    multiple: false,
+    load: load_config,
+    load_late: None,
+    // the parsed cmdline borrows its strings from the config
+    resident: |_| true,
+};
+
+fn load_config(module: &Module) -> Result<(), ()> {
+    if str::from_utf8(module.data).is_err() {
+        warn!("mod({}): config is not valid utf8", module.path);
+        return Err(());
+    }
+
+    Ok(())
+}
+
+// the first module of some type that `parse` accepts, for the things that are needed before
+// logging is up; problems are reported by load_modules_early instead. once modules are released,
+// only those that are still resident are looked at
+fn find_module<T>(parse: impl Fn(ModuleCmdline, &'static [u8]) -> Option<T>) -> Option<T> {
+    if RELEASED.load(Ordering::Acquire) {
+        return LOADED
+            .lock()
+            .iter()
+            .filter(|module| !module.released)
+            .find_map(|module| parse(module.cmdline, module.data));
+    }
+
+    let res = MODULE_REQUEST.get_response()?;
+
+    res.modules().iter().find_map(|module| {
+        let mut cmdline = ModuleCmdline::InternalNull;
+        CmdlineLexer::parse(module.string().to_str().ok()?, &mut cmdline).ok()?;
+        parse(cmdline, module_data(module))
+    })
+}
+
+// the contents of the first config module or, failing that, the config in the initrd
+pub fn find_config_module() -> Option<&'static str> {
+    find_module(|cmdline, data| match cmdline {
+        ModuleCmdline::Config => str::from_utf8(data).ok(),
+        _ => None,
+    })
+    .or_else(|| str::from_utf8(initrd::find(INITRD_CONFIG)?).ok())
+}
+
+// the first font module that can be used or, failing that, the font in the initrd
+pub fn find_font_module() -> Option<font::Font> {
+    find_module(|cmdline, data| match cmdline {
+        ModuleCmdline::Font => font::parse(data),
+        _ => None,
+    })
+    .or_else(|| font::parse(initrd::find(INITRD_FONT)?))
+}
+
+// the type a module string starts with
+fn module_kind(cmdline: &'static str) -> Option<&'static str> {
+    CmdlineLexer::new(cmdline)
+        .ok()?
+        .next()
+        .ok()?
+        .unwrap_ident()
+        .ok()
+}
+
+fn load_module(module: &'static File) {
+    let path = match module.path().to_str() {
+        Ok(x) => x,
+        Err(e) => {
+            warn!("failed to decode module path to utf8: {e}");
+            "<unk>"
+        }
+    };
+
+    let cmdline_str = match module.string().to_str() {
+        Ok(x) => x,
+        Err(e) => {
+            warn!("mod({path}): failed to decode module cmdline to utf8: {e}");
+            return;
+        }
+    };
+
+    let Some(kind) = module_kind(cmdline_str) else {
+        warn!("mod({path}): module cmdline `{cmdline_str}` doesn't start with a type");
+        return;
+    };
+
+    let Some(handler) = handler_for(kind) else {
+        warn!("mod({path}): nothing handles `{kind}` modules");
+        return;
+    };
+
+    let mut cmdline = ModuleCmdline::InternalNull;
+
+    if find_handler(kind).is_some()
+        && let Err(e) = CmdlineLexer::parse(cmdline_str, &mut cmdline)
+    {
+        warn!("mod({path}): failed to parse module cmdline `{cmdline_str}`: {e}");
+        return;
+    }
+
+    if !handler.multiple && find_loaded(kind, None).is_some() {
+        warn!("mod({path}): only the first {kind} module is used");
+        return;
+    }
+
+    let module = Module {
+        path,
+        kind,
+        string: cmdline_str,
+        cmdline,
+        data: module_data(module),
+        released: false,
+    };
+
+    if (handler.load)(&module).is_ok() && LOADED.lock().try_push(module).is_err() {
+        warn!("mod({path}): loaded, but too many modules to keep track of");
+    }
+}
+
+// the first stage, before the memory manager is up
 pub fn load_modules_early() {
     if let Some(res) = MODULE_REQUEST.get_response() {
         for module in res.modules() {
-            let path = match module.path().to_str() {
-                Ok(x) => x,
-                Err(e) => {
-                    warn!("failed to decode module path to utf8: {e}");
-                    "<unk>"
-                }
-            };
-
-            let cmdline_str = match module.string().to_str() {
-                Ok(x) => x,
-                Err(e) => {
-                    warn!("mod({path}): failed to decode module cmdline to utf8: {e}");
-                    continue;
-                }
-            };
-
-            let mut cmdline = ModuleCmdline::InternalNull;
-
-            match CmdlineLexer::parse(cmdline_str, &mut cmdline) {
-                Ok(_) => {}
-                Err(e) => {
-                    warn!("mod({path}): failed to parse module cmdline `{cmdline_str}`: {e}");
-                    continue;
-                }
-            };
-
-            match cmdline {
-                ModuleCmdline::InternalNull => {
-                    warn!("mod({path}): do not use `internalnull` module type");
-                    continue;
-                }
-                ModuleCmdline::Symbols => {
-                    let Some(syms) = symbols::parse(unsafe {
-                        &*slice_from_raw_parts(module.addr(), module.size() as usize)
-                    }) else {
-                        warn!("mod({path}): failed to parse symbols");
-                        continue;
-                    };
-
-                    if !symbols::try_init(syms) {
-                        warn!("mod({path}): cannot load multiple global symbol modules");
-                    }
-                }
-            }
+            load_module(module);
         }
     }
 }
+
+// the second stage, for modules that need the allocator
+pub fn load_modules_late() {
+    // a copy, so that handlers can look at the other modules
+    for module in loaded() {
+        let Some(load_late) = handler_for(module.kind).and_then(|handler| handler.load_late) else {
+            continue;
+        };
+
+        if load_late(&module).is_err() {
+            LOADED
+                .lock()
+                .retain(|loaded| !ptr::eq(loaded.data, module.data));
+        }
+    }
+}
+
+static RELEASED: AtomicBool = AtomicBool::new(false);
+
+// hands the pages that only hold `data` to the PMM, returning how many there were; limine gives
+// every module its own pages, but a partial page at either end is kept to be safe
+//
+// modules are in their own memory map entries, so this doesn't touch bootloader reclaimable
+// memory, which cores that haven't been started are still running out of (see mp::start_core)
+fn release_range(data: &'static [u8]) -> PageSize {
+    let start = VirtualAddress::from(data.as_ptr())
+        .hhdm_to_physical()
+        .value();
+    let end = start + data.len() as u64;
+
+    let first = PageFrameNumber::new(start.div_ceil(PAGE_SMALL_SIZE));
+    let last = PageFrameNumber::new(end / PAGE_SMALL_SIZE);
+
+    let pmm = PMM::get();
+    let mut count = PageSize::new(0);
+
+    for frame in first..last {
+        pmm.free_page(frame);
+        count += PageSize::new(1);
+    }
+
+    count
+}
+
+// the reclaim phase, after load_modules_late: gives back the memory of every module that didn't
+// load, and of those whose handler is done with them. nothing may look at module data it got
+// before this, other than that of resident modules
+pub fn release_modules() {
+    let Some(res) = MODULE_REQUEST.get_response() else {
+        return;
+    };
+
+    RELEASED.store(true, Ordering::Release);
+
+    let mut loaded = LOADED.lock();
+    let mut total = PageSize::new(0);
+
+    // a module that loaded after LOADED filled up can't be told apart from one that didn't load
+    let untracked = loaded.is_full();
+
+    for module in res.modules() {
+        let data = module_data(module);
+
+        let entry = loaded.iter_mut().find(|loaded| ptr::eq(loaded.data, data));
+
+        let keep = match &entry {
+            Some(entry) => handler_for(entry.kind).is_none_or(|handler| (handler.resident)(data)),
+            None => untracked,
+        };
+
+        if keep {
+            continue;
+        }
+
+        if let Some(entry) = entry {
+            entry.data = &[];
+            entry.released = true;
+        }
+
+        total += release_range(data);
+    }
+
+    drop(loaded);
+
+    if total.value() > 0 {
+        info!("modules::release_modules(): released {} pages", total);
+    }
+}
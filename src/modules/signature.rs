@@ -0,0 +1,63 @@
+// ed25519 signatures on modules
+//
+// the public key is built into the kernel: build.rs takes it from the file MODULE_SIGNING_KEY
+// points at (the 32 raw bytes of the key), and without one nothing verifies. a signature is either
+// given in the module string, e.g. `aot{signature:ab:cd:...}`, or appended to the module, as the 64
+// signature bytes followed by SIGNATURE_MAGIC, which is what `buildtool sign` does.
+
+use core::fmt;
+use ed25519_compact::{PublicKey, Signature};
+
+use crate::cmdline::{CmdlineLexer, CmdlineParsable, CmdlineParseError, SchemaWriter};
+
+const PUBLIC_KEY: &[u8] = include_bytes!(concat!(env!("OUT_DIR"), "/module_signing_key"));
+
+pub const SIGNATURE_MAGIC: &[u8] = b"~module signature~\n";
+
+// a signature from the module string, if there was one
+#[derive(Clone, Copy, Default)]
+pub struct ModuleSignature(Option<[u8; Signature::BYTES]>);
+
+impl CmdlineParsable for ModuleSignature {
+    fn parse<'a>(&mut self, lexer: &mut CmdlineLexer<'a>) -> Result<(), CmdlineParseError<'a>> {
+        let mut bytes = [0; Signature::BYTES];
+        bytes.parse(lexer)?;
+        self.0 = Some(bytes);
+
+        Ok(())
+    }
+
+    fn schema(&self, writer: &mut SchemaWriter) -> fmt::Result {
+        match self.0 {
+            Some(bytes) => bytes.schema(writer),
+            None => writer.value("bytes", "appended"),
+        }
+    }
+}
+
+pub fn has_key() -> bool {
+    PublicKey::from_slice(PUBLIC_KEY).is_ok()
+}
+
+// splits off an appended signature
+fn appended(data: &'static [u8]) -> Option<(&'static [u8], &'static [u8])> {
+    let data = data.strip_suffix(SIGNATURE_MAGIC)?;
+    let split = data.len().checked_sub(Signature::BYTES)?;
+    Some(data.split_at(split))
+}
+
+// the module contents, without an appended signature, if they are signed with the built-in key
+pub fn verify(data: &'static [u8], signature: ModuleSignature) -> Option<&'static [u8]> {
+    let key = PublicKey::from_slice(PUBLIC_KEY).ok()?;
+
+    let (contents, signature) = match signature.0 {
+        Some(bytes) => (data, Signature::new(bytes)),
+        None => {
+            let (contents, signature) = appended(data)?;
+            (contents, Signature::from_slice(signature).ok()?)
+        }
+    };
+
+    key.verify(contents, &signature).ok()?;
+    Some(contents)
+}
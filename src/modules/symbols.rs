@@ -1,17 +1,30 @@
-use core::{ffi::CStr, iter};
+extern crate alloc;
 
+use alloc::{boxed::Box, vec::Vec};
+use core::{ffi::CStr, hint, iter};
+
+use arrayvec::ArrayVec;
+use log::{info, warn};
+use ruzstd::{decoding::StreamingDecoder, io::Read};
 use spin::Once;
 use static_assertions::const_assert;
 
+use super::{Module, ModuleCmdline, ModuleHandler, builtin_symbols};
+use crate::sync::RwIntLock;
+
 pub struct SymbolModule<'a> {
     strings: &'a [u8],
     functions: &'a [u8],
     location_search: &'a [u8],
     function_search: &'a [u8],
+    unwind_search: &'a [u8],
+    data_symbols: &'a [u8],
 
     functions_count: usize,
     location_search_count: usize,
     function_search_count: usize,
+    unwind_search_count: usize,
+    data_symbols_count: usize,
 }
 
 const_assert!(size_of::<usize>() == size_of::<u64>());
@@ -26,15 +39,16 @@ macro generate_reader($name:ident, $ty:ty) {
 }
 
 generate_reader!(read_usize, usize);
+generate_reader!(read_u16, u16);
 generate_reader!(read_u32, u32);
-generate_reader!(read_u64, u64);
+generate_reader!(read_i32, i32);
 
 fn read_string<'a>(buf: &'a [u8], str_tab: &'a [u8], offset: usize) -> Option<&'a str> {
     let file = read_usize(buf, offset)?;
 
     if file != usize::MAX {
         Some(
-            CStr::from_bytes_until_nul(&str_tab[file..])
+            CStr::from_bytes_until_nul(str_tab.get(file..)?)
                 .ok()?
                 .to_str()
                 .ok()?,
@@ -78,6 +92,47 @@ impl<'a> TableEntry<'a> for Option<usize> {
     }
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CfaRegister {
+    Rsp,
+    Rbp,
+}
+
+/// how to find the caller's frame from a given address
+/// the canonical frame address is `cfa_register + cfa_offset`, the return address is stored right
+/// below it, and rbp is saved at `cfa + rbp_offset` if it was saved at all.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct UnwindRule {
+    pub cfa_register: CfaRegister,
+    pub cfa_offset: i32,
+    pub rbp_offset: Option<i32>,
+}
+
+impl<'a> TableEntry<'a> for Option<UnwindRule> {
+    const SIZE: usize = 4 + 4 + 4;
+
+    fn read(buf: &'a [u8], _str_tab: &'a [u8]) -> Option<Self> {
+        let cfa_register = match read_u32(buf, 0)? {
+            0 => return Some(None),
+            1 => CfaRegister::Rsp,
+            2 => CfaRegister::Rbp,
+            _ => return None,
+        };
+
+        let rbp_offset = read_i32(buf, 8)?;
+
+        Some(Some(UnwindRule {
+            cfa_register,
+            cfa_offset: read_i32(buf, 4)?,
+            rbp_offset: if rbp_offset == 0 {
+                None
+            } else {
+                Some(rbp_offset)
+            },
+        }))
+    }
+}
+
 pub struct FunctionEntry<'a> {
     inline_parent: Option<usize>,
     pub name: Option<&'a str>,
@@ -101,44 +156,108 @@ impl<'a> TableEntry<'a> for FunctionEntry<'a> {
     }
 }
 
-pub fn parse<'a>(src: &'a [u8]) -> Option<SymbolModule<'a>> {
-    let mut head = 0;
-
-    let header = read_u64(src, head)?;
-    assert!(header == 0);
-    head += 8;
-
-    let string_table_len = read_usize(src, head)?;
-    head += 8;
-    let string_table = &src[head..head + string_table_len];
-    head += string_table_len;
+// the module starts with MODULE_MAGIC, the version as two u16s and the number of sections as a
+// u32, followed by the section table: an id and a padding u32, then the offset and size of the
+// section as u64s. sections can come in any order, and ids this kernel doesn't know are skipped.
+//
+// the buildtool compresses the whole module with zstd, which can't be undone before there is a
+// heap, so such a module is only loaded in the late stage; until then, backtraces fall back to the
+// builtin symbols. an uncompressed module is still loaded early.
+const MODULE_MAGIC: &[u8; 8] = b"KSYMBOLS";
+const ZSTD_MAGIC: &[u8; 4] = &[0x28, 0xb5, 0x2f, 0xfd];
+const HEADER_SIZE: usize = 16;
+const SECTION_ENTRY_SIZE: usize = 24;
+
+// modules of another major version can't be read at all; a newer minor version only adds
+// sections, which are skipped, and an older one may lack some, which are taken as empty
+const VERSION_MAJOR: u16 = 1;
+const VERSION_MINOR: u16 = 1;
+
+const SECTION_STRINGS: u32 = 1;
+const SECTION_FUNCTIONS: u32 = 2;
+const SECTION_LOCATION_SEARCH: u32 = 3;
+const SECTION_FUNCTION_SEARCH: u32 = 4;
+const SECTION_UNWIND_SEARCH: u32 = 5;
+// since 1.1: the statics, sorted by address, as their offset and size as u32s and then their name
+const SECTION_DATA_SYMBOLS: u32 = 6;
+const DATA_SYMBOL_SIZE: usize = 4 + 4 + 8;
+
+pub enum SymbolFormatError {
+    // not a symbol module, or from before the format had a header
+    BadMagic,
+    Version(u16, u16),
+    Malformed,
+}
 
-    let function_table_len = read_usize(src, head)?;
-    head += 8;
-    let function_table = &src[head..head + function_table_len];
-    head += function_table_len;
+pub fn parse<'a>(src: &'a [u8]) -> Result<SymbolModule<'a>, SymbolFormatError> {
+    if !src.starts_with(MODULE_MAGIC) {
+        return Err(SymbolFormatError::BadMagic);
+    }
 
-    let location_search_table_len = read_usize(src, head)?;
-    head += 8;
-    let location_search_table = &src[head..head + location_search_table_len];
-    head += location_search_table_len;
+    let (Some(major), Some(minor), Some(section_count)) =
+        (read_u16(src, 8), read_u16(src, 10), read_u32(src, 12))
+    else {
+        return Err(SymbolFormatError::Malformed);
+    };
 
-    let function_search_table_len = read_usize(src, head)?;
-    head += 8;
-    let function_search_table = &src[head..head + function_search_table_len];
-    head += function_search_table_len;
+    if major != VERSION_MAJOR {
+        return Err(SymbolFormatError::Version(major, minor));
+    }
 
-    assert!(head == src.len());
+    if minor > VERSION_MINOR {
+        info!(
+            "modules::symbols::parse(): symbols are version {}.{}, newer parts are skipped",
+            major, minor
+        );
+    }
 
-    Some(SymbolModule {
-        strings: string_table,
-        functions: function_table,
-        location_search: location_search_table,
-        function_search: function_search_table,
+    let mut strings: &[u8] = &[];
+    let mut functions: &[u8] = &[];
+    let mut location_search: &[u8] = &[];
+    let mut function_search: &[u8] = &[];
+    let mut unwind_search: &[u8] = &[];
+    let mut data_symbols: &[u8] = &[];
+
+    for i in 0..section_count as usize {
+        let entry = HEADER_SIZE + i * SECTION_ENTRY_SIZE;
+
+        let (Some(id), Some(offset), Some(size)) = (
+            read_u32(src, entry),
+            read_usize(src, entry + 8),
+            read_usize(src, entry + 16),
+        ) else {
+            return Err(SymbolFormatError::Malformed);
+        };
+
+        let data = offset
+            .checked_add(size)
+            .and_then(|end| src.get(offset..end))
+            .ok_or(SymbolFormatError::Malformed)?;
+
+        match id {
+            SECTION_STRINGS => strings = data,
+            SECTION_FUNCTIONS => functions = data,
+            SECTION_LOCATION_SEARCH => location_search = data,
+            SECTION_FUNCTION_SEARCH => function_search = data,
+            SECTION_UNWIND_SEARCH => unwind_search = data,
+            SECTION_DATA_SYMBOLS => data_symbols = data,
+            _ => {}
+        }
+    }
 
-        functions_count: function_table_len / FunctionEntry::SIZE,
-        location_search_count: location_search_table_len / (LocationEntry::SIZE + 4),
-        function_search_count: function_search_table_len / (Option::<usize>::SIZE + 4),
+    Ok(SymbolModule {
+        strings,
+        functions,
+        location_search,
+        function_search,
+        unwind_search,
+        data_symbols,
+
+        functions_count: functions.len() / FunctionEntry::SIZE,
+        location_search_count: location_search.len() / (LocationEntry::SIZE + 4),
+        function_search_count: function_search.len() / (Option::<usize>::SIZE + 4),
+        unwind_search_count: unwind_search.len() / (Option::<UnwindRule>::SIZE + 4),
+        data_symbols_count: data_symbols.len() / DATA_SYMBOL_SIZE,
     })
 }
 
@@ -181,6 +300,15 @@ impl<'a> SymbolModule<'a> {
         )
     }
 
+    fn get_unwind_search(&self, index: usize) -> Option<(u32, Option<UnwindRule>)> {
+        Self::do_read(
+            index,
+            self.unwind_search_count,
+            self.unwind_search,
+            self.strings,
+        )
+    }
+
     fn binary_search_table<T>(
         count: usize,
         get_entry: impl Fn(usize) -> Option<(u32, T)>,
@@ -208,15 +336,14 @@ impl<'a> SymbolModule<'a> {
         }
     }
 
+    // `offset` is from the base the symbols were generated against
     fn symbolize<'b>(
         &'b self,
-        addr: u64,
+        offset: u32,
     ) -> (
         impl Iterator<Item = FunctionEntry<'b>> + 'b,
         Option<LocationEntry<'a>>,
     ) {
-        let offset = (addr - 0xffffffff80000000) as u32;
-
         // Find the function containing the address
         let func_opt = Self::binary_search_table(
             self.function_search_count,
@@ -244,8 +371,54 @@ impl<'a> SymbolModule<'a> {
             location,
         )
     }
+
+    fn unwind_rule(&self, offset: u32) -> Option<UnwindRule> {
+        Self::binary_search_table(
+            self.unwind_search_count,
+            |i| self.get_unwind_search(i),
+            offset,
+        )
+        .flatten()
+    }
+
+    // the static containing `offset`, and how far into it that is
+    fn find_data(&self, offset: u32) -> Option<(&'a str, u32)> {
+        let table = self.data_symbols;
+
+        let get_entry = |index: usize| {
+            let entry = &table[index * DATA_SYMBOL_SIZE..];
+            Some((read_u32(entry, 0)?, (read_u32(entry, 4)?, entry)))
+        };
+
+        let (size, entry) = Self::binary_search_table(self.data_symbols_count, get_entry, offset)?;
+        let start = read_u32(entry, 0)?;
+
+        if offset - start >= size {
+            return None;
+        }
+
+        Some((read_string(entry, self.strings, 8)?, offset - start))
+    }
+}
+
+// the kernel is linked at the start of the top 2 GiB (the kernel code model), which is what the
+// offsets in the kernel's symbols are from
+pub const KERNEL_BASE: u64 = 0xffffffff80000000;
+const KERNEL_SIZE: u64 = 1 << 31;
+
+pub const MAX_RANGES: usize = 32;
+
+// code loaded at `base`, and the symbols for it; offsets past `size` don't belong to it
+#[derive(Clone, Copy)]
+struct SymbolRange {
+    base: u64,
+    size: u64,
+    symbols: &'static SymbolModule<'static>,
 }
 
+// this is read from backtraces, so it can't be a plain mutex
+static RANGES: RwIntLock<ArrayVec<SymbolRange, MAX_RANGES>> = RwIntLock::new(ArrayVec::new_const());
+
 static GLOBAL_SYMBOLS: Once<SymbolModule<'static>> = Once::new();
 
 pub fn try_init(data: SymbolModule<'static>) -> bool {
@@ -253,9 +426,172 @@ pub fn try_init(data: SymbolModule<'static>) -> bool {
         return false;
     }
 
-    GLOBAL_SYMBOLS.call_once(|| data);
+    let symbols = GLOBAL_SYMBOLS.call_once(|| data);
+
+    register(KERNEL_BASE, KERNEL_SIZE, symbols).is_ok()
+}
+
+// makes `symbols` resolve addresses in `base..base + size`, for code that was loaded there, such as
+// an AOT module; ranges may not overlap, except with the kernel's
+pub fn register(base: u64, size: u64, symbols: &'static SymbolModule<'static>) -> Result<(), ()> {
+    // the last byte rather than the end, since the kernel's range goes up to the top of memory
+    let last = base.checked_add(size.checked_sub(1).ok_or(())?).ok_or(())?;
+    let mut ranges = RANGES.write();
+
+    if ranges.iter().any(|range| {
+        range.base != KERNEL_BASE && base <= range.base + (range.size - 1) && range.base <= last
+    }) {
+        return Err(());
+    }
 
-    true
+    ranges
+        .try_push(SymbolRange {
+            base,
+            size,
+            symbols,
+        })
+        .map_err(|_| ())
+}
+
+// for when the code at `base` is unloaded
+pub fn unregister(base: u64) {
+    RANGES.write().retain(|range| range.base != base);
+}
+
+// how many times lookup() tries to read RANGES. a writer only holds it for a moment, so if it is
+// still held after this, the writer is never going to let go: it is a core the panic stopped, or
+// the code that this core's panic or NMI interrupted
+const LOOKUP_TRIES: usize = 1 << 16;
+
+// the symbols covering `addr`, and the offset into them; where a range sits inside the kernel's,
+// the inner one wins. None if RANGES can't be read, which leaves just the builtin symbols
+fn lookup(addr: u64) -> Option<(&'static SymbolModule<'static>, u32)> {
+    let ranges = (0..LOOKUP_TRIES).find_map(|_| {
+        let ranges = RANGES.try_read();
+
+        if ranges.is_none() {
+            hint::spin_loop();
+        }
+
+        ranges
+    })?;
+
+    let range = ranges
+        .iter()
+        .filter(|range| range.base <= addr && addr - range.base < range.size)
+        .max_by_key(|range| range.base)
+        .copied()?;
+
+    Some((range.symbols, (addr - range.base).try_into().ok()?))
+}
+
+pub(super) const HANDLER: ModuleHandler = ModuleHandler {
+    kind: "symbols",
+    multiple: false,
+    load,
+    load_late: Some(load_late),
+    // a compressed module is done with once it is decompressed onto the heap
+    resident: |data| !data.starts_with(ZSTD_MAGIC),
+};
+
+fn load(module: &Module) -> Result<(), ()> {
+    if module.data.starts_with(ZSTD_MAGIC) {
+        return Ok(());
+    }
+
+    required(module, load_symbols(module, module.data))
+}
+
+fn load_late(module: &Module) -> Result<(), ()> {
+    if !module.data.starts_with(ZSTD_MAGIC) {
+        return Ok(());
+    }
+
+    required(
+        module,
+        decompress(module).and_then(|data| load_symbols(module, data)),
+    )
+}
+
+fn required(module: &Module, res: Result<(), ()>) -> Result<(), ()> {
+    if res.is_err()
+        && let ModuleCmdline::Symbols { required: true } = module.cmdline
+    {
+        panic!(
+            "mod({}): symbols are required, but didn't load",
+            module.path
+        );
+    }
+
+    res
+}
+
+// the module, decompressed into memory that is never freed, since the symbols are kept for good
+fn decompress(module: &Module) -> Result<&'static [u8], ()> {
+    let mut decoder = StreamingDecoder::new(module.data).map_err(|_| {
+        warn!("mod({}): bad zstd frame header", module.path);
+    })?;
+
+    // the buildtool always records the size in the frame header, so the output is allocated once
+    // rather than grown to about twice its size; 0 if the header doesn't say
+    let mut data = Vec::new();
+    let size = decoder.decoder.content_size() as usize;
+
+    data.try_reserve_exact(size).map_err(|_| {
+        warn!(
+            "mod({}): no memory for {} bytes of symbols",
+            module.path, size
+        );
+    })?;
+
+    let mut chunk = [0; 4096];
+
+    loop {
+        match decoder.read(&mut chunk) {
+            Ok(0) => break,
+            Ok(len) => data.extend_from_slice(&chunk[..len]),
+            Err(_) => {
+                warn!("mod({}): failed to decompress symbols", module.path);
+                return Err(());
+            }
+        }
+    }
+
+    Ok(Box::leak(data.into_boxed_slice()))
+}
+
+fn load_symbols(module: &Module, data: &'static [u8]) -> Result<(), ()> {
+    let syms = match parse(data) {
+        Ok(syms) => syms,
+        Err(SymbolFormatError::BadMagic) => {
+            warn!(
+                "mod({}): not a symbol module, or one from an older buildtool",
+                module.path
+            );
+            return Err(());
+        }
+        Err(SymbolFormatError::Version(major, minor)) => {
+            warn!(
+                "mod({}): symbols are version {}.{}, but only {}.x can be read",
+                module.path, major, minor, VERSION_MAJOR
+            );
+            return Err(());
+        }
+        Err(SymbolFormatError::Malformed) => {
+            warn!("mod({}): failed to parse symbols", module.path);
+            return Err(());
+        }
+    };
+
+    if !try_init(syms) {
+        warn!(
+            "mod({}): cannot load multiple global symbol modules",
+            module.path
+        );
+        return Err(());
+    }
+
+    Ok(())
 }
 
 pub fn symbolize(
@@ -264,10 +600,44 @@ pub fn symbolize(
     Option<impl Iterator<Item = FunctionEntry<'static>> + 'static>,
     Option<LocationEntry<'static>>,
 ) {
-    if let Some(data) = GLOBAL_SYMBOLS.get() {
-        let (iter, loc) = data.symbolize(addr);
-        (Some(iter), loc)
-    } else {
-        (None, None)
+    let (iter, loc) = match lookup(addr) {
+        Some((data, offset)) => {
+            let (iter, loc) = data.symbolize(offset);
+            (Some(iter), loc)
+        }
+        None => (None, None),
+    };
+
+    // only the name, from the table in the kernel image, if there is nothing better
+    let fallback = iter
+        .is_none()
+        .then(|| builtin_symbols::find(addr))
+        .flatten()
+        .map(|name| FunctionEntry {
+            inline_parent: None,
+            name: Some(name),
+            location: LocationEntry {
+                file: None,
+                row: 0,
+                col: 0,
+            },
+        });
+
+    if iter.is_none() && fallback.is_none() {
+        return (None, None);
     }
+
+    (Some(iter.into_iter().flatten().chain(fallback)), loc)
+}
+
+// the static that `addr` points into, and the offset into it, for putting a name to fault addresses
+pub fn symbolize_data(addr: u64) -> Option<(&'static str, u32)> {
+    let (data, offset) = lookup(addr)?;
+    data.find_data(offset)
+}
+
+// the unwind rule covering `addr`, if there are symbols for it and they have one
+pub fn unwind_rule(addr: u64) -> Option<UnwindRule> {
+    let (data, offset) = lookup(addr)?;
+    data.unwind_rule(offset)
 }
@@ -0,0 +1,76 @@
+// tracking of which cores have finished coming up
+//
+// a core counts as online once initialize_core is done with it: its GDT, IDT and core locals are
+// all set up, and it is about to enter ksmp.
+
+extern crate alloc;
+
+use alloc::vec::Vec;
+use core::{
+    hint,
+    sync::atomic::{AtomicBool, AtomicUsize, Ordering},
+    time::Duration,
+};
+use log::info;
+use spin::Once;
+
+use super::{CORE_ID, CoreId};
+use crate::arch::{self, tsc};
+
+static ONLINE: Once<Vec<AtomicBool>> = Once::new();
+static ONLINE_COUNT: AtomicUsize = AtomicUsize::new(0);
+
+pub fn init(n_cores: usize) {
+    ONLINE.call_once(|| (0..n_cores).map(|_| AtomicBool::new(false)).collect());
+}
+
+// called by each core once it is done initializing
+pub fn mark_online() {
+    let online = ONLINE.get().expect("mp::mark_online(): MP not initialized");
+
+    if !online[CORE_ID.get().0].swap(true, Ordering::SeqCst) {
+        ONLINE_COUNT.fetch_add(1, Ordering::SeqCst);
+    }
+}
+
+pub fn is_online(core: CoreId) -> bool {
+    ONLINE
+        .get()
+        .and_then(|online| online.get(core.0))
+        .is_some_and(|online| online.load(Ordering::SeqCst))
+}
+
+pub fn online_cores() -> usize {
+    ONLINE_COUNT.load(Ordering::SeqCst)
+}
+
+fn wait_until(condition: impl Fn() -> bool, timeout: Duration) -> bool {
+    let deadline = tsc::now() + timeout;
+
+    while !condition() {
+        if tsc::now() >= deadline {
+            return false;
+        }
+
+        hint::spin_loop();
+    }
+
+    true
+}
+
+// waits until `count` cores are online, giving up after `timeout`; returns whether they made it
+pub fn wait_for_cores(count: usize, timeout: Duration) -> bool {
+    wait_until(|| online_cores() >= count, timeout)
+}
+
+// starts a core that was held back at boot (see mp:{max_cores}) and waits for it to come online
+pub fn start_core(core: CoreId) -> Result<(), ()> {
+    arch::mp::start_core(core)?;
+
+    if !wait_until(|| is_online(core), Duration::from_secs(1)) {
+        return Err(());
+    }
+
+    info!("mp::start_core(): core {} is online", core);
+    Ok(())
+}
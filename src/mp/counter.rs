@@ -0,0 +1,68 @@
+// per-core statistics counters
+//
+// the count lives in a core local, so bumping it only ever touches a cache line owned by the
+// current core. reads sum up every core's copy, and are only as accurate as a snapshot can be.
+
+use core::sync::atomic::{AtomicU64, Ordering};
+
+use super::{CoreId, CoreLocal, MP_STATE, MpState, core_count};
+use crate::arch::mp::cpu_local_add;
+
+/// a counter that is cheap to bump from any core
+/// declared with `per_cpu_counter!`, since the per-core part has to be a core local.
+pub struct PerCpuCounter {
+    // only ever written by the owning core, with a single add that interrupts can't split; it is
+    // atomic so that other cores can read it
+    local: &'static CoreLocal<AtomicU64>,
+    // bumps from before core locals were set up (the template copy is read-only)
+    early: AtomicU64,
+}
+
+pub macro per_cpu_counter($(#[$meta:meta])* $vis:vis $name:ident) {
+    $(#[$meta])*
+    $vis static $name: crate::mp::PerCpuCounter = {
+        crate::mp::core_local! {
+            LOCAL: core::sync::atomic::AtomicU64 = core::sync::atomic::AtomicU64::new(0);
+        }
+
+        crate::mp::PerCpuCounter::new(&LOCAL)
+    };
+}
+
+impl PerCpuCounter {
+    pub const fn new(local: &'static CoreLocal<AtomicU64>) -> PerCpuCounter {
+        PerCpuCounter {
+            local,
+            early: AtomicU64::new(0),
+        }
+    }
+
+    pub fn add(&self, value: u64) {
+        // the BSP sets up its core locals before leaving KInit, and the APs before doing anything
+        // else, so this is the only check needed
+        if MP_STATE.load(Ordering::Relaxed) == MpState::KInit {
+            self.early.fetch_add(value, Ordering::Relaxed);
+            return;
+        }
+
+        cpu_local_add(self.local.offset_in_block(), value);
+    }
+
+    pub fn inc(&self) {
+        self.add(1);
+    }
+
+    pub fn get_on(&self, core: CoreId) -> u64 {
+        if MP_STATE.load(Ordering::Relaxed) == MpState::KInit {
+            return 0;
+        }
+
+        unsafe { &*self.local.addr_on(core).as_ptr::<AtomicU64>() }.load(Ordering::Relaxed)
+    }
+
+    pub fn sum(&self) -> u64 {
+        (0..core_count())
+            .map(|core| self.get_on(CoreId(core)))
+            .fold(self.early.load(Ordering::Relaxed), |acc, count| acc + count)
+    }
+}
@@ -0,0 +1,144 @@
+// core locals allocated at runtime
+//
+// every core's local block has a spare area after the `.cpu_local` template, at the same offset
+// on all cores. dynamic core locals are carved out of it, so code that comes up after
+// init_cpu_local_table can still get per-core state.
+
+extern crate alloc;
+
+use alloc::vec::Vec;
+use core::{
+    alloc::Layout,
+    marker::PhantomData,
+    mem::{align_of, size_of},
+    ops::Deref,
+    ptr,
+};
+
+use super::{CoreId, OFFSET_ARRAY, core_count};
+use crate::{
+    arch::mp::get_cpu_local_pointer,
+    mem::{ByteSize, PageSize, SizeType, VirtualAddress, Wrapper},
+    sync::IntMutex,
+};
+
+// size of the spare area in each core's local block
+pub(super) const DYNAMIC_AREA_SIZE: PageSize = PageSize::new(16);
+
+// free offsets within the spare area as (start, length), sorted by start
+static FREE: IntMutex<Vec<(u64, u64)>> = IntMutex::new(Vec::new());
+
+pub(super) fn init(template_size: PageSize) {
+    FREE.lock()
+        .push((template_size.size_bytes(), DYNAMIC_AREA_SIZE.size_bytes()));
+}
+
+fn allocate(layout: Layout) -> Option<u64> {
+    let mut free = FREE.lock();
+    let size = layout.size() as u64;
+
+    let (index, start) = free.iter().enumerate().find_map(|(index, &(start, len))| {
+        let aligned = start.next_multiple_of(layout.align() as u64);
+        (aligned + size <= start + len).then_some((index, aligned))
+    })?;
+
+    let (range_start, range_len) = free.remove(index);
+    let range_end = range_start + range_len;
+
+    // put back whatever is left on either side
+    if start + size < range_end {
+        free.insert(index, (start + size, range_end - start - size));
+    }
+
+    if range_start < start {
+        free.insert(index, (range_start, start - range_start));
+    }
+
+    Some(start)
+}
+
+fn release(offset: u64, layout: Layout) {
+    let mut free = FREE.lock();
+
+    let index = free.partition_point(|&(start, _)| start < offset);
+    free.insert(index, (offset, layout.size() as u64));
+
+    // merge with the neighbours, if they touch
+    if index + 1 < free.len() && free[index].0 + free[index].1 == free[index + 1].0 {
+        free[index].1 += free.remove(index + 1).1;
+    }
+
+    if index > 0 && free[index - 1].0 + free[index - 1].1 == free[index].0 {
+        free[index - 1].1 += free.remove(index).1;
+    }
+}
+
+/// a core local allocated at runtime
+/// dereferences to the current core's copy, like CoreLocal; dropping it drops every core's copy.
+pub struct DynCoreLocal<T> {
+    offset: u64,
+    _marker: PhantomData<T>,
+}
+
+impl<T> DynCoreLocal<T> {
+    // allocates a core local, with `init` providing each core's initial value. only usable once
+    // core locals are set up; returns None once the spare area is used up
+    pub fn new(init: impl Fn(CoreId) -> T) -> Option<DynCoreLocal<T>> {
+        assert!(
+            OFFSET_ARRAY.is_completed(),
+            "mp::DynCoreLocal::new(): core locals not initialized"
+        );
+
+        let layout = Layout::from_size_align(size_of::<T>().max(1), align_of::<T>()).unwrap();
+
+        let local = DynCoreLocal {
+            offset: allocate(layout)?,
+            _marker: PhantomData,
+        };
+
+        for core in 0..core_count() {
+            unsafe { ptr::write(local.addr_on(CoreId(core)).as_ptr_mut(), init(CoreId(core))) };
+        }
+
+        Some(local)
+    }
+
+    pub fn addr(&self) -> VirtualAddress {
+        get_cpu_local_pointer() + ByteSize::new(self.offset)
+    }
+
+    // address of `core`'s copy, which may be in use by that core right now
+    pub fn addr_on(&self, core: CoreId) -> VirtualAddress {
+        VirtualAddress::new(OFFSET_ARRAY.get().unwrap()[core.0]) + ByteSize::new(self.offset)
+    }
+}
+
+impl<T: Sync> DynCoreLocal<T> {
+    // another core's copy; T must be Sync, since that core may be using it concurrently
+    pub fn get_on(&self, core: CoreId) -> &T {
+        unsafe { &*self.addr_on(core).as_ptr() }
+    }
+}
+
+impl<T> Deref for DynCoreLocal<T> {
+    type Target = T;
+
+    fn deref(&self) -> &Self::Target {
+        unsafe { &*self.addr().as_ptr() }
+    }
+}
+
+impl<T> Drop for DynCoreLocal<T> {
+    fn drop(&mut self) {
+        for core in 0..core_count() {
+            unsafe { ptr::drop_in_place(self.addr_on(CoreId(core)).as_ptr_mut::<T>()) };
+        }
+
+        let layout = Layout::from_size_align(size_of::<T>().max(1), align_of::<T>()).unwrap();
+        release(self.offset, layout);
+    }
+}
+
+// like CoreLocal, each core only ever touches its own copy through Deref
+unsafe impl<T: Send> Send for DynCoreLocal<T> {}
+unsafe impl<T: Send> Sync for DynCoreLocal<T> {}
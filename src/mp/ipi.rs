@@ -0,0 +1,108 @@
+// running code on other cores
+//
+// each core has a mailbox of closures for it to run. senders queue a closure and raise an IPI,
+// and the target runs everything in its mailbox from the IPI handler, so closures run in
+// interrupt context: with interrupts disabled, and without blocking.
+
+extern crate alloc;
+
+use alloc::{boxed::Box, collections::VecDeque, vec::Vec};
+use core::mem;
+use spin::Once;
+
+use super::{CORE_ID, CoreId, is_online};
+use crate::{
+    arch::{IrqState, irq_disable, mp::send_ipi},
+    sync::{Event, IntMutex, ResetMode},
+};
+
+type Job = Box<dyn FnOnce() + Send>;
+
+static MAILBOXES: Once<Vec<IntMutex<VecDeque<Job>>>> = Once::new();
+
+pub fn init(n_cores: usize) {
+    MAILBOXES.call_once(|| {
+        (0..n_cores)
+            .map(|_| IntMutex::new(VecDeque::new()))
+            .collect()
+    });
+}
+
+// runs everything queued for the current core; called from the IPI handler
+pub(super) fn run_pending() {
+    let Some(mailbox) = MAILBOXES
+        .get()
+        .and_then(|mailboxes| mailboxes.get(CORE_ID.get().0))
+    else {
+        return;
+    };
+
+    // jobs are run without the mailbox lock held, since they may well queue more
+    loop {
+        let Some(job) = mailbox.lock().pop_front() else {
+            return;
+        };
+
+        job();
+    }
+}
+
+fn post(core: CoreId, job: Job) -> Result<(), ()> {
+    if !is_online(core) {
+        return Err(());
+    }
+
+    MAILBOXES
+        .get()
+        .and_then(|mailboxes| mailboxes.get(core.0))
+        .ok_or(())?
+        .lock()
+        .push_back(job);
+
+    send_ipi(core);
+    Ok(())
+}
+
+fn run_here<R>(action: impl FnOnce() -> R) -> R {
+    // same environment as if it had come in through the IPI handler
+    let state = IrqState::save();
+    irq_disable();
+    let res = action();
+    state.restore();
+    res
+}
+
+// queues `action` to run on `core` without waiting for it; fails if the core isn't online
+pub fn run_on_async(core: CoreId, action: impl FnOnce() + Send + 'static) -> Result<(), ()> {
+    if core == CORE_ID.get() {
+        run_here(action);
+        return Ok(());
+    }
+
+    post(core, Box::new(action))
+}
+
+// runs `action` on `core` and waits for its result; fails if the core isn't online
+// the caller spins until `core` gets to it, so this must not be called with interrupts disabled,
+// or two cores running things on each other would deadlock
+pub fn run_on<R: Send>(core: CoreId, action: impl FnOnce() -> R + Send) -> Result<R, ()> {
+    if core == CORE_ID.get() {
+        return Ok(run_here(action));
+    }
+
+    let result = IntMutex::new(None);
+    let done = Event::new(ResetMode::Manual);
+
+    let job: Box<dyn FnOnce() + Send + '_> = Box::new(|| {
+        *result.lock() = Some(action());
+        done.set();
+    });
+
+    // the job only borrows from this frame, which outlives it since we wait for it to finish
+    let job: Job = unsafe { mem::transmute(job) };
+
+    post(core, job)?;
+    done.wait();
+
+    Ok(result.lock().take().unwrap())
+}
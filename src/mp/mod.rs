@@ -1,9 +1,32 @@
+pub mod bringup;
+pub mod counter;
+pub mod dynamic;
+pub mod ipi;
+pub mod options;
+pub mod park;
+pub mod percpu;
+pub mod topology;
+
+pub use bringup::{is_online, online_cores, start_core};
+pub use counter::{PerCpuCounter, per_cpu_counter};
+pub use dynamic::DynCoreLocal;
+pub use ipi::{run_on, run_on_async};
+pub use park::{is_parked, park, unpark};
+pub use topology::topology;
+
 use crate::{
     arch::{
+        IrqState, irq_disable,
         mp::get_cpu_local_pointer,
         paging::{PageFlags, PageTableSet},
+        wait_for_interrupt,
+    },
+    mem::{
+        AddressRange, ByteDiff, PMM, PageSize, SizeType, VFRange, VirtualAddress, Wrapper, stack,
+        vpa,
     },
-    mem::{AddressRange, ByteDiff, PMM, PageSize, SizeType, VFRange, VirtualAddress, Wrapper, vpa},
+    profile,
+    sync::rcu,
 };
 use alloc::vec::Vec;
 use atomic_enum::atomic_enum;
@@ -83,9 +106,19 @@ impl<T> CoreLocal<T> {
         self_addr - template_range.start().address()
     }
 
+    // the offset of this core local in every core's block
+    pub fn offset_in_block(&self) -> u64 {
+        self.offset().value() as u64
+    }
+
     pub fn addr(&self) -> VirtualAddress {
         get_cpu_local_pointer() + self.offset()
     }
+
+    // address of `core`'s copy, which may be in use by that core right now
+    pub fn addr_on(&self, core: CoreId) -> VirtualAddress {
+        VirtualAddress::new(OFFSET_ARRAY.get().unwrap()[core.0]) + self.offset()
+    }
 }
 
 // core locals can always be "sent" and "synced" across threads (which is meaningless)
@@ -106,6 +139,11 @@ impl<T> DerefMut for CoreLocal<T> {
     }
 }
 
+// number of cores that have core locals, whether or not they are running yet
+pub fn core_count() -> usize {
+    OFFSET_ARRAY.get().map_or(1, |offsets| offsets.len())
+}
+
 pub fn get_cpu_local_offset(core: CoreId) -> VirtualAddress {
     VirtualAddress::from(&raw const OFFSET_ARRAY.get().unwrap()[core.0])
 }
@@ -115,6 +153,10 @@ pub fn init_cpu_local_table(tables: &PageTableSet, n_cores: usize) {
     let alloc = vpa::get_global_vpa();
     let pmm = PMM::get();
 
+    // the spare area for dynamic core locals goes right after the template
+    let block_size = PageSize::new(template.size().value() + dynamic::DYNAMIC_AREA_SIZE.value());
+    dynamic::init(template.size());
+
     OFFSET_ARRAY.call_once(|| {
         (0..n_cores)
             .map(|_| {
@@ -122,9 +164,10 @@ pub fn init_cpu_local_table(tables: &PageTableSet, n_cores: usize) {
                     .allocate_backed_padded(
                         &pmm,
                         tables,
-                        template.size(),
+                        block_size,
                         PageSize::new(1),
                         PageFlags::KERNEL_RW,
+                        false,
                     )
                     .expect("failed!")
                     .leak();
@@ -145,3 +188,47 @@ pub fn init_cpu_local_table(tables: &PageTableSet, n_cores: usize) {
 core_local! {
     pub CORE_ID: Cell<CoreId> = Cell::new(CoreId(0));
 }
+
+// handles IPI_VECTOR on the receiving core
+pub fn handle_ipi() {
+    ipi::run_pending();
+
+    // park requests need nothing else, since the idle loop re-checks for them after every
+    // interrupt
+}
+
+// what a core does once it has nothing left to run
+pub fn idle() -> ! {
+    idle_until(|| false);
+    unreachable!()
+}
+
+// idles until `ready` holds; it is checked with interrupts disabled, so that a wakeup coming from
+// an interrupt handler can't be missed
+pub fn idle_until(ready: impl Fn() -> bool) {
+    let state = IrqState::save();
+
+    loop {
+        irq_disable();
+
+        // sinks may be holding back output until a flush, which nothing would do while this core
+        // sleeps; that includes whatever the interrupt that woke it up logged
+        log::logger().flush();
+
+        park::park_if_requested();
+        stack::check();
+        profile::flush();
+
+        if ready() {
+            break;
+        }
+
+        // TODO: interrupt handlers run while the core is marked idle, so they must not enter RCU
+        // read-side sections yet
+        rcu::enter_idle();
+        wait_for_interrupt();
+        rcu::online();
+    }
+
+    state.restore();
+}
@@ -0,0 +1,15 @@
+use proc_macros::CmdlineParsable;
+
+use crate::cmdline::CmdlineParsable;
+
+#[derive(CmdlineParsable, Clone, Copy)]
+pub struct MpOptions {
+    // bitmask of cores to park as soon as they are up, see mp::park; core 0 is never parked
+    #[default_value(0)]
+    pub park: u64,
+    // number of cores to start at boot, or all of them if unset; the rest can be started later
+    // with mp::start_core
+    #[default_value(None)]
+    #[validate(min = 1)]
+    pub max_cores: Option<u64>,
+}
@@ -0,0 +1,97 @@
+// core parking
+//
+// a parked core stays in a hlt loop, taking no work and holding up no RCU grace periods, until it
+// is explicitly resumed. this is mostly a debugging aid: it allows shrinking the set of active
+// cores at runtime when chasing SMP races.
+
+extern crate alloc;
+
+use alloc::vec::Vec;
+use core::sync::atomic::{AtomicBool, Ordering};
+use log::info;
+use spin::Once;
+
+use super::{CORE_ID, CoreId};
+use crate::{
+    arch::{self, mp::send_ipi},
+    cmdline::get_cmdline,
+    sync::rcu,
+};
+
+struct ParkState {
+    requested: AtomicBool,
+    parked: AtomicBool,
+}
+
+static PARK_STATE: Once<Vec<ParkState>> = Once::new();
+
+pub fn init(n_cores: usize) {
+    let mask = get_cmdline().mp.park;
+
+    PARK_STATE.call_once(|| {
+        (0..n_cores)
+            .map(|i| ParkState {
+                requested: AtomicBool::new(i != 0 && i < 64 && mask & (1 << i) != 0),
+                parked: AtomicBool::new(false),
+            })
+            .collect()
+    });
+}
+
+fn state(core: CoreId) -> Option<&'static ParkState> {
+    PARK_STATE.get().and_then(|cores| cores.get(core.0))
+}
+
+// asks `core` to park itself, which it does the next time it is idle
+pub fn park(core: CoreId) -> Result<(), ()> {
+    // the BSP is the one core that is guaranteed to be around to resume the others
+    if core == CoreId(0) || core == CORE_ID.get() {
+        return Err(());
+    }
+
+    state(core)
+        .ok_or(())?
+        .requested
+        .store(true, Ordering::SeqCst);
+    send_ipi(core);
+
+    Ok(())
+}
+
+pub fn unpark(core: CoreId) -> Result<(), ()> {
+    state(core)
+        .ok_or(())?
+        .requested
+        .store(false, Ordering::SeqCst);
+    send_ipi(core);
+
+    Ok(())
+}
+
+pub fn is_parked(core: CoreId) -> bool {
+    state(core).is_some_and(|state| state.parked.load(Ordering::SeqCst))
+}
+
+// called from the idle loop with interrupts disabled; only returns once the core is resumed
+pub(super) fn park_if_requested() {
+    let id = CORE_ID.get();
+    let Some(state) = state(id) else {
+        return;
+    };
+
+    if !state.requested.load(Ordering::SeqCst) {
+        return;
+    }
+
+    info!("mp::park: parking core {}", id);
+    state.parked.store(true, Ordering::SeqCst);
+    rcu::enter_idle();
+
+    while state.requested.load(Ordering::SeqCst) {
+        arch::wait_for_interrupt();
+    }
+
+    rcu::online();
+    state.parked.store(false, Ordering::SeqCst);
+    info!("mp::park: core {} resumed", id);
+}
@@ -0,0 +1,67 @@
+// guarded access to core locals
+//
+// a bare CoreLocal deref resolves the current core once, so if the task is moved to another core
+// halfway through, it ends up touching the wrong core's copy. these helpers keep the task on the
+// current core for as long as the reference is in use.
+
+use core::{cell::Cell, marker::PhantomData, sync::atomic::Ordering};
+
+use super::{CoreLocal, MP_STATE, MpState, core_local};
+use crate::arch::{IrqState, irq_disable};
+
+core_local! {
+    PREEMPT_COUNT: Cell<usize> = Cell::new(0);
+}
+
+// core locals aren't set up yet, but there is nothing to be preempted by either
+fn early() -> bool {
+    MP_STATE.load(Ordering::Relaxed) == MpState::KInit
+}
+
+/// keeps the current task on this core until dropped
+pub struct PreemptGuard {
+    // tied to the core it was created on
+    _marker: PhantomData<*const ()>,
+}
+
+impl Drop for PreemptGuard {
+    fn drop(&mut self) {
+        if !early() {
+            PREEMPT_COUNT.set(PREEMPT_COUNT.get() - 1);
+        }
+
+        // TODO: run a reschedule that was deferred while preemption was disabled
+    }
+}
+
+pub fn preempt_disable() -> PreemptGuard {
+    if !early() {
+        PREEMPT_COUNT.set(PREEMPT_COUNT.get() + 1);
+    }
+
+    PreemptGuard {
+        _marker: PhantomData,
+    }
+}
+
+// whether the scheduler may move the current task off this core
+pub fn preemptible() -> bool {
+    !early() && PREEMPT_COUNT.get() == 0
+}
+
+// runs `action` on the current core's copy of `local`, with preemption disabled
+pub fn with<T, R>(local: &CoreLocal<T>, action: impl FnOnce(&T) -> R) -> R {
+    let _guard = preempt_disable();
+    action(local)
+}
+
+// like `with`, but also keeps interrupt handlers on this core from running, for data they share
+pub fn with_irqs_disabled<T, R>(local: &CoreLocal<T>, action: impl FnOnce(&T) -> R) -> R {
+    let state = IrqState::save();
+    irq_disable();
+
+    let res = with(local, action);
+
+    state.restore();
+    res
+}
@@ -0,0 +1,134 @@
+// cpu topology
+//
+// every core describes itself while it is brought up; the map is then used to tell which cores
+// share a package, a physical core or a cache, e.g. for picking work-stealing victims close to
+// home or sizing per-CPU caches.
+// TODO: cross-check against the MADT once it is parsed
+
+extern crate alloc;
+
+use alloc::vec::Vec;
+use arrayvec::ArrayVec;
+use log::info;
+use spin::Once;
+
+use super::CoreId;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum CacheKind {
+    Data,
+    Instruction,
+    Unified,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct CacheInfo {
+    pub level: u8,
+    pub kind: CacheKind,
+    pub size: usize,
+    pub line_size: usize,
+    // equal for every core sharing this cache
+    pub id: u32,
+}
+
+#[derive(Clone, Debug)]
+pub struct CoreTopology {
+    pub apic_id: u32,
+    pub package: u32,
+    // the physical core within the package
+    pub core: u32,
+    // the SMT thread within the physical core
+    pub thread: u32,
+    pub caches: ArrayVec<CacheInfo, 8>,
+}
+
+impl CoreTopology {
+    pub fn cache(&self, level: u8, kind: CacheKind) -> Option<&CacheInfo> {
+        self.caches
+            .iter()
+            .find(|cache| cache.level == level && cache.kind == kind)
+    }
+}
+
+static TOPOLOGY: Once<Vec<Once<CoreTopology>>> = Once::new();
+
+pub fn init(n_cores: usize) {
+    TOPOLOGY.call_once(|| (0..n_cores).map(|_| Once::new()).collect());
+}
+
+// records the topology of the current core
+pub fn register(id: CoreId, topology: CoreTopology) {
+    let topology =
+        TOPOLOGY.get().expect("mp::topology not initialized")[id.0].call_once(|| topology);
+
+    info!(
+        "mp::topology: core {} is package {} core {} thread {} (apic id {})",
+        id, topology.package, topology.core, topology.thread, topology.apic_id
+    );
+}
+
+#[derive(Clone, Copy)]
+pub struct Topology {
+    cores: &'static [Once<CoreTopology>],
+}
+
+// the topology of every core that has been brought up so far
+pub fn topology() -> Topology {
+    Topology {
+        cores: TOPOLOGY.get().map(Vec::as_slice).unwrap_or(&[]),
+    }
+}
+
+impl Topology {
+    pub fn core(&self, id: CoreId) -> Option<&'static CoreTopology> {
+        self.cores.get(id.0).and_then(Once::get)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (CoreId, &'static CoreTopology)> {
+        let cores = self.cores;
+
+        cores
+            .iter()
+            .enumerate()
+            .filter_map(|(i, core)| core.get().map(|core| (CoreId(i), core)))
+    }
+
+    pub fn same_package(&self, a: CoreId, b: CoreId) -> bool {
+        matches!((self.core(a), self.core(b)), (Some(a), Some(b)) if a.package == b.package)
+    }
+
+    // SMT siblings running on the same physical core
+    pub fn same_core(&self, a: CoreId, b: CoreId) -> bool {
+        matches!(
+            (self.core(a), self.core(b)),
+            (Some(a), Some(b)) if a.package == b.package && a.core == b.core
+        )
+    }
+
+    pub fn shares_cache(&self, a: CoreId, b: CoreId, level: u8) -> bool {
+        let (Some(a), Some(b)) = (self.core(a), self.core(b)) else {
+            return false;
+        };
+
+        a.caches
+            .iter()
+            .filter(|cache| cache.level == level)
+            .any(|ca| {
+                b.caches
+                    .iter()
+                    .any(|cb| cb.level == level && cb.kind == ca.kind && cb.id == ca.id)
+            })
+    }
+
+    pub fn packages(&self) -> usize {
+        let mut packages = ArrayVec::<u32, 64>::new();
+
+        for (_, core) in self.iter() {
+            if !packages.contains(&core.package) {
+                let _ = packages.try_push(core.package);
+            }
+        }
+
+        packages.len()
+    }
+}
@@ -0,0 +1,101 @@
+// a sampling profiler, read by `buildtool profile`
+//
+// with `profile:{enable}`, every core takes a LAPIC timer tick `hz` times a second and records the
+// kernel stack it interrupted. the unwinder reads the CFI through symbols::RANGES, but names are
+// only put to the addresses on the host.
+//
+// the timer interrupt only buffers the sample on its core; the idle loop sends the buffer over
+// serial, each sample as a `sample` frame in the crash dump's framing: the core id as a u32, then
+// the interrupted pc and the return addresses above it as u64s, innermost first. samples taken
+// while the buffer is full are dropped, so keep `hz` low on hardware, where the UART is slow.
+
+pub mod options;
+
+use core::cell::RefCell;
+
+use arrayvec::ArrayVec;
+use log::info;
+use spin::Once;
+
+use crate::{
+    arch::{UnwindContext, apic},
+    cmdline::get_cmdline,
+    crashdump::{FRAME_DATA, Writer},
+    mp::{CORE_ID, core_local},
+    sync::IntMutex,
+};
+
+// samples a core holds on to until it next idles
+const BUFFERED_SAMPLES: usize = 16;
+
+type Sample = ArrayVec<u8, FRAME_DATA>;
+
+static WRITER: Once<IntMutex<Writer>> = Once::new();
+
+core_local! {
+    // only touched with interrupts disabled, by the timer interrupt and the idle loop of the core
+    SAMPLES: RefCell<ArrayVec<Sample, BUFFERED_SAMPLES>> = RefCell::new(ArrayVec::new_const());
+}
+
+// starts sampling the current core, if the profiler is enabled
+pub fn start() {
+    let options = get_cmdline().profile;
+
+    if !options.enable {
+        return;
+    }
+
+    WRITER.call_once(|| {
+        info!(
+            "profile::start(): sampling at {} Hz, to port {:#x}",
+            options.hz, options.port
+        );
+        IntMutex::new(Writer::open(options.port))
+    });
+
+    apic::start_timer(apic::TIMER_VECTOR, options.hz);
+}
+
+// from the timer interrupt, with the kernel code it interrupted; the stack is cut off where the
+// frame runs out of room
+pub fn sample(pc: u64, mut context: UnwindContext) {
+    if WRITER.get().is_none() {
+        return;
+    }
+
+    let mut samples = SAMPLES.borrow_mut();
+
+    if samples.is_full() {
+        return;
+    }
+
+    let mut data = Sample::new();
+    data.extend((CORE_ID.get().0 as u32).to_le_bytes());
+    data.extend(pc.to_le_bytes());
+
+    while data.remaining_capacity() >= 8 && unsafe { context.valid() } {
+        data.extend(unsafe { context.return_address() }.to_le_bytes());
+        context = unsafe { context.next() };
+    }
+
+    samples.push(data);
+}
+
+// sends the samples buffered on the current core; from the idle loop, with interrupts disabled
+pub fn flush() {
+    let Some(writer) = WRITER.get() else {
+        return;
+    };
+
+    let mut samples = SAMPLES.borrow_mut();
+
+    if samples.is_empty() {
+        return;
+    }
+
+    let mut writer = writer.lock();
+
+    for data in samples.drain(..) {
+        writer.frame("sample", &data);
+    }
+}
@@ -0,0 +1,16 @@
+use proc_macros::CmdlineParsable;
+
+use crate::cmdline::CmdlineParsable;
+
+#[derive(CmdlineParsable, Clone, Copy)]
+pub struct ProfileOptions {
+    #[default_value(false)]
+    pub enable: bool,
+    // samples a second, on each core
+    #[default_value(100)]
+    #[validate(min = 1)]
+    pub hz: u64,
+    // COM3, so that the samples stay out of the log on COM1 and the gdb stub on COM2
+    #[default_value(0x3e8)]
+    pub port: u16,
+}
@@ -0,0 +1,70 @@
+use core::{
+    hint,
+    sync::atomic::{AtomicBool, Ordering},
+};
+
+use crate::mp::{MP_STATE, MpState};
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum ResetMode {
+    // stays signaled until reset, releasing every waiter
+    Manual,
+    // each signal releases exactly one waiter, which resets the event
+    Auto,
+}
+
+/// one-shot or repeating completion signal, e.g. for a driver waiting on "DMA finished"
+/// like Semaphore, waiters spin, so an event set from an interrupt handler must not be waited on
+/// with interrupts disabled on the core that takes that interrupt.
+pub struct Event {
+    signaled: AtomicBool,
+    mode: ResetMode,
+    // TODO: we need a blocked queue here
+}
+
+impl Event {
+    pub const fn new(mode: ResetMode) -> Event {
+        Event {
+            signaled: AtomicBool::new(false),
+            mode,
+        }
+    }
+
+    pub fn set(&self) {
+        // TODO: wake things up from the queue
+        self.signaled.store(true, Ordering::Release);
+    }
+
+    pub fn reset(&self) {
+        self.signaled.store(false, Ordering::Relaxed);
+    }
+
+    pub fn is_set(&self) -> bool {
+        self.signaled.load(Ordering::Acquire)
+    }
+
+    pub fn try_wait(&self) -> bool {
+        match self.mode {
+            ResetMode::Manual => self.is_set(),
+            ResetMode::Auto => self
+                .signaled
+                .compare_exchange(true, false, Ordering::Acquire, Ordering::Relaxed)
+                .is_ok(),
+        }
+    }
+
+    pub fn wait(&self) {
+        // TODO: once cores are pre-emptable (MPPreempt), this should sleep; until then, spinning
+        // still works, it just burns the time slice
+        debug_assert!(
+            MP_STATE.load(Ordering::Relaxed) != MpState::MPPreempt,
+            "Event::wait(): preemption isn't supported yet"
+        );
+
+        while !self.try_wait() {
+            while !self.signaled.load(Ordering::Relaxed) {
+                hint::spin_loop();
+            }
+        }
+    }
+}
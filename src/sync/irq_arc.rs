@@ -0,0 +1,153 @@
+// reference counted pointer for objects shared with interrupt handlers
+//
+// clones and drops only touch the embedded atomic refcount, so they are safe anywhere, including
+// with interrupts disabled and with the heap lock held. dropping the last reference doesn't free
+// the object in place either: it is pushed onto a lock-free list and destroyed later from thread
+// context or at the end of a device interrupt, since a destructor may well need to allocate, take
+// locks or unmap memory.
+
+extern crate alloc;
+
+use alloc::boxed::Box;
+use core::{
+    cell::UnsafeCell,
+    ops::Deref,
+    ptr::{self, NonNull},
+    sync::atomic::{AtomicPtr, AtomicUsize, Ordering, fence},
+};
+
+/// intrusive header for objects managed by IrqArc
+pub struct IrqArcLink {
+    refcount: AtomicUsize,
+    // next object on the deferred destruction list
+    next: UnsafeCell<*mut IrqArcLink>,
+    object: UnsafeCell<*mut ()>,
+    destroy: UnsafeCell<Option<unsafe fn(*mut ())>>,
+}
+
+impl IrqArcLink {
+    pub const fn new() -> IrqArcLink {
+        IrqArcLink {
+            refcount: AtomicUsize::new(1),
+            next: UnsafeCell::new(ptr::null_mut()),
+            object: UnsafeCell::new(ptr::null_mut()),
+            destroy: UnsafeCell::new(None),
+        }
+    }
+}
+
+// the cells are only touched while the object is unreachable: at creation and after the last drop
+unsafe impl Send for IrqArcLink {}
+unsafe impl Sync for IrqArcLink {}
+
+impl Default for IrqArcLink {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// implemented by types that embed an IrqArcLink
+/// safety: `link` must always return the same embedded link, which is not used for anything else
+pub unsafe trait IrqArcObject: Send + Sync + 'static {
+    fn link(&self) -> &IrqArcLink;
+}
+
+pub struct IrqArc<T: IrqArcObject> {
+    ptr: NonNull<T>,
+}
+
+// objects whose last reference was dropped, waiting for reap()
+static DEFERRED: AtomicPtr<IrqArcLink> = AtomicPtr::new(ptr::null_mut());
+
+unsafe fn destroy<T>(object: *mut ()) {
+    drop(unsafe { Box::from_raw(object as *mut T) });
+}
+
+impl<T: IrqArcObject> IrqArc<T> {
+    // allocates, so this must be called from thread context
+    pub fn new(value: T) -> IrqArc<T> {
+        let ptr = NonNull::from(Box::leak(Box::new(value)));
+        let link = unsafe { ptr.as_ref() }.link();
+
+        // nobody else can see the object yet
+        unsafe {
+            *link.object.get() = ptr.as_ptr() as *mut ();
+            *link.destroy.get() = Some(destroy::<T>);
+        }
+
+        IrqArc { ptr }
+    }
+
+    pub fn ref_count(this: &IrqArc<T>) -> usize {
+        this.link().refcount.load(Ordering::Relaxed)
+    }
+
+    pub fn ptr_eq(a: &IrqArc<T>, b: &IrqArc<T>) -> bool {
+        a.ptr == b.ptr
+    }
+}
+
+impl<T: IrqArcObject> Deref for IrqArc<T> {
+    type Target = T;
+
+    fn deref(&self) -> &Self::Target {
+        unsafe { self.ptr.as_ref() }
+    }
+}
+
+impl<T: IrqArcObject> Clone for IrqArc<T> {
+    fn clone(&self) -> Self {
+        let prev = self.link().refcount.fetch_add(1, Ordering::Relaxed);
+        assert!(prev < usize::MAX / 2, "IrqArc::clone(): refcount overflow");
+
+        IrqArc { ptr: self.ptr }
+    }
+}
+
+impl<T: IrqArcObject> Drop for IrqArc<T> {
+    fn drop(&mut self) {
+        let link = self.link();
+
+        if link.refcount.fetch_sub(1, Ordering::Release) != 1 {
+            return;
+        }
+
+        fence(Ordering::Acquire);
+
+        let link = link as *const IrqArcLink as *mut IrqArcLink;
+        let mut head = DEFERRED.load(Ordering::Relaxed);
+
+        loop {
+            // the object is unreachable now, so its link is ours to use
+            unsafe { *(*link).next.get() = head };
+
+            match DEFERRED.compare_exchange_weak(head, link, Ordering::Release, Ordering::Relaxed) {
+                Ok(_) => break,
+                Err(current) => head = current,
+            }
+        }
+    }
+}
+
+unsafe impl<T: IrqArcObject> Send for IrqArc<T> {}
+unsafe impl<T: IrqArcObject> Sync for IrqArc<T> {}
+
+// destroys every object whose last reference was dropped; must be called from thread context, or
+// from an interrupt that came in with interrupts enabled
+pub fn reap() {
+    let mut link = DEFERRED.swap(ptr::null_mut(), Ordering::Acquire);
+
+    while !link.is_null() {
+        // read everything out before the destructor frees the link along with its object
+        let (next, object, destroy) = unsafe {
+            (
+                *(*link).next.get(),
+                *(*link).object.get(),
+                (*(*link).destroy.get()).expect("IrqArc: object was never initialized"),
+            )
+        };
+
+        unsafe { destroy(object) };
+        link = next;
+    }
+}
@@ -0,0 +1,328 @@
+// lock ordering validation
+//
+// locks are grouped into classes by the place they were created at (their `new` call), so that a
+// lock on the heap or stack doesn't inherit the ordering history of whatever lock used the same
+// address before it. each core keeps a stack of the classes it currently holds, and whenever a
+// lock is taken while others are held, the "held before" edges are recorded in a global table. if
+// the reverse of a new edge was seen before, the two orders can deadlock against each other
+// (ABBA), so both acquisition sites are reported, once per pair. taking a lock the core already
+// holds is reported too, since none of the locks are recursive.
+
+use core::{
+    cell::{SyncUnsafeCell, UnsafeCell},
+    hint,
+    panic::Location,
+    sync::atomic::{AtomicBool, Ordering},
+};
+use log::error;
+use rustc_demangle::demangle;
+
+use crate::{
+    arch::UnwindContext,
+    modules::symbols,
+    mp::{MP_STATE, MpState, core_local},
+};
+
+const MAX_HELD: usize = 16;
+const MAX_EDGES: usize = 1024;
+const TRACE_DEPTH: usize = 8;
+
+type Site = [u64; TRACE_DEPTH];
+
+// where a lock was created; the address of the location is the class
+pub type Class = &'static Location<'static>;
+
+fn key(class: Class) -> usize {
+    class as *const _ as usize
+}
+
+// only ever called on keys made by key()
+fn class(key: usize) -> Class {
+    unsafe { &*(key as *const Location<'static>) }
+}
+
+#[derive(Clone, Copy)]
+struct HeldLock {
+    class: usize,
+    // the address of the lock itself
+    lock: usize,
+    site: Site,
+}
+
+struct CoreState {
+    held: [HeldLock; MAX_HELD],
+    depth: usize,
+    // set while lockdep itself is running, so that the locks it takes (mostly the logger's) aren't
+    // tracked recursively
+    busy: bool,
+}
+
+impl CoreState {
+    const fn new() -> CoreState {
+        CoreState {
+            held: [HeldLock {
+                class: 0,
+                lock: 0,
+                site: [0; TRACE_DEPTH],
+            }; MAX_HELD],
+            depth: 0,
+            busy: false,
+        }
+    }
+}
+
+core_local! {
+    STATE: UnsafeCell<CoreState> = UnsafeCell::new(CoreState::new());
+}
+
+// used by the BSP before core locals are set up
+static EARLY_STATE: SyncUnsafeCell<CoreState> = SyncUnsafeCell::new(CoreState::new());
+
+#[derive(Clone, Copy)]
+struct Edge {
+    from: usize,
+    to: usize,
+    // where `to` was taken while `from` was held
+    site: Site,
+    reported: bool,
+}
+
+struct EdgeTable {
+    edges: [Edge; MAX_EDGES],
+    len: usize,
+}
+
+// lockdep can't use an IntMutex for its own state, so this is a bare spinlock; it is only ever
+// taken with interrupts disabled
+static EDGES_LOCK: AtomicBool = AtomicBool::new(false);
+static EDGES: SyncUnsafeCell<EdgeTable> = SyncUnsafeCell::new(EdgeTable {
+    edges: [Edge {
+        from: 0,
+        to: 0,
+        site: [0; TRACE_DEPTH],
+        reported: false,
+    }; MAX_EDGES],
+    len: 0,
+});
+
+fn with_edges<R>(action: impl FnOnce(&mut EdgeTable) -> R) -> R {
+    while EDGES_LOCK.swap(true, Ordering::Acquire) {
+        hint::spin_loop();
+    }
+
+    let res = action(unsafe { &mut *EDGES.get() });
+
+    EDGES_LOCK.store(false, Ordering::Release);
+    res
+}
+
+fn with_state(action: impl FnOnce(&mut CoreState)) {
+    // past KInit, every core has its core locals set up before it can take a lock
+    if MP_STATE.load(Ordering::Relaxed) == MpState::KInit {
+        action(unsafe { &mut *EARLY_STATE.get() });
+    } else {
+        action(unsafe { &mut *STATE.get() });
+    }
+}
+
+fn hash(from: usize, to: usize) -> usize {
+    ((from >> 3) ^ (to >> 3).rotate_left(17)).wrapping_mul(0x9e3779b97f4a7c15)
+}
+
+impl EdgeTable {
+    fn find(&mut self, from: usize, to: usize) -> Option<&mut Edge> {
+        let start = hash(from, to);
+
+        let index = (0..MAX_EDGES)
+            .map(|i| (start % MAX_EDGES + i) % MAX_EDGES)
+            .take_while(|&i| self.edges[i].from != 0)
+            .find(|&i| self.edges[i].from == from && self.edges[i].to == to)?;
+
+        Some(&mut self.edges[index])
+    }
+
+    fn insert(&mut self, edge: Edge) {
+        // once the table fills up, new orders just go unchecked
+        if self.len >= MAX_EDGES * 3 / 4 {
+            return;
+        }
+
+        let start = hash(edge.from, edge.to);
+
+        if let Some(index) = (0..MAX_EDGES)
+            .map(|i| (start % MAX_EDGES + i) % MAX_EDGES)
+            .find(|&i| self.edges[i].from == 0)
+        {
+            self.edges[index] = edge;
+            self.len += 1;
+        }
+    }
+
+    // records that `to` was taken while `from` was held; returns the site of the reverse order
+    // if this is the first time the inversion was seen
+    fn record(&mut self, from: usize, to: usize, site: &Site) -> Option<Site> {
+        let inversion = self
+            .find(to, from)
+            .filter(|edge| !edge.reported)
+            .map(|edge| {
+                edge.reported = true;
+                edge.site
+            });
+
+        match self.find(from, to) {
+            Some(edge) if inversion.is_some() => edge.reported = true,
+            Some(_) => {}
+            None => self.insert(Edge {
+                from,
+                to,
+                site: *site,
+                reported: inversion.is_some(),
+            }),
+        }
+
+        inversion
+    }
+}
+
+// inlined into acquiring and acquired, which are deliberately not inlined themselves: the lock
+// functions are, so the first frame is the function that took the lock
+#[inline(always)]
+fn current_site() -> Site {
+    let mut site = [0; TRACE_DEPTH];
+    let mut context = unsafe { UnwindContext::get() };
+
+    for slot in site.iter_mut() {
+        if !unsafe { context.valid() } {
+            break;
+        }
+
+        *slot = unsafe { context.return_address() };
+        context = unsafe { context.next() };
+    }
+
+    site
+}
+
+fn dump_site(site: &Site) {
+    for &addr in site.iter().take_while(|&&addr| addr != 0) {
+        let name = symbols::symbolize(addr)
+            .0
+            .and_then(|mut iter| iter.next())
+            .and_then(|func| func.name)
+            .unwrap_or("unk");
+
+        error!("    {:#016x} in {:#}", addr, demangle(name));
+    }
+}
+
+fn report(held: &HeldLock, class: usize, site: &Site, reverse: &Site) {
+    let (from, to) = (self::class(held.class), self::class(class));
+
+    error!(
+        "sync::lockdep: possible ABBA deadlock between locks created at {} and {}",
+        from, to
+    );
+
+    error!("  {} taken while holding {} at:", to, from);
+    dump_site(site);
+    error!("  where {} was taken at:", from);
+    dump_site(&held.site);
+
+    error!(
+        "  but previously {} was taken while holding {} at:",
+        from, to
+    );
+    dump_site(reverse);
+}
+
+fn report_recursive(held: &HeldLock, site: &Site) {
+    error!(
+        "sync::lockdep: deadlock: lock created at {} taken again by the core holding it",
+        self::class(held.class)
+    );
+
+    dump_site(site);
+    error!("  where it was taken at:");
+    dump_site(&held.site);
+}
+
+// called with interrupts disabled, right before waiting for `lock`; a read lock taken again by
+// the core holding it only gets in as long as no writer is waiting, so that is caught here rather
+// than whenever a writer happens to show up
+#[inline(never)]
+pub fn acquiring(lock: *const ()) {
+    let lock = lock as usize;
+    let site = current_site();
+
+    with_state(|state| {
+        if state.busy {
+            return;
+        }
+
+        state.busy = true;
+
+        if let Some(held) = state.held[..state.depth]
+            .iter()
+            .find(|held| held.lock == lock)
+        {
+            report_recursive(held, &site);
+        }
+
+        state.busy = false;
+    });
+}
+
+// called with interrupts disabled, right after `lock` was acquired
+#[inline(never)]
+pub fn acquired(class: Class, lock: *const ()) {
+    let class = key(class);
+    let lock = lock as usize;
+    let site = current_site();
+
+    with_state(|state| {
+        if state.busy {
+            return;
+        }
+
+        state.busy = true;
+
+        for held in state.held[..state.depth].iter() {
+            // locks of one class (e.g. one per core) could only be told apart by their order
+            // within that class, which isn't tracked; the same lock twice is left to acquiring
+            if held.class == class {
+                continue;
+            }
+
+            if let Some(reverse) = with_edges(|edges| edges.record(held.class, class, &site)) {
+                report(held, class, &site, &reverse);
+            }
+        }
+
+        if state.depth < MAX_HELD {
+            state.held[state.depth] = HeldLock { class, lock, site };
+            state.depth += 1;
+        }
+
+        state.busy = false;
+    });
+}
+
+// called with interrupts disabled, right before a lock is released
+pub fn released(class: Class) {
+    let class = key(class);
+
+    with_state(|state| {
+        if state.busy {
+            return;
+        }
+
+        // guards aren't necessarily dropped in reverse order
+        if let Some(index) = state.held[..state.depth]
+            .iter()
+            .rposition(|held| held.class == class)
+        {
+            state.held.copy_within(index + 1..state.depth, index);
+            state.depth -= 1;
+        }
+    });
+}
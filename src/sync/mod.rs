@@ -0,0 +1,185 @@
+mod event;
+pub mod irq_arc;
+#[cfg(debug_assertions)]
+mod lockdep;
+pub mod rcu;
+mod rwlock;
+mod semaphore;
+mod seqlock;
+
+pub use event::*;
+pub use irq_arc::{IrqArc, IrqArcLink, IrqArcObject};
+pub use rwlock::*;
+pub use semaphore::*;
+pub use seqlock::*;
+
+use core::{
+    cell::UnsafeCell,
+    hint,
+    ops::{Deref, DerefMut},
+    sync::atomic::{AtomicU32, Ordering},
+    time::Duration,
+};
+
+use crate::{
+    arch::{IrqState, irq_disable, tsc},
+    mp::{MP_STATE, MpState},
+};
+
+pub struct IntMutexGuard<'a, T> {
+    mutex: &'a IntMutex<T>,
+    irq_state: IrqState,
+}
+
+impl<'a, T> Drop for IntMutexGuard<'a, T> {
+    fn drop(&mut self) {
+        #[cfg(debug_assertions)]
+        lockdep::released(self.mutex.class);
+
+        // TODO: wake things up from the queue
+        self.mutex.now_serving.fetch_add(1, Ordering::Release);
+        self.irq_state.restore();
+    }
+}
+
+impl<'a, T> Deref for IntMutexGuard<'a, T> {
+    type Target = T;
+
+    fn deref(&self) -> &Self::Target {
+        unsafe { &*self.mutex.data.get() }
+    }
+}
+
+impl<'a, T> DerefMut for IntMutexGuard<'a, T> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        unsafe { &mut *self.mutex.data.get() }
+    }
+}
+
+/// interrupt-disabled "smart" mutex
+/// will spin for a fixed number of cycles before sleeping the current thread, but only if the
+/// current context can be preempted.
+pub struct IntMutex<T> {
+    // underlying ticket lock, so that contending cores are served in FIFO order
+    next_ticket: AtomicU32,
+    now_serving: AtomicU32,
+    data: UnsafeCell<T>,
+    #[cfg(debug_assertions)]
+    class: lockdep::Class,
+    // TODO: we need a blocked queue here
+}
+
+
+impl<T> IntMutex<T> {
+    // the caller is the lockdep class of the lock
+    #[track_caller]
+    pub const fn new(init: T) -> IntMutex<T> {
+        IntMutex {
+            next_ticket: AtomicU32::new(0),
+            now_serving: AtomicU32::new(0),
+            data: UnsafeCell::new(init),
+            #[cfg(debug_assertions)]
+            class: core::panic::Location::caller(),
+        }
+    }
+
+    #[inline(always)]
+    pub fn lock(&self) -> IntMutexGuard<'_, T> {
+        // TODO: for performance, the lock should be implemented as a optimistic xchg lock (then
+        // check preemption state, then block/poll)
+
+        if MP_STATE.load(Ordering::Relaxed) == MpState::MPPreempt {
+            // TODO: this is a pre-emptable state, we need to be able to pre-empt.
+            todo!()
+        }
+
+        let state = IrqState::save();
+
+        // once a ticket is taken, it can't be given back, so unlike a test-and-set lock we have to
+        // spin with interrupts disabled: an interrupt handler on this core taking the same lock
+        // would otherwise wait behind a ticket that can never be served
+        irq_disable();
+
+        #[cfg(debug_assertions)]
+        lockdep::acquiring(self as *const _ as *const ());
+
+        let ticket = self.next_ticket.fetch_add(1, Ordering::Relaxed);
+
+        while self.now_serving.load(Ordering::Acquire) != ticket {
+            hint::spin_loop();
+        }
+
+        #[cfg(debug_assertions)]
+        lockdep::acquired(self.class, self as *const _ as *const ());
+
+        IntMutexGuard {
+            mutex: self,
+            irq_state: state,
+        }
+    }
+
+    // takes the lock only if nobody holds or is waiting for it
+    #[inline(always)]
+    pub fn try_lock(&self) -> Option<IntMutexGuard<'_, T>> {
+        let state = IrqState::save();
+        irq_disable();
+
+        let serving = self.now_serving.load(Ordering::Acquire);
+
+        if self
+            .next_ticket
+            .compare_exchange(
+                serving,
+                serving.wrapping_add(1),
+                Ordering::Acquire,
+                Ordering::Relaxed,
+            )
+            .is_err()
+        {
+            state.restore();
+            return None;
+        }
+
+        #[cfg(debug_assertions)]
+        lockdep::acquired(self.class, self as *const _ as *const ());
+
+        Some(IntMutexGuard {
+            mutex: self,
+            irq_state: state,
+        })
+    }
+
+    // gives up after `timeout`, e.g. so a watchdog doesn't hang on a lock held by a wedged core
+    // this polls try_lock rather than taking a ticket, since a ticket can't be handed back, so it
+    // doesn't get the FIFO guarantee of lock(). before the TSC is calibrated, it never times out.
+    #[inline(always)]
+    pub fn lock_timeout(&self, timeout: Duration) -> Option<IntMutexGuard<'_, T>> {
+        let deadline = tsc::now() + timeout;
+
+        loop {
+            if let Some(guard) = self.try_lock() {
+                return Some(guard);
+            }
+
+            if tsc::now() >= deadline {
+                return None;
+            }
+
+            hint::spin_loop();
+        }
+    }
+
+    // releases the lock, whoever holds it, and returns whether anyone did. this is for the panic
+    // path, where the holder may be a stopped core, or the code that panicked; whoever held or was
+    // waiting for the lock must never touch the data again.
+    pub unsafe fn force_unlock(&self) -> bool {
+        #[cfg(debug_assertions)]
+        lockdep::released(self.class);
+
+        let next = self.next_ticket.load(Ordering::Relaxed);
+        self.now_serving.swap(next, Ordering::Release) != next
+    }
+}
+
+unsafe impl<T: Send> Send for IntMutex<T> {}
+unsafe impl<T: Send> Sync for IntMutex<T> {}
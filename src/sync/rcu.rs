@@ -0,0 +1,211 @@
+// read-copy-update
+//
+// the kernel is not preemptible, so a read-side critical section is simply a stretch of code that
+// doesn't pass through a quiescent state (idle or a context switch). writers publish a new copy
+// of the data, then wait for every online core to pass through a quiescent state before the old
+// copy can be freed, since no reader can still be looking at it by then.
+
+extern crate alloc;
+
+use alloc::{boxed::Box, vec::Vec};
+use core::{
+    hint,
+    marker::PhantomData,
+    sync::atomic::{AtomicBool, AtomicPtr, AtomicU64, Ordering},
+};
+use spin::Once;
+
+use super::{IntMutex, irq_arc};
+use crate::mp::{CORE_ID, MP_STATE, MpState};
+
+struct CoreState {
+    // the latest grace period this core has passed through a quiescent state for
+    seen: AtomicU64,
+    // idle (or not yet online) cores can't be inside a read-side section, so they never hold up
+    // a grace period
+    idle: AtomicBool,
+}
+
+static GRACE_PERIOD: AtomicU64 = AtomicU64::new(0);
+static CORES: Once<Vec<CoreState>> = Once::new();
+
+type Callback = Box<dyn FnOnce() + Send>;
+
+// pending callbacks along with the grace period they are waiting for, in ascending order
+static CALLBACKS: IntMutex<Vec<(u64, Callback)>> = IntMutex::new(Vec::new());
+
+pub fn init(n_cores: usize) {
+    CORES.call_once(|| {
+        (0..n_cores)
+            .map(|_| CoreState {
+                seen: AtomicU64::new(0),
+                idle: AtomicBool::new(true),
+            })
+            .collect()
+    });
+}
+
+fn current_core() -> Option<&'static CoreState> {
+    if MP_STATE.load(Ordering::Relaxed) == MpState::KInit {
+        return None;
+    }
+
+    CORES.get().map(|cores| &cores[CORE_ID.get().0])
+}
+
+fn is_complete(grace_period: u64) -> bool {
+    let Some(cores) = CORES.get() else {
+        return true;
+    };
+
+    cores.iter().all(|core| {
+        core.idle.load(Ordering::SeqCst) || core.seen.load(Ordering::SeqCst) >= grace_period
+    })
+}
+
+fn start_grace_period() -> u64 {
+    GRACE_PERIOD.fetch_add(1, Ordering::SeqCst) + 1
+}
+
+fn run_callbacks() {
+    loop {
+        // callbacks are run without the queue lock held, since they may well queue more
+        let callback = {
+            let mut callbacks = CALLBACKS.lock();
+
+            match callbacks.first() {
+                Some(&(grace_period, _)) if is_complete(grace_period) => callbacks.remove(0).1,
+                _ => return,
+            }
+        };
+
+        callback();
+    }
+}
+
+// marks the current core as having left every read-side section it was in; called from the idle
+// loop and on context switches
+pub fn quiescent() {
+    let Some(core) = current_core() else {
+        return;
+    };
+
+    core.seen
+        .store(GRACE_PERIOD.load(Ordering::SeqCst), Ordering::SeqCst);
+
+    run_callbacks();
+
+    // quiescent states are always reached from thread context, so this is also where objects
+    // released from interrupt handlers get destroyed, besides the end of device interrupts
+    irq_arc::reap();
+}
+
+// brings the current core into grace period tracking, once its core locals are set up
+pub fn online() {
+    let Some(core) = current_core() else {
+        return;
+    };
+
+    core.seen
+        .store(GRACE_PERIOD.load(Ordering::SeqCst), Ordering::SeqCst);
+    core.idle.store(false, Ordering::SeqCst);
+}
+
+// called before the current core goes idle; `online` brings it back
+pub fn enter_idle() {
+    let Some(core) = current_core() else {
+        return;
+    };
+
+    quiescent();
+    core.idle.store(true, Ordering::SeqCst);
+}
+
+// waits until every read-side section that was running when this was called has finished
+// must not be called from inside a read-side section, or it will never return
+pub fn synchronize() {
+    let grace_period = start_grace_period();
+
+    // before the other cores are up, the caller is the only possible reader
+    if current_core().is_none() {
+        return;
+    }
+
+    quiescent();
+
+    while !is_complete(grace_period) {
+        hint::spin_loop();
+    }
+
+    run_callbacks();
+}
+
+// runs `callback` once every read-side section running right now has finished, without waiting
+// for it; callbacks run from whichever core next passes through a quiescent state. this only
+// queues, since the caller may well be inside a read-side section itself
+pub fn call_rcu(callback: impl FnOnce() + Send + 'static) {
+    let grace_period = start_grace_period();
+
+    CALLBACKS.lock().push((grace_period, Box::new(callback)));
+}
+
+/// marker for a read-side critical section
+/// this is purely a static check; it must not be held across anything that can pass through a
+/// quiescent state, such as going idle.
+pub struct RcuReadGuard {
+    // read-side sections are tied to the core they started on
+    _marker: PhantomData<*const ()>,
+}
+
+pub fn read_lock() -> RcuReadGuard {
+    RcuReadGuard {
+        _marker: PhantomData,
+    }
+}
+
+/// a pointer to RCU-protected data
+/// readers get a reference that stays valid for the duration of their read-side section, while
+/// writers swap in a new copy and have the old one dropped once all readers are gone. writers
+/// that update based on the current value must be serialized by the caller.
+pub struct RcuCell<T> {
+    ptr: AtomicPtr<T>,
+}
+
+impl<T: Send + Sync + 'static> RcuCell<T> {
+    pub fn new(init: T) -> RcuCell<T> {
+        RcuCell {
+            ptr: AtomicPtr::new(Box::into_raw(Box::new(init))),
+        }
+    }
+
+    pub fn read<'a>(&'a self, _guard: &'a RcuReadGuard) -> &'a T {
+        unsafe { &*self.ptr.load(Ordering::Acquire) }
+    }
+
+    // publishes `value`, freeing the old copy after a grace period
+    pub fn replace(&self, value: T) {
+        let old = self
+            .ptr
+            .swap(Box::into_raw(Box::new(value)), Ordering::AcqRel);
+
+        // raw pointers aren't Send, so carry the address instead
+        let old = old as usize;
+        call_rcu(move || drop(unsafe { Box::from_raw(old as *mut T) }));
+    }
+
+    // publishes a modified copy of the current value; see the note on serializing writers
+    pub fn update(&self, update: impl FnOnce(&T) -> T) {
+        let guard = read_lock();
+        let value = update(self.read(&guard));
+        self.replace(value);
+    }
+}
+
+impl<T> Drop for RcuCell<T> {
+    fn drop(&mut self) {
+        drop(unsafe { Box::from_raw(*self.ptr.get_mut()) });
+    }
+}
+
+unsafe impl<T: Send + Sync> Send for RcuCell<T> {}
+unsafe impl<T: Send + Sync> Sync for RcuCell<T> {}
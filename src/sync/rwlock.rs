@@ -0,0 +1,234 @@
+use core::{
+    cell::UnsafeCell,
+    hint,
+    ops::{Deref, DerefMut},
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+use crate::{
+    arch::{IrqState, irq_disable},
+    mp::{MP_STATE, MpState},
+};
+
+// the low bits count active readers
+const WRITER: usize = 1 << (usize::BITS - 1);
+// set by a spinning writer so that new readers back off instead of starving it
+const WRITER_WAITING: usize = 1 << (usize::BITS - 2);
+const READERS_MASK: usize = WRITER_WAITING - 1;
+
+pub struct RwIntLockReadGuard<'a, T> {
+    lock: &'a RwIntLock<T>,
+    irq_state: IrqState,
+}
+
+impl<'a, T> Drop for RwIntLockReadGuard<'a, T> {
+    fn drop(&mut self) {
+        #[cfg(debug_assertions)]
+        super::lockdep::released(self.lock.class);
+
+        self.lock.state.fetch_sub(1, Ordering::Release);
+        self.irq_state.restore();
+    }
+}
+
+impl<'a, T> Deref for RwIntLockReadGuard<'a, T> {
+    type Target = T;
+
+    fn deref(&self) -> &Self::Target {
+        unsafe { &*self.lock.data.get() }
+    }
+}
+
+pub struct RwIntLockWriteGuard<'a, T> {
+    lock: &'a RwIntLock<T>,
+    irq_state: IrqState,
+}
+
+impl<'a, T> Drop for RwIntLockWriteGuard<'a, T> {
+    fn drop(&mut self) {
+        #[cfg(debug_assertions)]
+        super::lockdep::released(self.lock.class);
+
+        self.lock.state.fetch_and(!WRITER, Ordering::Release);
+        self.irq_state.restore();
+    }
+}
+
+impl<'a, T> Deref for RwIntLockWriteGuard<'a, T> {
+    type Target = T;
+
+    fn deref(&self) -> &Self::Target {
+        unsafe { &*self.lock.data.get() }
+    }
+}
+
+impl<'a, T> DerefMut for RwIntLockWriteGuard<'a, T> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        unsafe { &mut *self.lock.data.get() }
+    }
+}
+
+/// interrupt-disabled reader-writer lock
+/// any number of readers may hold the lock at once, but writers are exclusive. waiting writers
+/// take priority over new readers, so a steady stream of readers can't lock a writer out forever.
+pub struct RwIntLock<T> {
+    state: AtomicUsize,
+    data: UnsafeCell<T>,
+    #[cfg(debug_assertions)]
+    class: super::lockdep::Class,
+}
+
+impl<T> RwIntLock<T> {
+    // the caller is the lockdep class of the lock
+    #[track_caller]
+    pub const fn new(init: T) -> RwIntLock<T> {
+        RwIntLock {
+            state: AtomicUsize::new(0),
+            data: UnsafeCell::new(init),
+            #[cfg(debug_assertions)]
+            class: core::panic::Location::caller(),
+        }
+    }
+
+    // not recursive: a core taking the read lock again while a writer is waiting backs off behind
+    // that writer, which in turn waits for the core's first read lock to be released
+    #[inline(always)]
+    pub fn read(&self) -> RwIntLockReadGuard<'_, T> {
+        // TODO: once cores are pre-emptable (MPPreempt), this should be able to pre-empt; until
+        // then, spinning still works, it just burns the time slice
+        debug_assert!(
+            MP_STATE.load(Ordering::Relaxed) != MpState::MPPreempt,
+            "RwIntLock::read(): preemption isn't supported yet"
+        );
+
+        let state = IrqState::save();
+        irq_disable();
+
+        #[cfg(debug_assertions)]
+        super::lockdep::acquiring(self as *const _ as *const ());
+
+        loop {
+            irq_disable();
+
+            let current = self.state.load(Ordering::Relaxed);
+
+            if current & (WRITER | WRITER_WAITING) == 0 {
+                assert!(
+                    current & READERS_MASK != READERS_MASK,
+                    "RwIntLock::read(): too many readers"
+                );
+
+                if self
+                    .state
+                    .compare_exchange_weak(
+                        current,
+                        current + 1,
+                        Ordering::Acquire,
+                        Ordering::Relaxed,
+                    )
+                    .is_ok()
+                {
+                    break;
+                }
+
+                continue;
+            }
+
+            state.restore();
+
+            while self.state.load(Ordering::Relaxed) & (WRITER | WRITER_WAITING) != 0 {
+                hint::spin_loop();
+            }
+        }
+
+        #[cfg(debug_assertions)]
+        super::lockdep::acquired(self.class, self as *const _ as *const ());
+
+        RwIntLockReadGuard {
+            lock: self,
+            irq_state: state,
+        }
+    }
+
+    // takes a read lock only if no writer holds or is waiting for it
+    #[inline(always)]
+    pub fn try_read(&self) -> Option<RwIntLockReadGuard<'_, T>> {
+        let state = IrqState::save();
+        irq_disable();
+
+        let current = self.state.load(Ordering::Relaxed);
+
+        if current & (WRITER | WRITER_WAITING) != 0
+            || current & READERS_MASK == READERS_MASK
+            || self
+                .state
+                .compare_exchange(current, current + 1, Ordering::Acquire, Ordering::Relaxed)
+                .is_err()
+        {
+            state.restore();
+            return None;
+        }
+
+        #[cfg(debug_assertions)]
+        super::lockdep::acquired(self.class, self as *const _ as *const ());
+
+        Some(RwIntLockReadGuard {
+            lock: self,
+            irq_state: state,
+        })
+    }
+
+    #[inline(always)]
+    pub fn write(&self) -> RwIntLockWriteGuard<'_, T> {
+        // TODO: once cores are pre-emptable (MPPreempt), this should be able to pre-empt; until
+        // then, spinning still works, it just burns the time slice
+        debug_assert!(
+            MP_STATE.load(Ordering::Relaxed) != MpState::MPPreempt,
+            "RwIntLock::write(): preemption isn't supported yet"
+        );
+
+        let state = IrqState::save();
+        irq_disable();
+
+        #[cfg(debug_assertions)]
+        super::lockdep::acquiring(self as *const _ as *const ());
+
+        loop {
+            irq_disable();
+
+            let current = self.state.load(Ordering::Relaxed);
+
+            if current & (WRITER | READERS_MASK) == 0 {
+                // clearing WRITER_WAITING here is fine even if another writer set it, since that
+                // writer sets it again if it has to wait another round
+                if self
+                    .state
+                    .compare_exchange_weak(current, WRITER, Ordering::Acquire, Ordering::Relaxed)
+                    .is_ok()
+                {
+                    break;
+                }
+
+                continue;
+            }
+
+            self.state.fetch_or(WRITER_WAITING, Ordering::Relaxed);
+            state.restore();
+
+            while self.state.load(Ordering::Relaxed) & (WRITER | READERS_MASK) != 0 {
+                hint::spin_loop();
+            }
+        }
+
+        #[cfg(debug_assertions)]
+        super::lockdep::acquired(self.class, self as *const _ as *const ());
+
+        RwIntLockWriteGuard {
+            lock: self,
+            irq_state: state,
+        }
+    }
+}
+
+unsafe impl<T: Send> Send for RwIntLock<T> {}
+unsafe impl<T: Send + Sync> Sync for RwIntLock<T> {}
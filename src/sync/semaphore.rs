@@ -0,0 +1,58 @@
+use core::{
+    hint,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+use crate::mp::{MP_STATE, MpState};
+
+/// counting semaphore
+/// waiters currently spin, so a count that is only ever released from an interrupt handler must
+/// not be waited on with interrupts disabled on the core that takes that interrupt.
+pub struct Semaphore {
+    count: AtomicUsize,
+    // TODO: we need a blocked queue here
+}
+
+impl Semaphore {
+    pub const fn new(count: usize) -> Semaphore {
+        Semaphore {
+            count: AtomicUsize::new(count),
+        }
+    }
+
+    pub fn try_acquire(&self) -> bool {
+        self.count
+            .fetch_update(Ordering::Acquire, Ordering::Relaxed, |count| {
+                count.checked_sub(1)
+            })
+            .is_ok()
+    }
+
+    pub fn acquire(&self) {
+        // TODO: once cores are pre-emptable (MPPreempt), this should sleep; until then, spinning
+        // still works, it just burns the time slice
+        debug_assert!(
+            MP_STATE.load(Ordering::Relaxed) != MpState::MPPreempt,
+            "Semaphore::acquire(): preemption isn't supported yet"
+        );
+
+        while !self.try_acquire() {
+            while self.count.load(Ordering::Relaxed) == 0 {
+                hint::spin_loop();
+            }
+        }
+    }
+
+    pub fn release(&self) {
+        self.release_n(1);
+    }
+
+    pub fn release_n(&self, n: usize) {
+        // TODO: wake things up from the queue
+        self.count.fetch_add(n, Ordering::Release);
+    }
+
+    pub fn count(&self) -> usize {
+        self.count.load(Ordering::Relaxed)
+    }
+}
@@ -0,0 +1,76 @@
+use core::{
+    cell::UnsafeCell,
+    hint, ptr,
+    sync::atomic::{AtomicUsize, Ordering, fence},
+};
+
+use super::IntMutex;
+
+/// sequence lock for small, frequently read and rarely written data
+/// readers never block writers and never disable interrupts; they just retry if a write raced
+/// with them. writers are serialized by an IntMutex, which also keeps interrupt handlers on the
+/// writing core from spinning on a write that can't finish.
+pub struct SeqLock<T: Copy> {
+    // odd while a write is in progress
+    seq: AtomicUsize,
+    data: UnsafeCell<T>,
+    writer: IntMutex<()>,
+}
+
+impl<T: Copy> SeqLock<T> {
+    // the caller is the lockdep class of the writer lock
+    #[track_caller]
+    pub const fn new(init: T) -> SeqLock<T> {
+        SeqLock {
+            seq: AtomicUsize::new(0),
+            data: UnsafeCell::new(init),
+            writer: IntMutex::new(()),
+        }
+    }
+
+    pub fn read(&self) -> T {
+        self.read_part(|data| data)
+    }
+
+    // copies out only the part of the value that `part` points to, given a pointer to the whole
+    // of it; for a large value of which only a little is wanted
+    pub fn read_part<U: Copy>(&self, part: impl Fn(*const T) -> *const U) -> U {
+        loop {
+            let start = self.seq.load(Ordering::Acquire);
+
+            if start & 1 != 0 {
+                hint::spin_loop();
+                continue;
+            }
+
+            // this may observe a torn value, which is thrown away below
+            let value = unsafe { ptr::read_volatile(part(self.data.get())) };
+
+            fence(Ordering::Acquire);
+
+            if self.seq.load(Ordering::Relaxed) == start {
+                return value;
+            }
+        }
+    }
+
+    pub fn write(&self, update: impl FnOnce(&mut T)) {
+        let _lock = self.writer.lock();
+
+        self.seq.fetch_add(1, Ordering::Relaxed);
+        fence(Ordering::Release);
+
+        let mut value = unsafe { ptr::read_volatile(self.data.get()) };
+        update(&mut value);
+        unsafe { ptr::write_volatile(self.data.get(), value) };
+
+        self.seq.fetch_add(1, Ordering::Release);
+    }
+
+    pub fn set(&self, value: T) {
+        self.write(|data| *data = value);
+    }
+}
+
+unsafe impl<T: Copy + Send> Send for SeqLock<T> {}
+unsafe impl<T: Copy + Send> Sync for SeqLock<T> {}